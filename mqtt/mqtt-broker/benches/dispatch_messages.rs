@@ -8,6 +8,7 @@
 //! Scenarios supported:
 //! * all subscribers receive messages on their own topic
 //! * all subscribers receive messages on shared topic
+//! * many subscribers on their own topic, only one of them receives a message
 //!
 //! How to run benches
 //! ```bash
@@ -55,7 +56,8 @@ use mqtt_broker::{
 criterion_group!(
     basic,
     subscribe_to_separate_topic,
-    subscribe_to_common_topic
+    subscribe_to_common_topic,
+    subscribe_to_separate_topic_many_clients
 );
 criterion_main!(basic);
 
@@ -113,6 +115,27 @@ fn subscribe_to_common_topic(c: &mut Criterion) {
     }
 }
 
+/// Many subscribers subscribe to its own topic.
+/// Publisher randomly selects a one of topics and publishes small messages to it.
+///
+/// Dispatch time is dominated by finding the single matching subscriber
+/// among all connected clients.
+fn subscribe_to_separate_topic_many_clients(c: &mut Criterion) {
+    init_logging();
+
+    let qos = proto::QoS::AtMostOnce;
+    let payload_size = Size::B(32);
+    let name = format!("sub_separate_many_{}_q{}", payload_size, u8::from(qos));
+    let mut group = c.benchmark_group(&name);
+
+    for count in &[100, 1000, 5000] {
+        let strategy = Strategy::SeparateTopic(*count);
+        dispatch_messages(&mut group, strategy, *count, payload_size, qos);
+    }
+
+    group.finish();
+}
+
 fn scenarios() -> (Vec<(proto::QoS, Size)>, Vec<usize>) {
     let sizes = vec![Size::B(32), Size::Kb(1), Size::Kb(128), Size::Mb(1)];
     let qoses = vec![proto::QoS::AtMostOnce, proto::QoS::AtLeastOnce];
@@ -244,6 +267,7 @@ impl Client {
             keep_alive: Duration::from_secs(10),
             protocol_name: PROTOCOL_NAME.into(),
             protocol_level: PROTOCOL_LEVEL,
+            properties: proto::Properties::default(),
         };
         let connreq = ConnReq::new(client.id.as_client_id(), connect, None, connection_handle);
         let message = Message::Client(client.id.as_client_id(), ClientEvent::ConnReq(connreq));
@@ -259,6 +283,7 @@ impl Client {
                 topic_filter,
                 qos: max_qos,
             }],
+            properties: proto::Properties::default(),
         };
         let message = Message::Client(self.id.as_client_id(), ClientEvent::Subscribe(subscribe));
         self.broker_handle.send(message).await.expect("subscribe");
@@ -292,11 +317,15 @@ impl Client {
                                     acks.insert(id);
                                     Some(ClientEvent::PubAck(proto::PubAck {
                                         packet_identifier: id,
+                                        reason_code: proto::ReasonCode::SUCCESS,
+                                        properties: proto::Properties::default(),
                                     }))
                                 }
                                 proto::PacketIdentifierDupQoS::ExactlyOnce(id, _) => {
                                     Some(ClientEvent::PubRec(proto::PubRec {
                                         packet_identifier: id,
+                                        reason_code: proto::ReasonCode::SUCCESS,
+                                        properties: proto::Properties::default(),
                                     }))
                                 }
                             }
@@ -368,6 +397,7 @@ impl PublishHandle {
             retain: false,
             topic_name,
             payload: Bytes::from_iter((0..payload_size.into()).map(|_| rand::random::<u8>())),
+            properties: proto::Properties::default(),
         }
     }
 }
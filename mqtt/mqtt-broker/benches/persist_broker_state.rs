@@ -7,10 +7,10 @@ use criterion::{
 };
 use tempfile::TempDir;
 
-use mqtt3::proto::{Publication, QoS};
+use mqtt3::proto::{Properties, Publication, QoS};
 use mqtt_broker::{
     BrokerState, ClientId, ConsolidatedStateFormat, FileFormat, FilePersistor, Persist,
-    PersistError, SessionState,
+    PersistError, RetainedPublication, SessionState,
 };
 
 fn test_write<F>(
@@ -97,7 +97,7 @@ fn make_fake_state(
     let retained = HashMap::from_iter((0..num_retained).map(|i| {
         (
             format!("Retained {}", i),
-            make_fake_publish(format!("Retained {}", i)),
+            RetainedPublication::new(make_fake_publish(format!("Retained {}", i))),
         )
     }));
 
@@ -129,6 +129,7 @@ fn make_fake_publish(topic_name: String) -> Publication {
         retain: false,
         qos: QoS::AtLeastOnce,
         payload: make_random_payload(10),
+        properties: Properties::default(),
     }
 }
 
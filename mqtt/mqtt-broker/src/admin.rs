@@ -0,0 +1,184 @@
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use mqtt3::proto;
+use openssl::base64;
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+use crate::broker::{BrokerHandle, RetainedPublication};
+use crate::session::Session;
+use crate::{ClientId, Error, Message, StateSnapshotHandle, SystemEvent};
+
+/// Administrative requests to a running broker.
+#[derive(Debug)]
+pub enum AdminRequest {
+    /// Lists every session the broker knows about.
+    ListSessions(oneshot::Sender<Vec<SessionInfo>>),
+
+    /// Drops the connection of a connected client.
+    DisconnectClient(ClientId, oneshot::Sender<Result<(), AdminError>>),
+
+    /// Removes an offline session with its subscriptions and queued publications.
+    PurgeSession(ClientId, oneshot::Sender<Result<(), AdminError>>),
+
+    /// Lists retained publications.
+    ListRetained(oneshot::Sender<Vec<RetainedInfo>>),
+
+    /// Removes the retained publication of a topic, or every retained
+    /// publication of clients if no topic is given. The broker's own `$` topics
+    /// are never removed. Replies with the number removed.
+    ClearRetained(Option<String>, oneshot::Sender<usize>),
+
+    /// Publishes a retained publication as if a client did. The topic must be
+    /// a valid topic name outside of the `$` topics the broker maintains.
+    SetRetained(proto::Publication, oneshot::Sender<Result<(), AdminError>>),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AdminError {
+    #[error("No session for client {0}.")]
+    NoSession(ClientId),
+
+    #[error("Session of client {0} is not connected.")]
+    SessionOffline(ClientId),
+
+    #[error("Session of client {0} is connected.")]
+    SessionConnected(ClientId),
+
+    #[error("Invalid topic name: {0:?}")]
+    InvalidTopic(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionStatus {
+    Connected,
+    Disconnecting,
+    Offline,
+}
+
+/// Describes a session as seen by the broker.
+#[derive(Clone, Debug, Serialize)]
+pub struct SessionInfo {
+    pub client_id: ClientId,
+    pub status: SessionStatus,
+    pub persistent: bool,
+    pub subscriptions: Vec<String>,
+    pub queued: usize,
+    pub inflight: usize,
+}
+
+impl SessionInfo {
+    pub(crate) fn new(session: &Session) -> Self {
+        let (status, persistent, state) = match session {
+            Session::Transient(connected) => {
+                (SessionStatus::Connected, false, Some(connected.state()))
+            }
+            Session::Persistent(connected) => {
+                (SessionStatus::Connected, true, Some(connected.state()))
+            }
+            Session::Offline(offline) => (SessionStatus::Offline, true, Some(offline.state())),
+            Session::Disconnecting(_) => (SessionStatus::Disconnecting, false, None),
+        };
+
+        let mut subscriptions = state
+            .map(|state| state.subscriptions().keys().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        subscriptions.sort();
+
+        Self {
+            client_id: session.client_id().clone(),
+            status,
+            persistent,
+            subscriptions,
+            queued: state.map_or(0, |state| state.queued_count()),
+            inflight: state.map_or(0, |state| state.inflight_count()),
+        }
+    }
+}
+
+/// Describes a retained publication. The payload is base64 encoded, as it
+/// may hold binary data.
+#[derive(Clone, Debug, Serialize)]
+pub struct RetainedInfo {
+    pub topic: String,
+    pub qos: u8,
+    pub payload: String,
+    pub stored_at: SystemTime,
+}
+
+impl RetainedInfo {
+    pub(crate) fn new(retained: &RetainedPublication) -> Self {
+        let publication = retained.publication();
+        Self {
+            topic: publication.topic_name.clone(),
+            qos: publication.qos.into(),
+            payload: base64::encode_block(&publication.payload),
+            stored_at: retained.stored_at(),
+        }
+    }
+}
+
+impl BrokerHandle {
+    pub async fn sessions(&mut self) -> Result<Vec<SessionInfo>, Error> {
+        self.request(AdminRequest::ListSessions).await
+    }
+
+    pub async fn disconnect_client(
+        &mut self,
+        client_id: ClientId,
+    ) -> Result<Result<(), AdminError>, Error> {
+        self.request(|reply| AdminRequest::DisconnectClient(client_id, reply))
+            .await
+    }
+
+    pub async fn purge_session(
+        &mut self,
+        client_id: ClientId,
+    ) -> Result<Result<(), AdminError>, Error> {
+        self.request(|reply| AdminRequest::PurgeSession(client_id, reply))
+            .await
+    }
+
+    pub async fn retained(&mut self) -> Result<Vec<RetainedInfo>, Error> {
+        self.request(AdminRequest::ListRetained).await
+    }
+
+    pub async fn clear_retained(&mut self, topic: Option<String>) -> Result<usize, Error> {
+        self.request(|reply| AdminRequest::ClearRetained(topic, reply))
+            .await
+    }
+
+    pub async fn set_retained(
+        &mut self,
+        topic: String,
+        qos: proto::QoS,
+        payload: Bytes,
+    ) -> Result<Result<(), AdminError>, Error> {
+        let publication = proto::Publication {
+            topic_name: topic,
+            qos,
+            retain: true,
+            payload,
+            properties: proto::Properties::default(),
+        };
+        self.request(|reply| AdminRequest::SetRetained(publication, reply))
+            .await
+    }
+
+    pub async fn snapshot(&mut self, snapshot_handle: StateSnapshotHandle) -> Result<(), Error> {
+        self.send(Message::System(SystemEvent::StateSnapshot(snapshot_handle)))
+            .await
+    }
+
+    async fn request<T, F>(&mut self, request: F) -> Result<T, Error>
+    where
+        F: FnOnce(oneshot::Sender<T>) -> AdminRequest,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.send(Message::System(SystemEvent::Admin(request(tx))))
+            .await?;
+        rx.await.map_err(Error::NoReply)
+    }
+}
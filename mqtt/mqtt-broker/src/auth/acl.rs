@@ -0,0 +1,471 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::auth::{Activity, AuthId, Authorizer, Operation};
+use crate::{ClientId, TopicFilter};
+
+const CLIENT_ID_PLACEHOLDER: &str = "%c";
+const IDENTITY_PLACEHOLDER: &str = "%u";
+const ANONYMOUS_IDENTITY: &str = "$anonymous";
+
+/// Authorizes client activities with the rules of an access control list file.
+///
+/// The file is either JSON or YAML (`.yaml` or `.yml` extension) and holds
+/// a list of rules. A rule applies to an activity when every given condition
+/// matches it. Conditions left out match anything:
+///
+/// ```json
+/// {
+///     "rules": [
+///         {
+///             "effect": "allow",
+///             "identities": ["device-1", "$anonymous"],
+///             "clients": ["device-1"],
+///             "operations": ["publish", "subscribe"],
+///             "topics": ["devices/%c/#", "users/%u/+"]
+///         }
+///     ]
+/// }
+/// ```
+///
+/// `%c` in a topic pattern stands for the client id and `%u` for the
+/// client identity. Rules with topics never apply to connect activities.
+///
+/// A deny rule takes precedence over any allow rule. Activities no rule
+/// applies to are denied.
+///
+/// The file can also override the inflight window of some clients. The first
+/// entry matching the identity and client id of a client applies:
+///
+/// ```json
+/// {
+///     "rules": [],
+///     "inflight_messages": [
+///         { "identities": ["leaf-device"], "max_count": 2 }
+///     ]
+/// }
+/// ```
+pub struct AclAuthorizer {
+    path: PathBuf,
+    acl: Acl,
+}
+
+impl AclAuthorizer {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, AclError> {
+        let path = path.as_ref().to_path_buf();
+        let acl = Acl::load(&path)?;
+        Ok(Self { path, acl })
+    }
+}
+
+impl Authorizer for AclAuthorizer {
+    type Error = AclError;
+
+    fn authorize(&self, activity: Activity) -> Result<bool, Self::Error> {
+        Ok(self.acl.is_allowed(&activity))
+    }
+
+    fn max_inflight_messages(&self, auth_id: &AuthId, client_id: &ClientId) -> Option<usize> {
+        self.acl
+            .inflight_messages
+            .iter()
+            .find(|limit| matches_client(&limit.identities, &limit.clients, auth_id, client_id))
+            .map(|limit| limit.max_count)
+    }
+
+    /// Reads the access control list file again.
+    ///
+    /// Rules in effect are kept if the file cannot be loaded.
+    fn reload(&mut self) -> Result<(), Self::Error> {
+        self.acl = Acl::load(&self.path)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Acl {
+    rules: Vec<Rule>,
+    #[serde(default)]
+    inflight_messages: Vec<InflightLimit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct InflightLimit {
+    #[serde(default)]
+    identities: Vec<String>,
+    #[serde(default)]
+    clients: Vec<String>,
+    max_count: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct Rule {
+    effect: Effect,
+    #[serde(default)]
+    identities: Vec<String>,
+    #[serde(default)]
+    clients: Vec<String>,
+    #[serde(default)]
+    operations: Vec<OperationKind>,
+    #[serde(default)]
+    topics: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Effect {
+    Allow,
+    Deny,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum OperationKind {
+    Connect,
+    Publish,
+    Subscribe,
+    Receive,
+}
+
+impl Acl {
+    fn load(path: &Path) -> Result<Self, AclError> {
+        let contents =
+            fs::read_to_string(path).map_err(|e| AclError::ReadFile(path.to_path_buf(), e))?;
+
+        let is_yaml = path
+            .extension()
+            .map_or(false, |ext| ext == "yaml" || ext == "yml");
+        let acl: Acl = if is_yaml {
+            serde_yaml::from_str(&contents)
+                .map_err(|e| AclError::ParseYaml(path.to_path_buf(), e))?
+        } else {
+            serde_json::from_str(&contents)
+                .map_err(|e| AclError::ParseJson(path.to_path_buf(), e))?
+        };
+
+        // placeholders are substituted with a single topic level
+        for pattern in acl.rules.iter().flat_map(|rule| &rule.topics) {
+            let topic = pattern
+                .replace(CLIENT_ID_PLACEHOLDER, "_")
+                .replace(IDENTITY_PLACEHOLDER, "_");
+            if topic.parse::<TopicFilter>().is_err() {
+                return Err(AclError::InvalidTopic(pattern.clone()));
+            }
+        }
+
+        Ok(acl)
+    }
+
+    fn is_allowed(&self, activity: &Activity) -> bool {
+        let mut allowed = false;
+        for rule in self.rules.iter().filter(|rule| rule.applies(activity)) {
+            match rule.effect {
+                Effect::Deny => return false,
+                Effect::Allow => allowed = true,
+            }
+        }
+        allowed
+    }
+}
+
+impl Rule {
+    fn applies(&self, activity: &Activity) -> bool {
+        let identity = match activity.auth_id() {
+            AuthId::Anonymous => None,
+            AuthId::Identity(identity) => Some(identity.as_str()),
+        };
+        let client_id = activity.client_id();
+
+        let operation_matches =
+            self.operations.is_empty() || self.operations.contains(&kind(activity.operation()));
+
+        matches_client(
+            &self.identities,
+            &self.clients,
+            activity.auth_id(),
+            client_id,
+        ) && operation_matches
+            && self.topic_matches(activity.operation(), client_id.as_str(), identity)
+    }
+
+    fn topic_matches(
+        &self,
+        operation: &Operation,
+        client_id: &str,
+        identity: Option<&str>,
+    ) -> bool {
+        if self.topics.is_empty() {
+            return true;
+        }
+
+        let mut filters = self
+            .topics
+            .iter()
+            .filter_map(|pattern| substitute(pattern, client_id, identity));
+
+        match operation {
+            Operation::Connect(_) => false,
+            Operation::Publish(publish) => {
+                let topic_name = publish.publication().topic_name();
+                filters.any(|filter| filter.matches(topic_name))
+            }
+            Operation::Receive(receive) => {
+                let topic_name = receive.publication().topic_name();
+                filters.any(|filter| filter.matches(topic_name))
+            }
+            Operation::Subscribe(subscribe) => match subscribe.topic_filter().parse() {
+                Ok(requested) => filters.any(|filter| filter.covers(&requested)),
+                Err(_) => false,
+            },
+        }
+    }
+}
+
+/// Empty lists of identities or clients match any client.
+fn matches_client(
+    identities: &[String],
+    clients: &[String],
+    auth_id: &AuthId,
+    client_id: &ClientId,
+) -> bool {
+    let identity_name = match auth_id {
+        AuthId::Anonymous => ANONYMOUS_IDENTITY,
+        AuthId::Identity(identity) => identity.as_str(),
+    };
+    let identity_matches =
+        identities.is_empty() || identities.iter().any(|expected| expected == identity_name);
+    let client_matches = clients.is_empty()
+        || clients
+            .iter()
+            .any(|expected| expected == client_id.as_str());
+    identity_matches && client_matches
+}
+
+fn kind(operation: &Operation) -> OperationKind {
+    match operation {
+        Operation::Connect(_) => OperationKind::Connect,
+        Operation::Publish(_) => OperationKind::Publish,
+        Operation::Subscribe(_) => OperationKind::Subscribe,
+        Operation::Receive(_) => OperationKind::Receive,
+    }
+}
+
+/// Replaces the placeholders of a topic pattern.
+///
+/// Returns `None` if the pattern refers to a value the client does not have
+/// or the value would change the topic levels the pattern matches.
+fn substitute(pattern: &str, client_id: &str, identity: Option<&str>) -> Option<TopicFilter> {
+    fn is_level(value: &str) -> bool {
+        !value.is_empty() && !value.contains(|c| c == '/' || c == '+' || c == '#')
+    }
+
+    let mut topic = pattern.to_string();
+    if topic.contains(CLIENT_ID_PLACEHOLDER) {
+        if !is_level(client_id) {
+            return None;
+        }
+        topic = topic.replace(CLIENT_ID_PLACEHOLDER, client_id);
+    }
+    if topic.contains(IDENTITY_PLACEHOLDER) {
+        match identity {
+            Some(identity) if is_level(identity) => {
+                topic = topic.replace(IDENTITY_PLACEHOLDER, identity);
+            }
+            _ => return None,
+        }
+    }
+
+    topic.parse().ok()
+}
+
+/// Represents errors occurred while loading an access control list.
+#[derive(Debug, thiserror::Error)]
+pub enum AclError {
+    #[error("An error occurred reading access control list file {0}.")]
+    ReadFile(PathBuf, #[source] std::io::Error),
+
+    #[error("An error occurred parsing access control list file {0}.")]
+    ParseJson(PathBuf, #[source] serde_json::Error),
+
+    #[error("An error occurred parsing access control list file {0}.")]
+    ParseYaml(PathBuf, #[source] serde_yaml::Error),
+
+    #[error("Access control list contains invalid topic pattern: {0}")]
+    InvalidTopic(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use mqtt3::{proto, PROTOCOL_LEVEL, PROTOCOL_NAME};
+
+    use super::{Acl, AclAuthorizer};
+    use crate::auth::{Activity, AuthId, Authorizer, Operation};
+    use crate::ClientId;
+
+    fn acl(rules: serde_json::Value) -> Acl {
+        serde_json::from_value(serde_json::json!({ "rules": rules })).unwrap()
+    }
+
+    fn connect(auth_id: AuthId, client_id: &str) -> Activity {
+        let connect = proto::Connect {
+            username: None,
+            password: None,
+            will: None,
+            client_id: proto::ClientId::IdWithCleanSession(client_id.to_string()),
+            keep_alive: Duration::from_secs(1),
+            protocol_name: PROTOCOL_NAME.to_string(),
+            protocol_level: PROTOCOL_LEVEL,
+            properties: proto::Properties::default(),
+        };
+        Activity::new(auth_id, client_id, Operation::new_connect(connect))
+    }
+
+    fn publish(auth_id: AuthId, client_id: &str, topic_name: &str) -> Activity {
+        let publish = proto::Publish {
+            packet_identifier_dup_qos: proto::PacketIdentifierDupQoS::AtMostOnce,
+            retain: false,
+            topic_name: topic_name.to_string(),
+            payload: Bytes::new(),
+            properties: proto::Properties::default(),
+        };
+        Activity::new(auth_id, client_id, Operation::new_publish(publish))
+    }
+
+    fn subscribe(auth_id: AuthId, client_id: &str, topic_filter: &str) -> Activity {
+        let subscribe_to = proto::SubscribeTo {
+            topic_filter: topic_filter.to_string(),
+            qos: proto::QoS::AtLeastOnce,
+        };
+        Activity::new(auth_id, client_id, Operation::new_subscribe(subscribe_to))
+    }
+
+    #[test]
+    fn it_denies_when_no_rule_applies() {
+        let acl = acl(serde_json::json!([]));
+
+        assert!(!acl.is_allowed(&connect(AuthId::Anonymous, "client")));
+    }
+
+    #[test]
+    fn it_matches_identities_and_operations() {
+        let acl = acl(serde_json::json!([
+            { "effect": "allow", "identities": ["device-1"], "operations": ["connect"] },
+            { "effect": "allow", "identities": ["$anonymous"], "operations": ["publish"] }
+        ]));
+
+        assert!(acl.is_allowed(&connect("device-1".into(), "client")));
+        assert!(!acl.is_allowed(&connect("device-2".into(), "client")));
+        assert!(!acl.is_allowed(&connect(AuthId::Anonymous, "client")));
+        assert!(acl.is_allowed(&publish(AuthId::Anonymous, "client", "topic")));
+    }
+
+    #[test]
+    fn it_substitutes_client_id_and_identity() {
+        let acl = acl(serde_json::json!([
+            { "effect": "allow", "operations": ["publish"], "topics": ["devices/%c/#"] },
+            { "effect": "allow", "operations": ["subscribe"], "topics": ["users/%u/+"] }
+        ]));
+
+        assert!(acl.is_allowed(&publish(
+            AuthId::Anonymous,
+            "device-1",
+            "devices/device-1/t"
+        )));
+        assert!(!acl.is_allowed(&publish(
+            AuthId::Anonymous,
+            "device-1",
+            "devices/device-2/t"
+        )));
+        assert!(!acl.is_allowed(&publish(AuthId::Anonymous, "#", "devices/device-2/t")));
+
+        assert!(acl.is_allowed(&subscribe("alice".into(), "client", "users/alice/inbox")));
+        assert!(acl.is_allowed(&subscribe("alice".into(), "client", "users/alice/+")));
+        assert!(!acl.is_allowed(&subscribe("alice".into(), "client", "users/alice/#")));
+        assert!(!acl.is_allowed(&subscribe(AuthId::Anonymous, "client", "users/alice/+")));
+    }
+
+    #[test]
+    fn it_prefers_deny_over_allow() {
+        let acl = acl(serde_json::json!([
+            { "effect": "allow" },
+            { "effect": "deny", "clients": ["blocked"] },
+            { "effect": "deny", "operations": ["subscribe"], "topics": ["$SYS/#"] }
+        ]));
+
+        assert!(acl.is_allowed(&connect(AuthId::Anonymous, "client")));
+        assert!(!acl.is_allowed(&connect(AuthId::Anonymous, "blocked")));
+        assert!(acl.is_allowed(&subscribe(AuthId::Anonymous, "client", "topic")));
+        assert!(!acl.is_allowed(&subscribe(AuthId::Anonymous, "client", "$SYS/broker/#")));
+    }
+
+    #[test]
+    fn it_overrides_inflight_window_of_matching_clients() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("acl.json");
+        fs::write(
+            &path,
+            r#"{
+                "rules": [],
+                "inflight_messages": [
+                    { "identities": ["leaf"], "clients": ["sensor"], "max_count": 1 },
+                    { "identities": ["leaf"], "max_count": 2 }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let authorizer = AclAuthorizer::from_file(&path).unwrap();
+        let max_inflight = |auth_id: AuthId, client_id: &str| {
+            authorizer.max_inflight_messages(&auth_id, &ClientId::from(client_id))
+        };
+
+        assert_eq!(max_inflight("leaf".into(), "sensor"), Some(1));
+        assert_eq!(max_inflight("leaf".into(), "client"), Some(2));
+        assert_eq!(max_inflight("bridge".into(), "sensor"), None);
+        assert_eq!(max_inflight(AuthId::Anonymous, "client"), None);
+    }
+
+    #[test]
+    fn it_reloads_rules_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("acl.yaml");
+        fs::write(
+            &path,
+            "rules:\n  - effect: allow\n    operations: [connect]\n",
+        )
+        .unwrap();
+
+        let mut authorizer = AclAuthorizer::from_file(&path).unwrap();
+        let allowed = authorizer.authorize(connect(AuthId::Anonymous, "client"));
+        assert!(allowed.unwrap());
+
+        fs::write(&path, "rules: []\n").unwrap();
+        authorizer.reload().unwrap();
+        let allowed = authorizer.authorize(connect(AuthId::Anonymous, "client"));
+        assert!(!allowed.unwrap());
+
+        fs::write(&path, "rules: [").unwrap();
+        assert!(authorizer.reload().is_err());
+        let allowed = authorizer.authorize(connect(AuthId::Anonymous, "client"));
+        assert!(!allowed.unwrap());
+    }
+
+    #[test]
+    fn it_refuses_invalid_topic_pattern() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("acl.json");
+        fs::write(
+            &path,
+            r#"{ "rules": [{ "effect": "allow", "topics": ["a/#/b"] }] }"#,
+        )
+        .unwrap();
+
+        assert!(AclAuthorizer::from_file(&path).is_err());
+    }
+}
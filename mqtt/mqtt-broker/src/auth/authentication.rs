@@ -1,15 +1,24 @@
 use crate::auth::AuthId;
+use crate::BrokerConfig;
 
 /// Describes a MQTT client credentials.
+///
+/// A client is authenticated with exactly one kind of credentials. The username
+/// and password from the CONNECT packet take precedence. Clients which provide
+/// no username are authenticated with the credentials of the transport, either
+/// a verified client certificate or the peer credentials of a Unix domain socket.
 pub enum Credentials {
     /// Basic username and password credentials.
     Basic(Option<String>, Option<String>),
 
-    /// Client certificate credentials.
-    ClientCertificate(Certificate),
+    /// Client certificate chain verified by the TLS transport.
+    ClientCertificate(CertificateChain),
+
+    /// Credentials of a local process connected over a Unix domain socket.
+    PeerCredentials(PeerCredentials),
 }
 
-/// Represents a client certificate.
+/// Represents a DER-encoded client certificate.
 #[derive(Clone, Debug)]
 pub struct Certificate(Vec<u8>);
 
@@ -19,6 +28,72 @@ impl From<Vec<u8>> for Certificate {
     }
 }
 
+impl AsRef<[u8]> for Certificate {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Represents a client certificate with the intermediate certificates
+/// the client presented during the TLS handshake.
+#[derive(Clone, Debug)]
+pub struct CertificateChain {
+    certificate: Certificate,
+    intermediates: Vec<Certificate>,
+}
+
+impl CertificateChain {
+    pub fn new(certificate: Certificate, intermediates: Vec<Certificate>) -> Self {
+        Self {
+            certificate,
+            intermediates,
+        }
+    }
+
+    /// The client certificate.
+    pub fn certificate(&self) -> &Certificate {
+        &self.certificate
+    }
+
+    /// Certificates between the client certificate and a trusted authority.
+    pub fn intermediates(&self) -> &[Certificate] {
+        &self.intermediates
+    }
+}
+
+impl From<Certificate> for CertificateChain {
+    fn from(certificate: Certificate) -> Self {
+        Self::new(certificate, Vec::new())
+    }
+}
+
+/// Represents credentials of a peer process reported by the operating system.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeerCredentials {
+    uid: u32,
+    gid: u32,
+    pid: Option<u32>,
+}
+
+impl PeerCredentials {
+    pub fn new(uid: u32, gid: u32, pid: Option<u32>) -> Self {
+        Self { uid, gid, pid }
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    /// Process id of the peer if the platform reports it.
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+}
+
 /// A trait to authenticate a MQTT client with given credentials.
 pub trait Authenticator {
     /// Authentication error.
@@ -31,6 +106,14 @@ pub trait Authenticator {
     /// * `Ok(None)` - authenticator is not able to identify a client with given credentials.
     /// * `Err(e)` - an error occurred when authenticating a client.
     fn authenticate(&self, credentials: Credentials) -> Result<Option<AuthId>, Self::Error>;
+
+    /// Applies an updated broker configuration, e.g. reloads a password file.
+    ///
+    /// Client connections authenticate concurrently with the broker, so the
+    /// authenticator applies the update through a shared reference.
+    fn update_config(&self, _config: &BrokerConfig) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
 
 impl<F> Authenticator for F
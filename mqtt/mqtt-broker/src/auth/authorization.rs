@@ -2,7 +2,7 @@
 
 use mqtt3::proto;
 
-use crate::{AuthId, ClientId};
+use crate::{AuthId, BrokerConfig, ClientId};
 
 /// A trait to check a MQTT client permissions to perform some actions.
 pub trait Authorizer {
@@ -11,6 +11,24 @@ pub trait Authorizer {
 
     /// Authorizes a MQTT client to perform some action.
     fn authorize(&self, activity: Activity) -> Result<bool, Self::Error>;
+
+    /// Returns the maximum number of unacknowledged publications the broker
+    /// sends to a client before it starts queueing them.
+    ///
+    /// `None` means that the broker-wide `inflight_messages` setting is used.
+    fn max_inflight_messages(&self, _auth_id: &AuthId, _client_id: &ClientId) -> Option<usize> {
+        None
+    }
+
+    /// Reloads the authorization policy, if it is loaded from an external source.
+    fn reload(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Applies an updated broker configuration. Reloads the authorization policy by default.
+    fn update_config(&mut self, _config: &BrokerConfig) -> Result<(), Self::Error> {
+        self.reload()
+    }
 }
 
 impl<F> Authorizer for F
@@ -61,6 +79,14 @@ impl Activity {
         }
     }
 
+    pub fn auth_id(&self) -> &AuthId {
+        &self.auth_id
+    }
+
+    pub fn client_id(&self) -> &ClientId {
+        &self.client_id
+    }
+
     pub fn operation(&self) -> &Operation {
         &self.operation
     }
@@ -119,6 +145,20 @@ pub struct Publication {
     retain: bool,
 }
 
+impl Publication {
+    pub fn topic_name(&self) -> &str {
+        &self.topic_name
+    }
+
+    pub fn qos(&self) -> proto::QoS {
+        self.qos
+    }
+
+    pub fn retain(&self) -> bool {
+        self.retain
+    }
+}
+
 impl From<proto::Publication> for Publication {
     fn from(publication: proto::Publication) -> Self {
         Self {
@@ -134,6 +174,12 @@ pub struct Publish {
     publication: Publication,
 }
 
+impl Publish {
+    pub fn publication(&self) -> &Publication {
+        &self.publication
+    }
+}
+
 impl From<proto::Publish> for Publish {
     fn from(publish: proto::Publish) -> Self {
         Self {
@@ -176,6 +222,12 @@ pub struct Receive {
     publication: Publication,
 }
 
+impl Receive {
+    pub fn publication(&self) -> &Publication {
+        &self.publication
+    }
+}
+
 impl From<proto::Publication> for Receive {
     fn from(publication: proto::Publication) -> Self {
         Self {
@@ -203,6 +255,7 @@ mod tests {
             keep_alive: Duration::from_secs(1),
             protocol_name: PROTOCOL_NAME.to_string(),
             protocol_level: PROTOCOL_LEVEL,
+            properties: proto::Properties::default(),
         }
     }
 
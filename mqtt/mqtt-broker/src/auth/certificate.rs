@@ -0,0 +1,190 @@
+use openssl::{error::ErrorStack, nid::Nid, x509::X509};
+use serde::Deserialize;
+
+use crate::auth::{AuthId, Authenticator, Credentials};
+
+/// Authenticates MQTT clients with a client certificate verified by the TLS transport.
+///
+/// The client identity is taken from the certificate subject common name or
+/// from the first DNS or email entry of the subject alternative name.
+/// Clients which connected without a certificate are not identified.
+pub struct CertificateAuthenticator {
+    identity: CertificateIdentity,
+}
+
+/// Certificate field used as a client identity.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CertificateIdentity {
+    CommonName,
+    SubjectAltName,
+}
+
+impl CertificateAuthenticator {
+    pub fn new(identity: CertificateIdentity) -> Self {
+        Self { identity }
+    }
+}
+
+impl Default for CertificateAuthenticator {
+    fn default() -> Self {
+        Self::new(CertificateIdentity::CommonName)
+    }
+}
+
+impl Authenticator for CertificateAuthenticator {
+    type Error = CertificateAuthenticateError;
+
+    fn authenticate(&self, credentials: Credentials) -> Result<Option<AuthId>, Self::Error> {
+        let chain = match credentials {
+            Credentials::ClientCertificate(chain) => chain,
+            _ => return Ok(None),
+        };
+
+        let certificate = X509::from_der(chain.certificate().as_ref())
+            .map_err(CertificateAuthenticateError::ParseCertificate)?;
+
+        let identity = match self.identity {
+            CertificateIdentity::CommonName => common_name(&certificate),
+            CertificateIdentity::SubjectAltName => subject_alt_name(&certificate),
+        };
+
+        Ok(identity.map(AuthId::from))
+    }
+}
+
+fn common_name(certificate: &X509) -> Option<String> {
+    certificate
+        .subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .next()
+        .and_then(|entry| entry.data().as_utf8().ok())
+        .map(|name| name.to_string())
+}
+
+fn subject_alt_name(certificate: &X509) -> Option<String> {
+    certificate.subject_alt_names().and_then(|names| {
+        names
+            .iter()
+            .find_map(|name| name.dnsname().or_else(|| name.email()))
+            .map(ToString::to_string)
+    })
+}
+
+/// Represents errors occurred while authenticating a client certificate.
+#[derive(Debug, thiserror::Error)]
+pub enum CertificateAuthenticateError {
+    #[error("Unable to parse client certificate.")]
+    ParseCertificate(#[source] ErrorStack),
+}
+
+#[cfg(test)]
+mod tests {
+    use matches::assert_matches;
+    use openssl::{
+        asn1::Asn1Time,
+        hash::MessageDigest,
+        pkey::PKey,
+        rsa::Rsa,
+        x509::{extension::SubjectAlternativeName, X509Builder, X509NameBuilder},
+    };
+
+    use super::{CertificateAuthenticator, CertificateIdentity};
+    use crate::auth::{AuthId, Authenticator, Certificate, CertificateChain, Credentials};
+
+    fn certificate(common_name: &str, alt_name: Option<&str>) -> Certificate {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", common_name).unwrap();
+        let name = name.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+
+        if let Some(alt_name) = alt_name {
+            let extension = SubjectAlternativeName::new()
+                .dns(alt_name)
+                .build(&builder.x509v3_context(None, None))
+                .unwrap();
+            builder.append_extension(extension).unwrap();
+        }
+
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        builder.build().to_der().unwrap().into()
+    }
+
+    #[test]
+    fn it_identifies_client_by_common_name() {
+        let authenticator = CertificateAuthenticator::default();
+        let credentials = Credentials::ClientCertificate(certificate("device-1", None).into());
+
+        let auth_id = authenticator.authenticate(credentials);
+
+        assert_matches!(auth_id, Ok(Some(AuthId::Identity(identity))) if identity == "device-1");
+    }
+
+    #[test]
+    fn it_identifies_client_by_certificate_of_chain() {
+        let authenticator = CertificateAuthenticator::default();
+        let chain = CertificateChain::new(
+            certificate("device-1", None),
+            vec![certificate("intermediate", None)],
+        );
+        let credentials = Credentials::ClientCertificate(chain);
+
+        let auth_id = authenticator.authenticate(credentials);
+
+        assert_matches!(auth_id, Ok(Some(AuthId::Identity(identity))) if identity == "device-1");
+    }
+
+    #[test]
+    fn it_identifies_client_by_subject_alt_name() {
+        let authenticator = CertificateAuthenticator::new(CertificateIdentity::SubjectAltName);
+        let credentials =
+            Credentials::ClientCertificate(certificate("device-1", Some("device-1.local")).into());
+
+        let auth_id = authenticator.authenticate(credentials);
+
+        assert_matches!(auth_id, Ok(Some(AuthId::Identity(identity))) if identity == "device-1.local");
+    }
+
+    #[test]
+    fn it_does_not_identify_client_without_subject_alt_name() {
+        let authenticator = CertificateAuthenticator::new(CertificateIdentity::SubjectAltName);
+        let credentials = Credentials::ClientCertificate(certificate("device-1", None).into());
+
+        let auth_id = authenticator.authenticate(credentials);
+
+        assert_matches!(auth_id, Ok(None));
+    }
+
+    #[test]
+    fn it_does_not_identify_client_without_certificate() {
+        let authenticator = CertificateAuthenticator::default();
+        let credentials = Credentials::Basic(Some("username".into()), Some("password".into()));
+
+        let auth_id = authenticator.authenticate(credentials);
+
+        assert_matches!(auth_id, Ok(None));
+    }
+
+    #[test]
+    fn it_fails_on_malformed_certificate() {
+        let authenticator = CertificateAuthenticator::default();
+        let credentials = Credentials::ClientCertificate(Certificate::from(vec![1, 2, 3]).into());
+
+        let auth_id = authenticator.authenticate(credentials);
+
+        assert_matches!(auth_id, Err(_));
+    }
+}
@@ -1,13 +1,26 @@
+mod acl;
 mod authentication;
 mod authorization;
+mod certificate;
+mod password;
+mod peer;
 
+pub use acl::{AclAuthorizer, AclError};
 pub use authentication::{
-    AuthenticateError, Authenticator, Certificate, Credentials, DefaultAuthenticator,
+    AuthenticateError, Authenticator, Certificate, CertificateChain, Credentials,
+    DefaultAuthenticator, PeerCredentials,
 };
 pub use authorization::{Activity, AuthorizeError, Authorizer, DefaultAuthorizer, Operation};
+pub use certificate::{
+    CertificateAuthenticateError, CertificateAuthenticator, CertificateIdentity,
+};
+pub use password::{PasswordAuthenticator, PasswordFile, PasswordFileError};
+pub use peer::PeerCredentialsAuthenticator;
+
+use serde::{Deserialize, Serialize};
 
 /// Authenticated MQTT client identity.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum AuthId {
     /// Identity for anonymous client.
     Anonymous,
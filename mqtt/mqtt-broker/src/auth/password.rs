@@ -0,0 +1,450 @@
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use openssl::{base64, hash::MessageDigest, memcmp, pkcs5, rand};
+
+use crate::auth::{AuthId, Authenticator, Credentials};
+
+const SCHEME: &str = "pbkdf2-sha512";
+const ITERATIONS: usize = 100_000;
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 64;
+
+/// A file of usernames and salted password hashes.
+///
+/// Every line of the file holds one user as `username:hash`, where the hash
+/// is stored as `$pbkdf2-sha512$<iterations>$<base64 salt>$<base64 key>`.
+/// Empty lines and lines starting with `#` are ignored, and written back
+/// unchanged when the file is saved.
+#[derive(Debug)]
+pub struct PasswordFile {
+    path: PathBuf,
+    lines: Vec<Line>,
+}
+
+#[derive(Debug)]
+enum Line {
+    Comment(String),
+    User(String, PasswordHash),
+}
+
+impl PasswordFile {
+    /// Loads users from a password file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, PasswordFileError> {
+        let path = path.as_ref().to_path_buf();
+        let contents =
+            fs::read_to_string(&path).map_err(|e| PasswordFileError::ReadFile(path.clone(), e))?;
+
+        let mut lines = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                lines.push(Line::Comment(line.to_string()));
+                continue;
+            }
+
+            let invalid = || PasswordFileError::InvalidEntry(path.clone(), number + 1);
+            let mut parts = trimmed.splitn(2, ':');
+            let username = parts.next().filter(|u| !u.is_empty()).ok_or_else(invalid)?;
+            let hash = parts.next().ok_or_else(invalid)?;
+            let hash = PasswordHash::parse(hash).ok_or_else(invalid)?;
+
+            lines.push(Line::User(username.to_string(), hash));
+        }
+
+        Ok(Self { path, lines })
+    }
+
+    /// Loads users from a password file, or starts with no users if the file does not exist yet.
+    pub fn load_or_empty(path: impl AsRef<Path>) -> Result<Self, PasswordFileError> {
+        match Self::load(path.as_ref()) {
+            Err(PasswordFileError::ReadFile(path, e)) if e.kind() == io::ErrorKind::NotFound => {
+                Ok(Self {
+                    path,
+                    lines: Vec::new(),
+                })
+            }
+            result => result,
+        }
+    }
+
+    /// Adds a user, or replaces the password of an existing one.
+    pub fn set(&mut self, username: &str, password: &str) -> Result<(), PasswordFileError> {
+        if username.is_empty() || username.contains(':') {
+            return Err(PasswordFileError::InvalidUsername(username.to_string()));
+        }
+
+        let hash = PasswordHash::new(password)?;
+        let existing = self.lines.iter_mut().find_map(|line| match line {
+            Line::User(name, hash) if name == username => Some(hash),
+            _ => None,
+        });
+        match existing {
+            Some(existing) => *existing = hash,
+            None => self.lines.push(Line::User(username.to_string(), hash)),
+        }
+        Ok(())
+    }
+
+    /// Removes a user. Returns `false` if there is no such user.
+    pub fn remove(&mut self, username: &str) -> bool {
+        let len = self.lines.len();
+        self.lines.retain(|line| match line {
+            Line::User(name, _) => name != username,
+            Line::Comment(_) => true,
+        });
+        self.lines.len() != len
+    }
+
+    /// Checks that a user exists and the password matches the stored hash.
+    ///
+    /// A password of an unknown user is hashed as well, so that the time it
+    /// takes does not tell which usernames exist.
+    pub fn verify(&self, username: &str, password: &str) -> Result<bool, PasswordFileError> {
+        let hash = self.lines.iter().find_map(|line| match line {
+            Line::User(name, hash) if name == username => Some(hash),
+            _ => None,
+        });
+        match hash {
+            Some(hash) => hash.verify(password),
+            None => PasswordHash::unknown_user().verify(password).map(|_| false),
+        }
+    }
+
+    /// Writes users back to the file.
+    ///
+    /// The file is replaced atomically with a new one readable only by the
+    /// owner, so that a concurrent reader never sees a partially written file.
+    pub fn save(&self) -> Result<(), PasswordFileError> {
+        let contents: String = self
+            .lines
+            .iter()
+            .map(|line| match line {
+                Line::Comment(comment) => format!("{}\n", comment),
+                Line::User(username, hash) => format!("{}:{}\n", username, hash.encode()),
+            })
+            .collect();
+
+        let error = |e| PasswordFileError::WriteFile(self.path.clone(), e);
+
+        let file_name = self
+            .path
+            .file_name()
+            .ok_or_else(|| error(io::ErrorKind::InvalidInput.into()))?;
+        let mut temp_name = OsString::from(".");
+        temp_name.push(file_name);
+        temp_name.push(".tmp");
+        let temp_path = self.path.with_file_name(temp_name);
+
+        write_private(&temp_path, contents.as_bytes()).map_err(error)?;
+        fs::rename(&temp_path, &self.path).map_err(error)?;
+
+        // make the rename itself durable
+        #[cfg(unix)]
+        {
+            let dir = match self.path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            fs::File::open(dir)
+                .and_then(|dir| dir.sync_all())
+                .map_err(error)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options.open(path)?;
+
+    // the mode only applies to a newly created file, not to a stale one
+    #[cfg(unix)]
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+#[derive(Debug)]
+struct PasswordHash {
+    iterations: usize,
+    salt: Vec<u8>,
+    key: Vec<u8>,
+}
+
+impl PasswordHash {
+    fn new(password: &str) -> Result<Self, PasswordFileError> {
+        let mut salt = vec![0; SALT_LEN];
+        rand::rand_bytes(&mut salt).map_err(PasswordFileError::Hash)?;
+
+        let key = derive_key(password, &salt, ITERATIONS, KEY_LEN)?;
+        Ok(Self {
+            iterations: ITERATIONS,
+            salt,
+            key,
+        })
+    }
+
+    /// A hash no password matches, verified in place of the hash of an unknown user.
+    fn unknown_user() -> Self {
+        Self {
+            iterations: ITERATIONS,
+            salt: vec![0; SALT_LEN],
+            key: vec![0; KEY_LEN],
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.split('$');
+        match (parts.next(), parts.next()) {
+            (Some(""), Some(SCHEME)) => {}
+            _ => return None,
+        }
+
+        let iterations = parts.next()?.parse().ok().filter(|i| *i > 0)?;
+        let salt = base64::decode_block(parts.next()?).ok()?;
+        let key = base64::decode_block(parts.next()?).ok()?;
+        if parts.next().is_some() || key.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            iterations,
+            salt,
+            key,
+        })
+    }
+
+    fn encode(&self) -> String {
+        format!(
+            "${}${}${}${}",
+            SCHEME,
+            self.iterations,
+            base64::encode_block(&self.salt),
+            base64::encode_block(&self.key)
+        )
+    }
+
+    fn verify(&self, password: &str) -> Result<bool, PasswordFileError> {
+        let key = derive_key(password, &self.salt, self.iterations, self.key.len())?;
+        Ok(memcmp::eq(&key, &self.key))
+    }
+}
+
+fn derive_key(
+    password: &str,
+    salt: &[u8],
+    iterations: usize,
+    len: usize,
+) -> Result<Vec<u8>, PasswordFileError> {
+    let mut key = vec![0; len];
+    pkcs5::pbkdf2_hmac(
+        password.as_bytes(),
+        salt,
+        iterations,
+        MessageDigest::sha512(),
+        &mut key,
+    )
+    .map_err(PasswordFileError::Hash)?;
+    Ok(key)
+}
+
+/// Authenticates clients with the username and password stored in a password file.
+///
+/// Clients which provide no username are authenticated as anonymous only
+/// if anonymous access is allowed.
+pub struct PasswordAuthenticator {
+    file: PasswordFile,
+    allow_anonymous: bool,
+}
+
+impl PasswordAuthenticator {
+    pub fn new(file: PasswordFile, allow_anonymous: bool) -> Self {
+        Self {
+            file,
+            allow_anonymous,
+        }
+    }
+}
+
+impl Authenticator for PasswordAuthenticator {
+    type Error = PasswordFileError;
+
+    fn authenticate(&self, credentials: Credentials) -> Result<Option<AuthId>, Self::Error> {
+        match credentials {
+            Credentials::Basic(Some(username), Some(password)) => {
+                if self.file.verify(&username, &password)? {
+                    Ok(Some(AuthId::from_identity(username)))
+                } else {
+                    Ok(None)
+                }
+            }
+            Credentials::Basic(None, _) if self.allow_anonymous => Ok(Some(AuthId::Anonymous)),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PasswordFileError {
+    #[error("An error occurred reading password file {0}.")]
+    ReadFile(PathBuf, #[source] io::Error),
+
+    #[error("An error occurred writing password file {0}.")]
+    WriteFile(PathBuf, #[source] io::Error),
+
+    #[error("Password file {0} contains invalid entry at line {1}.")]
+    InvalidEntry(PathBuf, usize),
+
+    #[error("Invalid username: {0:?}")]
+    InvalidUsername(String),
+
+    #[error("An error occurred hashing password.")]
+    Hash(#[source] openssl::error::ErrorStack),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use matches::assert_matches;
+
+    use crate::auth::{
+        AuthId, Authenticator, Credentials, PasswordAuthenticator, PasswordFile, PasswordFileError,
+    };
+
+    fn credentials(username: Option<&str>, password: Option<&str>) -> Credentials {
+        Credentials::Basic(username.map(Into::into), password.map(Into::into))
+    }
+
+    #[test]
+    fn it_stores_and_verifies_passwords() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("passwd");
+
+        let mut file = PasswordFile::load_or_empty(&path).unwrap();
+        file.set("device-1", "secret").unwrap();
+        file.set("device-2", "other").unwrap();
+        file.save().unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("secret"));
+        assert!(contents.starts_with("device-1:$pbkdf2-sha512$"));
+
+        let file = PasswordFile::load(&path).unwrap();
+        assert_matches!(file.verify("device-1", "secret"), Ok(true));
+        assert_matches!(file.verify("device-1", "other"), Ok(false));
+        assert_matches!(file.verify("device-3", "secret"), Ok(false));
+    }
+
+    #[test]
+    fn it_removes_users() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = PasswordFile::load_or_empty(dir.path().join("passwd")).unwrap();
+        file.set("device-1", "secret").unwrap();
+
+        assert!(file.remove("device-1"));
+        assert!(!file.remove("device-1"));
+        assert_matches!(file.verify("device-1", "secret"), Ok(false));
+    }
+
+    #[test]
+    fn it_keeps_comments_and_restricts_permissions_on_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("passwd");
+        fs::write(&path, "# users\n\n").unwrap();
+
+        let mut file = PasswordFile::load(&path).unwrap();
+        file.set("device-1", "secret").unwrap();
+        file.save().unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("# users\n\ndevice-1:$pbkdf2-sha512$"));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn it_refuses_invalid_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("passwd");
+        fs::write(&path, "# users\n\ndevice-1:plaintext\n").unwrap();
+
+        assert_matches!(
+            PasswordFile::load(&path),
+            Err(PasswordFileError::InvalidEntry(_, 3))
+        );
+    }
+
+    #[test]
+    fn it_refuses_invalid_usernames() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = PasswordFile::load_or_empty(dir.path().join("passwd")).unwrap();
+
+        assert_matches!(
+            file.set("device:1", "secret"),
+            Err(PasswordFileError::InvalidUsername(_))
+        );
+    }
+
+    #[test]
+    fn it_authenticates_users() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = PasswordFile::load_or_empty(dir.path().join("passwd")).unwrap();
+        file.set("device-1", "secret").unwrap();
+        let authenticator = PasswordAuthenticator::new(file, false);
+
+        assert_matches!(
+            authenticator.authenticate(credentials(Some("device-1"), Some("secret"))),
+            Ok(Some(AuthId::Identity(identity))) if identity == "device-1"
+        );
+        assert_matches!(
+            authenticator.authenticate(credentials(Some("device-1"), Some("wrong"))),
+            Ok(None)
+        );
+        assert_matches!(
+            authenticator.authenticate(credentials(Some("device-1"), None)),
+            Ok(None)
+        );
+        assert_matches!(
+            authenticator.authenticate(credentials(None, None)),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn it_authenticates_anonymous_clients_when_allowed() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = PasswordFile::load_or_empty(dir.path().join("passwd")).unwrap();
+        let authenticator = PasswordAuthenticator::new(file, true);
+
+        assert_matches!(
+            authenticator.authenticate(credentials(None, None)),
+            Ok(Some(AuthId::Anonymous))
+        );
+        assert_matches!(
+            authenticator.authenticate(credentials(Some("device-1"), Some("secret"))),
+            Ok(None)
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+
+use crate::auth::{AuthId, Authenticator, Credentials, Identity};
+
+/// Authenticates local processes connected over a Unix domain socket by
+/// the user id the operating system reports for the peer.
+///
+/// Processes running as a user id without a configured identity, as well as
+/// clients which connected over any other transport, are not identified.
+#[derive(Default)]
+pub struct PeerCredentialsAuthenticator {
+    identities: HashMap<u32, Identity>,
+}
+
+impl PeerCredentialsAuthenticator {
+    pub fn new<I>(identities: I) -> Self
+    where
+        I: IntoIterator<Item = (u32, Identity)>,
+    {
+        Self {
+            identities: identities.into_iter().collect(),
+        }
+    }
+}
+
+impl Authenticator for PeerCredentialsAuthenticator {
+    type Error = Infallible;
+
+    fn authenticate(&self, credentials: Credentials) -> Result<Option<AuthId>, Self::Error> {
+        let auth_id = match credentials {
+            Credentials::PeerCredentials(peer) => self
+                .identities
+                .get(&peer.uid())
+                .map(|identity| AuthId::from_identity(identity.clone())),
+            _ => None,
+        };
+        Ok(auth_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use matches::assert_matches;
+
+    use super::PeerCredentialsAuthenticator;
+    use crate::auth::{AuthId, Authenticator, Credentials, PeerCredentials};
+
+    fn authenticator() -> PeerCredentialsAuthenticator {
+        PeerCredentialsAuthenticator::new(vec![(1000, "local-module".to_string())])
+    }
+
+    #[test]
+    fn it_identifies_known_peer_user() {
+        let credentials = Credentials::PeerCredentials(PeerCredentials::new(1000, 1000, None));
+
+        let auth_id = authenticator().authenticate(credentials);
+
+        assert_eq!(auth_id, Ok(Some(AuthId::from_identity("local-module"))));
+    }
+
+    #[test]
+    fn it_does_not_identify_unknown_peer_user() {
+        let credentials = Credentials::PeerCredentials(PeerCredentials::new(0, 0, Some(1)));
+
+        let auth_id = authenticator().authenticate(credentials);
+
+        assert_matches!(auth_id, Ok(None));
+    }
+
+    #[test]
+    fn it_does_not_identify_client_without_peer_credentials() {
+        let credentials = Credentials::Basic(Some("local-module".into()), Some("secret".into()));
+
+        let auth_id = authenticator().authenticate(credentials);
+
+        assert_matches!(auth_id, Ok(None));
+    }
+}
@@ -1,24 +1,43 @@
-use std::collections::HashMap;
-use std::convert::TryInto;
+use std::collections::{HashMap, HashSet};
+use std::convert::{TryFrom, TryInto};
+use std::mem;
 use std::panic;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
 use mqtt3::proto;
 use serde::{Deserialize, Serialize};
 use tracing::{debug, error, info, span, warn, Level};
 
+use crate::admin::{AdminError, AdminRequest, RetainedInfo, SessionInfo};
 use crate::auth::{
-    Activity, Authenticator, Authorizer, Credentials, DefaultAuthenticator, DefaultAuthorizer,
+    Activity, Authenticator, Authorizer, DefaultAuthenticator, DefaultAuthorizer, Identity,
     Operation,
 };
-use crate::session::{ConnectedSession, Session, SessionState};
-use crate::state_change::StateChange;
+use crate::configuration::{BrokerConfig, RetainedFullAction, SessionPersistence};
+use crate::connection::TOPIC_ALIAS_MAXIMUM;
+use crate::metrics;
+use crate::persist::Change;
+use crate::rate_limit::{ConnectionRateLimit, RateLimiter};
+use crate::session::{
+    ConnectedSession, Delivery, DropReason, Session, SessionConfig, SessionState,
+};
+use crate::snapshot::StateSnapshotHandle;
+use crate::state_change::{self, StateChange};
+use crate::stats::BrokerStats;
+use crate::trace::{TraceEvent, Tracer};
 use crate::{
-    subscription::Subscription, AuthId, ClientEvent, ClientId, ConnReq, Error, Message, SystemEvent,
+    subscription::{self, Subscription, SubscriptionTrie},
+    AuthId, ClientEvent, ClientId, ConnReq, Error, Message, SystemEvent,
 };
 use tokio::sync::mpsc::{self, Receiver, Sender};
 
 static EXPECTED_PROTOCOL_NAME: &str = mqtt3::PROTOCOL_NAME;
-const EXPECTED_PROTOCOL_LEVEL: u8 = mqtt3::PROTOCOL_LEVEL;
+const EXPECTED_PROTOCOL_LEVELS: [u8; 2] = [mqtt3::PROTOCOL_LEVEL, mqtt3::PROTOCOL_LEVEL_V5];
+
+/// CONNACK reason code for a MQTT 5.0 client asking for an authentication method
+/// the broker does not support.
+const BAD_AUTHENTICATION_METHOD: u8 = 0x8C;
 
 macro_rules! try_send {
     ($session:expr, $msg:expr) => {{
@@ -32,9 +51,20 @@ pub struct Broker<N, Z> {
     sender: Sender<Message>,
     messages: Receiver<Message>,
     sessions: HashMap<ClientId, Session>,
-    retained: HashMap<String, proto::Publication>,
-    authenticator: N,
+    retained: HashMap<String, RetainedPublication>,
+    authenticator: Arc<N>,
     authorizer: Z,
+    config: BrokerConfig,
+    snapshot_handle: Option<StateSnapshotHandle>,
+    unsaved_publications: u32,
+    shared_positions: HashMap<String, usize>,
+    subscribers: SubscriptionTrie,
+    stats: BrokerStats,
+    rate_limiters: HashMap<Identity, RateLimiter>,
+    client_rate_limiters: HashMap<ClientId, RateLimiter>,
+    tracer: Tracer,
+    session_gauges: HashMap<ClientId, (Option<&'static str>, usize)>,
+    journal: Option<Journal>,
 
     #[cfg(feature = "__internal_broker_callbacks")]
     pub on_publish: Option<tokio::sync::mpsc::UnboundedSender<std::time::Duration>>,
@@ -49,6 +79,12 @@ where
         BrokerHandle(self.sender.clone())
     }
 
+    /// The authenticator client connections use to authenticate clients
+    /// before they hand them over to the broker.
+    pub(crate) fn authenticator(&self) -> Arc<N> {
+        self.authenticator.clone()
+    }
+
     pub async fn run(mut self) -> Result<BrokerState, Error> {
         while let Some(message) = self.messages.recv().await {
             match message {
@@ -58,6 +94,7 @@ where
                     if let Err(e) = self.process_message(client_id, event) {
                         warn!(message = "an error occurred processing a message", error = %e);
                     }
+                    self.flush_journal();
                 }
                 Message::System(event) => {
                     let span = span!(Level::INFO, "broker", event = "system");
@@ -69,10 +106,15 @@ where
                             if let Err(e) = self.process_shutdown() {
                                 warn!(message = "an error occurred shutting down the broker", error = %e);
                             }
+                            self.journal_all_sessions();
+                            self.flush_journal();
                             break;
                         }
                         SystemEvent::StateSnapshot(mut handle) => {
+                            self.journal_all_sessions();
+                            self.flush_journal();
                             let state = self.snapshot();
+                            self.unsaved_publications = 0;
                             let _guard = span.enter();
                             info!("asking snapshotter to persist state...");
                             if let Err(e) = handle.try_send(state) {
@@ -81,7 +123,42 @@ where
                                 info!("sent state to snapshotter.");
                             }
                         }
+                        SystemEvent::Cleanup => {
+                            debug!("removing expired state...");
+                            if let Err(e) = self.process_cleanup() {
+                                warn!(message = "an error occurred removing expired state", error = %e);
+                            }
+                        }
+                        SystemEvent::PublishStats => {
+                            debug!("publishing broker statistics...");
+                            if let Err(e) = self.process_publish_stats() {
+                                warn!(message = "an error occurred publishing broker statistics", error = %e);
+                            }
+                        }
+                        SystemEvent::Admin(request) => {
+                            debug!("handling administrative request...");
+                            if let Err(e) = self.process_admin_request(request) {
+                                warn!(message = "an error occurred handling administrative request", error = %e);
+                            }
+                        }
+                        SystemEvent::Publish(publication) => {
+                            debug!("publishing on behalf of the broker...");
+                            if let Err(e) = self.publish_all(publication) {
+                                warn!(message = "an error occurred publishing on behalf of the broker", error = %e);
+                            }
+                            self.track_unsaved_publication();
+                        }
+                        SystemEvent::PublishTrace(publication) => {
+                            if let Err(e) = self.publish_all(publication) {
+                                warn!(message = "an error occurred publishing trace record", error = %e);
+                            }
+                        }
+                        SystemEvent::ConfigUpdate(config) => {
+                            info!("applying updated configuration...");
+                            self.process_config_update(config);
+                        }
                     }
+                    self.flush_journal();
                 }
             }
         }
@@ -134,7 +211,7 @@ where
                 info!("broker received CONNACK, ignoring");
                 Ok(())
             }
-            ClientEvent::Disconnect(_) => self.process_disconnect(&client_id),
+            ClientEvent::Disconnect(disconnect) => self.process_disconnect(&client_id, &disconnect),
             ClientEvent::DropConnection => self.process_drop_connection(&client_id),
             ClientEvent::CloseSession => self.process_close_session(&client_id),
             ClientEvent::PingReq(ping) => self.process_ping_req(&client_id, &ping),
@@ -154,7 +231,10 @@ where
                 info!("broker received UNSUBACK, ignoring");
                 Ok(())
             }
-            ClientEvent::PublishFrom(publish) => self.process_publish(&client_id, publish),
+            ClientEvent::PublishFrom(publish) => {
+                let _timer = metrics::PUBLISH_DURATION.start_timer();
+                self.process_publish(&client_id, publish)
+            }
             ClientEvent::PublishTo(_publish) => {
                 info!("broker received a PublishTo, ignoring");
                 Ok(())
@@ -164,7 +244,13 @@ where
             ClientEvent::PubRec(pubrec) => self.process_pubrec(&client_id, &pubrec),
             ClientEvent::PubRel(pubrel) => self.process_pubrel(&client_id, &pubrel),
             ClientEvent::PubComp(pubcomp) => self.process_pubcomp(&client_id, &pubcomp),
+            ClientEvent::RateLimit(_) => {
+                info!("broker received a RateLimit, ignoring");
+                Ok(())
+            }
         };
+        self.update_session_gauges(&client_id);
+        self.journal_session(&client_id);
 
         if let Err(e) = result {
             warn!(message = "error processing message", %e);
@@ -173,6 +259,300 @@ where
         Ok(())
     }
 
+    fn session_config(&self, auth_id: &AuthId, client_id: &ClientId) -> SessionConfig {
+        let config = SessionConfig::from(&self.config);
+        match self.authorizer.max_inflight_messages(auth_id, client_id) {
+            Some(max_inflight_messages) => config.with_max_inflight_messages(max_inflight_messages),
+            None => config,
+        }
+    }
+
+    /// Rate limit of a new connection. Connections of the same identity share
+    /// a limiter on top of the one of their client, which a client keeps
+    /// across reconnects, so that reconnecting does not refill its buckets.
+    fn connection_rate_limit(
+        &mut self,
+        auth_id: &AuthId,
+        client_id: &ClientId,
+    ) -> Option<ConnectionRateLimit> {
+        let rate_limits = self.config.rate_limits()?;
+        let identity = match auth_id {
+            AuthId::Identity(identity) => Some(identity),
+            AuthId::Anonymous => None,
+        };
+
+        let limit = rate_limits.limit(identity.map(String::as_str));
+        if limit.is_unlimited() {
+            return None;
+        }
+
+        let now = Instant::now();
+        let shared = identity.map(|identity| {
+            self.rate_limiters
+                .entry(identity.clone())
+                .or_insert_with(|| RateLimiter::new(limit, now))
+                .clone()
+        });
+        let client = self
+            .client_rate_limiters
+            .entry(client_id.clone())
+            .or_insert_with(|| RateLimiter::new(limit, now))
+            .clone();
+        Some(ConnectionRateLimit::new(
+            client,
+            shared,
+            rate_limits.when_exceeded(),
+        ))
+    }
+
+    fn process_publish_stats(&mut self) -> Result<(), Error> {
+        self.update_session_metrics();
+        let report = self.stats.report();
+
+        let mut connected = 0;
+        let mut offline = 0;
+        for session in self.sessions.values() {
+            match session {
+                Session::Transient(_) | Session::Persistent(_) => connected += 1,
+                Session::Offline(_) => offline += 1,
+                Session::Disconnecting(_) => (),
+            }
+        }
+
+        // the broker's own statistics are retained as well
+        let retained = self
+            .retained
+            .keys()
+            .filter(|topic| !is_system_topic(topic))
+            .count();
+
+        let stats = vec![
+            StateChange::new_stat("uptime", format!("{} seconds", report.uptime.as_secs())),
+            StateChange::new_stat("clients/connected", connected),
+            StateChange::new_stat("clients/disconnected", offline),
+            StateChange::new_stat("messages/received", report.messages_received),
+            StateChange::new_stat("messages/sent", report.messages_sent),
+            StateChange::new_stat(
+                "load/messages/received",
+                report.messages_received_per_second,
+            ),
+            StateChange::new_stat("load/messages/sent", report.messages_sent_per_second),
+            StateChange::new_stat("bytes/received", report.bytes_received),
+            StateChange::new_stat("bytes/sent", report.bytes_sent),
+            StateChange::new_stat("retained messages/count", retained),
+        ];
+        for stat in stats {
+            self.publish_all(stat.try_into()?)?;
+        }
+
+        if !self.config.sys_client_stats() {
+            return Ok(());
+        }
+
+        let queued = self
+            .sessions
+            .iter()
+            .filter(|(client_id, _)| state_change::is_topic_level(client_id))
+            .filter_map(|(client_id, session)| {
+                session
+                    .queued_count()
+                    .map(|count| (client_id.clone(), count))
+            })
+            .collect::<Vec<_>>();
+        for (client_id, count) in &queued {
+            self.publish_all(StateChange::SessionQueue(client_id, *count).try_into()?)?;
+        }
+
+        Ok(())
+    }
+
+    fn process_admin_request(&mut self, request: AdminRequest) -> Result<(), Error> {
+        // a requester which went away does not need a reply
+        match request {
+            AdminRequest::ListSessions(reply) => {
+                let mut sessions = self
+                    .sessions
+                    .values()
+                    .map(SessionInfo::new)
+                    .collect::<Vec<_>>();
+                sessions.sort_by(|a, b| a.client_id.as_str().cmp(b.client_id.as_str()));
+                let _ = reply.send(sessions);
+            }
+            AdminRequest::DisconnectClient(client_id, reply) => {
+                let result = match self.sessions.get(&client_id) {
+                    Some(Session::Transient(_)) | Some(Session::Persistent(_)) => {
+                        info!("disconnecting {} on administrative request", client_id);
+                        self.drop_connection(&client_id)?;
+                        self.update_session_gauges(&client_id);
+                        self.journal_session(&client_id);
+                        Ok(())
+                    }
+                    Some(_) => Err(AdminError::SessionOffline(client_id)),
+                    None => Err(AdminError::NoSession(client_id)),
+                };
+                let _ = reply.send(result);
+            }
+            AdminRequest::PurgeSession(client_id, reply) => {
+                let result = match self.sessions.get(&client_id) {
+                    Some(Session::Offline(_)) => {
+                        info!(
+                            "removing offline session for {} on administrative request",
+                            client_id
+                        );
+                        self.sessions.remove(&client_id);
+                        self.subscribers.remove(&client_id);
+                        self.update_session_gauges(&client_id);
+                        self.journal_session(&client_id);
+                        self.publish_all(
+                            StateChange::new_subscription_change(&client_id, None).try_into()?,
+                        )?;
+                        self.publish_all(
+                            StateChange::new_session_change(&self.sessions).try_into()?,
+                        )?;
+                        Ok(())
+                    }
+                    Some(_) => Err(AdminError::SessionConnected(client_id)),
+                    None => Err(AdminError::NoSession(client_id)),
+                };
+                let _ = reply.send(result);
+            }
+            AdminRequest::ListRetained(reply) => {
+                let mut retained = self
+                    .retained
+                    .values()
+                    .map(RetainedInfo::new)
+                    .collect::<Vec<_>>();
+                retained.sort_by(|a, b| a.topic.cmp(&b.topic));
+                let _ = reply.send(retained);
+            }
+            AdminRequest::ClearRetained(topic, reply) => {
+                // the broker's own topics are kept, as it does not publish them again
+                let removed = match topic {
+                    Some(topic) if is_system_topic(&topic) => 0,
+                    Some(topic) => usize::from(self.remove_retained(&topic).is_some()),
+                    None => {
+                        let topics = self
+                            .retained
+                            .keys()
+                            .filter(|topic| !is_system_topic(topic))
+                            .cloned()
+                            .collect::<Vec<_>>();
+                        for topic in &topics {
+                            self.remove_retained(topic);
+                        }
+                        topics.len()
+                    }
+                };
+                info!(
+                    "removed {} retained messages on administrative request",
+                    removed
+                );
+                let _ = reply.send(removed);
+            }
+            AdminRequest::SetRetained(publication, reply) => {
+                let topic = &publication.topic_name;
+                if topic.is_empty() || topic.contains(&['+', '#'][..]) || is_system_topic(topic) {
+                    let _ = reply.send(Err(AdminError::InvalidTopic(topic.clone())));
+                    return Ok(());
+                }
+
+                self.publish_all(publication)?;
+                self.track_unsaved_publication();
+                let _ = reply.send(Ok(()));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process_config_update(&mut self, config: BrokerConfig) {
+        if let Err(e) = self.authenticator.update_config(&config) {
+            warn!(message = "an error occurred updating authentication settings", error = %e);
+        }
+
+        if let Err(e) = self.authorizer.update_config(&config) {
+            warn!(message = "an error occurred updating authorization policy", error = %e);
+        }
+
+        let rate_limits_changed = self.config.rate_limits() != config.rate_limits();
+        let trace_changed = self.config.trace() != config.trace();
+        self.config = config;
+
+        if rate_limits_changed {
+            self.update_rate_limits();
+        }
+
+        if trace_changed {
+            self.tracer = Tracer::new(self.config.trace(), &self.sender);
+        }
+
+        let client_ids = self.sessions.keys().cloned().collect::<Vec<_>>();
+        for client_id in client_ids {
+            let config = match self.sessions.get(&client_id).map(Session::auth_id) {
+                Some(auth_id) => self.session_config(auth_id, &client_id),
+                None => SessionConfig::from(&self.config),
+            };
+            if let Some(session) = self.sessions.get_mut(&client_id) {
+                session.update_config(config);
+            }
+        }
+
+        self.trim_retained();
+        self.remove_expired_retained();
+    }
+
+    /// Replaces the rate limits of every connected client with the configured ones.
+    fn update_rate_limits(&mut self) {
+        self.rate_limiters.clear();
+        self.client_rate_limiters.clear();
+
+        let connected = self
+            .sessions
+            .iter()
+            .filter_map(|(client_id, session)| match session {
+                Session::Transient(connected) | Session::Persistent(connected) => {
+                    Some((client_id.clone(), connected.auth_id().clone()))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        for (client_id, auth_id) in connected {
+            let rate_limit = self.connection_rate_limit(&auth_id, &client_id);
+            if let Some(session) = self.sessions.get_mut(&client_id) {
+                try_send!(session, ClientEvent::RateLimit(rate_limit));
+            }
+        }
+    }
+
+    /// Removes the oldest retained messages above the configured limit.
+    fn trim_retained(&mut self) {
+        let max_count = self.config.retained_messages().max_count();
+        let max_count = usize::try_from(max_count).unwrap_or(usize::max_value());
+        if max_count == 0 {
+            return;
+        }
+
+        let mut retained = self
+            .retained
+            .iter()
+            .filter(|(topic, _)| !is_system_topic(topic))
+            .map(|(topic, retained)| (retained.stored_at(), topic.clone()))
+            .collect::<Vec<_>>();
+
+        if retained.len() > max_count {
+            retained.sort();
+            let excess = retained.len() - max_count;
+            for (_, topic) in retained.into_iter().take(excess) {
+                info!(
+                    "retained messages limit lowered. removing retained message for topic \"{}\"",
+                    topic
+                );
+                self.remove_retained(&topic);
+            }
+        }
+    }
+
     fn process_shutdown(&mut self) -> Result<(), Error> {
         let mut sessions = vec![];
         let client_ids = self.sessions.keys().cloned().collect::<Vec<ClientId>>();
@@ -181,6 +561,7 @@ where
             if let Some(session) = self.close_session(&client_id)? {
                 sessions.push(session)
             }
+            self.journal_session(&client_id);
         }
 
         for mut session in sessions {
@@ -200,9 +581,11 @@ where
                 let ack = proto::ConnAck {
                     session_present: false,
                     return_code: proto::ConnectReturnCode::Refused($reason),
+                    properties: proto::Properties::default(),
                 };
 
                 debug!("sending connack with: {:?}", ack.return_code);
+                metrics::connack(ack.return_code);
                 let event = ClientEvent::ConnAck(ack);
                 let message = Message::Client(client_id.clone(), event);
                 try_send!(connreq.handle_mut(), message);
@@ -235,7 +618,7 @@ where
         // with a CONNACK return code 0x01 (unacceptable protocol level)
         // and then disconnect the Client if the Protocol Level is not supported
         // by the Server.
-        if connreq.connect().protocol_level != EXPECTED_PROTOCOL_LEVEL {
+        if !EXPECTED_PROTOCOL_LEVELS.contains(&connreq.connect().protocol_level) {
             warn!(
                 "invalid protocol level received from client: {}",
                 connreq.connect().protocol_level
@@ -244,35 +627,57 @@ where
             return Ok(());
         }
 
+        // [MQTT5-4.12.0-1] If the Server does not support the Authentication Method supplied
+        // by the Client, it MAY send a CONNACK with a Reason Code of 0x8C (Bad authentication
+        // method) as described in section 4.13 and MUST close the Network Connection.
+        //
+        // Enhanced authentication is not supported.
+        if let Some(method) = &connreq.connect().properties.authentication_method {
+            warn!(
+                "unsupported authentication method received from client: {}",
+                method
+            );
+            refuse_connection!(proto::ConnectionRefusedReason::Other(
+                BAD_AUTHENTICATION_METHOD
+            ));
+            return Ok(());
+        }
+
         // [MQTT-3.1.4-3] - The Server MAY check that the contents of the CONNECT
         // Packet meet any further restrictions and MAY perform authentication
         // and authorization checks. If any of these checks fail, it SHOULD send an
         // appropriate CONNACK response with a non-zero return code as described in
         // section 3.2 and it MUST close the Network Connection.
-        let credentials = connreq.certificate().map_or(
-            Credentials::Basic(
-                connreq.connect().username.clone(),
-                connreq.connect().password.clone(),
-            ),
-            |certificate| Credentials::ClientCertificate(certificate.clone()),
-        );
-        let auth_id = match self.authenticator.authenticate(credentials) {
-            Ok(Some(auth_id)) => {
-                debug!(
-                    "client {} successfully authenticated: {}",
-                    client_id, auth_id
-                );
-                auth_id
-            }
-            Ok(None) => {
-                warn!("unable to authenticate client: {}", client_id);
-                refuse_connection!(proto::ConnectionRefusedReason::BadUserNameOrPassword);
-                return Ok(());
-            }
-            Err(e) => {
-                warn!(message = "error authenticating client: {}", error = %e);
-                refuse_connection!(proto::ConnectionRefusedReason::ServerUnavailable);
-                return Ok(());
+        //
+        // Client connections authenticate the client before they hand it over,
+        // so that hashing a password does not hold up the broker.
+        let auth_id = if let Some(reason) = connreq.auth_failure() {
+            debug!("client {} failed to authenticate", client_id);
+            refuse_connection!(reason);
+            return Ok(());
+        } else if let Some(auth_id) = connreq.auth_id() {
+            debug!("client {} connected as {}", client_id, auth_id);
+            auth_id.clone()
+        } else {
+            let credentials = connreq.credentials();
+            match self.authenticator.authenticate(credentials) {
+                Ok(Some(auth_id)) => {
+                    debug!(
+                        "client {} successfully authenticated: {}",
+                        client_id, auth_id
+                    );
+                    auth_id
+                }
+                Ok(None) => {
+                    warn!("unable to authenticate client: {}", client_id);
+                    refuse_connection!(proto::ConnectionRefusedReason::BadUserNameOrPassword);
+                    return Ok(());
+                }
+                Err(e) => {
+                    warn!(message = "error authenticating client: {}", error = %e);
+                    refuse_connection!(proto::ConnectionRefusedReason::ServerUnavailable);
+                    return Ok(());
+                }
             }
         };
 
@@ -297,29 +702,45 @@ where
 
         // Process the CONNECT packet after it has been validated
         // TODO - fix ConnAck return_code != accepted to not add session to sessions map
+        let properties = connack_properties(&connreq);
+        let rate_limit = self.connection_rate_limit(&auth_id, &client_id);
         match self.open_session(auth_id, connreq)? {
-            OpenSession::OpenedSession(ack, events) => {
+            OpenSession::OpenedSession(mut ack, events) => {
+                ack.properties = properties;
+
                 // Send ConnAck on new session
+                metrics::connack(ack.return_code);
                 let session = self
-                    .get_session_mut(&client_id)
+                    .sessions
+                    .get_mut(&client_id)
                     .expect("session must exist");
+                if rate_limit.is_some() {
+                    session.send(ClientEvent::RateLimit(rate_limit))?;
+                }
                 session.send(ClientEvent::ConnAck(ack))?;
 
                 for event in events {
+                    self.tracer.sent(&client_id, &event);
                     session.send(event)?;
                 }
 
                 self.publish_all(StateChange::new_connection_change(&self.sessions).try_into()?)?;
             }
-            OpenSession::DuplicateSession(mut old_session, ack) => {
+            OpenSession::DuplicateSession(mut old_session, mut ack) => {
+                ack.properties = properties;
+
                 // Drop the old connection
                 old_session.send(ClientEvent::DropConnection)?;
 
                 // Send ConnAck on new connection
+                metrics::connack(ack.return_code);
                 let should_drop = ack.return_code != proto::ConnectReturnCode::Accepted;
                 let session = self
                     .get_session_mut(&client_id)
                     .expect("session must exist");
+                if rate_limit.is_some() && !should_drop {
+                    session.send(ClientEvent::RateLimit(rate_limit))?;
+                }
                 session.send(ClientEvent::ConnAck(ack))?;
 
                 if should_drop {
@@ -335,10 +756,23 @@ where
         Ok(())
     }
 
-    fn process_disconnect(&mut self, client_id: &ClientId) -> Result<(), Error> {
+    fn process_disconnect(
+        &mut self,
+        client_id: &ClientId,
+        disconnect: &proto::Disconnect,
+    ) -> Result<(), Error> {
         debug!("handling disconnect...");
         if let Some(mut session) = self.close_session(client_id)? {
-            session.send(ClientEvent::Disconnect(proto::Disconnect))?;
+            session.send(ClientEvent::Disconnect(proto::Disconnect::default()))?;
+
+            // [MQTT5-3.14.4-3] On receipt of DISCONNECT with a Reason Code of 0x00 (Success)
+            // the Server MUST discard any Will Message associated with the current Connection
+            // without publishing it. A client can still ask for the will to be published.
+            if disconnect.reason_code == proto::ReasonCode::DISCONNECT_WITH_WILL_MESSAGE {
+                if let Some(will) = session.into_will() {
+                    self.publish_all(will)?;
+                }
+            }
         } else {
             debug!("no session for {}", client_id);
         }
@@ -410,28 +844,50 @@ where
             debug!("no session for {}", client_id);
             return Ok(());
         };
+        self.index_subscriptions(client_id);
 
         // Handle retained messages
-        let publications = self
-            .retained
-            .values()
-            .filter(|p| {
-                subscriptions
-                    .iter()
-                    .any(|sub| sub.filter().matches(&p.topic_name))
-            })
-            .cloned()
-            .collect::<Vec<proto::Publication>>();
+        let now = SystemTime::now();
+        let mut publications = vec![];
+        let mut expired = vec![];
+        for (topic, retained) in &self.retained {
+            if subscriptions.iter().any(|sub| sub.filter().matches(topic)) {
+                if self.is_retained_expired(topic, retained, now) {
+                    expired.push(topic.clone());
+                } else {
+                    publications.push(retained.publication_at(now));
+                }
+            }
+        }
+
+        for topic in expired {
+            info!("removing expired retained message for topic \"{}\"", topic);
+            self.remove_retained(&topic);
+        }
 
         if let Some(session) = self.sessions.get_mut(client_id) {
+            let mut queue_full = false;
             for mut publication in publications {
                 publication.retain = true;
-                publish_to(&self.authorizer, session, &publication)?;
+                match publish_to(&self.authorizer, &mut self.tracer, session, &publication) {
+                    Ok(true) => self.stats.sent(&publication),
+                    Ok(false) => (),
+                    Err(Error::SessionQueueFull) => {
+                        queue_full = true;
+                        break;
+                    }
+                    Err(e) => return Err(e),
+                }
             }
 
             let change =
                 StateChange::new_subscription_change(client_id, Some(&session)).try_into()?;
             self.publish_all(change)?;
+
+            if queue_full {
+                info!("dropping connection for {} due to a full queue", client_id);
+                self.drop_connection(client_id)?;
+            }
         } else {
             debug!("no session for {}", client_id);
         }
@@ -451,6 +907,7 @@ where
 
                 let change =
                     StateChange::new_subscription_change(client_id, Some(&session)).try_into()?;
+                self.index_subscriptions(client_id);
                 self.publish_all(change)?;
 
                 Ok(())
@@ -493,10 +950,14 @@ where
     ) -> Result<(), Error> {
         let operation = Operation::new_publish(publish.clone());
         if let Some(session) = self.sessions.get_mut(client_id) {
-            let activity = Activity::new(session.auth_id()?.clone(), client_id.clone(), operation);
+            self.tracer
+                .publish(TraceEvent::Received, client_id, &publish);
+            let activity = Activity::new(session.auth_id().clone(), client_id.clone(), operation);
             match self.authorizer.authorize(activity) {
                 Ok(true) => {
                     debug!("client {} successfully authorized", client_id);
+                    self.tracer
+                        .publish(TraceEvent::Authorized, client_id, &publish);
                     let (maybe_publication, maybe_event) = session.handle_publish(publish)?;
 
                     if let Some(event) = maybe_event {
@@ -504,15 +965,31 @@ where
                     }
 
                     if let Some(publication) = maybe_publication {
-                        self.publish_all(publication)?
+                        self.stats.received(&publication);
+                        self.publish_all_from(Some(client_id), publication)?;
+                        self.track_unsaved_publication();
                     }
                 }
                 Ok(false) => {
+                    self.tracer.publish(TraceEvent::Denied, client_id, &publish);
                     warn!(
                         "client {} not allowed to publish to topic {}",
                         client_id, publish.topic_name,
                     );
-                    self.drop_connection(&client_id)?;
+
+                    // A MQTT 5.0 client is told about the failure in the acknowledgement
+                    // instead of losing its connection.
+                    if session.protocol_level() == Some(mqtt3::PROTOCOL_LEVEL_V5) {
+                        let reason =
+                            format!("not allowed to publish to topic {}", publish.topic_name);
+                        if let Some(event) =
+                            not_authorized_ack(publish.packet_identifier_dup_qos, reason)
+                        {
+                            session.send(event)?;
+                        }
+                    } else {
+                        self.drop_connection(&client_id)?;
+                    }
                 }
                 Err(e) => {
                     warn!(message="error authorizing client: {}", error = %e);
@@ -531,14 +1008,18 @@ where
         client_id: &ClientId,
         puback: &proto::PubAck,
     ) -> Result<(), Error> {
-        match self.get_session_mut(client_id) {
-            Ok(session) => {
+        match self.sessions.get_mut(client_id) {
+            Some(session) => {
+                if let Some(publish) = session.inflight(puback.packet_identifier) {
+                    self.tracer.publish(TraceEvent::Acked, client_id, publish);
+                }
                 if let Some(event) = session.handle_puback(puback)? {
+                    self.tracer.sent(client_id, &event);
                     session.send(event)?
                 }
                 Ok(())
             }
-            Err(NoSessionError) => {
+            None => {
                 debug!("no session for {}", client_id);
                 Ok(())
             }
@@ -550,14 +1031,15 @@ where
         client_id: &ClientId,
         id: proto::PacketIdentifier,
     ) -> Result<(), Error> {
-        match self.get_session_mut(client_id) {
-            Ok(session) => {
+        match self.sessions.get_mut(client_id) {
+            Some(session) => {
                 if let Some(event) = session.handle_puback0(id)? {
+                    self.tracer.sent(client_id, &event);
                     session.send(event)?
                 }
                 Ok(())
             }
-            Err(NoSessionError) => {
+            None => {
                 debug!("no session for {}", client_id);
                 Ok(())
             }
@@ -569,14 +1051,18 @@ where
         client_id: &ClientId,
         pubrec: &proto::PubRec,
     ) -> Result<(), Error> {
-        match self.get_session_mut(client_id) {
-            Ok(session) => {
+        match self.sessions.get_mut(client_id) {
+            Some(session) => {
+                if let Some(publish) = session.inflight(pubrec.packet_identifier) {
+                    self.tracer.publish(TraceEvent::Acked, client_id, publish);
+                }
                 if let Some(event) = session.handle_pubrec(pubrec)? {
+                    self.tracer.sent(client_id, &event);
                     session.send(event)?
                 }
                 Ok(())
             }
-            Err(NoSessionError) => {
+            None => {
                 debug!("no session for {}", client_id);
                 Ok(())
             }
@@ -593,7 +1079,11 @@ where
                 let packet_identifier = pubrel.packet_identifier;
                 let maybe_publication = session.handle_pubrel(pubrel)?;
 
-                let pubcomp = proto::PubComp { packet_identifier };
+                let pubcomp = proto::PubComp {
+                    packet_identifier,
+                    reason_code: proto::ReasonCode::SUCCESS,
+                    properties: proto::Properties::default(),
+                };
                 session.send(ClientEvent::PubComp(pubcomp))?;
                 maybe_publication
             }
@@ -604,7 +1094,9 @@ where
         };
 
         if let Some(publication) = maybe_publication {
-            self.publish_all(publication)?
+            self.stats.received(&publication);
+            self.publish_all_from(Some(client_id), publication)?;
+            self.track_unsaved_publication();
         }
         Ok(())
     }
@@ -614,14 +1106,15 @@ where
         client_id: &ClientId,
         pubcomp: &proto::PubComp,
     ) -> Result<(), Error> {
-        match self.get_session_mut(client_id) {
-            Ok(session) => {
+        match self.sessions.get_mut(client_id) {
+            Some(session) => {
                 if let Some(event) = session.handle_pubcomp(pubcomp)? {
+                    self.tracer.sent(client_id, &event);
                     session.send(event)?
                 }
                 Ok(())
             }
-            Err(NoSessionError) => {
+            None => {
                 debug!("no session for {}", client_id);
                 Ok(())
             }
@@ -634,6 +1127,21 @@ where
             .ok_or_else(|| NoSessionError)
     }
 
+    /// Brings the subscription index in line with the subscriptions
+    /// of the client's session, if there is one.
+    fn index_subscriptions(&mut self, client_id: &ClientId) {
+        match self
+            .sessions
+            .get(client_id)
+            .and_then(Session::subscriptions)
+        {
+            Some(subscriptions) => self
+                .subscribers
+                .update(client_id, subscriptions.values().map(Subscription::filter)),
+            None => self.subscribers.remove(client_id),
+        }
+    }
+
     fn open_session(&mut self, auth_id: AuthId, connreq: ConnReq) -> Result<OpenSession, Error> {
         let client_id = connreq.client_id().clone();
 
@@ -650,7 +1158,8 @@ where
                 let (new_session, events, session_present) =
                     if let proto::ClientId::IdWithExistingSession(_) = connreq.connect().client_id {
                         debug!("moving offline session to online for {}", client_id);
-                        if let Ok((state, events)) = offline.into_online() {
+                        let config = self.session_config(&auth_id, &client_id);
+                        if let Ok((state, events)) = offline.into_online(config) {
                             let new_session = Session::new_persistent(auth_id, connreq, state);
                             (new_session, events, true)
                         } else {
@@ -658,9 +1167,16 @@ where
                                 "Session identifiers exhausted, this can only be caused by a bug."
                             );
                         }
+                    } else if is_persistent(connreq.connect()) {
+                        info!("cleaning offline session for {}", client_id);
+                        let config = self.session_config(&auth_id, &client_id);
+                        let state = SessionState::new(client_id.clone(), config);
+                        let new_session = Session::new_persistent(auth_id, connreq, state);
+                        (new_session, vec![], false)
                     } else {
                         info!("cleaning offline session for {}", client_id);
-                        let new_session = Session::new_transient(auth_id, connreq);
+                        let config = self.session_config(&auth_id, &client_id);
+                        let new_session = Session::new_transient(auth_id, connreq, config);
                         (new_session, vec![], false)
                     };
 
@@ -669,6 +1185,7 @@ where
                 let ack = proto::ConnAck {
                     session_present,
                     return_code: proto::ConnectReturnCode::Accepted,
+                    properties: proto::Properties::default(),
                 };
 
                 OpenSession::OpenedSession(ack, events)
@@ -678,15 +1195,15 @@ where
             }
             None => {
                 // No session present - create a new one.
-                let new_session = if let proto::ClientId::IdWithExistingSession(_) =
-                    connreq.connect().client_id
-                {
+                let new_session = if is_persistent(connreq.connect()) {
                     info!("creating new persistent session for {}", client_id);
-                    let state = SessionState::new(client_id.clone());
+                    let config = self.session_config(&auth_id, &client_id);
+                    let state = SessionState::new(client_id.clone(), config);
                     Session::new_persistent(auth_id, connreq, state)
                 } else {
                     info!("creating new transient session for {}", client_id);
-                    Session::new_transient(auth_id, connreq)
+                    let config = self.session_config(&auth_id, &client_id);
+                    Session::new_transient(auth_id, connreq, config)
                 };
 
                 let subscription_change =
@@ -697,6 +1214,7 @@ where
                 let ack = proto::ConnAck {
                     session_present: false,
                     return_code: proto::ConnectReturnCode::Accepted,
+                    properties: proto::Properties::default(),
                 };
                 let events = vec![];
 
@@ -706,6 +1224,7 @@ where
                 OpenSession::OpenedSession(ack, events)
             }
         };
+        self.index_subscriptions(&client_id);
 
         Ok(session)
     }
@@ -741,17 +1260,24 @@ where
             let client_id = connreq.client_id().clone();
             let (auth_id_, state, _will, handle) = current_connected.into_parts();
             let old_session = Session::new_disconnecting(auth_id_, client_id.clone(), None, handle);
+            let config = self.session_config(&auth_id, &client_id);
             let (new_session, session_present) =
                 if let proto::ClientId::IdWithExistingSession(_) = connreq.connect().client_id {
                     debug!(
                         "moving persistent session to this connection for {}",
                         client_id
                     );
+                    let state = state.with_config(config);
                     let new_session = Session::new_persistent(auth_id, connreq, state);
                     (new_session, true)
+                } else if is_persistent(connreq.connect()) {
+                    info!("cleaning session for {}", client_id);
+                    let state = SessionState::new(client_id.clone(), config);
+                    let new_session = Session::new_persistent(auth_id, connreq, state);
+                    (new_session, false)
                 } else {
                     info!("cleaning session for {}", client_id);
-                    let new_session = Session::new_transient(auth_id, connreq);
+                    let new_session = Session::new_transient(auth_id, connreq, config);
                     (new_session, false)
                 };
 
@@ -759,6 +1285,7 @@ where
             let ack = proto::ConnAck {
                 session_present,
                 return_code: proto::ConnectReturnCode::Accepted,
+                properties: proto::Properties::default(),
             };
 
             OpenSession::DuplicateSession(old_session, ack)
@@ -783,6 +1310,26 @@ where
                     handle,
                 ))
             }
+            Some(Session::Persistent(connected))
+                if connected.state().session_expiry_interval() == Some(Duration::default()) =>
+            {
+                // [MQTT5-3.1.2-23] If the Session Expiry Interval is 0, the Session ends
+                // when the Network Connection is closed.
+                info!("closing expired persistent session for {}", client_id);
+                self.publish_all(StateChange::new_connection_change(&self.sessions).try_into()?)?;
+                self.publish_all(StateChange::new_session_change(&self.sessions).try_into()?)?;
+                self.publish_all(
+                    StateChange::new_subscription_change(client_id, None).try_into()?,
+                )?;
+
+                let (auth_id, _state, will, handle) = connected.into_parts();
+                Some(Session::new_disconnecting(
+                    auth_id,
+                    client_id.clone(),
+                    will,
+                    handle,
+                ))
+            }
             Some(Session::Persistent(connected)) => {
                 // Move a persistent session into the offline state
                 // Return a disconnecting session to allow a disconnect
@@ -792,7 +1339,7 @@ where
                 self.publish_all(StateChange::new_connection_change(&self.sessions).try_into()?)?;
 
                 let (auth_id, state, will, handle) = connected.into_parts();
-                let new_session = Session::new_offline(state);
+                let new_session = Session::new_offline(state.with_last_active(SystemTime::now()));
                 self.sessions.insert(client_id.clone(), new_session);
                 Some(Session::new_disconnecting(
                     auth_id,
@@ -809,11 +1356,23 @@ where
             }
             _ => None,
         };
+        self.index_subscriptions(client_id);
 
         Ok(new_session)
     }
 
-    fn publish_all(&mut self, mut publication: proto::Publication) -> Result<(), Error> {
+    fn publish_all(&mut self, publication: proto::Publication) -> Result<(), Error> {
+        self.publish_all_from(None, publication)
+    }
+
+    /// Delivers a publication to every subscriber. Publications of clients,
+    /// as opposed to the broker's own, are traced with the number of sessions
+    /// they were delivered to.
+    fn publish_all_from(
+        &mut self,
+        publisher: Option<&ClientId>,
+        mut publication: proto::Publication,
+    ) -> Result<(), Error> {
         if publication.retain {
             // [MQTT-3.3.1-6]. If the Server receives a QoS 0 message with the
             // RETAIN flag set to 1 it MUST discard any message previously
@@ -828,17 +1387,9 @@ where
                     "removing retained message for topic \"{}\"",
                     publication.topic_name
                 );
-                self.retained.remove(&publication.topic_name);
+                self.remove_retained(&publication.topic_name);
             } else {
-                let maybe_retained = self
-                    .retained
-                    .insert(publication.topic_name.to_owned(), publication.clone());
-                if maybe_retained.is_none() {
-                    info!(
-                        "new retained message for topic \"{}\"",
-                        publication.topic_name
-                    );
-                }
+                self.store_retained(publication.clone());
             }
         }
 
@@ -848,42 +1399,418 @@ where
         // This will not happen here.
         publication.retain = false;
 
-        for session in self.sessions.values_mut() {
-            if let Err(e) = publish_to(&self.authorizer, session, &publication) {
-                warn!(message = "error processing message", error = %e);
+        let subscribers = self.subscribers.matches(&publication.topic_name);
+
+        let mut delivered = 0;
+        let mut queue_full = vec![];
+        for client_id in &subscribers {
+            if let Some(session) = self.sessions.get_mut(client_id) {
+                match publish_to(&self.authorizer, &mut self.tracer, session, &publication) {
+                    Ok(true) => {
+                        delivered += 1;
+                        self.stats.sent(&publication);
+                    }
+                    Ok(false) => (),
+                    Err(Error::SessionQueueFull) => queue_full.push(client_id.clone()),
+                    Err(e) => warn!(message = "error processing message", error = %e),
+                }
+            }
+        }
+
+        for (client_id, max_qos) in
+            self.pick_shared_subscribers(&subscribers, &publication.topic_name)
+        {
+            if let Some(session) = self.sessions.get_mut(&client_id) {
+                match publish_to_shared(
+                    &self.authorizer,
+                    &mut self.tracer,
+                    session,
+                    &publication,
+                    max_qos,
+                ) {
+                    Ok(true) => {
+                        delivered += 1;
+                        self.stats.sent(&publication);
+                    }
+                    Ok(false) => (),
+                    Err(Error::SessionQueueFull) => queue_full.push(client_id),
+                    Err(e) => warn!(message = "error processing message", error = %e),
+                }
             }
         }
 
+        if let Some(publisher) = publisher {
+            let event = TraceEvent::Routed {
+                sessions: delivered,
+            };
+            self.tracer.publication(event, publisher, &publication);
+        }
+
+        for client_id in queue_full {
+            info!("dropping connection for {} due to a full queue", client_id);
+            self.drop_connection(&client_id)?;
+        }
+
+        for client_id in &subscribers {
+            self.update_session_gauges(client_id);
+            self.journal_session(client_id);
+        }
+
         Ok(())
     }
-}
 
-fn subscribe<Z>(
-    authorizer: &Z,
-    session: &mut Session,
-    subscribe: proto::Subscribe,
-) -> Result<(proto::SubAck, Vec<Subscription>), Error>
-where
-    Z: Authorizer,
-{
-    let auth_id = session.auth_id()?.clone();
-    let client_id = session.client_id().clone();
+    /// Picks the session each share group matching the topic delivers a publication to.
+    ///
+    /// Members of a share group take turns in the order of their client ids.
+    fn pick_shared_subscribers(
+        &mut self,
+        subscribers: &HashSet<ClientId>,
+        topic_name: &str,
+    ) -> Vec<(ClientId, proto::QoS)> {
+        let mut groups: HashMap<&str, Vec<(&ClientId, proto::QoS)>> = HashMap::new();
+        for session in subscribers.iter().filter_map(|id| self.sessions.get(id)) {
+            let subscriptions = session.subscriptions().into_iter().flatten();
+            for (topic_filter, subscription) in subscriptions {
+                if subscription::is_shared(topic_filter)
+                    && subscription.filter().matches(topic_name)
+                {
+                    groups
+                        .entry(topic_filter.as_str())
+                        .or_insert_with(Vec::new)
+                        .push((session.client_id(), *subscription.max_qos()));
+                }
+            }
+        }
 
-    let mut subscriptions = Vec::with_capacity(subscribe.subscribe_to.len());
-    let mut acks = Vec::with_capacity(subscribe.subscribe_to.len());
+        let mut picked = Vec::with_capacity(groups.len());
+        for (topic_filter, mut members) in groups {
+            members.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
 
-    let auth_results = subscribe.subscribe_to.into_iter().map(|subscribe_to| {
-        let operation = Operation::new_subscribe(subscribe_to.clone());
-        let activity = Activity::new(auth_id.clone(), client_id.clone(), operation);
-        let auth = authorizer.authorize(activity);
-        auth.map(|auth| (auth, subscribe_to))
-    });
+            let position = self
+                .shared_positions
+                .entry(topic_filter.to_owned())
+                .or_insert(0);
+            let (client_id, max_qos) = members[*position % members.len()];
+            *position = position.wrapping_add(1);
 
-    for auth in auth_results {
-        let ack_qos = match auth {
-            Ok((true, subscribe_to)) => match session.subscribe_to(subscribe_to) {
-                Ok((qos, subscription)) => {
-                    if let Some(subscription) = subscription {
+            picked.push((client_id.clone(), max_qos));
+        }
+
+        picked
+    }
+
+    /// Asks the snapshotter to persist the state once the configured number
+    /// of publications has been accepted since the last snapshot.
+    fn track_unsaved_publication(&mut self) {
+        let max_unsaved = self
+            .config
+            .persistence()
+            .map_or(0, SessionPersistence::unsaved_message_count);
+
+        if max_unsaved == 0 {
+            return;
+        }
+
+        if let Some(mut handle) = self.snapshot_handle.clone() {
+            self.unsaved_publications = self.unsaved_publications.saturating_add(1);
+            if self.unsaved_publications >= max_unsaved {
+                info!(
+                    "{} publications accepted since the last snapshot. asking snapshotter to persist state...",
+                    self.unsaved_publications
+                );
+                self.unsaved_publications = 0;
+                self.journal_all_sessions();
+                self.flush_journal();
+                if let Err(e) = handle.try_send(self.snapshot()) {
+                    warn!(message = "an error occurred communicating with the snapshotter", error = %e);
+                }
+            }
+        }
+    }
+
+    fn store_retained(&mut self, publication: proto::Publication) {
+        let topic_name = publication.topic_name.clone();
+        let limits = self.config.retained_messages();
+        let max_count = usize::try_from(limits.max_count()).unwrap_or(usize::max_value());
+
+        let is_limited = max_count != 0
+            && !is_system_topic(&topic_name)
+            && !self.retained.contains_key(&topic_name);
+
+        if is_limited {
+            let count = self
+                .retained
+                .keys()
+                .filter(|topic| !is_system_topic(topic))
+                .count();
+
+            if count >= max_count {
+                match limits.when_full() {
+                    RetainedFullAction::DropNew => {
+                        warn!(
+                            "retained messages limit reached. ignoring retained message for topic \"{}\"",
+                            topic_name
+                        );
+                        return;
+                    }
+                    RetainedFullAction::DropOld => {
+                        let oldest = self
+                            .retained
+                            .iter()
+                            .filter(|(topic, _)| !is_system_topic(topic))
+                            .min_by_key(|(_, retained)| retained.stored_at())
+                            .map(|(topic, _)| topic.clone());
+
+                        if let Some(oldest) = oldest {
+                            info!(
+                                "retained messages limit reached. removing retained message for topic \"{}\"",
+                                oldest
+                            );
+                            self.remove_retained(&oldest);
+                        }
+                    }
+                }
+            }
+        }
+
+        let retained = RetainedPublication::new(publication);
+        self.record(|| Change::RetainedStored(topic_name.clone(), retained.clone()));
+        let maybe_retained = self.retained.insert(topic_name.clone(), retained);
+        if maybe_retained.is_none() {
+            info!("new retained message for topic \"{}\"", topic_name);
+        }
+    }
+
+    fn remove_retained(&mut self, topic: &str) -> Option<RetainedPublication> {
+        let removed = self.retained.remove(topic);
+        if removed.is_some() {
+            self.record(|| Change::RetainedRemoved(topic.to_owned()));
+        }
+        removed
+    }
+
+    fn is_retained_expired(
+        &self,
+        topic: &str,
+        retained: &RetainedPublication,
+        now: SystemTime,
+    ) -> bool {
+        let expiration = self.config.retained_messages().expiration();
+        let is_expired = expiration != Duration::default()
+            && !is_system_topic(topic)
+            && retained.is_expired(expiration, now);
+
+        is_expired || retained.is_message_expired(now)
+    }
+
+    fn process_cleanup(&mut self) -> Result<(), Error> {
+        self.remove_expired_retained();
+        self.remove_expired_sessions()?;
+        self.update_session_metrics();
+
+        // limiters with no connections left, once they are no different from new ones
+        let now = Instant::now();
+        self.rate_limiters
+            .retain(|_, limiter| limiter.is_shared() || !limiter.is_full(now));
+        self.client_rate_limiters
+            .retain(|_, limiter| limiter.is_shared() || !limiter.is_full(now));
+        Ok(())
+    }
+
+    /// Brings the session gauges up to date with a session which changed its
+    /// state or the number of publications queued for it.
+    fn update_session_gauges(&mut self, client_id: &ClientId) {
+        let current = session_metrics(self.sessions.get(client_id));
+        let previous = match current {
+            (None, 0) => self.session_gauges.remove(client_id),
+            current => self.session_gauges.insert(client_id.clone(), current),
+        };
+        metrics::session_changed(previous.unwrap_or((None, 0)), current);
+    }
+
+    fn record<F>(&mut self, change: F)
+    where
+        F: FnOnce() -> Change,
+    {
+        if let Some(journal) = &mut self.journal {
+            journal.changes.push(change());
+        }
+    }
+
+    /// Records the changes made to a session, or the whole session once it
+    /// starts to be persisted and its removal once it stops to be.
+    fn journal_session(&mut self, client_id: &ClientId) {
+        let journal = match &mut self.journal {
+            Some(journal) => journal,
+            None => return,
+        };
+
+        let state = self
+            .sessions
+            .get_mut(client_id)
+            .and_then(Session::persisted_state_mut);
+
+        match state {
+            Some(state) if state.is_journaled() && journal.sessions.contains(client_id) => {
+                let changes = state
+                    .take_changes()
+                    .into_iter()
+                    .map(|change| Change::Session(client_id.clone(), change));
+                journal.changes.extend(changes);
+            }
+            Some(state) => {
+                // a new session, or one which replaced the session journaled before
+                state.take_changes();
+                state.start_journal();
+                journal.changes.push(Change::SessionStored(state.clone()));
+                journal.sessions.insert(client_id.clone());
+            }
+            None => {
+                if journal.sessions.remove(client_id) {
+                    journal
+                        .changes
+                        .push(Change::SessionRemoved(client_id.clone()));
+                }
+            }
+        }
+    }
+
+    /// Records the changes of every session, so that a state taken afterwards
+    /// includes no change which is not journaled yet.
+    fn journal_all_sessions(&mut self) {
+        let client_ids = match &self.journal {
+            Some(journal) => self
+                .sessions
+                .keys()
+                .chain(&journal.sessions)
+                .cloned()
+                .collect::<HashSet<_>>(),
+            None => return,
+        };
+
+        for client_id in &client_ids {
+            self.journal_session(client_id);
+        }
+    }
+
+    /// Sends the changes recorded so far to the snapshotter.
+    fn flush_journal(&mut self) {
+        if let (Some(journal), Some(handle)) = (&mut self.journal, &mut self.snapshot_handle) {
+            if !journal.changes.is_empty() {
+                let changes = mem::take(&mut journal.changes);
+                if let Err(e) = handle.send_changes(changes) {
+                    warn!(message = "an error occurred communicating with the snapshotter", error = %e);
+                }
+            }
+        }
+    }
+
+    /// Counts all sessions again, correcting the session gauges for sessions
+    /// which changed without an event, e.g. expired ones.
+    fn update_session_metrics(&mut self) {
+        self.session_gauges = self
+            .sessions
+            .iter()
+            .map(|(client_id, session)| (client_id.clone(), session_metrics(Some(session))))
+            .collect();
+
+        let mut connected = 0;
+        let mut offline = 0;
+        let mut queued = 0;
+        let mut max_queued = 0;
+        for session in self.sessions.values() {
+            match session {
+                Session::Transient(_) | Session::Persistent(_) => connected += 1,
+                Session::Offline(_) => offline += 1,
+                Session::Disconnecting(_) => (),
+            }
+
+            let count = session.queued_count().unwrap_or_default();
+            queued += count;
+            max_queued = max_queued.max(count);
+        }
+
+        metrics::SESSIONS
+            .with_label_values(&["connected"])
+            .set(metrics::gauge(connected));
+        metrics::SESSIONS
+            .with_label_values(&["offline"])
+            .set(metrics::gauge(offline));
+        metrics::QUEUED_MESSAGES.set(metrics::gauge(queued));
+        metrics::MAX_QUEUED_MESSAGES.set(metrics::gauge(max_queued));
+    }
+
+    fn remove_expired_retained(&mut self) {
+        let now = SystemTime::now();
+        let expired = self
+            .retained
+            .iter()
+            .filter(|(topic, retained)| self.is_retained_expired(topic, retained, now))
+            .map(|(topic, _)| topic.clone())
+            .collect::<Vec<_>>();
+
+        for topic in expired {
+            info!("removing expired retained message for topic \"{}\"", topic);
+            self.remove_retained(&topic);
+        }
+    }
+
+    fn remove_expired_sessions(&mut self) -> Result<(), Error> {
+        let expiration = self.config.session().expiration();
+        let now = SystemTime::now();
+        let expired = self
+            .sessions
+            .iter()
+            .filter_map(|(client_id, session)| match session {
+                Session::Offline(offline) if offline.is_expired(expiration, now) => {
+                    Some(client_id.clone())
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        if expired.is_empty() {
+            return Ok(());
+        }
+
+        for client_id in expired {
+            info!("removing expired offline session for {}", client_id);
+            self.sessions.remove(&client_id);
+            self.subscribers.remove(&client_id);
+            self.journal_session(&client_id);
+            self.publish_all(StateChange::new_subscription_change(&client_id, None).try_into()?)?;
+        }
+
+        self.publish_all(StateChange::new_session_change(&self.sessions).try_into()?)
+    }
+}
+
+fn subscribe<Z>(
+    authorizer: &Z,
+    session: &mut Session,
+    subscribe: proto::Subscribe,
+) -> Result<(proto::SubAck, Vec<Subscription>), Error>
+where
+    Z: Authorizer,
+{
+    let auth_id = session.auth_id().clone();
+    let client_id = session.client_id().clone();
+
+    let mut subscriptions = Vec::with_capacity(subscribe.subscribe_to.len());
+    let mut acks = Vec::with_capacity(subscribe.subscribe_to.len());
+
+    let auth_results = subscribe.subscribe_to.into_iter().map(|subscribe_to| {
+        let operation = Operation::new_subscribe(subscribe_to.clone());
+        let activity = Activity::new(auth_id.clone(), client_id.clone(), operation);
+        let auth = authorizer.authorize(activity);
+        auth.map(|auth| (auth, subscribe_to))
+    });
+
+    for auth in auth_results {
+        let ack_qos = match auth {
+            Ok((true, subscribe_to)) => match session.subscribe_to(subscribe_to) {
+                Ok((qos, subscription)) => {
+                    if let Some(subscription) = subscription {
                         subscriptions.push(subscription);
                     }
                     qos
@@ -910,35 +1837,80 @@ where
         acks.push(ack_qos);
     }
 
+    let reason_string = if acks.contains(&proto::SubAckQos::Failure) {
+        Some("subscription to one or more topic filters was refused".to_owned())
+    } else {
+        None
+    };
+
     let suback = proto::SubAck {
         packet_identifier: subscribe.packet_identifier,
         qos: acks,
+        properties: proto::Properties {
+            reason_string,
+            ..proto::Properties::default()
+        },
     };
 
     Ok((suback, subscriptions))
 }
 
+/// Delivers a publication to a session the client is allowed to receive it.
+///
+/// Returns `true` if the publication was delivered.
 fn publish_to<Z>(
     authorizer: &Z,
+    tracer: &mut Tracer,
     session: &mut Session,
     publication: &proto::Publication,
-) -> Result<(), Error>
+) -> Result<bool, Error>
 where
     Z: Authorizer,
 {
     let operation = Operation::new_receive(publication.clone());
     let client_id = session.client_id().clone();
-    // TODO refactor auth_id logic for offline sessions
-    let auth_id = session.auth_id().unwrap_or(&AuthId::Anonymous);
-    let activity = Activity::new(auth_id.clone(), client_id, operation);
+    let activity = Activity::new(session.auth_id().clone(), client_id, operation);
 
     match authorizer.authorize(activity) {
         Ok(true) => {
-            if let Some(event) = session.publish_to(&publication)? {
-                session.send(event)?
-            }
+            let delivery = session.publish_to(&publication);
+            return deliver(tracer, session, publication, delivery);
+        }
+        Ok(false) => {
+            tracer.publication(TraceEvent::Denied, session.client_id(), publication);
+            debug!(
+                "client {} not allowed to receive messages",
+                session.client_id()
+            );
+        }
+        Err(e) => {
+            warn!(message="error authorizing client: {}", error = %e);
+        }
+    }
+    Ok(false)
+}
+
+fn publish_to_shared<Z>(
+    authorizer: &Z,
+    tracer: &mut Tracer,
+    session: &mut Session,
+    publication: &proto::Publication,
+    max_qos: proto::QoS,
+) -> Result<bool, Error>
+where
+    Z: Authorizer,
+{
+    let operation = Operation::new_receive(publication.clone());
+    let client_id = session.client_id().clone();
+    let activity = Activity::new(session.auth_id().clone(), client_id, operation);
+
+    match authorizer.authorize(activity) {
+        Ok(true) => {
+            let delivery = session.publish_to_shared(&publication, max_qos);
+            return deliver(tracer, session, publication, delivery);
         }
         Ok(false) => {
+            tracer.publication(TraceEvent::Denied, session.client_id(), publication);
             debug!(
                 "client {} not allowed to receive messages",
                 session.client_id()
@@ -948,29 +1920,224 @@ where
             warn!(message="error authorizing client: {}", error = %e);
         }
     }
-    Ok(())
+    Ok(false)
+}
+
+/// Sends a publication delivered to a session, and traces what became of it.
+///
+/// Returns whether the publication was sent or queued to the session.
+fn deliver(
+    tracer: &mut Tracer,
+    session: &mut Session,
+    publication: &proto::Publication,
+    delivery: Result<Delivery, Error>,
+) -> Result<bool, Error> {
+    let client_id = session.client_id().clone();
+    let queue_full = TraceEvent::Dropped {
+        reason: DropReason::QueueFull,
+    };
+
+    match delivery {
+        Ok(Delivery::Send(event)) => {
+            tracer.sent(&client_id, &event);
+            session.send(event)?;
+        }
+        Ok(Delivery::Queued(dropped)) => {
+            tracer.publication(TraceEvent::Queued, &client_id, publication);
+            for dropped in &dropped {
+                tracer.publication(queue_full, &client_id, dropped);
+            }
+        }
+        Ok(Delivery::Dropped(reason)) => {
+            tracer.publication(TraceEvent::Dropped { reason }, &client_id, publication);
+            return Ok(false);
+        }
+        Ok(Delivery::Skipped) => return Ok(false),
+        Err(Error::SessionQueueFull) => {
+            tracer.publication(queue_full, &client_id, publication);
+            return Err(Error::SessionQueueFull);
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(true)
+}
+
+/// A session outlives its connection if the client asks to resume it,
+/// or if a MQTT 5.0 client asks for a non-zero session expiry interval.
+fn is_persistent(connect: &proto::Connect) -> bool {
+    match connect.client_id {
+        proto::ClientId::IdWithExistingSession(_) => true,
+        _ => {
+            connect.protocol_level == mqtt3::PROTOCOL_LEVEL_V5
+                && connect.properties.session_expiry_interval.unwrap_or(0) > 0
+        }
+    }
+}
+
+/// Properties the broker announces to a MQTT 5.0 client in CONNACK.
+///
+/// They are not encoded for clients of earlier protocol levels.
+fn connack_properties(connreq: &ConnReq) -> proto::Properties {
+    let assigned_client_identifier = match connreq.connect().client_id {
+        proto::ClientId::ServerGenerated => Some(connreq.client_id().to_string()),
+        _ => None,
+    };
+
+    proto::Properties {
+        assigned_client_identifier,
+        topic_alias_maximum: Some(TOPIC_ALIAS_MAXIMUM),
+        shared_subscription_available: Some(1),
+        subscription_identifier_available: Some(0),
+        ..proto::Properties::default()
+    }
+}
+
+/// Acknowledgement sent to a MQTT 5.0 client which is not allowed to publish.
+/// A QoS 0 publication has no acknowledgement.
+pub(crate) fn not_authorized_ack(
+    packet_identifier_dup_qos: proto::PacketIdentifierDupQoS,
+    reason: String,
+) -> Option<ClientEvent> {
+    let properties = proto::Properties {
+        reason_string: Some(reason),
+        ..proto::Properties::default()
+    };
+
+    match packet_identifier_dup_qos {
+        proto::PacketIdentifierDupQoS::AtMostOnce => None,
+        proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, _) => {
+            Some(ClientEvent::PubAck(proto::PubAck {
+                packet_identifier,
+                reason_code: proto::ReasonCode::NOT_AUTHORIZED,
+                properties,
+            }))
+        }
+        proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, _) => {
+            Some(ClientEvent::PubRec(proto::PubRec {
+                packet_identifier,
+                reason_code: proto::ReasonCode::NOT_AUTHORIZED,
+                properties,
+            }))
+        }
+    }
+}
+
+/// State label and number of queued publications the session metrics track a session by.
+fn session_metrics(session: Option<&Session>) -> (Option<&'static str>, usize) {
+    let state = match session {
+        Some(Session::Transient(_)) | Some(Session::Persistent(_)) => Some("connected"),
+        Some(Session::Offline(_)) => Some("offline"),
+        Some(Session::Disconnecting(_)) | None => None,
+    };
+    let queued = session.and_then(Session::queued_count).unwrap_or_default();
+    (state, queued)
+}
+
+/// Topics starting with `$` are maintained by the broker itself and
+/// are not subject to the retained messages limits.
+fn is_system_topic(topic: &str) -> bool {
+    topic.starts_with('$')
+}
+
+/// A retained publication along with the time it was stored by the broker.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct RetainedPublication {
+    publication: proto::Publication,
+    stored_at: SystemTime,
+}
+
+impl RetainedPublication {
+    pub fn new(publication: proto::Publication) -> Self {
+        Self::from_parts(publication, SystemTime::now())
+    }
+
+    pub fn from_parts(publication: proto::Publication, stored_at: SystemTime) -> Self {
+        Self {
+            publication,
+            stored_at,
+        }
+    }
+
+    pub fn publication(&self) -> &proto::Publication {
+        &self.publication
+    }
+
+    pub fn stored_at(&self) -> SystemTime {
+        self.stored_at
+    }
+
+    pub fn into_parts(self) -> (proto::Publication, SystemTime) {
+        (self.publication, self.stored_at)
+    }
+
+    /// Returns `true` if the publication was stored more than `expiration` ago.
+    pub fn is_expired(&self, expiration: Duration, now: SystemTime) -> bool {
+        now.duration_since(self.stored_at)
+            .map_or(false, |age| age >= expiration)
+    }
+
+    /// Returns `true` if the message expiry interval set by the publisher has elapsed.
+    pub fn is_message_expired(&self, now: SystemTime) -> bool {
+        match self.publication.properties.message_expiry_interval {
+            Some(interval) => self.is_expired(Duration::from_secs(u64::from(interval)), now),
+            None => false,
+        }
+    }
+
+    /// Returns the publication with the message expiry interval reduced by
+    /// the time it has been retained.
+    ///
+    /// [MQTT5-3.3.2-6] The PUBLISH packet sent to a Client by the Server MUST contain
+    /// a Message Expiry Interval set to the received value minus the time that
+    /// the Application Message has been waiting in the Server.
+    pub fn publication_at(&self, now: SystemTime) -> proto::Publication {
+        let mut publication = self.publication.clone();
+        if let Some(interval) = publication.properties.message_expiry_interval {
+            let waited = now
+                .duration_since(self.stored_at)
+                .map_or(0, |waited| waited.as_secs());
+            let waited = u32::try_from(waited).unwrap_or(u32::max_value());
+            publication.properties.message_expiry_interval = Some(interval.saturating_sub(waited));
+        }
+        publication
+    }
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
 pub struct BrokerState {
-    retained: HashMap<String, proto::Publication>,
+    retained: HashMap<String, RetainedPublication>,
     sessions: Vec<SessionState>,
 }
 
 impl BrokerState {
-    pub fn new(retained: HashMap<String, proto::Publication>, sessions: Vec<SessionState>) -> Self {
+    pub fn new(
+        retained: HashMap<String, RetainedPublication>,
+        sessions: Vec<SessionState>,
+    ) -> Self {
         Self { retained, sessions }
     }
 
-    pub fn into_parts(self) -> (HashMap<String, proto::Publication>, Vec<SessionState>) {
+    pub fn into_parts(self) -> (HashMap<String, RetainedPublication>, Vec<SessionState>) {
         (self.retained, self.sessions)
     }
 }
 
+/// Changes made to the persisted state, which the broker sends to the snapshotter
+/// after every message when `persistence` is configured to journal them.
+#[derive(Debug, Default)]
+struct Journal {
+    changes: Vec<Change>,
+
+    // sessions the journal has a `SessionStored` change for
+    sessions: HashSet<ClientId>,
+}
+
 pub struct BrokerBuilder<N, Z> {
     state: Option<BrokerState>,
     authenticator: N,
     authorizer: Z,
+    config: BrokerConfig,
+    snapshot_handle: Option<StateSnapshotHandle>,
 }
 
 impl Default for BrokerBuilder<DefaultAuthenticator, DefaultAuthorizer> {
@@ -979,6 +2146,8 @@ impl Default for BrokerBuilder<DefaultAuthenticator, DefaultAuthorizer> {
             state: None,
             authenticator: DefaultAuthenticator,
             authorizer: DefaultAuthorizer,
+            config: BrokerConfig::default(),
+            snapshot_handle: None,
         }
     }
 }
@@ -996,6 +2165,8 @@ where
             state: self.state,
             authenticator,
             authorizer: self.authorizer,
+            config: self.config,
+            snapshot_handle: self.snapshot_handle,
         }
     }
 
@@ -1007,6 +2178,8 @@ where
             state: self.state,
             authenticator: self.authenticator,
             authorizer,
+            config: self.config,
+            snapshot_handle: self.snapshot_handle,
         }
     }
 
@@ -1015,28 +2188,80 @@ where
         self
     }
 
+    pub fn with_config(mut self, config: BrokerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Sets the snapshotter used to persist the state once the number of
+    /// unsaved publications configured in `persistence` is reached.
+    pub fn with_snapshot_handle(mut self, snapshot_handle: StateSnapshotHandle) -> Self {
+        self.snapshot_handle = Some(snapshot_handle);
+        self
+    }
+
     pub fn build(self) -> Broker<N, Z> {
+        let session_config = SessionConfig::from(&self.config);
+
+        // the loaded state is what the journal is replayed into, so it starts from there
+        let journaled = self.snapshot_handle.is_some()
+            && self
+                .config
+                .persistence()
+                .map_or(false, SessionPersistence::journal);
+        let mut journal = if journaled {
+            Some(Journal::default())
+        } else {
+            None
+        };
+
         let (retained, sessions) = match self.state {
             Some(state) => {
                 let sessions = state
                     .sessions
                     .into_iter()
-                    .map(|s| (s.client_id().clone(), Session::new_offline(s)))
+                    .map(|s| {
+                        let mut s = s.with_config(session_config.clone());
+                        if let Some(journal) = &mut journal {
+                            s.start_journal();
+                            journal.sessions.insert(s.client_id().clone());
+                        }
+                        (s.client_id().clone(), Session::new_offline(s))
+                    })
                     .collect::<HashMap<ClientId, Session>>();
                 (state.retained, sessions)
             }
             None => (HashMap::default(), HashMap::default()),
         };
 
+        let mut subscribers = SubscriptionTrie::default();
+        for (client_id, session) in &sessions {
+            if let Some(subscriptions) = session.subscriptions() {
+                subscribers.update(client_id, subscriptions.values().map(Subscription::filter));
+            }
+        }
+
         let (sender, messages) = mpsc::channel(1024);
+        let tracer = Tracer::new(self.config.trace(), &sender);
 
         Broker {
             sender,
             messages,
             sessions,
             retained,
-            authenticator: self.authenticator,
+            authenticator: Arc::new(self.authenticator),
             authorizer: self.authorizer,
+            config: self.config,
+            snapshot_handle: self.snapshot_handle,
+            unsaved_publications: 0,
+            shared_positions: HashMap::new(),
+            subscribers,
+            stats: BrokerStats::default(),
+            rate_limiters: HashMap::new(),
+            client_rate_limiters: HashMap::new(),
+            tracer,
+            session_gauges: HashMap::new(),
+            journal,
 
             #[cfg(feature = "__internal_broker_callbacks")]
             on_publish: None,
@@ -1066,23 +2291,39 @@ pub struct NoSessionError;
 
 #[cfg(test)]
 pub(crate) mod tests {
-    use std::time::Duration;
+    use std::collections::{HashMap, VecDeque};
+    use std::fs;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::{Duration, Instant, SystemTime};
 
+    use async_trait::async_trait;
     use bytes::Bytes;
     use futures_util::future::FutureExt;
     use matches::assert_matches;
     use tokio::sync::mpsc::{self, error::TryRecvError, UnboundedReceiver};
+    use tokio::sync::oneshot;
     use uuid::Uuid;
 
     use mqtt3::{proto, PROTOCOL_LEVEL, PROTOCOL_NAME};
 
     use super::OpenSession;
     use crate::{
-        auth::{Activity, AuthenticateError, AuthorizeError, Operation},
-        broker::{BrokerBuilder, BrokerHandle},
+        auth::{
+            AclAuthorizer, Activity, AuthenticateError, Authenticator, AuthorizeError, Authorizer,
+            Credentials, Operation, PeerCredentials,
+        },
+        broker::{Broker, BrokerBuilder, BrokerHandle, BrokerState, RetainedPublication},
+        configuration::BrokerConfig,
         error::Error,
-        session::Session,
-        AuthId, ClientEvent, ClientId, ConnReq, ConnectionHandle, Message, Publish,
+        persist::{Change, Persist, PersistError},
+        rate_limit::Throttle,
+        session::{Session, SessionState},
+        snapshot::Snapshotter,
+        subscription::{Subscription, TopicFilter},
+        trace::{tests::RecordingSink, Tracer},
+        AdminError, AdminRequest, AuthId, ClientEvent, ClientId, ConnReq, ConnectionHandle,
+        Message, Publish, SessionStatus, SystemEvent,
     };
 
     pub fn connection_handle() -> ConnectionHandle {
@@ -1100,6 +2341,7 @@ pub(crate) mod tests {
             keep_alive: Duration::default(),
             protocol_name: PROTOCOL_NAME.to_string(),
             protocol_level: PROTOCOL_LEVEL,
+            properties: proto::Properties::default(),
         }
     }
 
@@ -1112,43 +2354,568 @@ pub(crate) mod tests {
             keep_alive: Duration::default(),
             protocol_name: PROTOCOL_NAME.to_string(),
             protocol_level: PROTOCOL_LEVEL,
+            properties: proto::Properties::default(),
         }
     }
 
-    #[tokio::test]
-    #[should_panic]
-    async fn test_double_connect_protocol_violation() {
-        let broker = BrokerBuilder::default()
+    fn retained_publication(topic: &str) -> proto::Publication {
+        proto::Publication {
+            topic_name: topic.to_string(),
+            qos: proto::QoS::AtLeastOnce,
+            retain: true,
+            payload: Bytes::from("payload"),
+            properties: proto::Properties::default(),
+        }
+    }
+
+    #[test]
+    fn test_retained_limit_evicts_oldest() {
+        let config =
+            BrokerConfig::from_file(Path::new("test/config_retained_limits.json")).unwrap();
+        let mut broker = BrokerBuilder::default()
+            .with_config(config)
             .authenticator(|_| Ok(Some(AuthId::Anonymous)))
             .authorizer(|_| Ok(true))
             .build();
 
-        let mut broker_handle = broker.handle();
-        tokio::spawn(broker.run().map(drop));
+        broker.publish_all(retained_publication("topic/1")).unwrap();
+        broker.publish_all(retained_publication("topic/2")).unwrap();
+        broker
+            .publish_all(retained_publication("$edgehub/connected"))
+            .unwrap();
 
-        let connect1 = proto::Connect {
-            username: None,
-            password: None,
-            will: None,
-            client_id: proto::ClientId::IdWithCleanSession("blah".to_string()),
-            keep_alive: Duration::default(),
-            protocol_name: PROTOCOL_NAME.to_string(),
-            protocol_level: PROTOCOL_LEVEL,
-        };
-        let connect2 = proto::Connect {
-            username: None,
-            password: None,
-            will: None,
-            client_id: proto::ClientId::IdWithCleanSession("blah".to_string()),
-            keep_alive: Duration::default(),
-            protocol_name: PROTOCOL_NAME.to_string(),
-            protocol_level: PROTOCOL_LEVEL,
-        };
-        let id = Uuid::new_v4();
-        let (tx1, mut rx1) = mpsc::unbounded_channel();
-        let conn1 = ConnectionHandle::new(id, tx1.clone());
-        let conn2 = ConnectionHandle::new(id, tx1);
-        let client_id = ClientId::from("blah".to_string());
+        // replacing an existing topic does not count against the limit
+        broker.publish_all(retained_publication("topic/2")).unwrap();
+        assert_eq!(3, broker.retained.len());
+        assert!(broker.retained.contains_key("topic/1"));
+
+        broker.publish_all(retained_publication("topic/3")).unwrap();
+        assert_eq!(3, broker.retained.len());
+        assert!(!broker.retained.contains_key("topic/1"));
+        assert!(broker.retained.contains_key("topic/2"));
+        assert!(broker.retained.contains_key("topic/3"));
+        assert!(broker.retained.contains_key("$edgehub/connected"));
+    }
+
+    #[test]
+    fn test_retained_limit_ignores_new() {
+        let mut broker = BrokerBuilder::default()
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(|_| Ok(true))
+            .build();
+
+        let max_count = broker.config.retained_messages().max_count();
+        for i in 0..max_count {
+            let topic = format!("topic/{}", i);
+            broker.publish_all(retained_publication(&topic)).unwrap();
+        }
+
+        broker
+            .publish_all(retained_publication("topic/new"))
+            .unwrap();
+        assert_eq!(max_count as usize, broker.retained.len());
+        assert!(!broker.retained.contains_key("topic/new"));
+    }
+
+    #[test]
+    fn test_cleanup_removes_expired_retained() {
+        let stored_at = SystemTime::now() - Duration::from_secs(2 * 60 * 60);
+
+        let mut retained = HashMap::new();
+        for topic in &["topic/expired", "$edgehub/connected"] {
+            let publication = retained_publication(topic);
+            let publication = RetainedPublication::from_parts(publication, stored_at);
+            retained.insert(topic.to_string(), publication);
+        }
+        let state = BrokerState::new(retained, vec![]);
+
+        let config =
+            BrokerConfig::from_file(Path::new("test/config_retained_limits.json")).unwrap();
+        let mut broker = BrokerBuilder::default()
+            .state(state)
+            .with_config(config)
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(|_| Ok(true))
+            .build();
+
+        broker
+            .publish_all(retained_publication("topic/fresh"))
+            .unwrap();
+        broker.process_cleanup().unwrap();
+
+        assert_eq!(2, broker.retained.len());
+        assert!(broker.retained.contains_key("topic/fresh"));
+        assert!(broker.retained.contains_key("$edgehub/connected"));
+    }
+
+    #[test]
+    fn test_publish_stats_retains_sys_topics() {
+        let active = SessionState::from_parts("active".into(), HashMap::new(), VecDeque::new());
+        let state = BrokerState::new(HashMap::new(), vec![active]);
+
+        let mut broker = BrokerBuilder::default()
+            .state(state)
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(|_| Ok(true))
+            .build();
+
+        broker.publish_all(retained_publication("topic/1")).unwrap();
+        for _ in 0..2 {
+            broker.process_publish_stats().unwrap();
+
+            let stat = |topic: &str| broker.retained[topic].publication().payload.clone();
+            assert_eq!(stat("$SYS/broker/clients/connected"), "0");
+            assert_eq!(stat("$SYS/broker/clients/disconnected"), "1");
+            assert_eq!(stat("$SYS/broker/retained messages/count"), "1");
+            assert!(!broker
+                .retained
+                .contains_key("$SYS/broker/clients/active/queued"));
+        }
+    }
+
+    #[test]
+    fn test_session_gauges_follow_queued_publications() {
+        let filter = "topic/+".parse::<TopicFilter>().unwrap();
+        let mut subscriptions = HashMap::new();
+        subscriptions.insert(
+            "topic/+".to_string(),
+            Subscription::new(filter, proto::QoS::AtLeastOnce),
+        );
+        let offline = SessionState::from_parts("offline".into(), subscriptions, VecDeque::new());
+        let state = BrokerState::new(HashMap::new(), vec![offline]);
+
+        let mut broker = BrokerBuilder::default()
+            .state(state)
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(|_| Ok(true))
+            .build();
+
+        broker.publish_all(retained_publication("topic/1")).unwrap();
+        broker.publish_all(retained_publication("topic/2")).unwrap();
+
+        let client_id = ClientId::from("offline");
+        assert_eq!(
+            broker.session_gauges.get(&client_id),
+            Some(&(Some("offline"), 2))
+        );
+
+        let (tx, _rx) = oneshot::channel();
+        broker
+            .process_admin_request(AdminRequest::PurgeSession(client_id.clone(), tx))
+            .unwrap();
+        assert_eq!(broker.session_gauges.get(&client_id), None);
+    }
+
+    #[test]
+    fn test_admin_purges_offline_session() {
+        let offline = SessionState::from_parts("offline".into(), HashMap::new(), VecDeque::new());
+        let state = BrokerState::new(HashMap::new(), vec![offline]);
+
+        let mut broker = BrokerBuilder::default()
+            .state(state)
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(|_| Ok(true))
+            .build();
+
+        let (tx, mut rx) = oneshot::channel();
+        broker
+            .process_admin_request(AdminRequest::ListSessions(tx))
+            .unwrap();
+        let sessions = rx.try_recv().unwrap();
+        assert_eq!(1, sessions.len());
+        assert_eq!(SessionStatus::Offline, sessions[0].status);
+
+        let (tx, mut rx) = oneshot::channel();
+        broker
+            .process_admin_request(AdminRequest::PurgeSession("offline".into(), tx))
+            .unwrap();
+        assert_matches!(rx.try_recv(), Ok(Ok(())));
+        assert!(broker.sessions.is_empty());
+
+        let (tx, mut rx) = oneshot::channel();
+        broker
+            .process_admin_request(AdminRequest::PurgeSession("offline".into(), tx))
+            .unwrap();
+        assert_matches!(rx.try_recv(), Ok(Err(AdminError::NoSession(_))));
+    }
+
+    #[test]
+    fn test_admin_sets_and_clears_retained() {
+        let mut broker = BrokerBuilder::default()
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(|_| Ok(true))
+            .build();
+
+        for topic in &["topic/1", "topic/2"] {
+            let (tx, mut rx) = oneshot::channel();
+            broker
+                .process_admin_request(AdminRequest::SetRetained(retained_publication(topic), tx))
+                .unwrap();
+            assert_matches!(rx.try_recv(), Ok(Ok(())));
+        }
+
+        for topic in &["", "topic/+", "topic/#", "$SYS/broker/uptime"] {
+            let (tx, mut rx) = oneshot::channel();
+            broker
+                .process_admin_request(AdminRequest::SetRetained(retained_publication(topic), tx))
+                .unwrap();
+            assert_matches!(rx.try_recv(), Ok(Err(AdminError::InvalidTopic(_))));
+        }
+        broker
+            .publish_all(retained_publication("$edgehub/connected"))
+            .unwrap();
+
+        let (tx, mut rx) = oneshot::channel();
+        broker
+            .process_admin_request(AdminRequest::ListRetained(tx))
+            .unwrap();
+        let retained = rx.try_recv().unwrap();
+        assert_eq!(
+            vec!["topic/1", "topic/2"],
+            retained
+                .iter()
+                .map(|r| r.topic.as_str())
+                .collect::<Vec<_>>()
+        );
+
+        let (tx, mut rx) = oneshot::channel();
+        broker
+            .process_admin_request(AdminRequest::ClearRetained(Some("topic/1".into()), tx))
+            .unwrap();
+        assert_matches!(rx.try_recv(), Ok(1));
+
+        let (tx, mut rx) = oneshot::channel();
+        broker
+            .process_admin_request(AdminRequest::ClearRetained(
+                Some("$edgehub/connected".into()),
+                tx,
+            ))
+            .unwrap();
+        assert_matches!(rx.try_recv(), Ok(0));
+        assert!(broker.retained.contains_key("$edgehub/connected"));
+
+        let (tx, mut rx) = oneshot::channel();
+        broker
+            .process_admin_request(AdminRequest::ClearRetained(None, tx))
+            .unwrap();
+        assert_matches!(rx.try_recv(), Ok(1));
+        assert_eq!(1, broker.retained.len());
+        assert!(broker.retained.contains_key("$edgehub/connected"));
+    }
+
+    #[test]
+    fn test_config_update_trims_retained() {
+        let now = SystemTime::now();
+
+        let mut retained = HashMap::new();
+        for (topic, age) in &[
+            ("topic/1", 10),
+            ("topic/2", 9),
+            ("topic/3", 8),
+            ("$edgehub/connected", 7),
+        ] {
+            let stored_at = now - Duration::from_secs(*age);
+            let publication = retained_publication(topic);
+            let publication = RetainedPublication::from_parts(publication, stored_at);
+            retained.insert(topic.to_string(), publication);
+        }
+        let state = BrokerState::new(retained, vec![]);
+
+        let mut broker = BrokerBuilder::default()
+            .state(state)
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(|_| Ok(true))
+            .build();
+
+        let config =
+            BrokerConfig::from_file(Path::new("test/config_retained_limits.json")).unwrap();
+        broker.process_config_update(config);
+
+        assert_eq!(3, broker.retained.len());
+        assert!(!broker.retained.contains_key("topic/1"));
+        assert!(broker.retained.contains_key("topic/2"));
+        assert!(broker.retained.contains_key("topic/3"));
+        assert!(broker.retained.contains_key("$edgehub/connected"));
+    }
+
+    #[test]
+    fn test_config_update_applies_authentication_settings() {
+        struct AnonymousAuthenticator(AtomicBool);
+
+        impl Authenticator for AnonymousAuthenticator {
+            type Error = AuthenticateError;
+
+            fn authenticate(&self, _: Credentials) -> Result<Option<AuthId>, Self::Error> {
+                Ok(Some(AuthId::Anonymous).filter(|_| self.0.load(Ordering::SeqCst)))
+            }
+
+            fn update_config(&self, config: &BrokerConfig) -> Result<(), Self::Error> {
+                let allowed = config.authentication().allow_anonymous();
+                self.0.store(allowed, Ordering::SeqCst);
+                Ok(())
+            }
+        }
+
+        let mut broker = BrokerBuilder::default()
+            .authenticator(AnonymousAuthenticator(AtomicBool::new(true)))
+            .authorizer(|_| Ok(true))
+            .build();
+
+        let config = BrokerConfig::from_file(Path::new("test/config_password.json")).unwrap();
+        broker.process_config_update(config);
+
+        let credentials = Credentials::Basic(None, None);
+        assert_matches!(broker.authenticator().authenticate(credentials), Ok(None));
+    }
+
+    #[test]
+    fn test_cleanup_removes_expired_offline_sessions() {
+        let expiration = BrokerConfig::default().session().expiration();
+        let last_active = SystemTime::now() - expiration - Duration::from_secs(1);
+
+        let expired = SessionState::from_parts("expired".into(), HashMap::new(), VecDeque::new())
+            .with_last_active(last_active);
+        let active = SessionState::from_parts("active".into(), HashMap::new(), VecDeque::new());
+        let state = BrokerState::new(HashMap::new(), vec![expired, active]);
+
+        let mut broker = BrokerBuilder::default()
+            .state(state)
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(|_| Ok(true))
+            .build();
+
+        broker.process_cleanup().unwrap();
+
+        assert_eq!(1, broker.sessions.len());
+        assert_matches!(
+            broker.sessions[&ClientId::from("active")],
+            Session::Offline(_)
+        );
+
+        let sessions = broker.retained["$edgehub/sessions"].publication();
+        assert_eq!(sessions.payload, Bytes::from("[\"active\"]"));
+        assert!(!broker
+            .retained
+            .contains_key("$edgehub/expired/subscriptions"));
+
+        let (_, sessions) = broker.snapshot().into_parts();
+        assert_eq!(1, sessions.len());
+        assert_eq!("active", sessions[0].client_id().as_str());
+    }
+
+    struct ChannelPersistor(mpsc::UnboundedSender<BrokerState>);
+
+    #[async_trait]
+    impl Persist for ChannelPersistor {
+        type Error = PersistError;
+
+        async fn load(&mut self) -> Result<Option<BrokerState>, Self::Error> {
+            Ok(None)
+        }
+
+        async fn store(&mut self, state: BrokerState) -> Result<(), Self::Error> {
+            self.0.send(state).expect("can't send a state");
+            Ok(())
+        }
+
+        async fn append(&mut self, _: Vec<Change>) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    struct JournalPersistor(mpsc::UnboundedSender<Vec<Change>>);
+
+    #[async_trait]
+    impl Persist for JournalPersistor {
+        type Error = PersistError;
+
+        async fn load(&mut self) -> Result<Option<BrokerState>, Self::Error> {
+            Ok(None)
+        }
+
+        async fn store(&mut self, _: BrokerState) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn append(&mut self, changes: Vec<Change>) -> Result<(), Self::Error> {
+            self.0.send(changes).expect("can't send changes");
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_after_unsaved_publications() {
+        let (tx, mut states) = mpsc::unbounded_channel();
+        let snapshotter = Snapshotter::new(ChannelPersistor(tx));
+        let snapshot_handle = snapshotter.snapshot_handle();
+        tokio::spawn(snapshotter.run());
+
+        let config = BrokerConfig::from_file(Path::new("test/config_persistence.json")).unwrap();
+        let broker = BrokerBuilder::default()
+            .with_config(config)
+            .with_snapshot_handle(snapshot_handle)
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(|_| Ok(true))
+            .build();
+
+        let mut broker_handle = broker.handle();
+        tokio::spawn(broker.run().map(drop));
+
+        let (client_id, _rx) = connect_client("pub", &mut broker_handle).await.unwrap();
+
+        for _ in 0..2 {
+            let publish = proto::Publish {
+                packet_identifier_dup_qos: proto::PacketIdentifierDupQoS::AtMostOnce,
+                retain: false,
+                topic_name: "/foo/bar".to_string(),
+                payload: Bytes::from("payload"),
+                properties: proto::Properties::default(),
+            };
+            let message = Message::Client(client_id.clone(), ClientEvent::PublishFrom(publish));
+            broker_handle.send(message).await.unwrap();
+        }
+
+        let state = states.recv().await.unwrap();
+        let (_, sessions) = state.into_parts();
+        assert_eq!(1, sessions.len());
+        assert_matches!(states.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[tokio::test]
+    async fn test_journal_replays_to_state() {
+        let (tx, mut journal) = mpsc::unbounded_channel();
+        let snapshotter = Snapshotter::new(JournalPersistor(tx));
+        let snapshot_handle = snapshotter.snapshot_handle();
+        let mut shutdown_handle = snapshotter.shutdown_handle();
+        let snapshotter = tokio::spawn(snapshotter.run());
+
+        let config = BrokerConfig::from_file(Path::new("test/config_journal.json")).unwrap();
+        let broker = BrokerBuilder::default()
+            .with_config(config)
+            .with_snapshot_handle(snapshot_handle)
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(|_| Ok(true))
+            .build();
+
+        let mut broker_handle = broker.handle();
+        let broker = tokio::spawn(broker.run());
+
+        let (sub_id, mut sub_rx) = connect_client("sub", &mut broker_handle).await.unwrap();
+        send_subscribe(&mut broker_handle, &mut sub_rx, sub_id, &["/foo/+"]).await;
+        send_unsubscribe(&mut broker_handle, &mut sub_rx, "sub".into(), &["/foo/+"]).await;
+        send_subscribe(&mut broker_handle, &mut sub_rx, "sub".into(), &["/foo/bar"]).await;
+
+        let (pub_id, mut pub_rx) = connect_client("pub", &mut broker_handle).await.unwrap();
+        for (id, retain) in &[(1, true), (2, false)] {
+            let publish = proto::Publish {
+                packet_identifier_dup_qos: proto::PacketIdentifierDupQoS::AtLeastOnce(
+                    proto::PacketIdentifier::new(*id).unwrap(),
+                    false,
+                ),
+                retain: *retain,
+                topic_name: "/foo/bar".to_string(),
+                payload: Bytes::from("payload"),
+                properties: proto::Properties::default(),
+            };
+            let message = Message::Client(pub_id.clone(), ClientEvent::PublishFrom(publish));
+            broker_handle.send(message).await.unwrap();
+            assert_matches!(
+                pub_rx.recv().await,
+                Some(Message::Client(_, ClientEvent::PubAck(_)))
+            );
+        }
+
+        // acknowledge only the first publication, leaving the second in flight
+        let packet_identifier = match sub_rx.recv().await {
+            Some(Message::Client(_, ClientEvent::PublishTo(Publish::QoS12(id, _)))) => id,
+            message => panic!("unexpected message {:?}", message),
+        };
+        let puback = proto::PubAck {
+            packet_identifier,
+            reason_code: proto::ReasonCode::SUCCESS,
+            properties: proto::Properties::default(),
+        };
+        let message = Message::Client("sub".into(), ClientEvent::PubAck(puback));
+        broker_handle.send(message).await.unwrap();
+        disconnect_client("pub", &mut broker_handle).await;
+
+        broker_handle
+            .send(Message::System(SystemEvent::Shutdown))
+            .await
+            .unwrap();
+        let state = broker.await.unwrap().unwrap();
+        shutdown_handle.shutdown().await.unwrap();
+        snapshotter.await.unwrap();
+
+        let mut retained = HashMap::new();
+        let mut sessions = HashMap::new();
+        while let Ok(changes) = journal.try_recv() {
+            for change in changes {
+                match change {
+                    Change::RetainedStored(topic, publication) => {
+                        retained.insert(topic, publication);
+                    }
+                    Change::RetainedRemoved(topic) => {
+                        retained.remove(&topic);
+                    }
+                    Change::SessionStored(session) => {
+                        sessions.insert(session.client_id().clone(), session);
+                    }
+                    Change::SessionRemoved(client_id) => {
+                        sessions.remove(&client_id);
+                    }
+                    Change::Session(client_id, change) => {
+                        sessions.get_mut(&client_id).unwrap().apply(change);
+                    }
+                }
+            }
+        }
+
+        let (expected_retained, expected_sessions) = state.into_parts();
+        assert_eq!(expected_retained, retained);
+        assert_eq!(2, expected_sessions.len());
+        for session in expected_sessions {
+            assert_eq!(Some(&session), sessions.get(session.client_id()));
+        }
+        assert_eq!(2, sessions.len());
+    }
+
+    #[tokio::test]
+    #[should_panic]
+    async fn test_double_connect_protocol_violation() {
+        let broker = BrokerBuilder::default()
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(|_| Ok(true))
+            .build();
+
+        let mut broker_handle = broker.handle();
+        tokio::spawn(broker.run().map(drop));
+
+        let connect1 = proto::Connect {
+            username: None,
+            password: None,
+            will: None,
+            client_id: proto::ClientId::IdWithCleanSession("blah".to_string()),
+            keep_alive: Duration::default(),
+            protocol_name: PROTOCOL_NAME.to_string(),
+            protocol_level: PROTOCOL_LEVEL,
+            properties: proto::Properties::default(),
+        };
+        let connect2 = proto::Connect {
+            username: None,
+            password: None,
+            will: None,
+            client_id: proto::ClientId::IdWithCleanSession("blah".to_string()),
+            keep_alive: Duration::default(),
+            protocol_name: PROTOCOL_NAME.to_string(),
+            protocol_level: PROTOCOL_LEVEL,
+            properties: proto::Properties::default(),
+        };
+        let id = Uuid::new_v4();
+        let (tx1, mut rx1) = mpsc::unbounded_channel();
+        let conn1 = ConnectionHandle::new(id, tx1.clone());
+        let conn2 = ConnectionHandle::new(id, tx1);
+        let client_id = ClientId::from("blah".to_string());
 
         let req1 = ConnReq::new(client_id.clone(), connect1, None, conn1);
         let req2 = ConnReq::new(client_id.clone(), connect2, None, conn2);
@@ -1197,6 +2964,7 @@ pub(crate) mod tests {
             keep_alive: Duration::default(),
             protocol_name: PROTOCOL_NAME.to_string(),
             protocol_level: PROTOCOL_LEVEL,
+            properties: proto::Properties::default(),
         };
         let connect2 = proto::Connect {
             username: None,
@@ -1206,6 +2974,7 @@ pub(crate) mod tests {
             keep_alive: Duration::default(),
             protocol_name: PROTOCOL_NAME.to_string(),
             protocol_level: PROTOCOL_LEVEL,
+            properties: proto::Properties::default(),
         };
         let (tx1, mut rx1) = mpsc::unbounded_channel();
         let (tx2, mut rx2) = mpsc::unbounded_channel();
@@ -1265,6 +3034,7 @@ pub(crate) mod tests {
             keep_alive: Duration::default(),
             protocol_name: "AMQP".to_string(),
             protocol_level: PROTOCOL_LEVEL,
+            properties: proto::Properties::default(),
         };
         let (tx1, mut rx1) = mpsc::unbounded_channel();
         let conn1 = ConnectionHandle::from_sender(tx1);
@@ -1304,7 +3074,61 @@ pub(crate) mod tests {
             keep_alive: Duration::default(),
             protocol_name: PROTOCOL_NAME.to_string(),
             protocol_level: 0x3,
+            properties: proto::Properties::default(),
+        };
+        let (tx1, mut rx1) = mpsc::unbounded_channel();
+        let conn1 = ConnectionHandle::from_sender(tx1);
+        let client_id = ClientId::from("blah".to_string());
+        let req1 = ConnReq::new(client_id.clone(), connect1, None, conn1);
+
+        broker_handle
+            .send(Message::Client(
+                client_id.clone(),
+                ClientEvent::ConnReq(req1),
+            ))
+            .await
+            .unwrap();
+
+        assert_matches!(
+            rx1.recv().await,
+            Some(Message::Client(
+                _,
+                ClientEvent::ConnAck(proto::ConnAck {
+                    return_code: proto::ConnectReturnCode::Refused(
+                        proto::ConnectionRefusedReason::UnacceptableProtocolVersion,
+                    ),
+                    ..
+                })
+            ))
+        );
+        assert_matches!(
+            rx1.recv().await,
+            Some(Message::Client(_, ClientEvent::DropConnection))
+        );
+        assert_matches!(rx1.recv().await, None)
+    }
+
+    #[tokio::test]
+    async fn test_connect_auth_succeeded() {
+        let broker = BrokerBuilder::default()
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(|_| Ok(true))
+            .build();
+
+        let mut broker_handle = broker.handle();
+        tokio::spawn(broker.run().map(drop));
+
+        let connect1 = proto::Connect {
+            username: None,
+            password: None,
+            will: None,
+            client_id: proto::ClientId::IdWithCleanSession("blah".to_string()),
+            keep_alive: Duration::default(),
+            protocol_name: PROTOCOL_NAME.to_string(),
+            protocol_level: PROTOCOL_LEVEL,
+            properties: proto::Properties::default(),
         };
+
         let (tx1, mut rx1) = mpsc::unbounded_channel();
         let conn1 = ConnectionHandle::from_sender(tx1);
         let client_id = ClientId::from("blah".to_string());
@@ -1320,23 +3144,246 @@ pub(crate) mod tests {
 
         assert_matches!(
             rx1.recv().await,
-            Some(Message::Client(_, ClientEvent::ConnAck(proto::ConnAck {
-                return_code:
-                    proto::ConnectReturnCode::Refused(
-                        proto::ConnectionRefusedReason::UnacceptableProtocolVersion,
-                    ),
-                ..
-            })))
-        );
-        assert_matches!(
-            rx1.recv().await,
-            Some(Message::Client(_, ClientEvent::DropConnection))
+            Some(Message::Client(
+                _,
+                ClientEvent::ConnAck(proto::ConnAck {
+                    return_code: proto::ConnectReturnCode::Accepted,
+                    ..
+                })
+            ))
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_survives_reconnect() {
+        let config = BrokerConfig::from_file(Path::new("test/config_rate_limits.json")).unwrap();
+        let mut broker = BrokerBuilder::default()
+            .with_config(config)
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(|_| Ok(true))
+            .build();
+
+        let publish = proto::Publish {
+            packet_identifier_dup_qos: proto::PacketIdentifierDupQoS::AtMostOnce,
+            retain: false,
+            topic_name: "topic".to_string(),
+            payload: Bytes::from("hello"),
+            properties: proto::Properties::default(),
+        };
+        let now = Instant::now();
+        let client_id = ClientId::from("device");
+
+        let first = broker
+            .connection_rate_limit(&AuthId::Anonymous, &client_id)
+            .unwrap();
+        while first.throttle(&publish, now) == Throttle::Allow {}
+        drop(first);
+
+        broker.process_cleanup().unwrap();
+
+        let second = broker
+            .connection_rate_limit(&AuthId::Anonymous, &client_id)
+            .unwrap();
+        assert_eq!(Throttle::Drop, second.throttle(&publish, now));
+    }
+
+    #[tokio::test]
+    async fn test_connect_sends_rate_limit_before_connack() {
+        let config = BrokerConfig::from_file(Path::new("test/config_rate_limits.json")).unwrap();
+        let broker = BrokerBuilder::default()
+            .with_config(config)
+            .authenticator(|_| Ok(Some(AuthId::from_identity("edgehub"))))
+            .authorizer(|_| Ok(true))
+            .build();
+
+        let mut broker_handle = broker.handle();
+        tokio::spawn(broker.run().map(drop));
+
+        let (tx1, mut rx1) = mpsc::unbounded_channel();
+        let conn1 = ConnectionHandle::from_sender(tx1);
+        let client_id = ClientId::from("blah".to_string());
+        let req1 = ConnReq::new(
+            client_id.clone(),
+            transient_connect("blah".to_string()),
+            None,
+            conn1,
+        );
+
+        broker_handle
+            .send(Message::Client(
+                client_id.clone(),
+                ClientEvent::ConnReq(req1),
+            ))
+            .await
+            .unwrap();
+
+        assert_matches!(
+            rx1.recv().await,
+            Some(Message::Client(_, ClientEvent::RateLimit(Some(_))))
+        );
+        assert_matches!(
+            rx1.recv().await,
+            Some(Message::Client(
+                _,
+                ClientEvent::ConnAck(proto::ConnAck {
+                    return_code: proto::ConnectReturnCode::Accepted,
+                    ..
+                })
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connect_auth_with_peer_credentials() {
+        let broker = BrokerBuilder::default()
+            .authenticator(|credentials| match credentials {
+                Credentials::PeerCredentials(peer) if peer.uid() == 1000 => {
+                    Ok(Some("local-module".into()))
+                }
+                _ => Ok(None),
+            })
+            .authorizer(|_| Ok(true))
+            .build();
+
+        let mut broker_handle = broker.handle();
+        tokio::spawn(broker.run().map(drop));
+
+        let connect1 = proto::Connect {
+            username: None,
+            password: None,
+            will: None,
+            client_id: proto::ClientId::IdWithCleanSession("blah".to_string()),
+            keep_alive: Duration::default(),
+            protocol_name: PROTOCOL_NAME.to_string(),
+            protocol_level: PROTOCOL_LEVEL,
+            properties: proto::Properties::default(),
+        };
+
+        let (tx1, mut rx1) = mpsc::unbounded_channel();
+        let conn1 = ConnectionHandle::from_sender(tx1);
+        let client_id = ClientId::from("blah".to_string());
+        let req1 = ConnReq::new(client_id.clone(), connect1, None, conn1)
+            .with_peer_credentials(Some(PeerCredentials::new(1000, 1000, Some(42))));
+
+        broker_handle
+            .send(Message::Client(
+                client_id.clone(),
+                ClientEvent::ConnReq(req1),
+            ))
+            .await
+            .unwrap();
+
+        assert_matches!(
+            rx1.recv().await,
+            Some(Message::Client(
+                _,
+                ClientEvent::ConnAck(proto::ConnAck {
+                    return_code: proto::ConnectReturnCode::Accepted,
+                    ..
+                })
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connect_auth_prefers_username_over_peer_credentials() {
+        let broker = BrokerBuilder::default()
+            .authenticator(|credentials| match credentials {
+                Credentials::Basic(Some(username), Some(password))
+                    if username == "device-1" && password == "secret" =>
+                {
+                    Ok(Some("device-1".into()))
+                }
+                _ => Ok(None),
+            })
+            .authorizer(|_| Ok(true))
+            .build();
+
+        let mut broker_handle = broker.handle();
+        tokio::spawn(broker.run().map(drop));
+
+        let connect1 = proto::Connect {
+            username: Some("device-1".to_string()),
+            password: Some("secret".to_string()),
+            will: None,
+            client_id: proto::ClientId::IdWithCleanSession("blah".to_string()),
+            keep_alive: Duration::default(),
+            protocol_name: PROTOCOL_NAME.to_string(),
+            protocol_level: PROTOCOL_LEVEL,
+            properties: proto::Properties::default(),
+        };
+
+        let (tx1, mut rx1) = mpsc::unbounded_channel();
+        let conn1 = ConnectionHandle::from_sender(tx1);
+        let client_id = ClientId::from("blah".to_string());
+        let req1 = ConnReq::new(client_id.clone(), connect1, None, conn1)
+            .with_peer_credentials(Some(PeerCredentials::new(1000, 1000, Some(42))));
+
+        broker_handle
+            .send(Message::Client(
+                client_id.clone(),
+                ClientEvent::ConnReq(req1),
+            ))
+            .await
+            .unwrap();
+
+        assert_matches!(
+            rx1.recv().await,
+            Some(Message::Client(
+                _,
+                ClientEvent::ConnAck(proto::ConnAck {
+                    return_code: proto::ConnectReturnCode::Accepted,
+                    ..
+                })
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_auth_id_skips_authentication() {
+        let broker = BrokerBuilder::default()
+            .authenticator(|_| Ok(None))
+            .authorizer(|activity| {
+                Ok(activity.auth_id() == &AuthId::from_identity("$bridge/parent"))
+            })
+            .build();
+
+        let mut broker_handle = broker.handle();
+        tokio::spawn(broker.run().map(drop));
+
+        let (tx1, mut rx1) = mpsc::unbounded_channel();
+        let conn1 = ConnectionHandle::from_sender(tx1);
+        let client_id = ClientId::from("$bridge/parent".to_string());
+        let req1 = ConnReq::new(
+            client_id.clone(),
+            persistent_connect("$bridge/parent".to_string()),
+            None,
+            conn1,
+        )
+        .with_auth_id(AuthId::from_identity("$bridge/parent"));
+
+        broker_handle
+            .send(Message::Client(
+                client_id.clone(),
+                ClientEvent::ConnReq(req1),
+            ))
+            .await
+            .unwrap();
+
+        assert_matches!(
+            rx1.recv().await,
+            Some(Message::Client(
+                _,
+                ClientEvent::ConnAck(proto::ConnAck {
+                    return_code: proto::ConnectReturnCode::Accepted,
+                    ..
+                })
+            ))
         );
-        assert_matches!(rx1.recv().await, None)
     }
 
     #[tokio::test]
-    async fn test_connect_auth_succeeded() {
+    async fn test_connect_with_auth_failure_refused_after_protocol_checks() {
         let broker = BrokerBuilder::default()
             .authenticator(|_| Ok(Some(AuthId::Anonymous)))
             .authorizer(|_| Ok(true))
@@ -1345,20 +3392,13 @@ pub(crate) mod tests {
         let mut broker_handle = broker.handle();
         tokio::spawn(broker.run().map(drop));
 
-        let connect1 = proto::Connect {
-            username: None,
-            password: None,
-            will: None,
-            client_id: proto::ClientId::IdWithCleanSession("blah".to_string()),
-            keep_alive: Duration::default(),
-            protocol_name: PROTOCOL_NAME.to_string(),
-            protocol_level: PROTOCOL_LEVEL,
-        };
-
         let (tx1, mut rx1) = mpsc::unbounded_channel();
         let conn1 = ConnectionHandle::from_sender(tx1);
         let client_id = ClientId::from("blah".to_string());
-        let req1 = ConnReq::new(client_id.clone(), connect1, None, conn1);
+        let mut connect1 = transient_connect("blah".to_string());
+        connect1.protocol_level = 0x3;
+        let req1 = ConnReq::new(client_id.clone(), connect1, None, conn1)
+            .with_auth_failure(proto::ConnectionRefusedReason::BadUserNameOrPassword);
 
         broker_handle
             .send(Message::Client(
@@ -1370,11 +3410,43 @@ pub(crate) mod tests {
 
         assert_matches!(
             rx1.recv().await,
-            Some(Message::Client(_, ClientEvent::ConnAck(proto::ConnAck {
-                return_code:
-                    proto::ConnectReturnCode::Accepted,
-                ..
-            })))
+            Some(Message::Client(
+                _,
+                ClientEvent::ConnAck(proto::ConnAck {
+                    return_code: proto::ConnectReturnCode::Refused(
+                        proto::ConnectionRefusedReason::UnacceptableProtocolVersion
+                    ),
+                    ..
+                })
+            ))
+        );
+
+        let (tx2, mut rx2) = mpsc::unbounded_channel();
+        let conn2 = ConnectionHandle::from_sender(tx2);
+        let req2 = ConnReq::new(
+            client_id.clone(),
+            transient_connect("blah".to_string()),
+            None,
+            conn2,
+        )
+        .with_auth_failure(proto::ConnectionRefusedReason::BadUserNameOrPassword);
+
+        broker_handle
+            .send(Message::Client(client_id, ClientEvent::ConnReq(req2)))
+            .await
+            .unwrap();
+
+        assert_matches!(
+            rx2.recv().await,
+            Some(Message::Client(
+                _,
+                ClientEvent::ConnAck(proto::ConnAck {
+                    return_code: proto::ConnectReturnCode::Refused(
+                        proto::ConnectionRefusedReason::BadUserNameOrPassword
+                    ),
+                    ..
+                })
+            ))
         );
     }
 
@@ -1396,6 +3468,7 @@ pub(crate) mod tests {
             keep_alive: Duration::default(),
             protocol_name: PROTOCOL_NAME.to_string(),
             protocol_level: PROTOCOL_LEVEL,
+            properties: proto::Properties::default(),
         };
 
         let (tx1, mut rx1) = mpsc::unbounded_channel();
@@ -1413,13 +3486,15 @@ pub(crate) mod tests {
 
         assert_matches!(
             rx1.recv().await,
-            Some(Message::Client(_, ClientEvent::ConnAck(proto::ConnAck {
-                return_code:
-                    proto::ConnectReturnCode::Refused(
+            Some(Message::Client(
+                _,
+                ClientEvent::ConnAck(proto::ConnAck {
+                    return_code: proto::ConnectReturnCode::Refused(
                         proto::ConnectionRefusedReason::BadUserNameOrPassword,
                     ),
-                ..
-            })))
+                    ..
+                })
+            ))
         );
         assert_matches!(
             rx1.recv().await,
@@ -1446,6 +3521,7 @@ pub(crate) mod tests {
             keep_alive: Duration::default(),
             protocol_name: PROTOCOL_NAME.to_string(),
             protocol_level: PROTOCOL_LEVEL,
+            properties: proto::Properties::default(),
         };
 
         let (tx1, mut rx1) = mpsc::unbounded_channel();
@@ -1463,13 +3539,15 @@ pub(crate) mod tests {
 
         assert_matches!(
             rx1.recv().await,
-            Some(Message::Client(_, ClientEvent::ConnAck(proto::ConnAck {
-                return_code:
-                    proto::ConnectReturnCode::Refused(
+            Some(Message::Client(
+                _,
+                ClientEvent::ConnAck(proto::ConnAck {
+                    return_code: proto::ConnectReturnCode::Refused(
                         proto::ConnectionRefusedReason::ServerUnavailable,
                     ),
-                ..
-            })))
+                    ..
+                })
+            ))
         );
         assert_matches!(
             rx1.recv().await,
@@ -1496,6 +3574,7 @@ pub(crate) mod tests {
             keep_alive: Duration::default(),
             protocol_name: PROTOCOL_NAME.to_string(),
             protocol_level: PROTOCOL_LEVEL,
+            properties: proto::Properties::default(),
         };
 
         let (tx1, mut rx1) = mpsc::unbounded_channel();
@@ -1513,13 +3592,15 @@ pub(crate) mod tests {
 
         assert_matches!(
             rx1.recv().await,
-            Some(Message::Client(_, ClientEvent::ConnAck(proto::ConnAck {
-                return_code:
-                    proto::ConnectReturnCode::Refused(
+            Some(Message::Client(
+                _,
+                ClientEvent::ConnAck(proto::ConnAck {
+                    return_code: proto::ConnectReturnCode::Refused(
                         proto::ConnectionRefusedReason::NotAuthorized
                     ),
-                ..
-            })))
+                    ..
+                })
+            ))
         );
         assert_matches!(
             rx1.recv().await,
@@ -1546,6 +3627,7 @@ pub(crate) mod tests {
             keep_alive: Duration::default(),
             protocol_name: PROTOCOL_NAME.to_string(),
             protocol_level: PROTOCOL_LEVEL,
+            properties: proto::Properties::default(),
         };
 
         let (tx1, mut rx1) = mpsc::unbounded_channel();
@@ -1563,13 +3645,15 @@ pub(crate) mod tests {
 
         assert_matches!(
             rx1.recv().await.unwrap(),
-            Message::Client(_, ClientEvent::ConnAck(proto::ConnAck {
-                return_code:
-                    proto::ConnectReturnCode::Refused(
+            Message::Client(
+                _,
+                ClientEvent::ConnAck(proto::ConnAck {
+                    return_code: proto::ConnectReturnCode::Refused(
                         proto::ConnectionRefusedReason::ServerUnavailable,
                     ),
-                ..
-            }))
+                    ..
+                })
+            )
         );
         assert_matches!(
             rx1.recv().await,
@@ -1630,6 +3714,365 @@ pub(crate) mod tests {
         assert_matches!(broker.sessions[&client_id], Session::Offline(_));
     }
 
+    #[test]
+    fn test_add_session_v5_clean_with_expiry_interval_outlives_connection() {
+        let mut broker = BrokerBuilder::default()
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(|_| Ok(true))
+            .build();
+
+        let id = "id1".to_string();
+        let client_id = ClientId::from(id.clone());
+        let mut connect = transient_connect(id);
+        connect.protocol_level = mqtt3::PROTOCOL_LEVEL_V5;
+        connect.properties.session_expiry_interval = Some(60);
+        let handle = connection_handle();
+        let req = ConnReq::new(client_id.clone(), connect, None, handle);
+
+        broker.open_session(AuthId::Anonymous, req).unwrap();
+        assert_matches!(broker.sessions[&client_id], Session::Persistent(_));
+
+        let old_session = broker.close_session(&client_id);
+        assert_matches!(old_session, Ok(Some(Session::Disconnecting(_))));
+        assert_matches!(broker.sessions[&client_id], Session::Offline(_));
+    }
+
+    #[test]
+    fn test_offline_session_receives_with_identity_of_client() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("acl.json");
+        let acl = serde_json::json!({
+            "rules": [
+                {
+                    "effect": "allow",
+                    "identities": ["device-1"],
+                    "operations": ["subscribe", "receive"],
+                    "topics": ["devices/%u/#"]
+                }
+            ]
+        });
+        fs::write(&path, acl.to_string()).unwrap();
+
+        let mut broker = BrokerBuilder::default()
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(AclAuthorizer::from_file(&path).unwrap())
+            .build();
+
+        let id = "id1".to_string();
+        let client_id = ClientId::from(id.clone());
+        let connect = persistent_connect(id);
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let handle = ConnectionHandle::from_sender(tx);
+        let req = ConnReq::new(client_id.clone(), connect, None, handle);
+        broker
+            .open_session(AuthId::from_identity("device-1"), req)
+            .unwrap();
+
+        let subscribe = proto::Subscribe {
+            packet_identifier: proto::PacketIdentifier::new(1).unwrap(),
+            subscribe_to: vec![proto::SubscribeTo {
+                topic_filter: "devices/device-1/#".to_string(),
+                qos: proto::QoS::AtLeastOnce,
+            }],
+            properties: proto::Properties::default(),
+        };
+        broker.process_subscribe(&client_id, subscribe).unwrap();
+        broker.close_session(&client_id).unwrap();
+        assert_matches!(broker.sessions[&client_id], Session::Offline(_));
+
+        let publication = proto::Publication {
+            topic_name: "devices/device-1/commands".to_string(),
+            qos: proto::QoS::AtLeastOnce,
+            retain: false,
+            payload: Bytes::from("reboot"),
+            properties: proto::Properties::default(),
+        };
+        broker.publish_all(publication).unwrap();
+
+        assert_eq!(broker.sessions[&client_id].queued_count(), Some(1));
+    }
+
+    #[test]
+    fn test_add_session_v5_zero_expiry_interval_ends_with_connection() {
+        let mut broker = BrokerBuilder::default()
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(|_| Ok(true))
+            .build();
+
+        let id = "id1".to_string();
+        let client_id = ClientId::from(id.clone());
+        let mut connect = persistent_connect(id);
+        connect.protocol_level = mqtt3::PROTOCOL_LEVEL_V5;
+        let handle = connection_handle();
+        let req = ConnReq::new(client_id.clone(), connect, None, handle);
+
+        broker.open_session(AuthId::Anonymous, req).unwrap();
+        assert_matches!(broker.sessions[&client_id], Session::Persistent(_));
+
+        let old_session = broker.close_session(&client_id);
+        assert_matches!(old_session, Ok(Some(Session::Disconnecting(_))));
+        assert_eq!(0, broker.sessions.len());
+    }
+
+    #[test]
+    fn test_subscription_index_follows_session() {
+        let mut broker = BrokerBuilder::default()
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(|_| Ok(true))
+            .build();
+
+        let id = "id1".to_string();
+        let client_id = ClientId::from(id.clone());
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let handle = ConnectionHandle::from_sender(tx);
+        let req = ConnReq::new(client_id.clone(), transient_connect(id), None, handle);
+        broker.open_session(AuthId::Anonymous, req).unwrap();
+
+        let subscribe = proto::Subscribe {
+            packet_identifier: proto::PacketIdentifier::new(1).unwrap(),
+            subscribe_to: vec![proto::SubscribeTo {
+                topic_filter: "topic/+".to_string(),
+                qos: proto::QoS::AtLeastOnce,
+            }],
+            properties: proto::Properties::default(),
+        };
+        broker
+            .process_subscribe(&client_id, subscribe.clone())
+            .unwrap();
+        assert!(broker.subscribers.matches("topic/a").contains(&client_id));
+
+        let unsubscribe = proto::Unsubscribe {
+            packet_identifier: proto::PacketIdentifier::new(2).unwrap(),
+            unsubscribe_from: vec!["topic/+".to_string()],
+            properties: proto::Properties::default(),
+        };
+        broker
+            .process_unsubscribe(&client_id, &unsubscribe)
+            .unwrap();
+        assert!(broker.subscribers.matches("topic/a").is_empty());
+
+        broker.process_subscribe(&client_id, subscribe).unwrap();
+        broker.close_session(&client_id).unwrap();
+        assert!(broker.subscribers.matches("topic/a").is_empty());
+    }
+
+    #[test]
+    fn test_trace_follows_publication() {
+        let mut broker = BrokerBuilder::default()
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(|_| Ok(true))
+            .build();
+        let sink = RecordingSink::default();
+        broker.tracer = Tracer::with_sink(Box::new(sink.clone()), 1.0, &[]);
+
+        let mut receivers = vec![];
+        for id in &["sub", "pub"] {
+            let (tx, rx) = mpsc::unbounded_channel();
+            let handle = ConnectionHandle::from_sender(tx);
+            let connect = transient_connect((*id).to_string());
+            let req = ConnReq::new(ClientId::from(*id), connect, None, handle);
+            broker.open_session(AuthId::Anonymous, req).unwrap();
+            receivers.push(rx);
+        }
+        let sub_id = ClientId::from("sub");
+        let pub_id = ClientId::from("pub");
+
+        let subscribe = proto::Subscribe {
+            packet_identifier: proto::PacketIdentifier::new(1).unwrap(),
+            subscribe_to: vec![proto::SubscribeTo {
+                topic_filter: "topic/+".to_string(),
+                qos: proto::QoS::AtLeastOnce,
+            }],
+            properties: proto::Properties::default(),
+        };
+        broker.process_subscribe(&sub_id, subscribe).unwrap();
+
+        let publish = proto::Publish {
+            packet_identifier_dup_qos: proto::PacketIdentifierDupQoS::AtLeastOnce(
+                proto::PacketIdentifier::new(5).unwrap(),
+                false,
+            ),
+            retain: false,
+            topic_name: "topic/a".to_string(),
+            payload: Bytes::from("payload"),
+            properties: proto::Properties::default(),
+        };
+        broker.process_publish(&pub_id, publish).unwrap();
+
+        let packet_identifier = loop {
+            let message = receivers[0].try_recv().unwrap();
+            if let Message::Client(_, ClientEvent::PublishTo(Publish::QoS12(id, _))) = message {
+                break id;
+            }
+        };
+        let puback = proto::PubAck {
+            packet_identifier,
+            reason_code: proto::ReasonCode::SUCCESS,
+            properties: proto::Properties::default(),
+        };
+        broker.process_puback(&sub_id, &puback).unwrap();
+
+        let records = sink.0.lock().unwrap();
+        let events = records
+            .iter()
+            .map(|record| {
+                (
+                    record["event"].as_str().unwrap(),
+                    record["client_id"].as_str().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            vec![
+                ("received", "pub"),
+                ("authorized", "pub"),
+                ("sent", "sub"),
+                ("routed", "pub"),
+                ("acked", "sub"),
+            ]
+        );
+        assert_eq!(records[0]["packet_id"], 5);
+        assert_eq!(records[3]["sessions"], 1);
+        assert_eq!(records[4]["packet_id"], records[2]["packet_id"]);
+    }
+
+    /// Opens a session subscribed to `topic/+` in a share group and another one
+    /// subscribed to it directly, along with a publisher session.
+    fn open_shared_and_plain_subscribers<N, Z>(
+        broker: &mut Broker<N, Z>,
+    ) -> Vec<UnboundedReceiver<Message>>
+    where
+        N: Authenticator + Send + 'static,
+        Z: Authorizer + Send + 'static,
+    {
+        let mut receivers = vec![];
+        for (id, topic_filter) in &[
+            ("shared", Some("$share/group/topic/+")),
+            ("plain", Some("topic/+")),
+            ("pub", None),
+        ] {
+            let (tx, rx) = mpsc::unbounded_channel();
+            let handle = ConnectionHandle::from_sender(tx);
+            let connect = transient_connect((*id).to_string());
+            let req = ConnReq::new(ClientId::from(*id), connect, None, handle);
+            broker.open_session(AuthId::Anonymous, req).unwrap();
+            receivers.push(rx);
+
+            if let Some(topic_filter) = topic_filter {
+                let subscribe = proto::Subscribe {
+                    packet_identifier: proto::PacketIdentifier::new(1).unwrap(),
+                    subscribe_to: vec![proto::SubscribeTo {
+                        topic_filter: (*topic_filter).to_string(),
+                        qos: proto::QoS::AtLeastOnce,
+                    }],
+                    properties: proto::Properties::default(),
+                };
+                broker
+                    .process_subscribe(&ClientId::from(*id), subscribe)
+                    .unwrap();
+            }
+        }
+        receivers
+    }
+
+    fn publish(topic: &str) -> proto::Publish {
+        proto::Publish {
+            packet_identifier_dup_qos: proto::PacketIdentifierDupQoS::AtLeastOnce(
+                proto::PacketIdentifier::new(5).unwrap(),
+                false,
+            ),
+            retain: false,
+            topic_name: topic.to_string(),
+            payload: Bytes::from("payload"),
+            properties: proto::Properties::default(),
+        }
+    }
+
+    #[test]
+    fn test_stats_count_publications_sent_to_shared_and_plain_subscribers() {
+        let mut broker = BrokerBuilder::default()
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(|_| Ok(true))
+            .build();
+        let mut receivers = open_shared_and_plain_subscribers(&mut broker);
+
+        broker
+            .process_publish(&ClientId::from("pub"), publish("topic/a"))
+            .unwrap();
+
+        // the shared subscriber is not counted again as a plain one
+        assert_eq!(broker.stats.report().messages_sent, 2);
+        for rx in &mut receivers[..2] {
+            let mut published = 0;
+            while let Ok(message) = rx.try_recv() {
+                if let Message::Client(_, ClientEvent::PublishTo(_)) = message {
+                    published += 1;
+                }
+            }
+            assert_eq!(published, 1);
+        }
+    }
+
+    #[test]
+    fn test_stats_count_qos2_publications_once_released() {
+        let mut broker = BrokerBuilder::default()
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(|_| Ok(true))
+            .build();
+        let _receivers = open_shared_and_plain_subscribers(&mut broker);
+
+        let pub_id = ClientId::from("pub");
+        let packet_identifier = proto::PacketIdentifier::new(5).unwrap();
+        let publish = proto::Publish {
+            packet_identifier_dup_qos: proto::PacketIdentifierDupQoS::ExactlyOnce(
+                packet_identifier,
+                false,
+            ),
+            ..publish("topic/a")
+        };
+        broker.process_publish(&pub_id, publish).unwrap();
+
+        let report = broker.stats.report();
+        assert_eq!(report.messages_received, 0);
+        assert_eq!(report.messages_sent, 0);
+
+        let pubrel = proto::PubRel {
+            packet_identifier,
+            reason_code: proto::ReasonCode::SUCCESS,
+            properties: proto::Properties::default(),
+        };
+        broker.process_pubrel(&pub_id, &pubrel).unwrap();
+
+        let report = broker.stats.report();
+        assert_eq!(report.messages_received, 1);
+        assert_eq!(report.bytes_received, 7);
+        assert_eq!(report.messages_sent, 2);
+    }
+
+    #[test]
+    fn test_trace_counts_sessions_routed_to_shared_and_plain_subscribers() {
+        let mut broker = BrokerBuilder::default()
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(|_| Ok(true))
+            .build();
+        let sink = RecordingSink::default();
+        broker.tracer = Tracer::with_sink(Box::new(sink.clone()), 1.0, &[]);
+        let _receivers = open_shared_and_plain_subscribers(&mut broker);
+
+        broker
+            .process_publish(&ClientId::from("pub"), publish("topic/a"))
+            .unwrap();
+
+        let records = sink.0.lock().unwrap();
+        let routed = records
+            .iter()
+            .find(|record| record["event"] == "routed")
+            .unwrap();
+        assert_eq!(routed["client_id"], "pub");
+        assert_eq!(routed["sessions"], 2);
+    }
+
     #[test]
     #[should_panic]
     fn test_add_session_same_connection_transient() {
@@ -1885,6 +4328,7 @@ pub(crate) mod tests {
             retain: true,
             topic_name: "/foo/bar".to_string(),
             payload: Bytes::new(),
+            properties: proto::Properties::default(),
         };
 
         let message = Message::Client(client_id.clone(), ClientEvent::PublishFrom(publish));
@@ -1932,6 +4376,7 @@ pub(crate) mod tests {
                     qos: proto::QoS::ExactlyOnce,
                 },
             ],
+            properties: proto::Properties::default(),
         };
 
         let message = Message::Client(client_id.clone(), ClientEvent::Subscribe(subscribe));
@@ -1971,6 +4416,7 @@ pub(crate) mod tests {
                 topic_filter: "/foo/bar".to_string(),
                 qos: proto::QoS::AtLeastOnce,
             }],
+            properties: proto::Properties::default(),
         };
 
         let message = Message::Client(sub_id.clone(), ClientEvent::Subscribe(subscribe));
@@ -1986,6 +4432,7 @@ pub(crate) mod tests {
             retain: true,
             topic_name: "/foo/bar".to_string(),
             payload: Bytes::new(),
+            properties: proto::Properties::default(),
         };
 
         let message = Message::Client(pub_id.clone(), ClientEvent::PublishFrom(publish));
@@ -2003,6 +4450,83 @@ pub(crate) mod tests {
         assert_matches!(sub_rx.try_recv(), Err(TryRecvError::Empty))
     }
 
+    #[tokio::test]
+    async fn test_disconnect_discards_will() {
+        let mut sub_rx = disconnect_with_will(proto::ReasonCode::SUCCESS).await;
+
+        assert_matches!(sub_rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_with_will_message_publishes_will() {
+        let mut sub_rx =
+            disconnect_with_will(proto::ReasonCode::DISCONNECT_WITH_WILL_MESSAGE).await;
+
+        assert_matches!(
+            sub_rx.recv().await,
+            Some(Message::Client(
+                _,
+                ClientEvent::PublishTo(Publish::QoS12(_, proto::Publish { topic_name, .. }))
+            )) if topic_name == "will"
+        );
+    }
+
+    /// Connects a client with a will and disconnects it with the reason code.
+    /// Returns the receiver of a client subscribed to the will topic.
+    async fn disconnect_with_will(reason_code: proto::ReasonCode) -> UnboundedReceiver<Message> {
+        let broker = BrokerBuilder::default()
+            .authenticator(|_| Ok(Some(AuthId::Anonymous)))
+            .authorizer(|_| Ok(true))
+            .build();
+
+        let mut broker_handle = broker.handle();
+        tokio::spawn(broker.run().map(drop));
+
+        let (sub_id, mut sub_rx) = connect_client("sub", &mut broker_handle).await.unwrap();
+        send_subscribe(&mut broker_handle, &mut sub_rx, sub_id, &["will"]).await;
+
+        let mut connect = persistent_connect("pub".into());
+        connect.will = Some(proto::Publication {
+            topic_name: "will".to_string(),
+            qos: proto::QoS::AtLeastOnce,
+            retain: false,
+            payload: Bytes::from("gone"),
+            properties: proto::Properties::default(),
+        });
+
+        let (tx, mut pub_rx) = mpsc::unbounded_channel();
+        let pub_id = ClientId::from("pub");
+        let req = ConnReq::new(
+            pub_id.clone(),
+            connect,
+            None,
+            ConnectionHandle::from_sender(tx),
+        );
+        broker_handle
+            .send(Message::Client(pub_id.clone(), ClientEvent::ConnReq(req)))
+            .await
+            .unwrap();
+        assert_matches!(
+            pub_rx.recv().await,
+            Some(Message::Client(_, ClientEvent::ConnAck(_)))
+        );
+
+        let disconnect = proto::Disconnect {
+            reason_code,
+            properties: proto::Properties::default(),
+        };
+        broker_handle
+            .send(Message::Client(pub_id, ClientEvent::Disconnect(disconnect)))
+            .await
+            .unwrap();
+        assert_matches!(
+            pub_rx.recv().await,
+            Some(Message::Client(_, ClientEvent::Disconnect(_)))
+        );
+
+        sub_rx
+    }
+
     #[tokio::test]
     async fn test_notify_state_change_single_connection() {
         let broker = BrokerBuilder::default()
@@ -2038,6 +4562,7 @@ pub(crate) mod tests {
                     retain: true,
                     topic_name: "$edgehub/connected".to_owned(),
                     payload: "[\"client_a\"]".into(),
+                    properties: proto::Properties::default(),
                 }
             );
         } else {
@@ -2243,18 +4768,20 @@ pub(crate) mod tests {
 
         assert_matches!(
             rx.recv().await,
-            Some(Message::Client(_, ClientEvent::ConnAck(proto::ConnAck {
-                return_code:
-                    proto::ConnectReturnCode::Accepted,
-                ..
-            })))
+            Some(Message::Client(
+                _,
+                ClientEvent::ConnAck(proto::ConnAck {
+                    return_code: proto::ConnectReturnCode::Accepted,
+                    ..
+                })
+            ))
         );
 
         Ok((client_id, rx))
     }
 
     async fn disconnect_client(client_id: &str, broker_handle: &mut BrokerHandle) {
-        let event = ClientEvent::Disconnect(proto::Disconnect {});
+        let event = ClientEvent::Disconnect(proto::Disconnect::default());
 
         broker_handle
             .send(Message::Client(client_id.into(), event))
@@ -2277,6 +4804,7 @@ pub(crate) mod tests {
                     qos: proto::QoS::AtLeastOnce,
                 })
                 .collect(),
+            properties: proto::Properties::default(),
         };
 
         let message = Message::Client(client_id, ClientEvent::Subscribe(subscribe));
@@ -2297,6 +4825,7 @@ pub(crate) mod tests {
         let unsubscribe = proto::Unsubscribe {
             packet_identifier: proto::PacketIdentifier::new(1).unwrap(),
             unsubscribe_from: topics.iter().map(|t| (*t).to_owned()).collect(),
+            properties: proto::Properties::default(),
         };
 
         let message = Message::Client(client_id, ClientEvent::Unsubscribe(unsubscribe));
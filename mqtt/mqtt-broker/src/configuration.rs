@@ -1,4 +1,5 @@
 use std::convert::From;
+use std::net::SocketAddr;
 use std::ops::Mul;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
@@ -6,12 +7,15 @@ use std::time::Duration;
 
 use config::{Config, ConfigError, File, FileFormat};
 use lazy_static::lazy_static;
+use mqtt3::proto;
 use regex::Regex;
 use serde::{Deserialize, Deserializer};
 
+use crate::auth::CertificateIdentity;
+
 pub const DEFAULTS: &str = include_str!("../config/default.json");
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Transport {
     Tcp {
@@ -20,10 +24,60 @@ pub enum Transport {
     Tls {
         address: String,
         certificate: PathBuf,
+        client_auth: Option<ClientAuth>,
+    },
+    Ws {
+        address: String,
+    },
+    Wss {
+        address: String,
+        certificate: PathBuf,
+        client_auth: Option<ClientAuth>,
     },
+    Uds {
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ClientAuth {
+    ca_bundle: PathBuf,
+    #[serde(default)]
+    mode: ClientAuthMode,
+}
+
+impl ClientAuth {
+    pub fn new(ca_bundle: PathBuf, mode: ClientAuthMode) -> Self {
+        Self { ca_bundle, mode }
+    }
+
+    /// PEM file with certificate authorities trusted to issue client certificates.
+    pub fn ca_bundle(&self) -> &Path {
+        &self.ca_bundle
+    }
+
+    pub fn mode(&self) -> ClientAuthMode {
+        self.mode
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientAuthMode {
+    /// Clients without a certificate can still connect and authenticate with username and password.
+    Optional,
+
+    /// Clients without a valid certificate are rejected during the TLS handshake.
+    Required,
+}
+
+impl Default for ClientAuthMode {
+    fn default() -> Self {
+        ClientAuthMode::Required
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum QueueFullAction {
     DropNew,
@@ -31,19 +85,53 @@ pub enum QueueFullAction {
     Disconnect,
 }
 
-#[derive(Debug, Deserialize)]
+impl Default for QueueFullAction {
+    fn default() -> Self {
+        QueueFullAction::DropNew
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
 pub struct InflightMessages {
     max_count: u32,
 }
 
-#[derive(Debug, Deserialize)]
+impl InflightMessages {
+    pub fn max_count(&self) -> u32 {
+        self.max_count
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetainedFullAction {
+    DropNew,
+    DropOld,
+}
+
+#[derive(Clone, Debug, Deserialize)]
 pub struct RetainedMessages {
     max_count: u32,
     #[serde(with = "humantime_serde")]
     expiration: Duration,
+    when_full: RetainedFullAction,
+}
+
+impl RetainedMessages {
+    pub fn max_count(&self) -> u32 {
+        self.max_count
+    }
+
+    pub fn expiration(&self) -> Duration {
+        self.expiration
+    }
+
+    pub fn when_full(&self) -> RetainedFullAction {
+        self.when_full
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct SessionMessages {
     #[serde(deserialize_with = "humansize")]
     max_message_size: u64,
@@ -53,34 +141,565 @@ pub struct SessionMessages {
     when_full: QueueFullAction,
 }
 
-#[derive(Debug, Deserialize)]
+impl SessionMessages {
+    pub fn max_message_size(&self) -> u64 {
+        self.max_message_size
+    }
+
+    pub fn max_count(&self) -> u32 {
+        self.max_count
+    }
+
+    pub fn max_total_space(&self) -> u64 {
+        self.max_total_space
+    }
+
+    pub fn when_full(&self) -> QueueFullAction {
+        self.when_full
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
 pub struct SessionPersistence {
     file_path: String,
     #[serde(with = "humantime_serde")]
     time_interval: Duration,
     unsaved_message_count: u32,
+    #[serde(default)]
+    journal: bool,
 }
 
-#[derive(Debug, Deserialize)]
+impl SessionPersistence {
+    pub fn file_path(&self) -> &str {
+        &self.file_path
+    }
+
+    pub fn time_interval(&self) -> Duration {
+        self.time_interval
+    }
+
+    pub fn unsaved_message_count(&self) -> u32 {
+        self.unsaved_message_count
+    }
+
+    /// Whether changes are journaled to a write-ahead log as they are made,
+    /// with snapshots only compacting the log, instead of persisting
+    /// the whole state at every snapshot.
+    pub fn journal(&self) -> bool {
+        self.journal
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
 pub struct Session {
     #[serde(with = "humantime_serde")]
     expiration: Duration,
     messages: SessionMessages,
 }
 
-#[derive(Debug, Deserialize)]
+impl Session {
+    pub fn expiration(&self) -> Duration {
+        self.expiration
+    }
+
+    pub fn messages(&self) -> &SessionMessages {
+        &self.messages
+    }
+}
+
+/// Settings used to authenticate clients.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Authentication {
+    password_file: Option<PathBuf>,
+    allow_anonymous: bool,
+    #[serde(default)]
+    peer_credentials: Vec<PeerIdentity>,
+    certificate_identity: Option<CertificateIdentity>,
+}
+
+impl Authentication {
+    /// File with usernames and password hashes clients authenticate against.
+    pub fn password_file(&self) -> Option<&Path> {
+        self.password_file.as_deref()
+    }
+
+    /// Whether clients which provide no username are allowed to connect.
+    pub fn allow_anonymous(&self) -> bool {
+        self.allow_anonymous
+    }
+
+    /// Identities of local processes connecting over a Unix domain socket
+    /// without a username.
+    pub fn peer_credentials(&self) -> &[PeerIdentity] {
+        &self.peer_credentials
+    }
+
+    /// Certificate field used as the identity of clients connecting with a
+    /// client certificate and without a username. Client certificates are not
+    /// used to authenticate clients if it is not set.
+    pub fn certificate_identity(&self) -> Option<CertificateIdentity> {
+        self.certificate_identity
+    }
+}
+
+/// Identity given to local processes running as a user id.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct PeerIdentity {
+    uid: u32,
+    identity: String,
+}
+
+impl PeerIdentity {
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    pub fn identity(&self) -> &str {
+        &self.identity
+    }
+}
+
+/// Action taken when a client publishes faster than its rate limit allows.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitAction {
+    /// Stops reading from the connection until the client is within its limit again.
+    Delay,
+
+    /// Drops QoS 0 publications over the limit. QoS 1 and 2 publications are delayed.
+    DropQos0,
+
+    /// Disconnects the client.
+    Disconnect,
+}
+
+/// Publish rate of a client. Zero is treated as "no limit".
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct RateLimit {
+    messages_per_second: u32,
+    #[serde(deserialize_with = "humansize")]
+    bytes_per_second: u64,
+}
+
+impl RateLimit {
+    pub fn new(messages_per_second: u32, bytes_per_second: u64) -> Self {
+        Self {
+            messages_per_second,
+            bytes_per_second,
+        }
+    }
+
+    pub fn messages_per_second(&self) -> u32 {
+        self.messages_per_second
+    }
+
+    /// Payload bytes a client may publish per second.
+    pub fn bytes_per_second(&self) -> u64 {
+        self.bytes_per_second
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.messages_per_second == 0 && self.bytes_per_second == 0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct IdentityRateLimit {
+    identity: String,
+    #[serde(flatten)]
+    limit: RateLimit,
+}
+
+/// Limits of the rate clients publish at.
+///
+/// Every connection is limited on its own, and connections of clients
+/// authenticated with the same identity share the limit as well.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct RateLimits {
+    #[serde(flatten)]
+    limit: RateLimit,
+    when_exceeded: RateLimitAction,
+    #[serde(default)]
+    identities: Vec<IdentityRateLimit>,
+}
+
+impl RateLimits {
+    /// Limit of clients authenticated with an identity, or of anonymous clients if none is given.
+    pub fn limit(&self, identity: Option<&str>) -> &RateLimit {
+        identity
+            .and_then(|identity| {
+                self.identities
+                    .iter()
+                    .find(|limit| limit.identity == identity)
+            })
+            .map_or(&self.limit, |limit| &limit.limit)
+    }
+
+    pub fn when_exceeded(&self) -> RateLimitAction {
+        self.when_exceeded
+    }
+}
+
+/// Settings of the HTTP listener serving Prometheus metrics.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Metrics {
+    address: SocketAddr,
+}
+
+impl Metrics {
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+/// Settings of the HTTP listener serving the administrative API.
+///
+/// The API bypasses authorization, so requests must carry the configured
+/// `token` as a bearer token. Without a token the API may only listen on
+/// a loopback address.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Admin {
+    address: SocketAddr,
+    token: Option<String>,
+}
+
+impl Admin {
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+}
+
+/// Destination of the message trace.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceOutput {
+    /// JSON lines appended to a file, which is rotated once it reaches `max_file_size`.
+    /// Up to `max_files` rotated files are kept next to it with a numeric suffix.
+    File {
+        path: PathBuf,
+        #[serde(deserialize_with = "humansize")]
+        max_file_size: u64,
+        max_files: u32,
+    },
+
+    /// JSON publications to `$SYS/trace/<client id>` topics. A `%`, `/`, `+`
+    /// or `#` in the client id is percent-encoded, e.g. `/` as `%2F`.
+    Topic,
+}
+
+/// Settings of the message trace, which records what happens to each
+/// publication inside the broker.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Trace {
+    output: TraceOutput,
+    sample_rate: Option<f64>,
+    #[serde(default)]
+    topics: Vec<String>,
+}
+
+impl Trace {
+    pub fn output(&self) -> &TraceOutput {
+        &self.output
+    }
+
+    /// Fraction of publications to trace, between 0 and 1. All of them are traced by default.
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate.unwrap_or(1.0)
+    }
+
+    /// Topic filters of the publications to trace. Publications to any topic
+    /// other than system topics are traced if none are given.
+    pub fn topics(&self) -> &[String] {
+        &self.topics
+    }
+}
+
+/// Policy used to authorize client activities.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Authorization {
+    /// Every client activity is allowed.
+    AllowAll,
+
+    /// Activities are authorized with the rules of an access control list file.
+    Acl { path: PathBuf },
+}
+
+/// Direction in which publications on a bridged topic are forwarded.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BridgeDirection {
+    /// From the remote broker to the local one.
+    In,
+
+    /// From the local broker to the remote one.
+    Out,
+
+    /// Both ways.
+    Both,
+}
+
+impl BridgeDirection {
+    pub fn is_in(self) -> bool {
+        self != BridgeDirection::Out
+    }
+
+    pub fn is_out(self) -> bool {
+        self != BridgeDirection::In
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BridgeTopic {
+    pattern: String,
+    direction: BridgeDirection,
+    #[serde(deserialize_with = "qos")]
+    qos: proto::QoS,
+    #[serde(default)]
+    local_prefix: String,
+    #[serde(default)]
+    remote_prefix: String,
+}
+
+impl BridgeTopic {
+    /// Topic filter of the forwarded publications, without the prefixes.
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn direction(&self) -> BridgeDirection {
+        self.direction
+    }
+
+    pub fn qos(&self) -> proto::QoS {
+        self.qos
+    }
+
+    /// Prefix prepended to topics on the local broker.
+    pub fn local_prefix(&self) -> &str {
+        &self.local_prefix
+    }
+
+    /// Prefix prepended to topics on the remote broker.
+    pub fn remote_prefix(&self) -> &str {
+        &self.remote_prefix
+    }
+}
+
+/// TLS settings of the connection of a bridge to the remote broker.
+///
+/// The certificate of the remote broker is verified against the system
+/// trust store and the certificate authorities of `ca_bundle`.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct BridgeTls {
+    ca_bundle: Option<PathBuf>,
+    client_certificate: Option<BridgeCertificate>,
+}
+
+impl BridgeTls {
+    /// PEM file with additional certificate authorities trusted to issue the
+    /// certificate of the remote broker.
+    pub fn ca_bundle(&self) -> Option<&Path> {
+        self.ca_bundle.as_deref()
+    }
+
+    pub fn client_certificate(&self) -> Option<&BridgeCertificate> {
+        self.client_certificate.as_ref()
+    }
+}
+
+/// Client certificate a bridge authenticates with to the remote broker.
+#[derive(Clone, Debug, Deserialize)]
+pub struct BridgeCertificate {
+    certificate: PathBuf,
+    private_key: PathBuf,
+}
+
+impl BridgeCertificate {
+    /// PEM file with the client certificate, optionally followed by intermediate certificates.
+    pub fn certificate(&self) -> &Path {
+        &self.certificate
+    }
+
+    /// PEM file with the private key of the client certificate.
+    pub fn private_key(&self) -> &Path {
+        &self.private_key
+    }
+}
+
+/// A bridge to a remote broker.
+///
+/// On the local broker the bridge connects with the client id and identity
+/// `$bridge/<name>` without authenticating. An access control list has to
+/// allow that identity to connect, to subscribe to the topics forwarded
+/// to the remote broker and to publish to the topics forwarded from it.
+#[derive(Clone, Debug, Deserialize)]
+pub struct BridgeConfig {
+    name: String,
+    address: String,
+    tls: Option<BridgeTls>,
+    client_id: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    #[serde(with = "humantime_serde")]
+    keep_alive: Duration,
+    #[serde(with = "humantime_serde")]
+    max_reconnect_back_off: Duration,
+    #[serde(default)]
+    clean_session: bool,
+    topics: Vec<BridgeTopic>,
+}
+
+impl BridgeConfig {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Address of the remote broker.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// TLS settings of the connection to the remote broker.
+    /// If not given, the bridge connects over plain TCP.
+    pub fn tls(&self) -> Option<&BridgeTls> {
+        self.tls.as_ref()
+    }
+
+    /// Client id the bridge uses with the remote broker.
+    /// If not given, the remote broker generates one.
+    pub fn client_id(&self) -> Option<&str> {
+        self.client_id.as_deref()
+    }
+
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    pub fn password(&self) -> Option<&str> {
+        self.password.as_deref()
+    }
+
+    pub fn keep_alive(&self) -> Duration {
+        self.keep_alive
+    }
+
+    pub fn max_reconnect_back_off(&self) -> Duration {
+        self.max_reconnect_back_off
+    }
+
+    pub fn clean_session(&self) -> bool {
+        self.clean_session
+    }
+
+    pub fn topics(&self) -> &[BridgeTopic] {
+        &self.topics
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
 pub struct BrokerConfig {
     transports: Vec<Transport>,
     inflight_messages: InflightMessages,
     retained_messages: RetainedMessages,
     session: Session,
     persistence: Option<SessionPersistence>,
+    #[serde(default)]
+    bridges: Vec<BridgeConfig>,
+    authentication: Authentication,
+    authorization: Option<Authorization>,
+    #[serde(with = "humantime_serde")]
+    sys_interval: Duration,
+    sys_client_stats: bool,
+    #[serde(default)]
+    edgehub_translation: Option<bool>,
+    metrics: Option<Metrics>,
+    admin: Option<Admin>,
+    rate_limits: Option<RateLimits>,
+    trace: Option<Trace>,
 }
 
 impl BrokerConfig {
     pub fn transports(&self) -> &Vec<Transport> {
         &self.transports
     }
+
+    pub fn inflight_messages(&self) -> &InflightMessages {
+        &self.inflight_messages
+    }
+
+    pub fn retained_messages(&self) -> &RetainedMessages {
+        &self.retained_messages
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn bridges(&self) -> &[BridgeConfig] {
+        &self.bridges
+    }
+
+    /// Interval to publish broker statistics to `$SYS/broker/` topics at. Zero disables them.
+    pub fn sys_interval(&self) -> Duration {
+        self.sys_interval
+    }
+
+    /// Whether the number of publications queued for every session is published
+    /// to `$SYS/broker/clients/<client id>/queued` along with the broker statistics.
+    pub fn sys_client_stats(&self) -> bool {
+        self.sys_client_stats
+    }
+
+    /// Whether topics of IoT Hub device SDKs are translated to the ones edgeHub expects.
+    /// The broker itself does not translate topics, so an unset value leaves the
+    /// choice to the application installing the interceptor.
+    pub fn edgehub_translation(&self) -> Option<bool> {
+        self.edgehub_translation
+    }
+
+    pub fn rate_limits(&self) -> Option<&RateLimits> {
+        self.rate_limits.as_ref()
+    }
+
+    pub fn trace(&self) -> Option<&Trace> {
+        self.trace.as_ref()
+    }
+
+    pub fn admin(&self) -> Option<&Admin> {
+        self.admin.as_ref()
+    }
+
+    pub fn metrics(&self) -> Option<&Metrics> {
+        self.metrics.as_ref()
+    }
+
+    pub fn authentication(&self) -> &Authentication {
+        &self.authentication
+    }
+
+    pub fn authorization(&self) -> Option<&Authorization> {
+        self.authorization.as_ref()
+    }
+}
+
+fn qos<'de, D>(deserializer: D) -> Result<proto::QoS, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match u8::deserialize(deserializer)? {
+        0 => Ok(proto::QoS::AtMostOnce),
+        1 => Ok(proto::QoS::AtLeastOnce),
+        2 => Ok(proto::QoS::ExactlyOnce),
+        qos => Err(serde::de::Error::invalid_value(
+            serde::de::Unexpected::Unsigned(qos.into()),
+            &"0, 1 or 2",
+        )),
+    }
 }
 
 pub fn humansize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
@@ -152,6 +771,12 @@ impl BrokerConfig {
     }
 }
 
+impl Default for BrokerConfig {
+    fn default() -> Self {
+        BrokerConfig::new().expect("default configuration must be valid")
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::convert::From;
@@ -164,7 +789,13 @@ mod tests {
     use serde_json::json;
     use test_case::test_case;
 
-    use crate::configuration::{humansize, BrokerConfig};
+    use mqtt3::proto;
+
+    use crate::auth::CertificateIdentity;
+    use crate::configuration::{
+        humansize, Admin, Authorization, BridgeDirection, BrokerConfig, ClientAuthMode, Metrics,
+        RateLimit, RateLimitAction, TraceOutput, Transport,
+    };
 
     #[test]
     fn it_loads_defaults() {
@@ -174,6 +805,9 @@ mod tests {
             settings.retained_messages.expiration,
             Duration::from_secs(60 * 24 * 60 * 60)
         );
+        assert_eq!(settings.sys_interval(), Duration::from_secs(10));
+        assert!(!settings.sys_client_stats());
+        assert_eq!(settings.edgehub_translation(), None);
     }
 
     #[test]
@@ -187,6 +821,212 @@ mod tests {
         );
     }
 
+    #[test]
+    fn it_loads_websocket_transports() {
+        let settings = BrokerConfig::from_file(Path::new("test/config_websockets.json"))
+            .expect("should be able to create instance from configuration file");
+
+        assert_matches!(
+            settings.transports().as_slice(),
+            [Transport::Ws { .. }, Transport::Wss {
+                client_auth: None, ..
+            }, Transport::Wss {
+                client_auth: Some(_),
+                ..
+            }]
+        );
+    }
+
+    #[test]
+    fn it_loads_tls_client_auth() {
+        let settings = BrokerConfig::from_file(Path::new("test/config_tls_client_auth.json"))
+            .expect("should be able to create instance from configuration file");
+
+        let transports = settings.transports();
+        assert_matches!(
+            &transports[0],
+            Transport::Tls { client_auth: Some(client_auth), .. }
+                if client_auth.mode() == ClientAuthMode::Optional
+                    && client_auth.ca_bundle() == Path::new("/path/to/ca.pem")
+        );
+        assert_matches!(
+            &transports[1],
+            Transport::Tls { client_auth: Some(client_auth), .. }
+                if client_auth.mode() == ClientAuthMode::Required
+        );
+        assert_matches!(
+            &transports[2],
+            Transport::Tls {
+                client_auth: None,
+                ..
+            }
+        );
+    }
+
+    #[test]
+    fn it_loads_bridges() {
+        let settings = BrokerConfig::from_file(Path::new("test/config_bridge.json"))
+            .expect("should be able to create instance from configuration file");
+
+        let bridge = &settings.bridges()[0];
+        assert_eq!(bridge.name(), "parent");
+        assert_eq!(bridge.address(), "parent:1883");
+        assert_eq!(bridge.client_id(), Some("child"));
+        assert_eq!(bridge.keep_alive(), Duration::from_secs(60));
+        assert!(bridge.tls().is_none());
+
+        let topics = bridge.topics();
+        assert_eq!(topics[0].direction(), BridgeDirection::Out);
+        assert_eq!(topics[0].qos(), proto::QoS::AtLeastOnce);
+        assert_eq!(topics[0].local_prefix(), "");
+        assert_eq!(topics[0].remote_prefix(), "child/");
+        assert_eq!(topics[1].direction(), BridgeDirection::In);
+        assert_eq!(topics[1].qos(), proto::QoS::AtMostOnce);
+
+        let tls = settings.bridges()[1].tls().expect("tls");
+        assert_eq!(tls.ca_bundle(), Some(Path::new("/certs/ca.pem")));
+        let client_certificate = tls.client_certificate().expect("client certificate");
+        assert_eq!(
+            client_certificate.certificate(),
+            Path::new("/certs/bridge.pem")
+        );
+        assert_eq!(
+            client_certificate.private_key(),
+            Path::new("/certs/bridge.key")
+        );
+    }
+
+    #[test]
+    fn it_loads_no_bridges_by_default() {
+        let settings = BrokerConfig::new().expect("should be able to create default instance");
+
+        assert!(settings.bridges().is_empty());
+    }
+
+    #[test]
+    fn it_refuses_bridge_with_invalid_qos() {
+        let settings = BrokerConfig::from_file(Path::new("test/config_bridge_bad_qos.json"));
+
+        assert_matches!(settings, Err(_err));
+    }
+
+    #[test]
+    fn it_allows_anonymous_clients_by_default() {
+        let settings = BrokerConfig::default();
+
+        assert!(settings.authentication().allow_anonymous());
+        assert_eq!(settings.authentication().password_file(), None);
+    }
+
+    #[test]
+    fn it_loads_password_authentication() {
+        let settings = BrokerConfig::from_file(Path::new("test/config_password.json"))
+            .expect("should be able to create instance from configuration file");
+
+        assert!(!settings.authentication().allow_anonymous());
+        assert_eq!(
+            settings.authentication().password_file(),
+            Some(Path::new("/etc/mqttd/passwd"))
+        );
+
+        let peers = settings.authentication().peer_credentials();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].uid(), 1000);
+        assert_eq!(peers[0].identity(), "local-module");
+
+        assert_eq!(
+            settings.authentication().certificate_identity(),
+            Some(CertificateIdentity::SubjectAltName)
+        );
+    }
+
+    #[test]
+    fn it_loads_metrics_listener() {
+        let settings = BrokerConfig::from_file(Path::new("test/config_metrics.json"))
+            .expect("should be able to create instance from configuration file");
+
+        assert_eq!(
+            settings.metrics().map(Metrics::address),
+            Some("127.0.0.1:9600".parse().unwrap())
+        );
+        assert!(BrokerConfig::default().metrics().is_none());
+    }
+
+    #[test]
+    fn it_loads_admin_listener() {
+        let settings = BrokerConfig::from_file(Path::new("test/config_admin.json"))
+            .expect("should be able to create instance from configuration file");
+
+        assert_eq!(
+            settings.admin().map(Admin::address),
+            Some("127.0.0.1:9700".parse().unwrap())
+        );
+        assert_eq!(settings.admin().and_then(Admin::token), Some("secret"));
+        assert!(BrokerConfig::default().admin().is_none());
+    }
+
+    #[test]
+    fn it_loads_rate_limits() {
+        let settings = BrokerConfig::from_file(Path::new("test/config_rate_limits.json"))
+            .expect("should be able to create instance from configuration file");
+
+        let rate_limits = settings
+            .rate_limits()
+            .expect("rate limits must be configured");
+        assert_eq!(rate_limits.when_exceeded(), RateLimitAction::DropQos0);
+        assert_eq!(rate_limits.limit(None), &RateLimit::new(100, 64 * 1024));
+        assert_eq!(
+            rate_limits.limit(Some("device-1")),
+            &RateLimit::new(100, 64 * 1024)
+        );
+        assert_eq!(
+            rate_limits.limit(Some("edgehub")),
+            &RateLimit::new(0, 1024 * 1024)
+        );
+        assert!(BrokerConfig::default().rate_limits().is_none());
+    }
+
+    #[test]
+    fn it_loads_trace() {
+        let settings = BrokerConfig::from_file(Path::new("test/config_trace.json"))
+            .expect("should be able to create instance from configuration file");
+
+        let trace = settings.trace().expect("trace must be configured");
+        assert_eq!(
+            trace.output(),
+            &TraceOutput::File {
+                path: "/var/log/mqttd/trace.log".into(),
+                max_file_size: 10 * 1024 * 1024,
+                max_files: 5,
+            }
+        );
+        assert!((trace.sample_rate() - 0.1).abs() < f64::EPSILON);
+        assert_eq!(trace.topics(), ["devices/+/messages/events/#"]);
+        assert!(BrokerConfig::default().trace().is_none());
+    }
+
+    #[test]
+    fn it_loads_acl_authorization() {
+        let settings = BrokerConfig::from_file(Path::new("test/config_acl.json"))
+            .expect("should be able to create instance from configuration file");
+
+        assert_matches!(
+            settings.authorization(),
+            Some(Authorization::Acl { path }) if path == Path::new("/etc/mqttd/acl.yaml")
+        );
+    }
+
+    #[test]
+    fn it_loads_journal_persistence() {
+        let settings = BrokerConfig::from_file(Path::new("test/config_journal.json"))
+            .expect("should be able to create instance from configuration file");
+        assert!(settings.persistence().unwrap().journal());
+
+        let settings = BrokerConfig::from_file(Path::new("test/config_persistence.json"))
+            .expect("should be able to create instance from configuration file");
+        assert!(!settings.persistence().unwrap().journal());
+    }
+
     #[test]
     fn it_refuses_persistence_with_no_file_path() {
         let settings = BrokerConfig::from_file(Path::new("test/config_no_file_path.json"));
@@ -1,6 +1,6 @@
-use std::net::SocketAddr;
+use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use futures_util::future::{select, Either};
 use futures_util::pin_mut;
@@ -8,7 +8,10 @@ use futures_util::sink::{Sink, SinkExt};
 use futures_util::stream::{Stream, StreamExt};
 use lazy_static::lazy_static;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    watch,
+};
 use tokio_io_timeout::TimeoutStream;
 use tokio_util::codec::Framed;
 use tracing::{debug, info, span, trace, warn, Level};
@@ -16,14 +19,14 @@ use tracing_futures::Instrument;
 use uuid::Uuid;
 
 use mqtt3::proto::{self, DecodeError, EncodeError, Packet, PacketCodec};
-use mqtt_edgehub::{
-    translate_incoming_publish, translate_incoming_subscribe, translate_incoming_unsubscribe,
-    translate_outgoing_publish,
-};
 
-use crate::broker::BrokerHandle;
-use crate::transport::GetPeerCertificate;
-use crate::{Certificate, ClientEvent, ClientId, ConnReq, Error, Message, Publish};
+use crate::auth::Authenticator;
+use crate::broker::{not_authorized_ack, BrokerHandle};
+use crate::interceptor::Interceptors;
+use crate::metrics;
+use crate::rate_limit::{ConnectionRateLimit, Throttle};
+use crate::transport::{Addr, GetPeerCertificate, GetPeerCredentials};
+use crate::{CertificateChain, ClientEvent, ClientId, ConnReq, Error, Message, Publish};
 
 lazy_static! {
     static ref DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
@@ -31,6 +34,9 @@ lazy_static! {
 
 const KEEPALIVE_MULT: f32 = 1.5;
 
+/// The highest topic alias value a MQTT 5.0 client may use when publishing to the broker.
+pub(crate) const TOPIC_ALIAS_MAXIMUM: u16 = 10;
+
 /// Allows sending events to a connection.
 ///
 /// It is important that this struct doesn't implement Clone,
@@ -73,16 +79,26 @@ impl PartialEq for ConnectionHandle {
 /// Handles packet processing for a single connection.
 ///
 /// Receives a source of packets and a handle to the Broker.
+/// Authenticates the client on a blocking thread, so that verifying a
+/// password does not hold up the broker, and hands it over to the broker.
 /// Starts two tasks (sending and receiving)
-pub async fn process<I>(
+pub async fn process<I, N>(
     io: I,
-    remote_addr: SocketAddr,
+    remote_addr: Addr,
     mut broker_handle: BrokerHandle,
+    interceptors: Interceptors,
+    authenticator: Arc<N>,
 ) -> Result<(), Error>
 where
-    I: AsyncRead + AsyncWrite + GetPeerCertificate<Certificate = Certificate> + Unpin,
+    I: AsyncRead
+        + AsyncWrite
+        + GetPeerCertificate<Certificate = CertificateChain>
+        + GetPeerCredentials
+        + Unpin,
+    N: Authenticator + Send + Sync + 'static,
 {
     let certificate = io.peer_certificate()?;
+    let peer_credentials = io.peer_credentials()?;
 
     let mut timeout = TimeoutStream::new(io);
     timeout.set_read_timeout(Some(*DEFAULT_TIMEOUT));
@@ -101,7 +117,29 @@ where
     match codec.next().await {
         Some(Ok(Packet::Connect(connect))) => {
             let client_id = client_id(&connect.client_id);
+            let connect = match interceptors
+                .connect(&client_id, connect, &mut broker_handle)
+                .await
+            {
+                Ok(connect) => connect,
+                Err(Error::PacketRejected(reason)) => {
+                    let ack = proto::ConnAck {
+                        session_present: false,
+                        return_code: proto::ConnectReturnCode::Refused(
+                            proto::ConnectionRefusedReason::NotAuthorized,
+                        ),
+                        properties: proto::Properties::default(),
+                    };
+                    metrics::connack(ack.return_code);
+                    codec.send(Packet::ConnAck(ack)).await?;
+                    return Err(Error::PacketRejected(reason));
+                }
+                Err(e) => return Err(e),
+            };
             let (sender, events) = mpsc::unbounded_channel();
+            // The incoming task answers the packets it rejects through the outgoing
+            // task. The broker still ends the connection with an explicit event.
+            let replies = sender.clone();
             let connection_handle = ConnectionHandle::from_sender(sender);
             let span = span!(Level::INFO, "connection", client_id=%client_id, remote_addr=%remote_addr, connection=%connection_handle);
 
@@ -124,16 +162,20 @@ where
                     codec.get_mut().set_read_timeout(Some(keep_alive));
                 }
 
-                let req = ConnReq::new(client_id.clone(), connect, certificate, connection_handle);
+                let protocol_level = connect.protocol_level;
+                let req = ConnReq::new(client_id.clone(), connect, certificate, connection_handle)
+                    .with_peer_credentials(peer_credentials);
+                let req = authenticate(req, authenticator).await?;
                 let event = ClientEvent::ConnReq(req);
                 let message = Message::Client(client_id.clone(), event);
                 broker_handle.send(message).await?;
 
                 // Start up the processing tasks
                 let (outgoing, incoming) = codec.split();
+                let (rate_limit_tx, rate_limit_rx) = watch::channel(None);
                 let incoming_task =
-                    incoming_task(client_id.clone(), incoming, broker_handle.clone());
-                let outgoing_task = outgoing_task(client_id.clone(), events, outgoing, broker_handle.clone());
+                    incoming_task(client_id.clone(), protocol_level, incoming, replies, rate_limit_rx, broker_handle.clone(), interceptors.clone());
+                let outgoing_task = outgoing_task(client_id.clone(), events, outgoing, rate_limit_tx, broker_handle.clone(), interceptors);
                 pin_mut!(incoming_task);
                 pin_mut!(outgoing_task);
 
@@ -199,20 +241,28 @@ where
                 .await
         }
         Some(Ok(packet)) => Err(Error::NoConnect(packet)),
-        Some(Err(e)) => Err(e.into()),
+        Some(Err(e)) => {
+            metrics::decode_error(&e);
+            Err(e.into())
+        }
         None => Err(Error::NoPackets),
     }
 }
 
 async fn incoming_task<S>(
     client_id: ClientId,
+    protocol_level: u8,
     mut incoming: S,
+    replies: UnboundedSender<Message>,
+    rate_limit: watch::Receiver<Option<ConnectionRateLimit>>,
     mut broker: BrokerHandle,
+    interceptors: Interceptors,
 ) -> Result<(), Error>
 where
     S: Stream<Item = Result<Packet, DecodeError>> + Unpin,
 {
     debug!("incoming_task start");
+    let mut topic_aliases = TopicAliases::default();
     while let Some(maybe_packet) = incoming.next().await {
         match maybe_packet {
             Ok(packet) => {
@@ -224,6 +274,13 @@ where
                         warn!("CONNECT packet received on an already established connection, dropping connection due to protocol violation");
                         return Err(Error::ProtocolViolation);
                     }
+                    Packet::Auth(_) => {
+                        // Enhanced authentication is refused during CONNECT,
+                        // so a client is not allowed to start an AUTH exchange.
+
+                        warn!("AUTH packet received without an authentication method, dropping connection due to protocol violation");
+                        return Err(Error::ProtocolViolation);
+                    }
                     Packet::ConnAck(connack) => ClientEvent::ConnAck(connack),
                     Packet::Disconnect(disconnect) => {
                         let event = ClientEvent::Disconnect(disconnect);
@@ -236,20 +293,116 @@ where
                     Packet::PingResp(pingresp) => ClientEvent::PingResp(pingresp),
                     Packet::PubAck(puback) => ClientEvent::PubAck(puback),
                     Packet::PubComp(pubcomp) => ClientEvent::PubComp(pubcomp),
-                    Packet::Publish(publish) => {
-                        let publish = translate_incoming_publish(&client_id.0, publish);
-                        ClientEvent::PublishFrom(publish)
+                    Packet::Publish(mut publish) => {
+                        topic_aliases.resolve(&mut publish)?;
+
+                        let limit = (*rate_limit.borrow()).clone();
+                        if let Some(limit) = limit {
+                            match limit.throttle(&publish, Instant::now()) {
+                                Throttle::Allow => (),
+                                Throttle::Delay(delay) => {
+                                    debug!("rate limit exceeded. delaying reading for {:?}", delay);
+                                    tokio::time::delay_for(delay).await;
+                                }
+                                Throttle::Drop => {
+                                    debug!("rate limit exceeded. dropping QoS 0 publication");
+                                    continue;
+                                }
+                                Throttle::Disconnect => {
+                                    warn!("rate limit exceeded. dropping connection");
+                                    return Err(Error::RateLimitExceeded);
+                                }
+                            }
+                        }
+
+                        let packet_identifier_dup_qos = publish.packet_identifier_dup_qos;
+                        match interceptors
+                            .incoming_publish(&client_id, publish, &mut broker)
+                            .await
+                        {
+                            Ok(publish) => ClientEvent::PublishFrom(publish),
+                            Err(Error::PacketRejected(reason))
+                                if protocol_level == mqtt3::PROTOCOL_LEVEL_V5 =>
+                            {
+                                // A MQTT 5.0 client is told about the rejection in the
+                                // acknowledgement instead of losing its connection.
+                                if let Some(event) =
+                                    not_authorized_ack(packet_identifier_dup_qos, reason)
+                                {
+                                    let message = Message::Client(client_id.clone(), event);
+                                    replies
+                                        .send(message)
+                                        .map_err(Error::SendConnectionMessage)?;
+                                }
+                                continue;
+                            }
+                            Err(e) => return Err(e),
+                        }
                     }
                     Packet::PubRec(pubrec) => ClientEvent::PubRec(pubrec),
                     Packet::PubRel(pubrel) => ClientEvent::PubRel(pubrel),
                     Packet::Subscribe(subscribe) => {
-                        let subscribe = translate_incoming_subscribe(&client_id.0, subscribe);
-                        ClientEvent::Subscribe(subscribe)
+                        let packet_identifier = subscribe.packet_identifier;
+                        let count = subscribe.subscribe_to.len();
+                        match interceptors
+                            .subscribe(&client_id, subscribe, &mut broker)
+                            .await
+                        {
+                            Ok(subscribe) => ClientEvent::Subscribe(subscribe),
+                            Err(Error::PacketRejected(reason)) => {
+                                // Refuse every topic filter of the packet instead of
+                                // dropping a client which asked for something it may not have.
+                                let suback = proto::SubAck {
+                                    packet_identifier,
+                                    qos: vec![proto::SubAckQos::Failure; count],
+                                    properties: proto::Properties {
+                                        reason_string: Some(reason),
+                                        ..proto::Properties::default()
+                                    },
+                                };
+                                let message =
+                                    Message::Client(client_id.clone(), ClientEvent::SubAck(suback));
+                                replies
+                                    .send(message)
+                                    .map_err(Error::SendConnectionMessage)?;
+                                continue;
+                            }
+                            Err(e) => return Err(e),
+                        }
                     }
                     Packet::SubAck(suback) => ClientEvent::SubAck(suback),
                     Packet::Unsubscribe(unsubscribe) => {
-                        let unsubscribe = translate_incoming_unsubscribe(&client_id.0, unsubscribe);
-                        ClientEvent::Unsubscribe(unsubscribe)
+                        let packet_identifier = unsubscribe.packet_identifier;
+                        let count = unsubscribe.unsubscribe_from.len();
+                        match interceptors
+                            .unsubscribe(&client_id, unsubscribe, &mut broker)
+                            .await
+                        {
+                            Ok(unsubscribe) => ClientEvent::Unsubscribe(unsubscribe),
+                            Err(Error::PacketRejected(reason))
+                                if protocol_level == mqtt3::PROTOCOL_LEVEL_V5 =>
+                            {
+                                // Only a MQTT 5.0 UNSUBACK carries reason codes
+                                // to refuse the topic filters with.
+                                let unsuback = proto::UnsubAck {
+                                    packet_identifier,
+                                    reason_codes: vec![proto::ReasonCode::NOT_AUTHORIZED; count],
+                                    properties: proto::Properties {
+                                        reason_string: Some(reason),
+                                        ..proto::Properties::default()
+                                    },
+                                };
+                                let message = Message::Client(
+                                    client_id.clone(),
+                                    ClientEvent::UnsubAck(unsuback),
+                                );
+                                replies
+                                    .send(message)
+                                    .map_err(Error::SendConnectionMessage)?;
+                                continue;
+                            }
+                            Err(e) => return Err(e),
+                        }
                     }
                     Packet::UnsubAck(unsuback) => ClientEvent::UnsubAck(unsuback),
                 };
@@ -259,6 +412,7 @@ where
             }
             Err(e) => {
                 warn!(message="error occurred while reading from connection", error=%e);
+                metrics::decode_error(&e);
                 return Err(e.into());
             }
         }
@@ -275,7 +429,9 @@ async fn outgoing_task<S>(
     client_id: ClientId,
     mut messages: UnboundedReceiver<Message>,
     mut outgoing: S,
+    rate_limit: watch::Sender<Option<ConnectionRateLimit>>,
     mut broker: BrokerHandle,
+    interceptors: Interceptors,
 ) -> Result<(), (UnboundedReceiver<Message>, Error)>
 where
     S: Sink<Packet, Error = EncodeError> + Unpin,
@@ -301,13 +457,41 @@ where
                 ClientEvent::SubAck(suback) => Some(Packet::SubAck(suback)),
                 ClientEvent::Unsubscribe(unsub) => Some(Packet::Unsubscribe(unsub)),
                 ClientEvent::UnsubAck(unsuback) => Some(Packet::UnsubAck(unsuback)),
-                ClientEvent::PublishTo(Publish::QoS12(_id, publish)) => {
-                    let publish = translate_outgoing_publish(publish);
-                    Some(Packet::Publish(publish))
+                ClientEvent::PublishTo(Publish::QoS12(id, publish)) => {
+                    match interceptors
+                        .outgoing_publish(&client_id, publish, &mut broker)
+                        .await
+                    {
+                        Ok(publish) => Some(Packet::Publish(publish)),
+                        Err(Error::PacketRejected(_)) => {
+                            // A rejected publication is never delivered, so settle it in the
+                            // session as if the client acknowledged it. Otherwise it would stay
+                            // inflight and be redelivered, and rejected, on every reconnect.
+                            let puback = proto::PubAck {
+                                packet_identifier: id,
+                                reason_code: proto::ReasonCode::SUCCESS,
+                                properties: proto::Properties::default(),
+                            };
+                            let message =
+                                Message::Client(client_id.clone(), ClientEvent::PubAck(puback));
+                            if let Err(e) = broker.send(message).await {
+                                warn!(message = "error occurred while sending QoS ack to broker", error=%e);
+                                return Err((messages, e));
+                            }
+                            None
+                        }
+                        Err(e) => return Err((messages, e)),
+                    }
                 }
                 ClientEvent::PublishTo(Publish::QoS0(id, publish)) => {
-                    let publish = translate_outgoing_publish(publish);
-                    let result = outgoing.send(Packet::Publish(publish)).await;
+                    let result = match interceptors
+                        .outgoing_publish(&client_id, publish, &mut broker)
+                        .await
+                    {
+                        Ok(publish) => outgoing.send(Packet::Publish(publish)).await,
+                        Err(Error::PacketRejected(_)) => Ok(()),
+                        Err(e) => return Err((messages, e)),
+                    };
 
                     if let Err(e) = result {
                         warn!(message = "error occurred while writing to connection", error=%e);
@@ -325,6 +509,12 @@ where
                 ClientEvent::PubRec(pubrec) => Some(Packet::PubRec(pubrec)),
                 ClientEvent::PubRel(pubrel) => Some(Packet::PubRel(pubrel)),
                 ClientEvent::PubComp(pubcomp) => Some(Packet::PubComp(pubcomp)),
+                ClientEvent::RateLimit(limit) => {
+                    debug!("updating rate limit of connection");
+                    // incoming_task may have already completed
+                    let _ = rate_limit.broadcast(limit);
+                    None
+                }
                 event => {
                     warn!("ignoring event for outgoing_task: {:?}", event);
                     None
@@ -346,6 +536,70 @@ where
     Ok(())
 }
 
+/// Keeps track of the topic aliases a MQTT 5.0 client established on a connection.
+#[derive(Debug, Default)]
+struct TopicAliases(HashMap<u16, String>);
+
+impl TopicAliases {
+    /// Replaces the topic alias of an incoming PUBLISH packet with the topic name it stands for.
+    fn resolve(&mut self, publish: &mut proto::Publish) -> Result<(), Error> {
+        let alias = match publish.properties.topic_alias.take() {
+            Some(alias) => alias,
+            None => return Ok(()),
+        };
+
+        // [MQTT5-3.3.2-8] A sender MUST NOT send a PUBLISH packet containing a Topic Alias
+        // which has the value 0.
+        // [MQTT5-3.3.2-9] A Client MUST NOT send a PUBLISH packet with a Topic Alias greater
+        // than the Topic Alias Maximum value returned by the Server in the CONNACK packet.
+        if alias == 0 || alias > TOPIC_ALIAS_MAXIMUM {
+            warn!("PUBLISH packet received with invalid topic alias {}", alias);
+            return Err(Error::ProtocolViolation);
+        }
+
+        if publish.topic_name.is_empty() {
+            match self.0.get(&alias) {
+                Some(topic_name) => publish.topic_name = topic_name.clone(),
+                None => {
+                    warn!("PUBLISH packet received with unknown topic alias {}", alias);
+                    return Err(Error::ProtocolViolation);
+                }
+            }
+        } else {
+            self.0.insert(alias, publish.topic_name.clone());
+        }
+
+        Ok(())
+    }
+}
+
+/// Authenticates a client, leaving it to the broker to refuse a client
+/// which failed to authenticate together with other invalid CONNECT packets.
+async fn authenticate<N>(req: ConnReq, authenticator: Arc<N>) -> Result<ConnReq, Error>
+where
+    N: Authenticator + Send + Sync + 'static,
+{
+    let credentials = req.credentials();
+    let auth_id =
+        tokio::task::spawn_blocking(move || authenticator.authenticate(credentials)).await?;
+
+    let req = match auth_id {
+        Ok(Some(auth_id)) => {
+            debug!("client successfully authenticated: {}", auth_id);
+            req.with_auth_id(auth_id)
+        }
+        Ok(None) => {
+            warn!("unable to authenticate client");
+            req.with_auth_failure(proto::ConnectionRefusedReason::BadUserNameOrPassword)
+        }
+        Err(e) => {
+            warn!(message = "error authenticating client", error = %e);
+            req.with_auth_failure(proto::ConnectionRefusedReason::ServerUnavailable)
+        }
+    };
+    Ok(req)
+}
+
 fn client_id(client_id: &proto::ClientId) -> ClientId {
     let id = match client_id {
         proto::ClientId::ServerGenerated => Uuid::new_v4().to_string(),
@@ -354,3 +608,204 @@ fn client_id(client_id: &proto::ClientId) -> ClientId {
     };
     ClientId(Arc::new(id))
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use futures_util::stream;
+    use matches::assert_matches;
+    use mqtt3::proto::{self, DecodeError, Packet};
+    use tokio::sync::{mpsc, watch};
+
+    use super::{incoming_task, TopicAliases, TOPIC_ALIAS_MAXIMUM};
+    use crate::interceptor::{Intercept, Interceptor, InterceptorContext, Interceptors};
+    use crate::{BrokerBuilder, ClientEvent, Error, Message};
+
+    fn publish(topic_name: &str, topic_alias: Option<u16>) -> proto::Publish {
+        proto::Publish {
+            packet_identifier_dup_qos: proto::PacketIdentifierDupQoS::AtMostOnce,
+            retain: false,
+            topic_name: topic_name.to_owned(),
+            payload: Bytes::from("payload"),
+            properties: proto::Properties {
+                topic_alias,
+                ..proto::Properties::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_topic_alias_resolves_to_established_topic() {
+        let mut aliases = TopicAliases::default();
+
+        let mut first = publish("topic/a", Some(1));
+        aliases.resolve(&mut first).unwrap();
+        assert_eq!(first.topic_name, "topic/a");
+        assert_eq!(first.properties.topic_alias, None);
+
+        let mut second = publish("", Some(1));
+        aliases.resolve(&mut second).unwrap();
+        assert_eq!(second.topic_name, "topic/a");
+    }
+
+    #[test]
+    fn test_topic_alias_can_be_remapped() {
+        let mut aliases = TopicAliases::default();
+
+        aliases.resolve(&mut publish("topic/a", Some(1))).unwrap();
+        aliases.resolve(&mut publish("topic/b", Some(1))).unwrap();
+
+        let mut publish = publish("", Some(1));
+        aliases.resolve(&mut publish).unwrap();
+        assert_eq!(publish.topic_name, "topic/b");
+    }
+
+    #[test]
+    fn test_unknown_topic_alias_is_protocol_violation() {
+        let mut aliases = TopicAliases::default();
+
+        let result = aliases.resolve(&mut publish("", Some(1)));
+        assert_matches!(result, Err(Error::ProtocolViolation));
+    }
+
+    #[test]
+    fn test_out_of_range_topic_alias_is_protocol_violation() {
+        let mut aliases = TopicAliases::default();
+
+        let result = aliases.resolve(&mut publish("topic/a", Some(0)));
+        assert_matches!(result, Err(Error::ProtocolViolation));
+
+        let result = aliases.resolve(&mut publish("topic/a", Some(TOPIC_ALIAS_MAXIMUM + 1)));
+        assert_matches!(result, Err(Error::ProtocolViolation));
+    }
+
+    struct RejectAll;
+
+    impl Interceptor for RejectAll {
+        fn on_incoming_publish(
+            &self,
+            _context: &mut InterceptorContext<'_>,
+            _publish: proto::Publish,
+        ) -> Intercept<proto::Publish> {
+            Intercept::Reject("rejected".to_string())
+        }
+
+        fn on_unsubscribe(
+            &self,
+            _context: &mut InterceptorContext<'_>,
+            _unsubscribe: proto::Unsubscribe,
+        ) -> Intercept<proto::Unsubscribe> {
+            Intercept::Reject("rejected".to_string())
+        }
+    }
+
+    /// Runs the incoming task of a connection over the packets with every
+    /// packet rejected. Returns its result along with the replies it sent.
+    async fn incoming_rejected(
+        protocol_level: u8,
+        packets: Vec<Packet>,
+    ) -> (Result<(), Error>, Vec<ClientEvent>) {
+        let broker = BrokerBuilder::default().build();
+        let (replies, mut rx) = mpsc::unbounded_channel();
+        let (_rate_limit_tx, rate_limit_rx) = watch::channel(None);
+        let interceptors = Interceptors::new(vec![Box::new(RejectAll)]);
+        let incoming = stream::iter(packets.into_iter().map(Ok::<_, DecodeError>));
+
+        let result = incoming_task(
+            "client".into(),
+            protocol_level,
+            incoming,
+            replies,
+            rate_limit_rx,
+            broker.handle(),
+            interceptors,
+        )
+        .await;
+
+        let mut events = vec![];
+        while let Ok(Message::Client(_, event)) = rx.try_recv() {
+            events.push(event);
+        }
+        (result, events)
+    }
+
+    fn unsubscribe() -> proto::Unsubscribe {
+        proto::Unsubscribe {
+            packet_identifier: proto::PacketIdentifier::new(3).unwrap(),
+            unsubscribe_from: vec!["topic/a".to_string(), "topic/b".to_string()],
+            properties: proto::Properties::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejected_publish_is_not_authorized_for_v5_client() {
+        let mut qos1 = publish("topic/a", None);
+        qos1.packet_identifier_dup_qos = proto::PacketIdentifierDupQoS::AtLeastOnce(
+            proto::PacketIdentifier::new(1).unwrap(),
+            false,
+        );
+        let mut qos2 = publish("topic/a", None);
+        qos2.packet_identifier_dup_qos = proto::PacketIdentifierDupQoS::ExactlyOnce(
+            proto::PacketIdentifier::new(2).unwrap(),
+            false,
+        );
+        let packets = vec![
+            Packet::Publish(publish("topic/a", None)),
+            Packet::Publish(qos1),
+            Packet::Publish(qos2),
+        ];
+
+        let (result, events) = incoming_rejected(mqtt3::PROTOCOL_LEVEL_V5, packets).await;
+
+        assert_matches!(result, Ok(()));
+        assert_eq!(events.len(), 2);
+        assert_matches!(
+            &events[0],
+            ClientEvent::PubAck(proto::PubAck { packet_identifier, reason_code, properties })
+                if packet_identifier.get() == 1
+                    && *reason_code == proto::ReasonCode::NOT_AUTHORIZED
+                    && properties.reason_string == Some("rejected".to_string())
+        );
+        assert_matches!(
+            &events[1],
+            ClientEvent::PubRec(proto::PubRec { packet_identifier, reason_code, .. })
+                if packet_identifier.get() == 2
+                    && *reason_code == proto::ReasonCode::NOT_AUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejected_publish_disconnects_v3_client() {
+        let packets = vec![Packet::Publish(publish("topic/a", None))];
+
+        let (result, events) = incoming_rejected(mqtt3::PROTOCOL_LEVEL, packets).await;
+
+        assert_matches!(result, Err(Error::PacketRejected(_)));
+        assert!(events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rejected_unsubscribe_is_not_authorized_for_v5_client() {
+        let packets = vec![Packet::Unsubscribe(unsubscribe())];
+
+        let (result, events) = incoming_rejected(mqtt3::PROTOCOL_LEVEL_V5, packets).await;
+
+        assert_matches!(result, Ok(()));
+        assert_matches!(
+            &events[..],
+            [ClientEvent::UnsubAck(proto::UnsubAck { packet_identifier, reason_codes, .. })]
+                if packet_identifier.get() == 3
+                    && reason_codes == &vec![proto::ReasonCode::NOT_AUTHORIZED; 2]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejected_unsubscribe_disconnects_v3_client() {
+        let packets = vec![Packet::Unsubscribe(unsubscribe())];
+
+        let (result, events) = incoming_rejected(mqtt3::PROTOCOL_LEVEL, packets).await;
+
+        assert_matches!(result, Err(Error::PacketRejected(_)));
+        assert!(events.is_empty());
+    }
+}
@@ -31,9 +31,18 @@ pub enum Error {
     #[error("Session is offline.")]
     SessionOffline,
 
+    #[error("Session queue of messages waiting to be sent is full.")]
+    SessionQueueFull,
+
     #[error("MQTT protocol violation occurred.")]
     ProtocolViolation,
 
+    #[error("Client exceeded its publish rate limit.")]
+    RateLimitExceeded,
+
+    #[error("Packet rejected by interceptor: {0}")]
+    PacketRejected(String),
+
     #[error("Provided topic filter is invalid: {0}")]
     InvalidTopicFilter(String),
 
@@ -50,13 +59,22 @@ pub enum Error {
     Persist(#[from] crate::persist::PersistError),
 
     #[error("Unable to obtain peer certificate.")]
-    PeerCertificate(#[source] native_tls::Error),
+    PeerCertificate(#[source] openssl::error::ErrorStack),
+
+    #[error("Unable to obtain peer credentials.")]
+    PeerCredentials(#[source] std::io::Error),
 
     #[error("Unable to start broker.")]
     InitializeBroker(#[from] InitializeBrokerError),
 
     #[error("An error occurred when constructing state change: {0}")]
     StateChange(#[from] serde_json::Error),
+
+    #[error("Broker did not reply to the request.")]
+    NoReply(#[source] tokio::sync::oneshot::error::RecvError),
+
+    #[error("An error occurred updating password file.")]
+    PasswordFile(#[from] crate::PasswordFileError),
 }
 
 /// Represents errors occurred while bootstrapping broker.
@@ -78,8 +96,25 @@ pub enum InitializeBrokerError {
     LoadIdentity(PathBuf, #[source] std::io::Error),
 
     #[error("An error occurred  decoding identity content.")]
-    DecodeIdentity(#[source] native_tls::Error),
+    DecodeIdentity(#[source] openssl::error::ErrorStack),
 
     #[error("An error occurred  bootstrapping TLS")]
-    Tls(#[source] native_tls::Error),
+    Tls(#[source] openssl::error::ErrorStack),
+
+    #[error("An error occurred loading trusted certificate authorities from file {0}.")]
+    LoadCertificateAuthority(PathBuf, #[source] openssl::error::ErrorStack),
+
+    #[error("Transport {0} is not supported on this platform.")]
+    UnsupportedTransport(&'static str),
+
+    #[error("An error occurred loading authorization policy.")]
+    LoadAuthorization(#[source] crate::AclError),
+
+    #[error("An error occurred loading password file.")]
+    LoadPasswordFile(#[source] crate::PasswordFileError),
+
+    #[error(
+        "Admin API listening on {0} requires a token, unless it listens on a loopback address."
+    )]
+    UnprotectedAdmin(std::net::SocketAddr),
 }
@@ -0,0 +1,310 @@
+use std::sync::Arc;
+
+use mqtt3::proto;
+use tracing::warn;
+
+use crate::broker::BrokerHandle;
+use crate::{ClientId, Error, Message, SystemEvent};
+
+/// Outcome of intercepting a packet.
+#[derive(Debug, PartialEq)]
+pub enum Intercept<T> {
+    /// Passes the packet on, possibly modified.
+    Continue(T),
+
+    /// Rejects the packet with a reason. A rejected CONNECT is refused with a CONNACK and
+    /// a rejected SUBSCRIBE with a failure for every topic filter. A rejected inbound
+    /// PUBLISH or UNSUBSCRIBE of a MQTT 5.0 client is acknowledged as not authorized,
+    /// while it closes the connection of a MQTT 3.1.1 client, which has no such reason code.
+    /// A rejected outbound PUBLISH is dropped without being delivered to the client.
+    Reject(String),
+}
+
+/// Observes and transforms packets of client connections.
+///
+/// Interceptors are invoked by the connection for inbound CONNECT, PUBLISH,
+/// SUBSCRIBE and UNSUBSCRIBE packets and for outbound PUBLISH packets,
+/// before they reach the broker or the client respectively.
+/// Every hook passes the packet on unchanged by default.
+pub trait Interceptor: Send + Sync {
+    /// Intercepts the CONNECT packet of a new connection. The client id of the context
+    /// is the one requested in the packet, or a generated one.
+    fn on_connect(
+        &self,
+        _context: &mut InterceptorContext<'_>,
+        connect: proto::Connect,
+    ) -> Intercept<proto::Connect> {
+        Intercept::Continue(connect)
+    }
+
+    fn on_incoming_publish(
+        &self,
+        _context: &mut InterceptorContext<'_>,
+        publish: proto::Publish,
+    ) -> Intercept<proto::Publish> {
+        Intercept::Continue(publish)
+    }
+
+    fn on_subscribe(
+        &self,
+        _context: &mut InterceptorContext<'_>,
+        subscribe: proto::Subscribe,
+    ) -> Intercept<proto::Subscribe> {
+        Intercept::Continue(subscribe)
+    }
+
+    fn on_unsubscribe(
+        &self,
+        _context: &mut InterceptorContext<'_>,
+        unsubscribe: proto::Unsubscribe,
+    ) -> Intercept<proto::Unsubscribe> {
+        Intercept::Continue(unsubscribe)
+    }
+
+    fn on_outgoing_publish(
+        &self,
+        _context: &mut InterceptorContext<'_>,
+        publish: proto::Publish,
+    ) -> Intercept<proto::Publish> {
+        Intercept::Continue(publish)
+    }
+}
+
+/// Connection an intercepted packet belongs to.
+///
+/// Interceptors can emit publications through the context. The broker
+/// publishes them on its own behalf once the packet is intercepted.
+#[derive(Debug)]
+pub struct InterceptorContext<'a> {
+    client_id: &'a ClientId,
+    publications: Vec<proto::Publication>,
+}
+
+impl<'a> InterceptorContext<'a> {
+    pub fn new(client_id: &'a ClientId) -> Self {
+        Self {
+            client_id,
+            publications: Vec::new(),
+        }
+    }
+
+    pub fn client_id(&self) -> &ClientId {
+        self.client_id
+    }
+
+    pub fn publish(&mut self, publication: proto::Publication) {
+        self.publications.push(publication);
+    }
+
+    pub fn into_publications(self) -> Vec<proto::Publication> {
+        self.publications
+    }
+}
+
+/// Chain of interceptors applied in the order they were added.
+#[derive(Clone, Default)]
+pub(crate) struct Interceptors(Arc<Vec<Box<dyn Interceptor>>>);
+
+impl Interceptors {
+    pub(crate) fn new(interceptors: Vec<Box<dyn Interceptor>>) -> Self {
+        Self(Arc::new(interceptors))
+    }
+
+    pub(crate) async fn connect(
+        &self,
+        client_id: &ClientId,
+        connect: proto::Connect,
+        broker: &mut BrokerHandle,
+    ) -> Result<proto::Connect, Error> {
+        self.apply(client_id, connect, broker, |i, c, p| i.on_connect(c, p))
+            .await
+    }
+
+    pub(crate) async fn incoming_publish(
+        &self,
+        client_id: &ClientId,
+        publish: proto::Publish,
+        broker: &mut BrokerHandle,
+    ) -> Result<proto::Publish, Error> {
+        self.apply(client_id, publish, broker, |i, c, p| {
+            i.on_incoming_publish(c, p)
+        })
+        .await
+    }
+
+    pub(crate) async fn subscribe(
+        &self,
+        client_id: &ClientId,
+        subscribe: proto::Subscribe,
+        broker: &mut BrokerHandle,
+    ) -> Result<proto::Subscribe, Error> {
+        self.apply(client_id, subscribe, broker, |i, c, p| i.on_subscribe(c, p))
+            .await
+    }
+
+    pub(crate) async fn unsubscribe(
+        &self,
+        client_id: &ClientId,
+        unsubscribe: proto::Unsubscribe,
+        broker: &mut BrokerHandle,
+    ) -> Result<proto::Unsubscribe, Error> {
+        self.apply(client_id, unsubscribe, broker, |i, c, p| {
+            i.on_unsubscribe(c, p)
+        })
+        .await
+    }
+
+    pub(crate) async fn outgoing_publish(
+        &self,
+        client_id: &ClientId,
+        publish: proto::Publish,
+        broker: &mut BrokerHandle,
+    ) -> Result<proto::Publish, Error> {
+        self.apply(client_id, publish, broker, |i, c, p| {
+            i.on_outgoing_publish(c, p)
+        })
+        .await
+    }
+
+    async fn apply<T, F>(
+        &self,
+        client_id: &ClientId,
+        packet: T,
+        broker: &mut BrokerHandle,
+        hook: F,
+    ) -> Result<T, Error>
+    where
+        F: Fn(&dyn Interceptor, &mut InterceptorContext<'_>, T) -> Intercept<T>,
+    {
+        let mut context = InterceptorContext::new(client_id);
+        let result = intercept(&self.0, &mut context, packet, hook);
+
+        for publication in context.into_publications() {
+            let message = Message::System(SystemEvent::Publish(publication));
+            broker.send(message).await?;
+        }
+
+        result
+    }
+}
+
+fn intercept<T, F>(
+    interceptors: &[Box<dyn Interceptor>],
+    context: &mut InterceptorContext<'_>,
+    mut packet: T,
+    hook: F,
+) -> Result<T, Error>
+where
+    F: Fn(&dyn Interceptor, &mut InterceptorContext<'_>, T) -> Intercept<T>,
+{
+    for interceptor in interceptors {
+        packet = match hook(interceptor.as_ref(), context, packet) {
+            Intercept::Continue(packet) => packet,
+            Intercept::Reject(reason) => {
+                warn!("packet rejected by interceptor: {}", reason);
+                return Err(Error::PacketRejected(reason));
+            }
+        };
+    }
+
+    Ok(packet)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use matches::assert_matches;
+    use mqtt3::proto;
+
+    use crate::interceptor::{intercept, Intercept, Interceptor, InterceptorContext};
+    use crate::{ClientId, Error};
+
+    struct Prefix(&'static str);
+
+    impl Interceptor for Prefix {
+        fn on_incoming_publish(
+            &self,
+            context: &mut InterceptorContext<'_>,
+            mut publish: proto::Publish,
+        ) -> Intercept<proto::Publish> {
+            publish.topic_name = format!("{}/{}", self.0, publish.topic_name);
+            context.publish(proto::Publication {
+                topic_name: format!("audit/{}", context.client_id()),
+                qos: proto::QoS::AtMostOnce,
+                retain: false,
+                payload: Bytes::from(publish.topic_name.clone()),
+                properties: proto::Properties::default(),
+            });
+            Intercept::Continue(publish)
+        }
+    }
+
+    struct RejectAll;
+
+    impl Interceptor for RejectAll {
+        fn on_incoming_publish(
+            &self,
+            _context: &mut InterceptorContext<'_>,
+            _publish: proto::Publish,
+        ) -> Intercept<proto::Publish> {
+            Intercept::Reject("not allowed".to_string())
+        }
+    }
+
+    fn publish(topic_name: &str) -> proto::Publish {
+        proto::Publish {
+            packet_identifier_dup_qos: proto::PacketIdentifierDupQoS::AtMostOnce,
+            retain: false,
+            topic_name: topic_name.to_string(),
+            payload: Bytes::from("payload"),
+            properties: proto::Properties::default(),
+        }
+    }
+
+    #[test]
+    fn it_applies_interceptors_in_order() {
+        let interceptors: Vec<Box<dyn Interceptor>> =
+            vec![Box::new(Prefix("a")), Box::new(Prefix("b"))];
+        let client_id = ClientId::from("client");
+        let mut context = InterceptorContext::new(&client_id);
+
+        let result = intercept(&interceptors, &mut context, publish("topic"), |i, c, p| {
+            i.on_incoming_publish(c, p)
+        });
+
+        assert_matches!(result, Ok(publish) if publish.topic_name == "b/a/topic");
+
+        let publications = context.into_publications();
+        assert_eq!(2, publications.len());
+        assert_eq!("audit/client", publications[0].topic_name);
+        assert_eq!(Bytes::from("b/a/topic"), publications[1].payload);
+    }
+
+    #[test]
+    fn it_stops_at_rejection() {
+        let interceptors: Vec<Box<dyn Interceptor>> =
+            vec![Box::new(RejectAll), Box::new(Prefix("a"))];
+        let client_id = ClientId::from("client");
+        let mut context = InterceptorContext::new(&client_id);
+
+        let result = intercept(&interceptors, &mut context, publish("topic"), |i, c, p| {
+            i.on_incoming_publish(c, p)
+        });
+
+        assert_matches!(result, Err(Error::PacketRejected(reason)) if reason == "not allowed");
+        assert!(context.into_publications().is_empty());
+    }
+
+    #[test]
+    fn it_passes_other_packets_through() {
+        let interceptors: Vec<Box<dyn Interceptor>> = vec![Box::new(RejectAll)];
+        let client_id = ClientId::from("client");
+        let mut context = InterceptorContext::new(&client_id);
+
+        let result = intercept(&interceptors, &mut context, publish("topic"), |i, c, p| {
+            i.on_outgoing_publish(c, p)
+        });
+
+        assert_matches!(result, Ok(publish) if publish.topic_name == "topic");
+    }
+}
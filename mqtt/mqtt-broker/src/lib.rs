@@ -16,29 +16,50 @@ use std::sync::Arc;
 use mqtt3::proto;
 use serde::{Deserialize, Serialize};
 
+mod admin;
 mod auth;
 mod broker;
 mod configuration;
 mod connection;
 mod error;
+mod interceptor;
+mod metrics;
 mod persist;
+mod rate_limit;
 mod server;
 mod session;
 mod snapshot;
 mod state_change;
+mod stats;
 mod subscription;
+mod trace;
 mod transport;
 
-pub use crate::auth::{AuthId, Authenticator, Authorizer, Certificate};
-pub use crate::broker::{Broker, BrokerBuilder, BrokerHandle, BrokerState};
-pub use crate::configuration::BrokerConfig;
+pub use crate::admin::{AdminError, AdminRequest, RetainedInfo, SessionInfo, SessionStatus};
+pub use crate::auth::{
+    AclAuthorizer, AclError, Activity, AuthId, Authenticator, Authorizer, Certificate,
+    CertificateAuthenticateError, CertificateAuthenticator, CertificateChain, CertificateIdentity,
+    Credentials, PasswordAuthenticator, PasswordFile, PasswordFileError, PeerCredentials,
+    PeerCredentialsAuthenticator,
+};
+pub use crate::broker::{Broker, BrokerBuilder, BrokerHandle, BrokerState, RetainedPublication};
+pub use crate::configuration::{
+    Admin, Authentication, Authorization, BridgeCertificate, BridgeConfig, BridgeDirection,
+    BridgeTls, BridgeTopic, BrokerConfig, ClientAuth, ClientAuthMode, IdentityRateLimit, Metrics,
+    PeerIdentity, QueueFullAction, RateLimit, RateLimitAction, RateLimits, RetainedFullAction,
+    SessionPersistence, Trace, TraceOutput, Transport,
+};
 pub use crate::connection::ConnectionHandle;
 pub use crate::error::{Error, InitializeBrokerError};
+pub use crate::interceptor::{Intercept, Interceptor, InterceptorContext};
+pub use crate::metrics::{encode_metrics, metrics_content_type};
 pub use crate::persist::{
-    FileFormat, FilePersistor, NullPersistor, Persist, PersistError, VersionedFileFormat,
+    Change, FileFormat, FilePersistor, NullPersistor, Persist, PersistError, VersionedFileFormat,
+    WalPersistor,
 };
+pub use crate::rate_limit::{ConnectionRateLimit, RateLimiter};
 pub use crate::server::Server;
-pub use crate::session::SessionState;
+pub use crate::session::{SessionChange, SessionConfig, SessionState};
 pub use crate::snapshot::{Snapshotter, StateSnapshotHandle};
 pub use crate::subscription::{Segment, Subscription, TopicFilter};
 pub use crate::transport::TransportBuilder;
@@ -70,7 +91,10 @@ impl std::fmt::Display for ClientId {
 pub struct ConnReq {
     client_id: ClientId,
     connect: proto::Connect,
-    certificate: Option<Certificate>,
+    certificate: Option<CertificateChain>,
+    peer_credentials: Option<PeerCredentials>,
+    auth_id: Option<AuthId>,
+    auth_failure: Option<proto::ConnectionRefusedReason>,
     handle: ConnectionHandle,
 }
 
@@ -78,17 +102,41 @@ impl ConnReq {
     pub fn new(
         client_id: ClientId,
         connect: proto::Connect,
-        certificate: Option<Certificate>,
+        certificate: Option<CertificateChain>,
         handle: ConnectionHandle,
     ) -> Self {
         Self {
             client_id,
             connect,
             certificate,
+            peer_credentials: None,
+            auth_id: None,
+            auth_failure: None,
             handle,
         }
     }
 
+    pub fn with_peer_credentials(mut self, peer_credentials: Option<PeerCredentials>) -> Self {
+        self.peer_credentials = peer_credentials;
+        self
+    }
+
+    /// Connects with an identity the caller has already established, as the
+    /// broker's own clients and client connections which authenticated the
+    /// client themselves do. The broker does not authenticate such a client
+    /// again but still authorizes its activities.
+    pub fn with_auth_id(mut self, auth_id: AuthId) -> Self {
+        self.auth_id = Some(auth_id);
+        self
+    }
+
+    /// Refuses the client the caller failed to authenticate, once the broker
+    /// has checked the rest of the CONNECT packet.
+    pub(crate) fn with_auth_failure(mut self, reason: proto::ConnectionRefusedReason) -> Self {
+        self.auth_failure = Some(reason);
+        self
+    }
+
     pub fn client_id(&self) -> &ClientId {
         &self.client_id
     }
@@ -101,10 +149,38 @@ impl ConnReq {
         &self.handle
     }
 
-    pub fn certificate(&self) -> Option<&Certificate> {
+    pub fn certificate(&self) -> Option<&CertificateChain> {
         self.certificate.as_ref()
     }
 
+    pub fn peer_credentials(&self) -> Option<&PeerCredentials> {
+        self.peer_credentials.as_ref()
+    }
+
+    pub fn auth_id(&self) -> Option<&AuthId> {
+        self.auth_id.as_ref()
+    }
+
+    pub(crate) fn auth_failure(&self) -> Option<proto::ConnectionRefusedReason> {
+        self.auth_failure
+    }
+
+    /// Credentials to authenticate the client with.
+    ///
+    /// An explicit username takes precedence over the credentials of the
+    /// transport, so that a client can still log in as a password file user.
+    pub fn credentials(&self) -> Credentials {
+        if self.connect.username.is_some() {
+            Credentials::Basic(self.connect.username.clone(), self.connect.password.clone())
+        } else if let Some(certificate) = &self.certificate {
+            Credentials::ClientCertificate(certificate.clone())
+        } else if let Some(peer_credentials) = self.peer_credentials {
+            Credentials::PeerCredentials(peer_credentials)
+        } else {
+            Credentials::Basic(None, self.connect.password.clone())
+        }
+    }
+
     pub fn handle_mut(&mut self) -> &mut ConnectionHandle {
         &mut self.handle
     }
@@ -179,13 +255,29 @@ pub enum ClientEvent {
 
     /// Publish complete (QoS 2 publish, part 3)
     PubComp(proto::PubComp),
+
+    /// Rate limit to apply to publications from a client
+    RateLimit(Option<ConnectionRateLimit>),
 }
 
 #[derive(Debug)]
 pub enum SystemEvent {
     Shutdown,
     StateSnapshot(StateSnapshotHandle),
-    // ConfigUpdate,
+    /// Periodic request to remove expired state from the broker
+    Cleanup,
+    /// Request to publish broker statistics to `$SYS/broker/` topics
+    PublishStats,
+    /// Request to apply an updated broker configuration, including the
+    /// authentication settings and the authorization policy
+    ConfigUpdate(BrokerConfig),
+    /// Administrative request to inspect or manage the broker
+    Admin(AdminRequest),
+    /// Publication the broker publishes on its own behalf
+    Publish(proto::Publication),
+    /// Trace record to publish to a `$SYS/trace/` topic. Unlike `Publish`,
+    /// it is neither retained nor persisted.
+    PublishTrace(proto::Publication),
 }
 
 #[derive(Debug)]
@@ -0,0 +1,160 @@
+use std::convert::TryFrom;
+
+use lazy_static::lazy_static;
+use mqtt3::proto::{self, DecodeError};
+use prometheus::{
+    exponential_buckets, register_histogram, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, Encoder, Histogram, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+
+lazy_static! {
+    pub(crate) static ref CONNECTIONS: IntCounterVec = register_int_counter_vec!(
+        "mqtt_broker_connections_total",
+        "Number of client connection requests by CONNACK return code",
+        &["return_code"]
+    )
+    .expect("failed to register metric");
+    // processing a publication takes microseconds, from 1us up to 262ms
+    pub(crate) static ref PUBLISH_DURATION: Histogram = register_histogram!(
+        "mqtt_broker_publish_duration_seconds",
+        "Time the broker takes to process a publication from a client",
+        exponential_buckets(1e-6, 4.0, 10).expect("failed to create buckets")
+    )
+    .expect("failed to register metric");
+    pub(crate) static ref SESSIONS: IntGaugeVec = register_int_gauge_vec!(
+        "mqtt_broker_sessions",
+        "Number of sessions by state",
+        &["state"]
+    )
+    .expect("failed to register metric");
+    pub(crate) static ref QUEUED_MESSAGES: IntGauge = register_int_gauge!(
+        "mqtt_broker_queued_messages",
+        "Number of publications waiting to be sent to clients"
+    )
+    .expect("failed to register metric");
+    pub(crate) static ref MAX_QUEUED_MESSAGES: IntGauge = register_int_gauge!(
+        "mqtt_broker_max_queued_messages",
+        "Largest number of publications waiting to be sent to a single client"
+    )
+    .expect("failed to register metric");
+    pub(crate) static ref SNAPSHOT_DURATION: Histogram = register_histogram!(
+        "mqtt_broker_snapshot_duration_seconds",
+        "Time taken to persist a state snapshot"
+    )
+    .expect("failed to register metric");
+    pub(crate) static ref SNAPSHOT_SIZE: IntGauge = register_int_gauge!(
+        "mqtt_broker_snapshot_size_bytes",
+        "Size of the last persisted state file"
+    )
+    .expect("failed to register metric");
+    pub(crate) static ref DECODE_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "mqtt_broker_decode_errors_total",
+        "Number of packets from clients which failed to decode by error kind",
+        &["kind"]
+    )
+    .expect("failed to register metric");
+}
+
+/// Encodes all registered metrics in the Prometheus text format.
+pub fn encode_metrics() -> Result<Vec<u8>, prometheus::Error> {
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(buffer)
+}
+
+/// Content type of the encoded metrics.
+pub fn metrics_content_type() -> String {
+    TextEncoder::new().format_type().to_string()
+}
+
+pub(crate) fn connack(return_code: proto::ConnectReturnCode) {
+    let label = match return_code {
+        proto::ConnectReturnCode::Accepted => "accepted",
+        proto::ConnectReturnCode::Refused(reason) => match reason {
+            proto::ConnectionRefusedReason::UnacceptableProtocolVersion => {
+                "unacceptable_protocol_version"
+            }
+            proto::ConnectionRefusedReason::IdentifierRejected => "identifier_rejected",
+            proto::ConnectionRefusedReason::ServerUnavailable => "server_unavailable",
+            proto::ConnectionRefusedReason::BadUserNameOrPassword => "bad_username_or_password",
+            proto::ConnectionRefusedReason::NotAuthorized => "not_authorized",
+            proto::ConnectionRefusedReason::Other(_) => "other",
+        },
+    };
+    CONNECTIONS.with_label_values(&[label]).inc();
+}
+
+/// Moves a session between the session gauges when its state, given as the
+/// `state` label, or the number of publications queued for it changed.
+pub(crate) fn session_changed(before: (Option<&str>, usize), after: (Option<&str>, usize)) {
+    if before.0 != after.0 {
+        if let Some(state) = before.0 {
+            SESSIONS.with_label_values(&[state]).dec();
+        }
+        if let Some(state) = after.0 {
+            SESSIONS.with_label_values(&[state]).inc();
+        }
+    }
+    if before.1 != after.1 {
+        QUEUED_MESSAGES.add(gauge(after.1) - gauge(before.1));
+    }
+}
+
+pub(crate) fn gauge(value: usize) -> i64 {
+    i64::try_from(value).unwrap_or(i64::max_value())
+}
+
+pub(crate) fn decode_error(e: &DecodeError) {
+    let label = match e {
+        DecodeError::ConnectReservedSet => "connect_reserved_set",
+        DecodeError::ConnectZeroLengthIdWithExistingSession => {
+            "connect_zero_length_id_with_existing_session"
+        }
+        DecodeError::DuplicateProperty(_) => "duplicate_property",
+        DecodeError::IncompletePacket => "incomplete_packet",
+        DecodeError::Io(_) => "io",
+        DecodeError::PublishDupAtMostOnce => "publish_dup_at_most_once",
+        DecodeError::NoTopics => "no_topics",
+        DecodeError::RemainingLengthTooHigh => "remaining_length_too_high",
+        DecodeError::StringNotUtf8(_) => "string_not_utf8",
+        DecodeError::UnrecognizedConnAckFlags(_) => "unrecognized_connack_flags",
+        DecodeError::UnrecognizedPacket { .. } => "unrecognized_packet",
+        DecodeError::UnrecognizedProperty(_) => "unrecognized_property",
+        DecodeError::UnrecognizedProtocolLevel(_) => "unrecognized_protocol_level",
+        DecodeError::UnrecognizedSubscriptionOptions(_) => "unrecognized_subscription_options",
+        DecodeError::UnrecognizedProtocolName(_) => "unrecognized_protocol_name",
+        DecodeError::UnrecognizedQoS(_) => "unrecognized_qos",
+        DecodeError::ZeroPacketIdentifier => "zero_packet_identifier",
+    };
+    DECODE_ERRORS.with_label_values(&[label]).inc();
+}
+
+#[cfg(test)]
+mod tests {
+    use mqtt3::proto::{self, DecodeError};
+
+    use crate::metrics::{self, CONNECTIONS, DECODE_ERRORS};
+
+    #[test]
+    fn it_counts_connections_by_return_code() {
+        let refused = CONNECTIONS.with_label_values(&["not_authorized"]).get();
+
+        metrics::connack(proto::ConnectReturnCode::Refused(
+            proto::ConnectionRefusedReason::NotAuthorized,
+        ));
+
+        assert_eq!(
+            refused + 1,
+            CONNECTIONS.with_label_values(&["not_authorized"]).get()
+        );
+    }
+
+    #[test]
+    fn it_encodes_metrics_as_text() {
+        metrics::decode_error(&DecodeError::NoTopics);
+        assert!(DECODE_ERRORS.with_label_values(&["no_topics"]).get() > 0);
+
+        let text = String::from_utf8(metrics::encode_metrics().unwrap()).unwrap();
+        assert!(text.contains("mqtt_broker_decode_errors_total{kind=\"no_topics\"}"));
+    }
+}
@@ -1,5 +1,6 @@
 use std::cmp;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fs::{self, OpenOptions};
 use std::io::{Read, Write};
 use std::iter::FromIterator;
@@ -8,6 +9,7 @@ use std::os::unix::fs::symlink;
 #[cfg(windows)]
 use std::os::windows::fs::symlink_file;
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 
 use async_trait::async_trait;
 use bytes::Bytes;
@@ -15,15 +17,22 @@ use fail::fail_point;
 use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
-use mqtt3::proto::Publication;
+use mqtt3::proto::{Properties, Publication};
 use serde::ser::SerializeMap;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use tracing::{debug, info, span, Level};
 
-use crate::session::SessionState;
+use crate::auth::AuthId;
+use crate::metrics;
+use crate::session::{SessionChange, SessionState};
 use crate::subscription::Subscription;
 use crate::BrokerState;
 use crate::ClientId;
+use crate::RetainedPublication;
+
+mod wal;
+
+pub use wal::WalPersistor;
 
 /// sets the number of past states to save - 2 means we save the current and the pervious
 const STATE_DEFAULT_PREVIOUS_COUNT: usize = 2;
@@ -37,6 +46,27 @@ pub trait Persist {
     async fn load(&mut self) -> Result<Option<BrokerState>, Self::Error>;
 
     async fn store(&mut self, state: BrokerState) -> Result<(), Self::Error>;
+
+    /// Records changes made to the state since the last stored state.
+    ///
+    /// The broker only sends changes when it is configured to journal them,
+    /// persistors which do not keep a journal ignore them.
+    async fn append(&mut self, changes: Vec<Change>) -> Result<(), Self::Error>;
+}
+
+/// A change made to the persisted part of the broker state.
+///
+/// Changes are recorded as the broker makes them, so that replaying them
+/// in order onto the state they were made to gives the current state.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Change {
+    RetainedStored(String, RetainedPublication),
+    RetainedRemoved(String),
+
+    /// A session is persisted for the first time or replaced by a new one.
+    SessionStored(SessionState),
+    SessionRemoved(ClientId),
+    Session(ClientId, SessionChange),
 }
 
 /// A persistor that does nothing.
@@ -54,6 +84,10 @@ impl Persist for NullPersistor {
     async fn store(&mut self, _: BrokerState) -> Result<(), Self::Error> {
         Ok(())
     }
+
+    async fn append(&mut self, _: Vec<Change>) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
 
 /// An abstraction over the broker state's file format.
@@ -110,19 +144,21 @@ impl<F> FilePersistor<F> {
 
 #[derive(Deserialize, Serialize)]
 enum VersionedState {
-    V1(ConsolidatedState),
+    V1(ConsolidatedStateV1),
+    V2(ConsolidatedState),
 }
 
 impl From<BrokerState> for VersionedState {
     fn from(state: BrokerState) -> Self {
-        VersionedState::V1(state.into())
+        VersionedState::V2(state.into())
     }
 }
 
 impl From<VersionedState> for BrokerState {
     fn from(state: VersionedState) -> Self {
         match state {
-            VersionedState::V1(state) => state.into(),
+            VersionedState::V1(state) => ConsolidatedState::from(state).into(),
+            VersionedState::V2(state) => state.into(),
         }
     }
 }
@@ -173,17 +209,28 @@ impl From<BrokerState> for ConsolidatedState {
                 qos: publication.qos,
                 retain: publication.retain,
                 payload: id,
+                properties: publication.properties,
             }
         };
 
         let retained = retained
             .into_iter()
-            .map(|(topic, publication)| (topic, shrink_payload(publication)))
+            .map(|(topic, retained)| {
+                let (publication, stored_at) = retained.into_parts();
+                let retained = SimplifiedRetainedPublication {
+                    publication: shrink_payload(publication),
+                    stored_at,
+                };
+                (topic, retained)
+            })
             .collect();
 
         let sessions = sessions
             .into_iter()
             .map(|session| {
+                let auth_id = session.auth_id().clone();
+                let last_active = session.last_active();
+                let session_expiry_interval = session.session_expiry_interval();
                 let (client_id, subscriptions, waiting_to_be_sent) = session.into_parts();
 
                 #[allow(clippy::redundant_closure)] // removing closure leads to borrow error
@@ -194,8 +241,11 @@ impl From<BrokerState> for ConsolidatedState {
 
                 ConsolidatedSession {
                     client_id,
+                    auth_id,
                     subscriptions,
                     waiting_to_be_sent,
+                    last_active,
+                    session_expiry_interval,
                 }
             })
             .collect();
@@ -231,11 +281,16 @@ impl From<ConsolidatedState> for BrokerState {
                 .get(&publication.payload)
                 .expect("corrupted data")
                 .clone(),
+            properties: publication.properties,
         };
 
         let retained = retained
             .into_iter()
-            .map(|(topic, publication)| (topic, expand_payload(publication)))
+            .map(|(topic, retained)| {
+                let publication = expand_payload(retained.publication);
+                let retained = RetainedPublication::from_parts(publication, retained.stored_at);
+                (topic, retained)
+            })
             .collect();
 
         #[allow(clippy::redundant_closure)] // removing closure leads to borrow error
@@ -252,6 +307,9 @@ impl From<ConsolidatedState> for BrokerState {
                     session.subscriptions,
                     waiting_to_be_sent,
                 )
+                .with_auth_id(session.auth_id)
+                .with_last_active(session.last_active)
+                .with_session_expiry_interval(session.session_expiry_interval)
             })
             .collect();
 
@@ -264,15 +322,87 @@ struct ConsolidatedState {
     #[serde(serialize_with = "serialize_payloads")]
     #[serde(deserialize_with = "deserialize_payloads")]
     payloads: HashMap<u64, Bytes>,
-    retained: HashMap<String, SimplifiedPublication>,
+    retained: HashMap<String, SimplifiedRetainedPublication>,
     sessions: Vec<ConsolidatedSession>,
 }
 
+/// State format used before publications carried MQTT 5.0 properties,
+/// retained messages and sessions carried timestamps and sessions
+/// carried an expiry interval.
+#[derive(Deserialize, Serialize)]
+struct ConsolidatedStateV1 {
+    #[serde(serialize_with = "serialize_payloads")]
+    #[serde(deserialize_with = "deserialize_payloads")]
+    payloads: HashMap<u64, Bytes>,
+    retained: HashMap<String, SimplifiedPublicationV1>,
+    sessions: Vec<ConsolidatedSessionV1>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ConsolidatedSessionV1 {
+    client_id: ClientId,
+    subscriptions: HashMap<String, Subscription>,
+    waiting_to_be_sent: Vec<SimplifiedPublicationV1>,
+}
+
+impl From<ConsolidatedStateV1> for ConsolidatedState {
+    fn from(state: ConsolidatedStateV1) -> Self {
+        // the original timestamps are unknown, so the expiration period
+        // of retained messages and offline sessions starts over from the moment of load
+        let stored_at = SystemTime::now();
+        let retained = state
+            .retained
+            .into_iter()
+            .map(|(topic, publication)| {
+                let retained = SimplifiedRetainedPublication {
+                    publication: publication.into(),
+                    stored_at,
+                };
+                (topic, retained)
+            })
+            .collect();
+
+        let sessions = state
+            .sessions
+            .into_iter()
+            .map(|session| ConsolidatedSession {
+                client_id: session.client_id,
+                // the identity of the client is unknown, so publications queued while
+                // it is offline are authorized as anonymous until it connects again
+                auth_id: AuthId::Anonymous,
+                subscriptions: session.subscriptions,
+                waiting_to_be_sent: session
+                    .waiting_to_be_sent
+                    .into_iter()
+                    .map(SimplifiedPublication::from)
+                    .collect(),
+                last_active: stored_at,
+                session_expiry_interval: None,
+            })
+            .collect();
+
+        ConsolidatedState {
+            payloads: state.payloads,
+            retained,
+            sessions,
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 struct ConsolidatedSession {
     client_id: ClientId,
+    auth_id: AuthId,
     subscriptions: HashMap<String, Subscription>,
     waiting_to_be_sent: Vec<SimplifiedPublication>,
+    last_active: SystemTime,
+    session_expiry_interval: Option<Duration>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct SimplifiedRetainedPublication {
+    publication: SimplifiedPublication,
+    stored_at: SystemTime,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -281,6 +411,27 @@ struct SimplifiedPublication {
     qos: crate::proto::QoS,
     retain: bool,
     payload: u64,
+    properties: Properties,
+}
+
+#[derive(Deserialize, Serialize)]
+struct SimplifiedPublicationV1 {
+    topic_name: String,
+    qos: crate::proto::QoS,
+    retain: bool,
+    payload: u64,
+}
+
+impl From<SimplifiedPublicationV1> for SimplifiedPublication {
+    fn from(publication: SimplifiedPublicationV1) -> Self {
+        SimplifiedPublication {
+            topic_name: publication.topic_name,
+            qos: publication.qos,
+            retain: publication.retain,
+            payload: publication.payload,
+            properties: Properties::default(),
+        }
+    }
 }
 
 fn serialize_payloads<S>(payloads: &HashMap<u64, Bytes>, serializer: S) -> Result<S::Ok, S::Error>
@@ -397,6 +548,10 @@ where
             match format.store(file, state) {
                 Ok(_) => {
                     debug!("state persisted to {}.", path.display());
+                    if let Ok(metadata) = fs::metadata(&path) {
+                        let size = i64::try_from(metadata.len()).unwrap_or(i64::max_value());
+                        metrics::SNAPSHOT_SIZE.set(size);
+                    }
 
                     // Swap the symlink
                     //   - remove the old link if it exists
@@ -500,6 +655,11 @@ where
         });
         res.map_err(|e| PersistError::TaskJoin(Some(e)))?
     }
+
+    /// Every store writes the whole state, so changes in between are not needed.
+    async fn append(&mut self, _: Vec<Change>) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -513,6 +673,15 @@ pub enum PersistError {
     #[error("failed to remove file {0}")]
     FileUnlink(PathBuf, #[source] Option<std::io::Error>),
 
+    #[error("failed to read file {0}")]
+    FileRead(PathBuf, #[source] Option<std::io::Error>),
+
+    #[error("failed to write file {0}")]
+    FileWrite(PathBuf, #[source] Option<std::io::Error>),
+
+    #[error("file {0} is corrupted: {1}")]
+    Corrupted(PathBuf, String),
+
     #[error("failed to create state directory {0}")]
     CreateDir(PathBuf, #[source] Option<std::io::Error>),
 
@@ -537,15 +706,23 @@ pub enum PersistError {
 
 #[cfg(test)]
 pub(crate) mod tests {
+    use std::collections::HashMap;
     use std::io::Cursor;
+    use std::time::SystemTime;
 
+    use bytes::Bytes;
+    use flate2::{write::GzEncoder, Compression};
     use proptest::prelude::*;
     use tempfile::TempDir;
 
     use crate::{
-        persist::{ConsolidatedState, FileFormat, FilePersistor, Persist, VersionedFileFormat},
+        persist::{
+            ConsolidatedSessionV1, ConsolidatedState, ConsolidatedStateV1, FileFormat,
+            FilePersistor, Persist, SimplifiedPublicationV1, VersionedFileFormat, VersionedState,
+        },
         proptest::arb_broker_state,
-        BrokerState,
+        proto::{Properties, QoS},
+        AuthId, BrokerState,
     };
 
     proptest! {
@@ -564,6 +741,9 @@ pub(crate) mod tests {
             prop_assert_eq!(expected_sessions.len(), result_sessions.len());
             for i in 0..expected_sessions.len(){
                 prop_assert_eq!(expected_sessions[i].clone().into_parts(), result_sessions[i].clone().into_parts());
+                prop_assert_eq!(expected_sessions[i].auth_id(), result_sessions[i].auth_id());
+                prop_assert_eq!(expected_sessions[i].last_active(), result_sessions[i].last_active());
+                prop_assert_eq!(expected_sessions[i].session_expiry_interval(), result_sessions[i].session_expiry_interval());
             }
         }
 
@@ -583,10 +763,69 @@ pub(crate) mod tests {
             prop_assert_eq!(expected_sessions.len(), result_sessions.len());
             for i in 0..expected_sessions.len(){
                 prop_assert_eq!(expected_sessions[i].clone().into_parts(), result_sessions[i].clone().into_parts());
+                prop_assert_eq!(expected_sessions[i].auth_id(), result_sessions[i].auth_id());
+                prop_assert_eq!(expected_sessions[i].last_active(), result_sessions[i].last_active());
+                prop_assert_eq!(expected_sessions[i].session_expiry_interval(), result_sessions[i].session_expiry_interval());
             }
         }
     }
 
+    #[test]
+    fn load_v1_state() {
+        let mut payloads = HashMap::new();
+        payloads.insert(0, Bytes::from("payload"));
+
+        let mut retained = HashMap::new();
+        retained.insert(
+            "topic".to_string(),
+            SimplifiedPublicationV1 {
+                topic_name: "topic".to_string(),
+                qos: QoS::AtLeastOnce,
+                retain: true,
+                payload: 0,
+            },
+        );
+
+        let sessions = vec![ConsolidatedSessionV1 {
+            client_id: "client".into(),
+            subscriptions: HashMap::new(),
+            waiting_to_be_sent: vec![SimplifiedPublicationV1 {
+                topic_name: "topic".to_string(),
+                qos: QoS::AtMostOnce,
+                retain: false,
+                payload: 0,
+            }],
+        }];
+
+        let state = VersionedState::V1(ConsolidatedStateV1 {
+            payloads,
+            retained,
+            sessions,
+        });
+
+        let mut buffer = vec![];
+        let encoder = GzEncoder::new(&mut buffer, Compression::default());
+        bincode::serialize_into(encoder, &state).unwrap();
+
+        let loaded_at = SystemTime::now();
+        let state = VersionedFileFormat.load(Cursor::new(buffer)).unwrap();
+        let (retained, sessions) = state.into_parts();
+
+        let publication = retained["topic"].publication();
+        assert_eq!(publication.topic_name, "topic");
+        assert_eq!(publication.payload, Bytes::from("payload"));
+        assert_eq!(publication.properties, Properties::default());
+        assert!(retained["topic"].stored_at() >= loaded_at);
+
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions[0].last_active() >= loaded_at);
+        assert_eq!(sessions[0].session_expiry_interval(), None);
+        assert_eq!(sessions[0].auth_id(), &AuthId::Anonymous);
+
+        let (_, _, waiting_to_be_sent) = sessions[0].clone().into_parts();
+        assert_eq!(waiting_to_be_sent[0].payload, Bytes::from("payload"));
+    }
+
     #[tokio::test]
     async fn filepersistor_smoketest() {
         let tmp_dir = TempDir::new().unwrap();
@@ -0,0 +1,761 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use fail::fail_point;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, span, warn, Level};
+
+use crate::persist::{Change, Persist, PersistError};
+use crate::session::SessionState;
+use crate::{BrokerState, ClientId, RetainedPublication};
+
+/// sets the number of batches appended to a log before the next stored state compacts it
+const DEFAULT_COMPACTION_THRESHOLD: usize = 1000;
+
+/// version of the batch header and of the snapshot, bumped whenever either format changes
+const FORMAT_VERSION: u8 = 1;
+
+/// version, length and checksum of the batch
+const BATCH_HEADER_LEN: usize = 9;
+
+static SNAPSHOT_FILE: &str = "snapshot.dat";
+static SNAPSHOT_TEMP_FILE: &str = "snapshot.dat.tmp";
+static LOG_STEM: &str = "wal";
+static LOG_EXTENSION: &str = "log";
+
+/// Loads/stores the broker state as a snapshot and an append-only log of changes.
+///
+/// The broker journals every change to the persisted state as it makes it.
+/// Changes sent together are appended to the current log file as one batch
+/// with a header carrying the format version, the length and a checksum of the batch.
+/// A batch cut short at the end of a log is what a crash in the middle of appending
+/// leaves behind, so it is discarded on load. Any other batch which does not match
+/// its header is corrupted and fails the load.
+///
+/// Stored states only compact the log. Once the number of batches in the log reaches
+/// the compaction threshold a new log file is started and the state is written to
+/// a snapshot in the background. The snapshot records the generation of the first log
+/// it does not include, so on load only the newer logs are replayed on top of it
+/// and already compacted logs are ignored even if they could not be removed.
+///
+/// If appending fails, the persistor doesn't know which changes made it to disk.
+/// It stops appending and the next stored state is written to a new snapshot instead.
+#[derive(Debug)]
+pub struct WalPersistor {
+    dir: PathBuf,
+    compaction_threshold: usize,
+
+    /// generation of the log file new batches are appended to
+    generation: u64,
+    batches: usize,
+
+    /// whether changes appended to the log continue the state on disk,
+    /// which is only known after a load or a full snapshot
+    consistent: bool,
+    compaction: Option<JoinHandle<Result<(), PersistError>>>,
+}
+
+impl WalPersistor {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        WalPersistor {
+            dir: dir.into(),
+            compaction_threshold: DEFAULT_COMPACTION_THRESHOLD,
+            generation: 0,
+            batches: 0,
+            consistent: false,
+            compaction: None,
+        }
+    }
+
+    /// Sets the number of batches appended to a log before it is compacted into a snapshot.
+    ///
+    /// The default is `1000`. The minimum value is `1`.
+    pub fn with_compaction_threshold(mut self, compaction_threshold: usize) -> Self {
+        self.compaction_threshold = cmp::max(1, compaction_threshold);
+        self
+    }
+
+    async fn wait_for_compaction(&mut self) {
+        if let Some(compaction) = self.compaction.take() {
+            match compaction.await {
+                Ok(Ok(())) => debug!("log compaction completed."),
+                Ok(Err(e)) => warn!(message = "an error occurred compacting the log", error = %e),
+                Err(e) => warn!(message = "an error occurred joining log compaction", error = %e),
+            }
+        }
+    }
+
+    async fn append_batch(&mut self, changes: Vec<Change>) -> Result<(), PersistError> {
+        let dir = self.dir.clone();
+        let path = log_path(&self.dir, self.generation);
+
+        let res = tokio::task::spawn_blocking(move || {
+            let batch = encode_batch(&changes)?;
+            append_batch(&dir, &path, &batch)
+        })
+        .await;
+
+        fail_point!("walpersistor.append.spawn_blocking", |_| {
+            Err(PersistError::TaskJoin(None))
+        });
+        res.map_err(|e| PersistError::TaskJoin(Some(e)))??;
+
+        self.batches += 1;
+        Ok(())
+    }
+
+    /// Writes the full state to a snapshot starting a new log generation.
+    async fn reset(&mut self, state: BrokerState) -> Result<(), PersistError> {
+        self.wait_for_compaction().await;
+
+        let dir = self.dir.clone();
+        let generation = self.generation;
+
+        let res = tokio::task::spawn_blocking(move || {
+            // never append to a log which may end with a torn batch
+            let generation = cmp::max(generation, next_generation(&dir)?);
+            write_snapshot(&dir, generation, &state)?;
+            prune_logs(&dir, generation)?;
+            Ok(generation)
+        })
+        .await;
+
+        fail_point!("walpersistor.reset.spawn_blocking", |_| {
+            Err(PersistError::TaskJoin(None))
+        });
+        self.generation = res.map_err(|e| PersistError::TaskJoin(Some(e)))??;
+        self.batches = 0;
+        self.consistent = true;
+        Ok(())
+    }
+
+    /// Starts a new log generation and writes the state to a snapshot in the background.
+    async fn compact(&mut self, state: BrokerState) {
+        self.wait_for_compaction().await;
+
+        self.generation += 1;
+        self.batches = 0;
+
+        let dir = self.dir.clone();
+        let generation = self.generation;
+
+        info!("compacting log into a snapshot...");
+        let compaction = tokio::task::spawn_blocking(move || {
+            write_snapshot(&dir, generation, &state)?;
+            prune_logs(&dir, generation)
+        });
+        self.compaction = Some(compaction);
+    }
+}
+
+#[async_trait]
+impl Persist for WalPersistor {
+    type Error = PersistError;
+
+    async fn load(&mut self) -> Result<Option<BrokerState>, Self::Error> {
+        self.wait_for_compaction().await;
+
+        let dir = self.dir.clone();
+        let res = tokio::task::spawn_blocking(move || load_state(&dir)).await;
+
+        fail_point!("walpersistor.load.spawn_blocking", |_| {
+            Err(PersistError::TaskJoin(None))
+        });
+        let state = match res.map_err(|e| PersistError::TaskJoin(Some(e)))?? {
+            Some((last_generation, state)) => {
+                // never append to a log which may end with a torn batch
+                self.generation = last_generation + 1;
+                Some(state.into())
+            }
+            None => None,
+        };
+
+        self.batches = 0;
+        self.consistent = true;
+        Ok(state)
+    }
+
+    async fn store(&mut self, state: BrokerState) -> Result<(), Self::Error> {
+        if !self.consistent {
+            info!("writing full state snapshot...");
+            return self.reset(state).await;
+        }
+
+        // the log already has every change the state includes
+        if self.batches >= self.compaction_threshold {
+            self.compact(state).await;
+        }
+        Ok(())
+    }
+
+    async fn append(&mut self, changes: Vec<Change>) -> Result<(), Self::Error> {
+        if !self.consistent {
+            debug!(
+                "dropping {} changes until the next full state snapshot",
+                changes.len()
+            );
+            return Ok(());
+        }
+
+        debug!("appending {} changes to the log...", changes.len());
+        let res = self.append_batch(changes).await;
+        if res.is_err() {
+            self.consistent = false;
+        }
+        res
+    }
+}
+
+/// Broker state in a form convenient to apply changes to.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct WalState {
+    retained: HashMap<String, RetainedPublication>,
+    sessions: HashMap<ClientId, SessionState>,
+}
+
+impl From<BrokerState> for WalState {
+    fn from(state: BrokerState) -> Self {
+        let (retained, sessions) = state.into_parts();
+        let sessions = sessions
+            .into_iter()
+            .map(|session| (session.client_id().clone(), session))
+            .collect();
+
+        Self { retained, sessions }
+    }
+}
+
+impl From<WalState> for BrokerState {
+    fn from(state: WalState) -> Self {
+        let sessions = state
+            .sessions
+            .into_iter()
+            .map(|(_, session)| session)
+            .collect();
+        BrokerState::new(state.retained, sessions)
+    }
+}
+
+impl WalState {
+    /// Applies a change, failing for a change of a session the state does not have.
+    fn apply(&mut self, change: Change) -> Result<(), String> {
+        match change {
+            Change::RetainedStored(topic, retained) => {
+                self.retained.insert(topic, retained);
+            }
+            Change::RetainedRemoved(topic) => {
+                self.retained.remove(&topic);
+            }
+            Change::SessionStored(session) => {
+                self.sessions.insert(session.client_id().clone(), session);
+            }
+            Change::SessionRemoved(client_id) => {
+                self.sessions.remove(&client_id);
+            }
+            Change::Session(client_id, change) => match self.sessions.get_mut(&client_id) {
+                Some(session) => session.apply(change),
+                None => return Err(format!("change of unknown session {}", client_id)),
+            },
+        }
+        Ok(())
+    }
+}
+
+fn log_path(dir: &Path, generation: u64) -> PathBuf {
+    dir.join(format!("{}.{:020}.{}", LOG_STEM, generation, LOG_EXTENSION))
+}
+
+fn encode_batch(changes: &[Change]) -> Result<Vec<u8>, PersistError> {
+    fail_point!("walpersistor.append.serialize", |_| {
+        Err(PersistError::Serialize(None))
+    });
+    let body = bincode::serialize(changes).map_err(|e| PersistError::Serialize(Some(e)))?;
+    let len = u32::try_from(body.len())
+        .map_err(|_| PersistError::Serialize(Some(Box::new(bincode::ErrorKind::SizeLimit))))?;
+
+    let mut batch = Vec::with_capacity(BATCH_HEADER_LEN + body.len());
+    batch.push(FORMAT_VERSION);
+    batch.extend_from_slice(&len.to_le_bytes());
+    batch.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+    batch.extend_from_slice(&body);
+    Ok(batch)
+}
+
+fn append_batch(dir: &Path, path: &Path, batch: &[u8]) -> Result<(), PersistError> {
+    if !dir.exists() {
+        fail_point!("walpersistor.append.createdir", |_| {
+            Err(PersistError::CreateDir(dir.to_path_buf(), None))
+        });
+        fs::create_dir_all(dir).map_err(|e| PersistError::CreateDir(dir.to_path_buf(), Some(e)))?;
+    }
+
+    fail_point!("walpersistor.append.fileopen", |_| {
+        Err(PersistError::FileOpen(path.to_path_buf(), None))
+    });
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| PersistError::FileOpen(path.to_path_buf(), Some(e)))?;
+
+    fail_point!("walpersistor.append.write", |_| {
+        Err(PersistError::FileWrite(path.to_path_buf(), None))
+    });
+    fail_point!("walpersistor.append.torn_write", |_| {
+        let _ = file.write_all(&batch[..batch.len() / 2]);
+        Err(PersistError::FileWrite(path.to_path_buf(), None))
+    });
+    file.write_all(batch)
+        .map_err(|e| PersistError::FileWrite(path.to_path_buf(), Some(e)))?;
+
+    fail_point!("walpersistor.append.sync", |_| {
+        Err(PersistError::FileWrite(path.to_path_buf(), None))
+    });
+    file.sync_data()
+        .map_err(|e| PersistError::FileWrite(path.to_path_buf(), Some(e)))
+}
+
+/// Reads as many bytes as are left up to the length of `buf`.
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+/// Replays the batches of a log file onto the state.
+///
+/// A batch cut short at the end of the file is discarded,
+/// a batch which does not match its header fails the replay.
+fn replay_log(path: &Path, state: &mut WalState) -> Result<(), PersistError> {
+    let corrupted = |reason: String| PersistError::Corrupted(path.to_path_buf(), reason);
+
+    fail_point!("walpersistor.load.log_fileopen", |_| {
+        Err(PersistError::FileOpen(path.to_path_buf(), None))
+    });
+    let file = File::open(path).map_err(|e| PersistError::FileOpen(path.to_path_buf(), Some(e)))?;
+    let mut reader = BufReader::new(file);
+
+    loop {
+        fail_point!("walpersistor.load.log_read", |_| {
+            Err(PersistError::FileRead(path.to_path_buf(), None))
+        });
+
+        let mut header = [0_u8; BATCH_HEADER_LEN];
+        let read = read_up_to(&mut reader, &mut header)
+            .map_err(|e| PersistError::FileRead(path.to_path_buf(), Some(e)))?;
+        if read == 0 {
+            break;
+        }
+        if read < header.len() {
+            warn!("discarding torn batch at the end of {}", path.display());
+            break;
+        }
+
+        if header[0] != FORMAT_VERSION {
+            return Err(corrupted(format!(
+                "unsupported batch version {}",
+                header[0]
+            )));
+        }
+
+        let mut len = [0_u8; 4];
+        len.copy_from_slice(&header[1..5]);
+        let len = u64::from(u32::from_le_bytes(len));
+
+        let mut checksum = [0_u8; 4];
+        checksum.copy_from_slice(&header[5..9]);
+        let checksum = u32::from_le_bytes(checksum);
+
+        let mut body = vec![];
+        reader
+            .by_ref()
+            .take(len)
+            .read_to_end(&mut body)
+            .map_err(|e| PersistError::FileRead(path.to_path_buf(), Some(e)))?;
+        if (body.len() as u64) < len {
+            warn!("discarding torn batch at the end of {}", path.display());
+            break;
+        }
+
+        if crc32fast::hash(&body) != checksum {
+            return Err(corrupted("batch checksum mismatch".to_string()));
+        }
+
+        let changes = bincode::deserialize::<Vec<Change>>(&body)
+            .map_err(|e| corrupted(format!("batch does not deserialize: {}", e)))?;
+        for change in changes {
+            state.apply(change).map_err(corrupted)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns log generations in the directory in ascending order.
+fn list_logs(dir: &Path) -> Result<Vec<(u64, PathBuf)>, PersistError> {
+    fail_point!("walpersistor.readdir", |_| {
+        Err(PersistError::ReadDir(dir.to_path_buf(), None))
+    });
+    let entries =
+        fs::read_dir(dir).map_err(|e| PersistError::ReadDir(dir.to_path_buf(), Some(e)))?;
+
+    let mut logs = vec![];
+    for entry in entries {
+        let entry = entry.map_err(|e| PersistError::ReadDir(dir.to_path_buf(), Some(e)))?;
+        let path = entry.path();
+
+        let generation = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(parse_log_generation);
+
+        if let Some(generation) = generation {
+            logs.push((generation, path));
+        }
+    }
+
+    logs.sort();
+    Ok(logs)
+}
+
+fn parse_log_generation(name: &str) -> Option<u64> {
+    let mut parts = name.split('.');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(stem), Some(generation), Some(extension), None)
+            if stem == LOG_STEM && extension == LOG_EXTENSION =>
+        {
+            generation.parse().ok()
+        }
+        _ => None,
+    }
+}
+
+fn next_generation(dir: &Path) -> Result<u64, PersistError> {
+    if !dir.exists() {
+        return Ok(0);
+    }
+
+    let logs = list_logs(dir)?;
+    Ok(logs.last().map_or(0, |(generation, _)| generation + 1))
+}
+
+/// Removes logs already included in the snapshot of the given generation.
+fn prune_logs(dir: &Path, generation: u64) -> Result<(), PersistError> {
+    for (log_generation, path) in list_logs(dir)? {
+        if log_generation < generation {
+            fail_point!("walpersistor.prune.unlink", |_| {
+                Err(PersistError::FileUnlink(path.clone(), None))
+            });
+            fs::remove_file(&path).map_err(|e| PersistError::FileUnlink(path.clone(), Some(e)))?;
+            debug!("{} pruned.", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the snapshot as a header with the format version and the generation,
+/// followed by the whole state, including publications in flight.
+fn write_snapshot(dir: &Path, generation: u64, state: &BrokerState) -> Result<(), PersistError> {
+    let span = span!(Level::INFO, "persistor", dir = %dir.display());
+    let _guard = span.enter();
+
+    if !dir.exists() {
+        fail_point!("walpersistor.snapshot.createdir", |_| {
+            Err(PersistError::CreateDir(dir.to_path_buf(), None))
+        });
+        fs::create_dir_all(dir).map_err(|e| PersistError::CreateDir(dir.to_path_buf(), Some(e)))?;
+    }
+
+    let path = dir.join(SNAPSHOT_FILE);
+    let temp_path = dir.join(SNAPSHOT_TEMP_FILE);
+
+    fail_point!("walpersistor.snapshot.fileopen", |_| {
+        Err(PersistError::FileOpen(temp_path.clone(), None))
+    });
+    let mut file =
+        File::create(&temp_path).map_err(|e| PersistError::FileOpen(temp_path.clone(), Some(e)))?;
+
+    file.write_all(&[FORMAT_VERSION])
+        .and_then(|_| file.write_all(&generation.to_le_bytes()))
+        .map_err(|e| PersistError::FileWrite(temp_path.clone(), Some(e)))?;
+
+    fail_point!("walpersistor.snapshot.serialize", |_| {
+        Err(PersistError::Serialize(None))
+    });
+    let mut encoder = GzEncoder::new(file, Compression::default());
+    bincode::serialize_into(&mut encoder, state).map_err(|e| PersistError::Serialize(Some(e)))?;
+    let file = encoder
+        .finish()
+        .map_err(|e| PersistError::FileWrite(temp_path.clone(), Some(e)))?;
+
+    fail_point!("walpersistor.snapshot.sync", |_| {
+        Err(PersistError::FileWrite(temp_path.clone(), None))
+    });
+    file.sync_all()
+        .map_err(|e| PersistError::FileWrite(temp_path.clone(), Some(e)))?;
+
+    // the rename "commits" the snapshot
+    fail_point!("walpersistor.snapshot.filerename", |_| {
+        Err(PersistError::FileRename(
+            temp_path.clone(),
+            path.clone(),
+            None,
+        ))
+    });
+    fs::rename(&temp_path, &path)
+        .map_err(|e| PersistError::FileRename(temp_path.clone(), path.clone(), Some(e)))?;
+
+    info!(message = "persisted snapshot.", file = %path.display(), generation = generation);
+    Ok(())
+}
+
+fn read_snapshot(dir: &Path) -> Result<Option<(u64, BrokerState)>, PersistError> {
+    let path = dir.join(SNAPSHOT_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    info!("loading snapshot from file {}.", path.display());
+    fail_point!("walpersistor.load.snapshot_fileopen", |_| {
+        Err(PersistError::FileOpen(path.clone(), None))
+    });
+    let mut file = File::open(&path).map_err(|e| PersistError::FileOpen(path.clone(), Some(e)))?;
+
+    let mut header = [0_u8; 9];
+    file.read_exact(&mut header)
+        .map_err(|e| PersistError::FileRead(path.clone(), Some(e)))?;
+    if header[0] != FORMAT_VERSION {
+        return Err(PersistError::Corrupted(
+            path,
+            format!("unsupported snapshot version {}", header[0]),
+        ));
+    }
+
+    let mut generation = [0_u8; 8];
+    generation.copy_from_slice(&header[1..]);
+
+    fail_point!("walpersistor.load.snapshot_deserialize", |_| {
+        Err(PersistError::Deserialize(None))
+    });
+    let state = bincode::deserialize_from(GzDecoder::new(file))
+        .map_err(|e| PersistError::Deserialize(Some(e)))?;
+    Ok(Some((u64::from_le_bytes(generation), state)))
+}
+
+/// Loads the snapshot and replays the logs which are not included in it.
+///
+/// Returns the generation of the last log replayed along with the state.
+fn load_state(dir: &Path) -> Result<Option<(u64, WalState)>, PersistError> {
+    if !dir.exists() {
+        info!("no state found at {}.", dir.display());
+        return Ok(None);
+    }
+
+    let snapshot = read_snapshot(dir)?;
+    let logs = list_logs(dir)?;
+    if snapshot.is_none() && logs.is_empty() {
+        info!("no state found at {}.", dir.display());
+        return Ok(None);
+    }
+
+    let (first_generation, mut state) = match snapshot {
+        Some((generation, state)) => (generation, WalState::from(state)),
+        None => (0, WalState::default()),
+    };
+
+    let mut last_generation = first_generation;
+    for (generation, path) in logs {
+        if generation >= first_generation {
+            debug!("replaying log {}...", path.display());
+            replay_log(&path, &mut state)?;
+            last_generation = generation;
+        }
+    }
+
+    Ok(Some((last_generation, state)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::time::SystemTime;
+
+    use matches::assert_matches;
+    use proptest::prelude::*;
+    use tempfile::TempDir;
+
+    use crate::persist::{Change, Persist, PersistError};
+    use crate::proptest::{arb_broker_state, arb_change};
+    use crate::{proto, BrokerState, RetainedPublication};
+
+    use super::{encode_batch, log_path, WalPersistor, WalState};
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Append(Vec<Change>),
+        Store,
+    }
+
+    fn arb_op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            proptest::collection::vec(arb_change(), 0..3).prop_map(Op::Append),
+            Just(Op::Store),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn append_load_roundtrip(
+            state in arb_broker_state(),
+            ops in proptest::collection::vec(arb_op(), 1..10),
+        ) {
+            tokio::runtime::Builder::new()
+                .basic_scheduler()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(async {
+                    let tmp_dir = TempDir::new().unwrap();
+                    let path = tmp_dir.path().to_owned();
+                    let mut persistor = WalPersistor::new(path.clone()).with_compaction_threshold(3);
+
+                    let mut expected = WalState::from(state.clone());
+                    persistor.store(state).await.unwrap();
+
+                    for op in ops {
+                        match op {
+                            Op::Append(changes) => {
+                                for change in changes.clone() {
+                                    expected.apply(change).unwrap();
+                                }
+                                persistor.append(changes).await.unwrap();
+                            }
+                            Op::Store => {
+                                persistor.store(expected.clone().into()).await.unwrap();
+                            }
+                        }
+                    }
+
+                    let state = persistor.load().await.unwrap().unwrap();
+                    assert_eq!(expected, WalState::from(state));
+
+                    let mut persistor = WalPersistor::new(path);
+                    let state = persistor.load().await.unwrap().unwrap();
+                    assert_eq!(expected, WalState::from(state));
+                });
+        }
+    }
+
+    #[tokio::test]
+    async fn walpersistor_smoketest() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().to_owned();
+        let mut persistor = WalPersistor::new(path.clone());
+
+        assert_eq!(None, persistor.load().await.unwrap());
+
+        persistor.store(BrokerState::default()).await.unwrap();
+        let state = WalPersistor::new(path).load().await.unwrap().unwrap();
+        assert_eq!(BrokerState::default(), state);
+    }
+
+    fn retained_change() -> Change {
+        let publication = proto::Publication {
+            topic_name: "topic".to_string(),
+            qos: proto::QoS::AtLeastOnce,
+            retain: true,
+            payload: "payload".into(),
+            properties: proto::Properties::default(),
+        };
+        let retained = RetainedPublication::from_parts(publication, SystemTime::now());
+        Change::RetainedStored("topic".to_string(), retained)
+    }
+
+    #[tokio::test]
+    async fn load_discards_torn_batch() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().to_owned();
+        let mut persistor = WalPersistor::new(path.clone());
+
+        persistor.load().await.unwrap();
+        let change = retained_change();
+        persistor.append(vec![change.clone()]).await.unwrap();
+
+        let batch = encode_batch(&[change.clone()]).unwrap();
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(log_path(&path, 0))
+            .unwrap();
+        file.write_all(&batch[..batch.len() - 1]).unwrap();
+
+        let mut expected = WalState::default();
+        expected.apply(change).unwrap();
+
+        let state = WalPersistor::new(path).load().await.unwrap().unwrap();
+        assert_eq!(expected, WalState::from(state));
+    }
+
+    #[tokio::test]
+    async fn load_fails_on_corrupted_batch() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().to_owned();
+        let mut persistor = WalPersistor::new(path.clone());
+
+        persistor.load().await.unwrap();
+        persistor.append(vec![retained_change()]).await.unwrap();
+
+        let log = log_path(&path, 0);
+        let mut bytes = fs::read(&log).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(&log, &bytes).unwrap();
+
+        let result = WalPersistor::new(path.clone()).load().await;
+        assert_matches!(result, Err(PersistError::Corrupted(_, _)));
+
+        bytes[last] ^= 0xff;
+        bytes[0] += 1;
+        fs::write(&log, &bytes).unwrap();
+
+        let result = WalPersistor::new(path).load().await;
+        assert_matches!(result, Err(PersistError::Corrupted(_, _)));
+    }
+
+    #[tokio::test]
+    async fn append_waits_for_known_state() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().to_owned();
+        let mut persistor = WalPersistor::new(path.clone());
+
+        // nothing is known about the state on disk before it is loaded
+        persistor.append(vec![retained_change()]).await.unwrap();
+        assert!(!log_path(&path, 0).exists());
+
+        persistor.store(BrokerState::default()).await.unwrap();
+        let change = retained_change();
+        persistor.append(vec![change.clone()]).await.unwrap();
+
+        let mut expected = WalState::default();
+        expected.apply(change).unwrap();
+
+        let state = WalPersistor::new(path).load().await.unwrap().unwrap();
+        assert_eq!(expected, WalState::from(state));
+    }
+}
@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use bytes::Bytes;
 use mqtt3::proto;
@@ -11,18 +11,44 @@ use proptest::{
 
 use crate::{
     session::{IdentifiersInUse, PacketIdentifiers},
-    BrokerState, ClientId, Publish, Segment, SessionState, Subscription, TopicFilter,
+    AuthId, BrokerState, Change, ClientId, Publish, RetainedPublication, Segment, SessionState,
+    Subscription, TopicFilter,
 };
 
 prop_compose! {
     pub fn arb_broker_state()(
-        retained in hash_map(arb_topic(), arb_publication(), 0..20),
+        retained in hash_map(arb_topic(), arb_retained_publication(), 0..20),
         sessions in vec(arb_session_state(), 0..10),
     ) -> BrokerState {
         BrokerState::new(retained, sessions)
     }
 }
 
+/// Generates changes which do not depend on the session they change being stored.
+pub fn arb_change() -> impl Strategy<Value = Change> {
+    prop_oneof![
+        (arb_topic(), arb_retained_publication())
+            .prop_map(|(topic, retained)| Change::RetainedStored(topic, retained)),
+        arb_topic().prop_map(Change::RetainedRemoved),
+        arb_session_state().prop_map(Change::SessionStored),
+        arb_clientid().prop_map(Change::SessionRemoved),
+    ]
+}
+
+prop_compose! {
+    pub fn arb_retained_publication()(
+        publication in arb_publication(),
+        stored_at in arb_system_time(),
+    ) -> RetainedPublication {
+        RetainedPublication::from_parts(publication, stored_at)
+    }
+}
+
+pub fn arb_system_time() -> impl Strategy<Value = SystemTime> {
+    (0..u64::from(u32::max_value()), 0..1_000_000_000_u32)
+        .prop_map(|(secs, nanos)| UNIX_EPOCH + Duration::new(secs, nanos))
+}
+
 prop_compose! {
     pub(crate) fn arb_packet_identifiers()(
         in_use in arb_identifiers_in_use(),
@@ -52,6 +78,9 @@ prop_compose! {
         waiting_to_be_acked in hash_map(arb_packet_identifier(), arb_publish(), 0..10),
         waiting_to_be_acked_qos0 in hash_map(arb_packet_identifier(), arb_publish(), 0..10),
         waiting_to_be_completed in hash_set(arb_packet_identifier(), 0..10),
+        auth_id in arb_auth_id(),
+        last_active in arb_system_time(),
+        session_expiry_interval in proptest::option::of(0..u64::from(u32::max_value())),
     ) -> SessionState {
         SessionState::from_state_parts(
             client_id,
@@ -64,6 +93,9 @@ prop_compose! {
             waiting_to_be_acked_qos0,
             waiting_to_be_completed,
         )
+        .with_auth_id(auth_id)
+        .with_last_active(last_active)
+        .with_session_expiry_interval(session_expiry_interval.map(Duration::from_secs))
     }
 }
 
@@ -79,8 +111,7 @@ prop_compose! {
             client_id: client_id.clone(),
             keep_alive: Duration::from_secs(1),
             protocol_name: mqtt3::PROTOCOL_NAME.into(),
-            protocol_level: mqtt3::PROTOCOL_LEVEL,
-        }
+            protocol_level: mqtt3::PROTOCOL_LEVEL, properties: proto::Properties::default(), }
     }
 }
 
@@ -91,8 +122,7 @@ prop_compose! {
     ) -> proto::Subscribe {
         proto::Subscribe {
             packet_identifier,
-            subscribe_to
-        }
+            subscribe_to, properties: proto::Properties::default(), }
     }
 }
 
@@ -115,8 +145,7 @@ prop_compose! {
     ) -> proto::Unsubscribe {
         proto::Unsubscribe {
             packet_identifier,
-            unsubscribe_from
-        }
+            unsubscribe_from, properties: proto::Properties::default(), }
     }
 }
 
@@ -128,6 +157,13 @@ pub fn arb_topic_filter_weighted() -> impl Strategy<Value = String> {
     ]
 }
 
+pub fn arb_auth_id() -> impl Strategy<Value = AuthId> {
+    prop_oneof![
+        Just(AuthId::Anonymous),
+        "[a-zA-Z0-9]{1,23}".prop_map(AuthId::from_identity)
+    ]
+}
+
 pub fn arb_username() -> impl Strategy<Value = Option<String>> {
     prop_oneof!["\\PC*".prop_map(Some), Just(None)]
 }
@@ -177,12 +213,31 @@ prop_compose! {
         qos in arb_qos(),
         retain in proptest::bool::ANY,
         payload in arb_payload(),
+        properties in arb_properties(),
     ) -> proto::Publication {
         proto::Publication {
             topic_name,
             qos,
             retain,
             payload,
+            properties,
+        }
+    }
+}
+
+prop_compose! {
+    pub fn arb_properties()(
+        message_expiry_interval in proptest::option::of(num::u32::ANY),
+        content_type in proptest::option::of("\\PC*"),
+        correlation_data in proptest::option::of(vec(num::u8::ANY, 0..16)),
+        user_properties in vec(("\\PC*", "\\PC*"), 0..4),
+    ) -> proto::Properties {
+        proto::Properties {
+            message_expiry_interval,
+            content_type,
+            correlation_data,
+            user_properties,
+            ..proto::Properties::default()
         }
     }
 }
@@ -208,8 +263,7 @@ prop_compose! {
             packet_identifier_dup_qos: pidq,
             retain,
             topic_name,
-            payload,
-        }
+            payload, properties: proto::Properties::default(), }
     }
 }
 
@@ -0,0 +1,327 @@
+use std::cmp;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use mqtt3::proto;
+
+use crate::configuration::{RateLimit, RateLimitAction};
+
+/// Limits the rate of publications and payload bytes with token buckets.
+///
+/// Buckets hold at most one second worth of tokens, so a client can publish
+/// a burst of that size at once. A single publication larger than that is
+/// allowed once the bucket is full and leaves it in debt, so that it does not
+/// exceed the rate on average. Clones share the same buckets.
+#[derive(Clone, Debug)]
+pub struct RateLimiter(Arc<Mutex<Buckets>>);
+
+#[derive(Debug)]
+struct Buckets {
+    messages: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: &RateLimit, now: Instant) -> Self {
+        #[allow(clippy::cast_precision_loss)]
+        let bytes_per_second = limit.bytes_per_second() as f64;
+
+        let buckets = Buckets {
+            messages: TokenBucket::new(f64::from(limit.messages_per_second()), now),
+            bytes: TokenBucket::new(bytes_per_second, now),
+        };
+        Self(Arc::new(Mutex::new(buckets)))
+    }
+
+    /// Whether the limiter is used by more than one connection.
+    pub(crate) fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.0) > 1
+    }
+
+    /// Whether the buckets are full, i.e. the limiter is no different from a new one.
+    pub(crate) fn is_full(&self, now: Instant) -> bool {
+        let mut buckets = self.0.lock().expect("rate limiter lock poisoned");
+        buckets.iter_mut().all(|(bucket, _)| {
+            bucket.refill(now);
+            bucket.tokens >= bucket.rate
+        })
+    }
+
+    /// Time to wait until the buckets hold enough tokens for a publication.
+    fn wait_time(&self, payload_len: f64, now: Instant) -> Duration {
+        let mut buckets = self.0.lock().expect("rate limiter lock poisoned");
+        buckets
+            .iter_mut()
+            .map(|(bucket, amount)| {
+                bucket.refill(now);
+                bucket.wait_time(amount.unwrap_or(payload_len))
+            })
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Takes tokens for a publication, leaving the buckets in debt if there are not enough.
+    fn take(&self, payload_len: f64) {
+        let mut buckets = self.0.lock().expect("rate limiter lock poisoned");
+        for (bucket, amount) in buckets.iter_mut() {
+            bucket.tokens -= amount.unwrap_or(payload_len);
+        }
+    }
+}
+
+impl Buckets {
+    /// Buckets with the amount of tokens a publication takes from them.
+    /// `None` stands for the payload length.
+    fn iter_mut(&mut self) -> impl Iterator<Item = (&mut TokenBucket, Option<f64>)> {
+        let messages = self.messages.as_mut().map(|bucket| (bucket, Some(1.0)));
+        let bytes = self.bytes.as_mut().map(|bucket| (bucket, None));
+        messages.into_iter().chain(bytes)
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, now: Instant) -> Option<Self> {
+        if rate > 0.0 {
+            Some(Self {
+                rate,
+                tokens: rate,
+                updated_at: now,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.rate);
+        self.updated_at = cmp::max(self.updated_at, now);
+    }
+
+    fn wait_time(&self, amount: f64) -> Duration {
+        // an amount above the capacity only needs a full bucket
+        let amount = amount.min(self.rate);
+        if self.tokens >= amount {
+            Duration::default()
+        } else {
+            Duration::from_secs_f64((amount - self.tokens) / self.rate)
+        }
+    }
+}
+
+/// What to do with a publication from a connection.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Throttle {
+    Allow,
+    Delay(Duration),
+    Drop,
+    Disconnect,
+}
+
+/// Rate limits applied to the publications of a single connection.
+#[derive(Clone, Debug)]
+pub struct ConnectionRateLimit {
+    limiters: Vec<RateLimiter>,
+    when_exceeded: RateLimitAction,
+}
+
+impl ConnectionRateLimit {
+    pub(crate) fn new(
+        client: RateLimiter,
+        identity: Option<RateLimiter>,
+        when_exceeded: RateLimitAction,
+    ) -> Self {
+        let mut limiters = vec![client];
+        limiters.extend(identity);
+        Self {
+            limiters,
+            when_exceeded,
+        }
+    }
+
+    pub(crate) fn throttle(&self, publish: &proto::Publish, now: Instant) -> Throttle {
+        #[allow(clippy::cast_precision_loss)]
+        let payload_len = publish.payload.len() as f64;
+
+        let wait_time = self
+            .limiters
+            .iter()
+            .map(|limiter| limiter.wait_time(payload_len, now))
+            .max()
+            .unwrap_or_default();
+
+        if wait_time > Duration::default() {
+            let is_qos0 =
+                publish.packet_identifier_dup_qos == proto::PacketIdentifierDupQoS::AtMostOnce;
+            match self.when_exceeded {
+                RateLimitAction::Disconnect => return Throttle::Disconnect,
+                RateLimitAction::DropQos0 if is_qos0 => return Throttle::Drop,
+                RateLimitAction::Delay | RateLimitAction::DropQos0 => (),
+            }
+        }
+
+        // a delayed publication takes the tokens up front and waits for the debt to be repaid
+        for limiter in &self.limiters {
+            limiter.take(payload_len);
+        }
+
+        if wait_time > Duration::default() {
+            Throttle::Delay(wait_time)
+        } else {
+            Throttle::Allow
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use bytes::Bytes;
+    use mqtt3::proto;
+
+    use crate::configuration::{RateLimit, RateLimitAction};
+    use crate::rate_limit::{ConnectionRateLimit, RateLimiter, Throttle};
+
+    fn publish(qos: proto::QoS, payload: &'static str) -> proto::Publish {
+        let packet_identifier_dup_qos = match qos {
+            proto::QoS::AtMostOnce => proto::PacketIdentifierDupQoS::AtMostOnce,
+            proto::QoS::AtLeastOnce => proto::PacketIdentifierDupQoS::AtLeastOnce(
+                proto::PacketIdentifier::new(1).unwrap(),
+                false,
+            ),
+            proto::QoS::ExactlyOnce => proto::PacketIdentifierDupQoS::ExactlyOnce(
+                proto::PacketIdentifier::new(1).unwrap(),
+                false,
+            ),
+        };
+
+        proto::Publish {
+            packet_identifier_dup_qos,
+            retain: false,
+            topic_name: "topic".to_string(),
+            payload: Bytes::from(payload),
+            properties: proto::Properties::default(),
+        }
+    }
+
+    fn rate_limit(
+        limit: &RateLimit,
+        when_exceeded: RateLimitAction,
+        now: Instant,
+    ) -> ConnectionRateLimit {
+        ConnectionRateLimit::new(RateLimiter::new(limit, now), None, when_exceeded)
+    }
+
+    #[test]
+    fn it_allows_burst_and_refills_over_time() {
+        let now = Instant::now();
+        let limit = rate_limit(&RateLimit::new(2, 0), RateLimitAction::Disconnect, now);
+        let qos0 = publish(proto::QoS::AtMostOnce, "hello");
+
+        assert_eq!(Throttle::Allow, limit.throttle(&qos0, now));
+        assert_eq!(Throttle::Allow, limit.throttle(&qos0, now));
+        assert_eq!(Throttle::Disconnect, limit.throttle(&qos0, now));
+
+        let later = now + Duration::from_millis(500);
+        assert_eq!(Throttle::Allow, limit.throttle(&qos0, later));
+        assert_eq!(Throttle::Disconnect, limit.throttle(&qos0, later));
+    }
+
+    #[test]
+    fn it_delays_until_payload_bytes_are_available() {
+        let now = Instant::now();
+        let limit = rate_limit(&RateLimit::new(0, 10), RateLimitAction::Delay, now);
+        let qos0 = publish(proto::QoS::AtMostOnce, "hello");
+
+        assert_eq!(Throttle::Allow, limit.throttle(&qos0, now));
+        assert_eq!(Throttle::Allow, limit.throttle(&qos0, now));
+        assert_eq!(
+            Throttle::Delay(Duration::from_millis(500)),
+            limit.throttle(&qos0, now)
+        );
+
+        // the delayed publication took the tokens which become available meanwhile
+        let later = now + Duration::from_millis(500);
+        assert_eq!(
+            Throttle::Delay(Duration::from_millis(500)),
+            limit.throttle(&qos0, later)
+        );
+    }
+
+    #[test]
+    fn it_allows_publication_larger_than_burst_once_full() {
+        let now = Instant::now();
+        let limit = rate_limit(&RateLimit::new(0, 4), RateLimitAction::Disconnect, now);
+        let qos0 = publish(proto::QoS::AtMostOnce, "hello");
+
+        assert_eq!(Throttle::Allow, limit.throttle(&qos0, now));
+        assert_eq!(Throttle::Disconnect, limit.throttle(&qos0, now));
+
+        // the debt of one byte is repaid before the bucket is full again
+        let later = now + Duration::from_millis(1000);
+        assert_eq!(Throttle::Disconnect, limit.throttle(&qos0, later));
+        let later = now + Duration::from_millis(1250);
+        assert_eq!(Throttle::Allow, limit.throttle(&qos0, later));
+    }
+
+    #[test]
+    fn it_tells_when_buckets_are_full() {
+        let now = Instant::now();
+        let limiter = RateLimiter::new(&RateLimit::new(1, 0), now);
+        let limit = ConnectionRateLimit::new(limiter.clone(), None, RateLimitAction::Delay);
+        assert!(limiter.is_full(now));
+
+        limit.throttle(&publish(proto::QoS::AtMostOnce, "hello"), now);
+        assert!(!limiter.is_full(now));
+        assert!(limiter.is_full(now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn it_drops_only_qos0_publications() {
+        let now = Instant::now();
+        let limit = rate_limit(&RateLimit::new(1, 0), RateLimitAction::DropQos0, now);
+
+        assert_eq!(
+            Throttle::Allow,
+            limit.throttle(&publish(proto::QoS::AtMostOnce, "1"), now)
+        );
+        assert_eq!(
+            Throttle::Drop,
+            limit.throttle(&publish(proto::QoS::AtMostOnce, "2"), now)
+        );
+        assert_eq!(
+            Throttle::Delay(Duration::from_secs(1)),
+            limit.throttle(&publish(proto::QoS::AtLeastOnce, "3"), now)
+        );
+    }
+
+    #[test]
+    fn it_shares_identity_limit_between_connections() {
+        let now = Instant::now();
+        let limit = RateLimit::new(10, 0);
+        let identity = RateLimiter::new(&RateLimit::new(1, 0), now);
+        let first = ConnectionRateLimit::new(
+            RateLimiter::new(&limit, now),
+            Some(identity.clone()),
+            RateLimitAction::Disconnect,
+        );
+        let second = ConnectionRateLimit::new(
+            RateLimiter::new(&limit, now),
+            Some(identity.clone()),
+            RateLimitAction::Disconnect,
+        );
+        let qos0 = publish(proto::QoS::AtMostOnce, "hello");
+
+        assert!(identity.is_shared());
+        assert_eq!(Throttle::Allow, first.throttle(&qos0, now));
+        assert_eq!(Throttle::Disconnect, second.throttle(&qos0, now));
+    }
+}
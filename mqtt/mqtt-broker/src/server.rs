@@ -1,15 +1,19 @@
+use std::convert::TryFrom;
 use std::future::Future;
+use std::sync::Arc;
 
 use futures_util::future::{self, Either, FutureExt};
 use futures_util::pin_mut;
-use futures_util::stream::StreamExt;
-use tokio::{net::ToSocketAddrs, sync::oneshot};
+use futures_util::stream::{Stream, StreamExt};
+use tokio::{net::ToSocketAddrs, sync::oneshot, task::JoinHandle};
 use tracing::{debug, error, info, span, warn, Level};
 use tracing_futures::Instrument;
 
 use crate::auth::{Authenticator, Authorizer};
 use crate::broker::{Broker, BrokerHandle, BrokerState};
-use crate::transport::TransportBuilder;
+use crate::configuration::Transport as TransportConfig;
+use crate::interceptor::{Interceptor, Interceptors};
+use crate::transport::{Transport, TransportBuilder};
 use crate::{connection, Error, InitializeBrokerError, Message, SystemEvent};
 
 pub struct Server<N, Z>
@@ -18,6 +22,7 @@ where
     Z: Authorizer,
 {
     broker: Broker<N, Z>,
+    interceptors: Vec<Box<dyn Interceptor>>,
 }
 
 impl<N, Z> Server<N, Z>
@@ -26,7 +31,20 @@ where
     Z: Authorizer + Send + Sync + 'static,
 {
     pub fn from_broker(broker: Broker<N, Z>) -> Self {
-        Self { broker }
+        Self {
+            broker,
+            interceptors: Vec::new(),
+        }
+    }
+
+    /// Adds an interceptor of client connections. Interceptors are applied
+    /// in the order they were added.
+    pub fn with_interceptor<I>(mut self, interceptor: I) -> Self
+    where
+        I: Interceptor + 'static,
+    {
+        self.interceptors.push(Box::new(interceptor));
+        self
     }
 
     pub async fn serve<A, F, I>(
@@ -39,8 +57,13 @@ where
         F: Future<Output = ()> + Unpin,
         I: IntoIterator<Item = TransportBuilder<A>>,
     {
-        let Server { broker } = self;
+        let Server {
+            broker,
+            interceptors,
+        } = self;
+        let interceptors = Interceptors::new(interceptors);
         let mut handle = broker.handle();
+        let authenticator = broker.authenticator();
         let broker_task = tokio::spawn(broker.run());
 
         let mut incoming_tasks = Vec::new();
@@ -49,7 +72,13 @@ where
             let (itx, irx) = oneshot::channel::<()>();
             shutdown_handles.push(itx);
 
-            let incoming_task = incoming_task(transport, handle.clone(), irx.map(drop));
+            let incoming_task = incoming_task(
+                transport,
+                handle.clone(),
+                interceptors.clone(),
+                authenticator.clone(),
+                irx.map(drop),
+            );
 
             let incoming_task = Box::pin(incoming_task);
             incoming_tasks.push(incoming_task);
@@ -150,6 +179,192 @@ where
             },
         }
     }
+
+    /// Serves connections like `serve`, while applying updated lists of
+    /// configured transports received from `transport_updates`.
+    ///
+    /// Transports which are no longer configured stop accepting new connections
+    /// and newly configured transports start listening. Connections which are
+    /// already established are not affected.
+    pub async fn serve_with_updates<F, U>(
+        self,
+        transports: Vec<TransportConfig>,
+        transport_updates: U,
+        shutdown_signal: F,
+    ) -> Result<BrokerState, Error>
+    where
+        F: Future<Output = ()> + Unpin,
+        U: Stream<Item = Vec<TransportConfig>> + Unpin,
+    {
+        let Server {
+            broker,
+            interceptors,
+        } = self;
+        let mut handle = broker.handle();
+
+        let mut listeners = Listeners::new(
+            handle.clone(),
+            Interceptors::new(interceptors),
+            broker.authenticator(),
+        );
+        for transport in transports {
+            listeners.start(transport).await?;
+        }
+
+        let broker_task = tokio::spawn(broker.run());
+        pin_mut!(broker_task);
+
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        let listeners_task = listeners.run(transport_updates, stop_rx.map(drop));
+        pin_mut!(listeners_task);
+
+        let main_task = future::select(broker_task, listeners_task);
+        match future::select(shutdown_signal, main_task).await {
+            Either::Left((_, tasks)) => {
+                info!("server received shutdown signal");
+
+                info!("shutting down accept loop...");
+                send_shutdown(vec![stop_tx]);
+
+                match tasks.await {
+                    Either::Right((_, broker_task)) => {
+                        debug!("sending Shutdown message to broker");
+                        handle.send(Message::System(SystemEvent::Shutdown)).await?;
+                        broker_task.await?
+                    }
+                    Either::Left((broker_state, listeners_task)) => {
+                        warn!("broker exited before accept loop");
+                        listeners_task.await;
+                        broker_state?
+                    }
+                }
+            }
+            Either::Right((Either::Right((_, broker_task)), _)) => {
+                debug!("sending Shutdown message to broker");
+                handle.send(Message::System(SystemEvent::Shutdown)).await?;
+                broker_task.await?
+            }
+            Either::Right((Either::Left((broker_state, listeners_task)), _)) => {
+                warn!("broker exited before accept loop");
+
+                debug!("sending stop signal for every protocol head");
+                send_shutdown(vec![stop_tx]);
+                listeners_task.await;
+
+                broker_state?
+            }
+        }
+    }
+}
+
+/// Accept loops of configured transports.
+struct Listeners<N> {
+    handle: BrokerHandle,
+    interceptors: Interceptors,
+    authenticator: Arc<N>,
+    listeners: Vec<Listener>,
+}
+
+struct Listener {
+    transport: TransportConfig,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<Result<(), Error>>,
+}
+
+impl<N> Listeners<N>
+where
+    N: Authenticator + Send + Sync + 'static,
+{
+    fn new(handle: BrokerHandle, interceptors: Interceptors, authenticator: Arc<N>) -> Self {
+        Self {
+            handle,
+            interceptors,
+            authenticator,
+            listeners: Vec::new(),
+        }
+    }
+
+    async fn start(&mut self, transport: TransportConfig) -> Result<(), Error> {
+        let io = TransportBuilder::try_from(transport.clone())?
+            .build()
+            .await?;
+
+        let (itx, irx) = oneshot::channel::<()>();
+        let task = tokio::spawn(accept_loop(
+            io,
+            self.handle.clone(),
+            self.interceptors.clone(),
+            self.authenticator.clone(),
+            irx.map(drop),
+        ));
+
+        self.listeners.push(Listener {
+            transport,
+            shutdown: itx,
+            task,
+        });
+        Ok(())
+    }
+
+    async fn update(&mut self, transports: Vec<TransportConfig>) {
+        let (listeners, removed): (Vec<_>, Vec<_>) = self
+            .listeners
+            .drain(..)
+            .partition(|listener| transports.contains(&listener.transport));
+        self.listeners = listeners;
+
+        for listener in removed {
+            info!(
+                "transport {:?} removed from configuration",
+                listener.transport
+            );
+            listener.stop().await;
+        }
+
+        for transport in transports {
+            if self.listeners.iter().all(|l| l.transport != transport) {
+                info!("transport {:?} added to configuration", transport);
+                if let Err(e) = self.start(transport).await {
+                    warn!(message = "failed to start protocol head", error=%e);
+                }
+            }
+        }
+    }
+
+    async fn run<U, F>(mut self, mut transport_updates: U, mut shutdown_signal: F)
+    where
+        U: Stream<Item = Vec<TransportConfig>> + Unpin,
+        F: Future<Output = ()> + Unpin,
+    {
+        loop {
+            match future::select(&mut shutdown_signal, transport_updates.next()).await {
+                Either::Left(_) => break,
+                Either::Right((Some(transports), _)) => self.update(transports).await,
+                Either::Right((None, _)) => {
+                    (&mut shutdown_signal).await;
+                    break;
+                }
+            }
+        }
+
+        debug!("sending stop signal for every protocol head");
+        for listener in self.listeners {
+            listener.stop().await;
+        }
+    }
+}
+
+impl Listener {
+    async fn stop(self) {
+        // the accept loop may have already exited on its own
+        let _ = self.shutdown.send(());
+
+        match self.task.await {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => warn!(message = "failed to shutdown protocol head", error=%e),
+            Err(e) => warn!(message = "failed to shutdown protocol head", error=%e),
+        }
+    }
 }
 
 fn send_shutdown<I>(handles: I)
@@ -163,16 +378,33 @@ where
     }
 }
 
-async fn incoming_task<A, F>(
+async fn incoming_task<A, F, N>(
     transport: TransportBuilder<A>,
     handle: BrokerHandle,
-    mut shutdown_signal: F,
+    interceptors: Interceptors,
+    authenticator: Arc<N>,
+    shutdown_signal: F,
 ) -> Result<(), Error>
 where
     A: ToSocketAddrs,
     F: Future<Output = ()> + Unpin,
+    N: Authenticator + Send + Sync + 'static,
 {
     let io = transport.build().await?;
+    accept_loop(io, handle, interceptors, authenticator, shutdown_signal).await
+}
+
+async fn accept_loop<F, N>(
+    io: Transport,
+    handle: BrokerHandle,
+    interceptors: Interceptors,
+    authenticator: Arc<N>,
+    mut shutdown_signal: F,
+) -> Result<(), Error>
+where
+    F: Future<Output = ()> + Unpin,
+    N: Authenticator + Send + Sync + 'static,
+{
     let addr = io.local_addr()?;
     let span = span!(Level::INFO, "server", listener=%addr);
     let _enter = span.enter();
@@ -189,11 +421,19 @@ where
                     .map_err(InitializeBrokerError::ConnectionPeerAddress)?;
 
                 let broker_handle = handle.clone();
+                let interceptors = interceptors.clone();
+                let authenticator = authenticator.clone();
                 let span = span.clone();
                 tokio::spawn(async move {
-                    if let Err(e) = connection::process(stream, peer, broker_handle)
-                        .instrument(span)
-                        .await
+                    if let Err(e) = connection::process(
+                        stream,
+                        peer,
+                        broker_handle,
+                        interceptors,
+                        authenticator,
+                    )
+                    .instrument(span)
+                    .await
                     {
                         warn!(message = "failed to process connection", error=%e);
                     }
@@ -1,4 +1,6 @@
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime};
 use std::{cmp, fmt, mem};
 
 use mqtt3::proto;
@@ -7,17 +9,111 @@ use serde::ser::SerializeTuple;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use tracing::{debug, warn};
 
-use crate::subscription::Subscription;
+use crate::configuration::{BrokerConfig, QueueFullAction};
+use crate::subscription::{self, Subscription};
 use crate::{AuthId, ClientEvent, ClientId, ConnReq, ConnectionHandle, Error, Message, Publish};
 
 const MAX_INFLIGHT_MESSAGES: usize = 16;
 
+/// Limits applied to the publications being sent to a client.
+///
+/// A queue limit of `None` means the queue is unbounded in that dimension.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionConfig {
+    max_inflight_messages: usize,
+    max_message_size: Option<usize>,
+    max_queued_messages: Option<usize>,
+    max_queued_size: Option<usize>,
+    when_full: QueueFullAction,
+}
+
+impl SessionConfig {
+    pub fn new(
+        max_message_size: Option<usize>,
+        max_queued_messages: Option<usize>,
+        max_queued_size: Option<usize>,
+        when_full: QueueFullAction,
+    ) -> Self {
+        Self {
+            max_inflight_messages: MAX_INFLIGHT_MESSAGES,
+            max_message_size,
+            max_queued_messages,
+            max_queued_size,
+            when_full,
+        }
+    }
+
+    /// Sets the number of publications that can be sent to a client
+    /// without being acknowledged.
+    pub fn with_max_inflight_messages(mut self, max_inflight_messages: usize) -> Self {
+        self.max_inflight_messages = cmp::max(1, max_inflight_messages);
+        self
+    }
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self::new(None, None, None, QueueFullAction::default())
+    }
+}
+
+impl From<&BrokerConfig> for SessionConfig {
+    fn from(config: &BrokerConfig) -> Self {
+        // zero is treated as "no limit" for every setting
+        let limit = |value: u64| match value {
+            0 => None,
+            value => Some(usize::try_from(value).unwrap_or(usize::max_value())),
+        };
+
+        let messages = config.session().messages();
+        let max_inflight_messages = config.inflight_messages().max_count();
+        Self::new(
+            limit(messages.max_message_size()),
+            limit(u64::from(messages.max_count())),
+            limit(messages.max_total_space()),
+            messages.when_full(),
+        )
+        .with_max_inflight_messages(
+            usize::try_from(max_inflight_messages).unwrap_or(MAX_INFLIGHT_MESSAGES),
+        )
+    }
+}
+
+/// Outcome of delivering a publication to a session.
+#[derive(Debug)]
+pub enum Delivery {
+    /// The publication is sent to the client right away.
+    Send(ClientEvent),
+
+    /// The publication waits in the queue of the session. Older publications
+    /// dropped from a full queue to make room for it are returned.
+    Queued(Vec<proto::Publication>),
+
+    /// The publication is not delivered because of the limits of the session.
+    Dropped(DropReason),
+
+    /// The session has no subscription the publication is delivered through.
+    Skipped,
+}
+
+/// Reason a publication is dropped instead of being delivered to a session.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropReason {
+    /// The publication exceeds the message size limit of the session.
+    TooLarge,
+
+    /// The queue of the session is full.
+    QueueFull,
+}
+
 #[derive(Debug)]
 pub struct ConnectedSession {
     state: SessionState,
     auth_id: AuthId,
     will: Option<proto::Publication>,
     handle: ConnectionHandle,
+    protocol_level: u8,
 }
 
 impl ConnectedSession {
@@ -26,12 +122,14 @@ impl ConnectedSession {
         state: SessionState,
         will: Option<proto::Publication>,
         handle: ConnectionHandle,
+        protocol_level: u8,
     ) -> Self {
         Self {
             auth_id,
             state,
             will,
             handle,
+            protocol_level,
         }
     }
 
@@ -51,6 +149,11 @@ impl ConnectedSession {
         &self.state
     }
 
+    /// The protocol level the client connected with.
+    pub fn protocol_level(&self) -> u8 {
+        self.protocol_level
+    }
+
     pub fn into_will(self) -> Option<proto::Publication> {
         self.will
     }
@@ -102,25 +205,45 @@ impl ConnectedSession {
         self.state.handle_pubcomp(pubcomp)
     }
 
-    pub fn publish_to(
+    pub fn publish_to(&mut self, publication: proto::Publication) -> Result<Delivery, Error> {
+        self.state.publish_to(publication)
+    }
+
+    pub fn publish_to_shared(
         &mut self,
         publication: proto::Publication,
-    ) -> Result<Option<ClientEvent>, Error> {
-        self.state.publish_to(publication)
+        max_qos: proto::QoS,
+    ) -> Result<Delivery, Error> {
+        self.state.publish_to_shared(publication, max_qos)
     }
 
+    /// Adds a subscription to the session.
+    ///
+    /// Returns the subscription which retained messages should be matched against,
+    /// which is `None` for shared subscriptions as they do not receive retained messages.
     pub fn subscribe_to(
         &mut self,
         subscribe_to: proto::SubscribeTo,
     ) -> Result<(proto::SubAckQos, Option<Subscription>), Error> {
-        match subscribe_to.topic_filter.parse() {
+        let is_shared = subscription::is_shared(&subscribe_to.topic_filter);
+        let filter = if is_shared {
+            subscription::parse_shared(&subscribe_to.topic_filter).map(|(_, filter)| filter)
+        } else {
+            subscribe_to.topic_filter.parse()
+        };
+
+        match filter {
             Ok(filter) => {
                 let proto::SubscribeTo { topic_filter, qos } = subscribe_to;
 
                 let subscription = Subscription::new(filter, qos);
                 self.state
                     .update_subscription(topic_filter, subscription.clone());
-                Ok((proto::SubAckQos::Success(qos), Some(subscription)))
+
+                // [MQTT5-4.8.2] Retained messages are not sent to a Shared Subscription
+                // when it is first established.
+                let subscription = if is_shared { None } else { Some(subscription) };
+                Ok((proto::SubAckQos::Success(qos), subscription))
             }
             Err(e) => {
                 warn!("invalid topic filter {}: {}", subscribe_to.topic_filter, e);
@@ -133,12 +256,19 @@ impl ConnectedSession {
         &mut self,
         unsubscribe: &proto::Unsubscribe,
     ) -> Result<proto::UnsubAck, Error> {
-        for filter in &unsubscribe.unsubscribe_from {
-            self.state.remove_subscription(&filter);
-        }
+        let reason_codes = unsubscribe
+            .unsubscribe_from
+            .iter()
+            .map(|filter| match self.state.remove_subscription(&filter) {
+                Some(_) => proto::ReasonCode::SUCCESS,
+                None => proto::ReasonCode::NO_SUBSCRIPTION_EXISTED,
+            })
+            .collect();
 
         let unsuback = proto::UnsubAck {
             packet_identifier: unsubscribe.packet_identifier,
+            reason_codes,
+            properties: proto::Properties::default(),
         };
         Ok(unsuback)
     }
@@ -152,32 +282,88 @@ impl ConnectedSession {
 #[derive(Debug)]
 pub struct OfflineSession {
     state: SessionState,
+    // set once the queue filled up, so that dropped publications are only warned about once
+    queue_full: bool,
 }
 
 impl OfflineSession {
     fn new(state: SessionState) -> Self {
-        Self { state }
+        Self {
+            state,
+            queue_full: false,
+        }
     }
 
     pub fn client_id(&self) -> &ClientId {
         &self.state.client_id
     }
 
+    /// Identity of the client when it was last connected.
+    pub fn auth_id(&self) -> &AuthId {
+        &self.state.auth_id
+    }
+
     pub fn state(&self) -> &SessionState {
         &self.state
     }
 
-    pub fn publish_to(
+    /// Returns `true` if the session has been offline for longer than its expiry interval.
+    ///
+    /// Sessions which did not ask for an expiry interval use the broker-wide `expiration`,
+    /// where zero means they never expire.
+    pub fn is_expired(&self, expiration: Duration, now: SystemTime) -> bool {
+        let expiration = self.state.session_expiry_interval.unwrap_or(expiration);
+        expiration != Duration::default()
+            && now
+                .duration_since(self.state.last_active)
+                .map_or(false, |offline| offline >= expiration)
+    }
+
+    pub fn publish_to(&mut self, publication: proto::Publication) -> Result<Delivery, Error> {
+        match self.state.filter(publication) {
+            Some(publication) => self.enqueue(publication),
+            None => Ok(Delivery::Skipped),
+        }
+    }
+
+    pub fn publish_to_shared(
         &mut self,
-        publication: proto::Publication,
-    ) -> Result<Option<ClientEvent>, Error> {
-        self.state.queue_publish(publication)?;
-        Ok(None)
+        mut publication: proto::Publication,
+        max_qos: proto::QoS,
+    ) -> Result<Delivery, Error> {
+        publication.qos = cmp::min(publication.qos, max_qos);
+        self.enqueue(publication)
+    }
+
+    /// There is no connection to drop for an offline session, so a full queue
+    /// drops new publications when the session is configured to disconnect.
+    fn enqueue(&mut self, publication: proto::Publication) -> Result<Delivery, Error> {
+        let when_full = match self.state.config.when_full {
+            QueueFullAction::Disconnect => QueueFullAction::DropNew,
+            when_full => when_full,
+        };
+
+        let delivery = self.state.enqueue_with(publication, when_full)?;
+        if let Delivery::Dropped(DropReason::QueueFull) = delivery {
+            if !self.queue_full {
+                warn!(
+                    "queue is full for offline session {}. dropping new messages",
+                    self.state.client_id
+                );
+                self.queue_full = true;
+            }
+        }
+        Ok(delivery)
     }
 
-    pub fn into_online(self) -> Result<(SessionState, Vec<ClientEvent>), Error> {
-        let mut events = Vec::with_capacity(MAX_INFLIGHT_MESSAGES);
-        let OfflineSession { mut state } = self;
+    /// Moves the session online using the limits configured for the connected client.
+    pub fn into_online(
+        self,
+        config: SessionConfig,
+    ) -> Result<(SessionState, Vec<ClientEvent>), Error> {
+        let OfflineSession { state, .. } = self;
+        let mut state = state.with_config(config);
+        let mut events = Vec::with_capacity(state.config.max_inflight_messages);
 
         // Handle the outstanding QoS 1 and QoS 2 packets
         for (id, publish) in &state.waiting_to_be_acked {
@@ -216,12 +402,14 @@ impl OfflineSession {
         for completed in &state.waiting_to_be_completed {
             events.push(ClientEvent::PubRel(proto::PubRel {
                 packet_identifier: *completed,
+                reason_code: proto::ReasonCode::SUCCESS,
+                properties: proto::Properties::default(),
             }));
         }
 
         // Dequeue any queued messages - up to the max inflight count
         while state.allowed_to_send() {
-            match state.waiting_to_be_sent.pop_front() {
+            match state.dequeue() {
                 Some(publication) => {
                     debug!("dequeueing a message for {}", state.client_id);
                     let event = state.prepare_to_send(&publication)?;
@@ -279,11 +467,16 @@ impl DisconnectingSession {
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct SessionState {
     client_id: ClientId,
+
+    // identity of the client when it was last connected, used to authorize
+    // publications queued while the session is offline
+    auth_id: AuthId,
+
     subscriptions: HashMap<String, Subscription>,
     packet_identifiers: PacketIdentifiers,
     packet_identifiers_qos0: PacketIdentifiers,
 
-    waiting_to_be_sent: VecDeque<proto::Publication>,
+    waiting_to_be_sent: VecDeque<QueuedPublication>,
 
     // for incoming messages - QoS2
     waiting_to_be_released: HashMap<proto::PacketIdentifier, proto::Publish>,
@@ -292,12 +485,27 @@ pub struct SessionState {
     waiting_to_be_acked: HashMap<proto::PacketIdentifier, Publish>,
     waiting_to_be_acked_qos0: HashMap<proto::PacketIdentifier, Publish>,
     waiting_to_be_completed: HashSet<proto::PacketIdentifier>,
+
+    // time the client was last connected, used to expire offline sessions
+    last_active: SystemTime,
+
+    // expiry interval requested by a MQTT 5.0 client, the broker configuration applies otherwise
+    session_expiry_interval: Option<Duration>,
+
+    // limits are not a part of the state and are set by the broker on load
+    #[serde(skip)]
+    config: SessionConfig,
+
+    // changes not yet taken by the broker, recorded once the session is journaled
+    #[serde(skip)]
+    journal: Journal,
 }
 
 impl SessionState {
-    pub fn new(client_id: ClientId) -> Self {
+    pub fn new(client_id: ClientId, config: SessionConfig) -> Self {
         Self {
             client_id,
+            auth_id: AuthId::Anonymous,
             subscriptions: HashMap::new(),
             packet_identifiers: PacketIdentifiers::default(),
             packet_identifiers_qos0: PacketIdentifiers::default(),
@@ -307,6 +515,11 @@ impl SessionState {
             waiting_to_be_acked_qos0: HashMap::new(),
             waiting_to_be_released: HashMap::new(),
             waiting_to_be_completed: HashSet::new(),
+
+            last_active: SystemTime::now(),
+            session_expiry_interval: None,
+            config,
+            journal: Journal::default(),
         }
     }
 
@@ -314,6 +527,67 @@ impl SessionState {
         &self.client_id
     }
 
+    pub fn auth_id(&self) -> &AuthId {
+        &self.auth_id
+    }
+
+    pub fn with_auth_id(mut self, auth_id: AuthId) -> Self {
+        self.record(|| SessionChange::AuthId(auth_id.clone()));
+        self.auth_id = auth_id;
+        self
+    }
+
+    pub fn last_active(&self) -> SystemTime {
+        self.last_active
+    }
+
+    pub fn with_last_active(mut self, last_active: SystemTime) -> Self {
+        self.record(|| SessionChange::LastActive(last_active));
+        self.last_active = last_active;
+        self
+    }
+
+    pub fn session_expiry_interval(&self) -> Option<Duration> {
+        self.session_expiry_interval
+    }
+
+    pub fn with_session_expiry_interval(
+        mut self,
+        session_expiry_interval: Option<Duration>,
+    ) -> Self {
+        self.record(|| SessionChange::SessionExpiryInterval(session_expiry_interval));
+        self.session_expiry_interval = session_expiry_interval;
+        self
+    }
+
+    pub fn with_config(mut self, config: SessionConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn set_config(&mut self, config: SessionConfig) {
+        self.config = config;
+    }
+
+    /// Number of publications waiting to be sent to the client.
+    pub fn queued_count(&self) -> usize {
+        self.waiting_to_be_sent.len()
+    }
+
+    /// PUBLISH packet sent to the client which is waiting for a PUBACK or PUBREC.
+    pub fn inflight(&self, packet_identifier: proto::PacketIdentifier) -> Option<&proto::Publish> {
+        self.waiting_to_be_acked
+            .get(&packet_identifier)
+            .map(|publish| match publish {
+                Publish::QoS0(_, publish) | Publish::QoS12(_, publish) => publish,
+            })
+    }
+
+    /// Number of publications sent to the client and waiting to be acknowledged.
+    pub fn inflight_count(&self) -> usize {
+        self.waiting_to_be_acked.len() + self.waiting_to_be_acked_qos0.len()
+    }
+
     pub fn subscriptions(&self) -> &HashMap<String, Subscription> {
         &self.subscriptions
     }
@@ -323,36 +597,50 @@ impl SessionState {
         topic_filter: String,
         subscription: Subscription,
     ) -> Option<Subscription> {
+        self.record(|| SessionChange::Subscribed(topic_filter.clone(), subscription.clone()));
         self.subscriptions.insert(topic_filter, subscription)
     }
 
     pub fn remove_subscription(&mut self, topic_filter: &str) -> Option<Subscription> {
-        self.subscriptions.remove(topic_filter)
+        let removed = self.subscriptions.remove(topic_filter);
+        if removed.is_some() {
+            self.record(|| SessionChange::Unsubscribed(topic_filter.to_owned()));
+        }
+        removed
     }
 
-    pub fn queue_publish(&mut self, publication: proto::Publication) -> Result<(), Error> {
-        if let Some(publication) = self.filter(publication) {
-            self.waiting_to_be_sent.push_back(publication);
+    pub fn queue_publish(&mut self, publication: proto::Publication) -> Result<Delivery, Error> {
+        match self.filter(publication) {
+            Some(publication) => self.enqueue(publication),
+            None => Ok(Delivery::Skipped),
         }
-        Ok(())
     }
 
-    /// Takes a publication and returns an optional Publish packet if sending is allowed.
-    /// This can return None if the current outstanding messages is at its limit.
-    pub fn publish_to(
-        &mut self,
-        publication: proto::Publication,
-    ) -> Result<Option<ClientEvent>, Error> {
-        if let Some(publication) = self.filter(publication) {
-            if self.allowed_to_send() {
+    /// Takes a publication and returns the Publish packet to send if sending is allowed.
+    /// The publication is queued if the current outstanding messages is at its limit.
+    pub fn publish_to(&mut self, publication: proto::Publication) -> Result<Delivery, Error> {
+        match self.filter(publication) {
+            Some(publication) if self.allowed_to_send() => {
                 let event = self.prepare_to_send(&publication)?;
-                Ok(Some(event))
-            } else {
-                self.waiting_to_be_sent.push_back(publication);
-                Ok(None)
+                Ok(Delivery::Send(event))
             }
+            Some(publication) => self.enqueue(publication),
+            None => Ok(Delivery::Skipped),
+        }
+    }
+
+    /// Takes a publication delivered to this session as a member of a shared subscription.
+    pub fn publish_to_shared(
+        &mut self,
+        mut publication: proto::Publication,
+        max_qos: proto::QoS,
+    ) -> Result<Delivery, Error> {
+        publication.qos = cmp::min(publication.qos, max_qos);
+        if self.allowed_to_send() {
+            let event = self.prepare_to_send(&publication)?;
+            Ok(Delivery::Send(event))
         } else {
-            Ok(None)
+            self.enqueue(publication)
         }
     }
 
@@ -367,6 +655,7 @@ impl SessionState {
                     qos: proto::QoS::AtMostOnce,
                     retain: publish.retain,
                     payload: publish.payload,
+                    properties: into_publication_properties(publish.properties),
                 };
                 (Some(publication), None)
             }
@@ -376,15 +665,25 @@ impl SessionState {
                     qos: proto::QoS::AtLeastOnce,
                     retain: publish.retain,
                     payload: publish.payload,
+                    properties: into_publication_properties(publish.properties),
+                };
+                let puback = proto::PubAck {
+                    packet_identifier,
+                    reason_code: proto::ReasonCode::SUCCESS,
+                    properties: proto::Properties::default(),
                 };
-                let puback = proto::PubAck { packet_identifier };
                 let event = ClientEvent::PubAck(puback);
                 (Some(publication), Some(event))
             }
             proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, _dup) => {
+                self.record(|| SessionChange::Publish(publish.clone()));
                 self.waiting_to_be_released
                     .insert(packet_identifier, publish);
-                let pubrec = proto::PubRec { packet_identifier };
+                let pubrec = proto::PubRec {
+                    packet_identifier,
+                    reason_code: proto::ReasonCode::SUCCESS,
+                    properties: proto::Properties::default(),
+                };
                 let event = ClientEvent::PubRec(pubrec);
                 (None, Some(event))
             }
@@ -393,11 +692,14 @@ impl SessionState {
     }
 
     pub fn handle_pubrec(&mut self, pubrec: &proto::PubRec) -> Result<Option<ClientEvent>, Error> {
+        self.record(|| SessionChange::PubRec(pubrec.packet_identifier));
         self.waiting_to_be_acked.remove(&pubrec.packet_identifier);
         self.waiting_to_be_completed
             .insert(pubrec.packet_identifier);
         let pubrel = proto::PubRel {
             packet_identifier: pubrec.packet_identifier,
+            reason_code: proto::ReasonCode::SUCCESS,
+            properties: proto::Properties::default(),
         };
         Ok(Some(ClientEvent::PubRel(pubrel)))
     }
@@ -406,15 +708,20 @@ impl SessionState {
         &mut self,
         pubrel: &proto::PubRel,
     ) -> Result<Option<proto::Publication>, Error> {
-        let publication = self
+        let publish = self
             .waiting_to_be_released
-            .remove(&pubrel.packet_identifier)
-            .map(|publish| proto::Publication {
-                topic_name: publish.topic_name,
-                qos: proto::QoS::ExactlyOnce,
-                retain: publish.retain,
-                payload: publish.payload,
-            });
+            .remove(&pubrel.packet_identifier);
+        if publish.is_some() {
+            self.record(|| SessionChange::PubRel(pubrel.packet_identifier));
+        }
+
+        let publication = publish.map(|publish| proto::Publication {
+            topic_name: publish.topic_name,
+            qos: proto::QoS::ExactlyOnce,
+            retain: publish.retain,
+            payload: publish.payload,
+            properties: into_publication_properties(publish.properties),
+        });
         Ok(publication)
     }
 
@@ -422,6 +729,7 @@ impl SessionState {
         &mut self,
         pubcomp: &proto::PubComp,
     ) -> Result<Option<ClientEvent>, Error> {
+        self.record(|| SessionChange::PubComp(pubcomp.packet_identifier));
         self.waiting_to_be_completed
             .remove(&pubcomp.packet_identifier);
         self.packet_identifiers.discard(pubcomp.packet_identifier);
@@ -430,6 +738,7 @@ impl SessionState {
 
     pub fn handle_puback(&mut self, puback: &proto::PubAck) -> Result<Option<ClientEvent>, Error> {
         debug!("discarding packet identifier {}", puback.packet_identifier);
+        self.record(|| SessionChange::PubAck(puback.packet_identifier));
         self.waiting_to_be_acked.remove(&puback.packet_identifier);
         self.packet_identifiers.discard(puback.packet_identifier);
         self.try_publish()
@@ -440,6 +749,7 @@ impl SessionState {
         id: proto::PacketIdentifier,
     ) -> Result<Option<ClientEvent>, Error> {
         debug!("discarding QoS 0 packet identifier {}", id);
+        self.record(|| SessionChange::PubAck0(id));
         self.waiting_to_be_acked_qos0.remove(&id);
         self.packet_identifiers_qos0.discard(id);
         self.try_publish()
@@ -447,7 +757,7 @@ impl SessionState {
 
     fn try_publish(&mut self) -> Result<Option<ClientEvent>, Error> {
         if self.allowed_to_send() {
-            if let Some(publication) = self.waiting_to_be_sent.pop_front() {
+            if let Some(publication) = self.dequeue() {
                 let event = self.prepare_to_send(&publication)?;
                 return Ok(Some(event));
             }
@@ -455,16 +765,120 @@ impl SessionState {
         Ok(None)
     }
 
+    /// Takes the next publication from the queue, dropping the ones whose
+    /// message expiry interval elapsed while they were queued.
+    ///
+    /// [MQTT5-3.3.2-5] If the Message Expiry Interval has passed and the Server has
+    /// not managed to start onward delivery to a matching subscriber, then it MUST
+    /// delete the copy of the message for that subscriber.
+    fn dequeue(&mut self) -> Option<proto::Publication> {
+        let now = SystemTime::now();
+        while let Some(queued) = self.waiting_to_be_sent.pop_front() {
+            self.record(|| SessionChange::Dequeued);
+            if let Some(publication) = queued.publication_at(now) {
+                return Some(publication);
+            }
+            debug!("queued message to {} expired. dropping", self.client_id);
+        }
+        None
+    }
+
     fn allowed_to_send(&self) -> bool {
         let num_inflight = self.waiting_to_be_acked.len()
             + self.waiting_to_be_acked_qos0.len()
             + self.waiting_to_be_completed.len();
-        num_inflight < MAX_INFLIGHT_MESSAGES
+        num_inflight < self.config.max_inflight_messages
+    }
+
+    /// Puts a publication to the queue of messages waiting to be sent,
+    /// applying configured limits when the queue is full.
+    ///
+    /// Returns `Error::SessionQueueFull` when the session must be disconnected.
+    fn enqueue(&mut self, publication: proto::Publication) -> Result<Delivery, Error> {
+        self.enqueue_with(publication, self.config.when_full)
     }
 
+    fn enqueue_with(
+        &mut self,
+        publication: proto::Publication,
+        when_full: QueueFullAction,
+    ) -> Result<Delivery, Error> {
+        let size = publication.payload.len();
+        let max_size = self
+            .config
+            .max_message_size
+            .into_iter()
+            .chain(self.config.max_queued_size)
+            .min();
+
+        if max_size.map_or(false, |max_size| size > max_size) {
+            warn!(
+                "publication to {} of {} bytes exceeds the session limit. dropping",
+                self.client_id, size
+            );
+            return Ok(Delivery::Dropped(DropReason::TooLarge));
+        }
+
+        let mut queued_size: usize = match self.config.max_queued_size {
+            Some(_) => self
+                .waiting_to_be_sent
+                .iter()
+                .map(|queued| queued.publication.payload.len())
+                .sum(),
+            None => 0,
+        };
+
+        let max_queued_messages = self.config.max_queued_messages;
+        let max_queued_size = self.config.max_queued_size;
+        let is_full = |count: usize, queued_size: usize| {
+            max_queued_messages.map_or(false, |max_count| count >= max_count)
+                || max_queued_size.map_or(false, |max_size| queued_size + size > max_size)
+        };
+
+        let mut dropped = vec![];
+        if is_full(self.waiting_to_be_sent.len(), queued_size) {
+            match when_full {
+                QueueFullAction::DropNew => {
+                    debug!("queue is full for {}. dropping new message", self.client_id);
+                    return Ok(Delivery::Dropped(DropReason::QueueFull));
+                }
+                QueueFullAction::DropOld => {
+                    debug!(
+                        "queue is full for {}. dropping old messages",
+                        self.client_id
+                    );
+                    while is_full(self.waiting_to_be_sent.len(), queued_size) {
+                        match self.waiting_to_be_sent.pop_front() {
+                            Some(queued) => {
+                                self.record(|| SessionChange::Dequeued);
+                                queued_size -= queued.publication.payload.len();
+                                dropped.push(queued.publication);
+                            }
+                            None => break,
+                        }
+                    }
+                }
+                QueueFullAction::Disconnect => {
+                    warn!("queue is full for {}", self.client_id);
+                    return Err(Error::SessionQueueFull);
+                }
+            }
+        }
+
+        let queued_at = SystemTime::now();
+        self.record(|| SessionChange::Queued(publication.clone(), queued_at));
+        self.waiting_to_be_sent
+            .push_back(QueuedPublication::new(publication, queued_at));
+        Ok(Delivery::Queued(dropped))
+    }
+
+    /// Shared subscriptions are skipped, the broker picks a single member
+    /// of a share group to deliver a publication to.
     fn filter(&self, mut publication: proto::Publication) -> Option<proto::Publication> {
         self.subscriptions
-            .values()
+            .iter()
+            .filter(|(topic_filter, _)| !subscription::is_shared(topic_filter))
+            .map(|(_, sub)| sub)
             .filter(|sub| sub.filter().matches(&publication.topic_name))
             .fold(None, |acc, sub| {
                 acc.map(|qos| cmp::max(qos, cmp::min(*sub.max_qos(), publication.qos)))
@@ -485,6 +899,7 @@ impl SessionState {
                     retain: publication.retain,
                     topic_name: publication.topic_name.to_owned(),
                     payload: publication.payload.to_owned(),
+                    properties: publication.properties.clone(),
                 };
                 Publish::QoS0(id, packet)
             }
@@ -497,6 +912,7 @@ impl SessionState {
                     retain: publication.retain,
                     topic_name: publication.topic_name.to_owned(),
                     payload: publication.payload.to_owned(),
+                    properties: publication.properties.clone(),
                 };
                 Publish::QoS12(id, packet)
             }
@@ -509,11 +925,13 @@ impl SessionState {
                     retain: publication.retain,
                     topic_name: publication.topic_name.to_owned(),
                     payload: publication.payload.to_owned(),
+                    properties: publication.properties.clone(),
                 };
                 Publish::QoS12(id, packet)
             }
         };
 
+        self.record(|| SessionChange::Sent(publish.clone()));
         let event = match publish {
             Publish::QoS0(id, publish) => {
                 self.waiting_to_be_acked_qos0
@@ -529,6 +947,93 @@ impl SessionState {
         Ok(event)
     }
 
+    /// Starts recording the changes made to the session for the broker to journal.
+    pub(crate) fn start_journal(&mut self) {
+        self.journal.0 = Some(vec![]);
+    }
+
+    pub(crate) fn is_journaled(&self) -> bool {
+        self.journal.0.is_some()
+    }
+
+    /// Takes the changes recorded since they were taken last.
+    pub(crate) fn take_changes(&mut self) -> Vec<SessionChange> {
+        self.journal.0.as_mut().map(mem::take).unwrap_or_default()
+    }
+
+    fn record<F>(&mut self, change: F)
+    where
+        F: FnOnce() -> SessionChange,
+    {
+        if let Some(changes) = &mut self.journal.0 {
+            changes.push(change());
+        }
+    }
+
+    /// Makes a recorded change to the session again, as when the journal is replayed.
+    pub fn apply(&mut self, change: SessionChange) {
+        match change {
+            SessionChange::AuthId(auth_id) => self.auth_id = auth_id,
+            SessionChange::LastActive(last_active) => self.last_active = last_active,
+            SessionChange::SessionExpiryInterval(session_expiry_interval) => {
+                self.session_expiry_interval = session_expiry_interval;
+            }
+            SessionChange::Subscribed(topic_filter, subscription) => {
+                self.subscriptions.insert(topic_filter, subscription);
+            }
+            SessionChange::Unsubscribed(topic_filter) => {
+                self.subscriptions.remove(&topic_filter);
+            }
+            SessionChange::Queued(publication, queued_at) => {
+                self.waiting_to_be_sent
+                    .push_back(QueuedPublication::new(publication, queued_at));
+            }
+            SessionChange::Dequeued => {
+                self.waiting_to_be_sent.pop_front();
+            }
+            SessionChange::Sent(publish) => match publish {
+                Publish::QoS0(id, _) => {
+                    self.packet_identifiers_qos0.restore(id);
+                    self.waiting_to_be_acked_qos0.insert(id, publish);
+                }
+                Publish::QoS12(id, _) => {
+                    self.packet_identifiers.restore(id);
+                    self.waiting_to_be_acked.insert(id, publish);
+                }
+            },
+            SessionChange::Publish(publish) => {
+                if let proto::PacketIdentifierDupQoS::ExactlyOnce(id, _) =
+                    publish.packet_identifier_dup_qos
+                {
+                    self.waiting_to_be_released.insert(id, publish);
+                }
+            }
+            SessionChange::PubRel(id) => {
+                self.waiting_to_be_released.remove(&id);
+            }
+            SessionChange::PubAck(id) => {
+                self.waiting_to_be_acked.remove(&id);
+                self.packet_identifiers.discard(id);
+            }
+            SessionChange::PubAck0(id) => {
+                self.waiting_to_be_acked_qos0.remove(&id);
+                self.packet_identifiers_qos0.discard(id);
+            }
+            SessionChange::PubRec(id) => {
+                self.waiting_to_be_acked.remove(&id);
+                self.waiting_to_be_completed.insert(id);
+            }
+            SessionChange::PubComp(id) => {
+                self.waiting_to_be_completed.remove(&id);
+                self.packet_identifiers.discard(id);
+            }
+        }
+    }
+
+    /// Splits the session into its persisted parts.
+    ///
+    /// The time publications have been queued is not a part of the state,
+    /// so their message expiry interval starts over when the session is loaded.
     pub fn into_parts(
         self,
     ) -> (
@@ -536,7 +1041,12 @@ impl SessionState {
         HashMap<String, Subscription>,
         VecDeque<proto::Publication>,
     ) {
-        (self.client_id, self.subscriptions, self.waiting_to_be_sent)
+        let waiting_to_be_sent = self
+            .waiting_to_be_sent
+            .into_iter()
+            .map(|queued| queued.publication)
+            .collect();
+        (self.client_id, self.subscriptions, waiting_to_be_sent)
     }
 
     pub fn from_parts(
@@ -544,8 +1054,14 @@ impl SessionState {
         subscriptions: HashMap<String, Subscription>,
         waiting_to_be_sent: VecDeque<proto::Publication>,
     ) -> Self {
+        let now = SystemTime::now();
+        let waiting_to_be_sent = waiting_to_be_sent
+            .into_iter()
+            .map(|publication| QueuedPublication::new(publication, now))
+            .collect();
         Self {
             client_id,
+            auth_id: AuthId::Anonymous,
             subscriptions,
             packet_identifiers: PacketIdentifiers::default(),
             packet_identifiers_qos0: PacketIdentifiers::default(),
@@ -555,6 +1071,11 @@ impl SessionState {
             waiting_to_be_acked_qos0: HashMap::new(),
             waiting_to_be_released: HashMap::new(),
             waiting_to_be_completed: HashSet::new(),
+
+            last_active: SystemTime::now(),
+            session_expiry_interval: None,
+            config: SessionConfig::default(),
+            journal: Journal::default(),
         }
     }
 }
@@ -573,20 +1094,108 @@ impl SessionState {
         waiting_to_be_acked_qos0: HashMap<proto::PacketIdentifier, Publish>,
         waiting_to_be_completed: HashSet<proto::PacketIdentifier>,
     ) -> Self {
+        let now = SystemTime::now();
         Self {
             client_id,
+            auth_id: AuthId::Anonymous,
             subscriptions,
             packet_identifiers,
             packet_identifiers_qos0,
-            waiting_to_be_sent,
+            waiting_to_be_sent: waiting_to_be_sent
+                .into_iter()
+                .map(|publication| QueuedPublication::new(publication, now))
+                .collect(),
             waiting_to_be_acked,
             waiting_to_be_acked_qos0,
             waiting_to_be_released,
             waiting_to_be_completed,
+
+            last_active: SystemTime::now(),
+            session_expiry_interval: None,
+            config: SessionConfig::default(),
+            journal: Journal::default(),
         }
     }
 }
 
+/// A publication waiting in the session queue along with the time it was queued.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct QueuedPublication {
+    publication: proto::Publication,
+    queued_at: SystemTime,
+}
+
+impl QueuedPublication {
+    fn new(publication: proto::Publication, queued_at: SystemTime) -> Self {
+        Self {
+            publication,
+            queued_at,
+        }
+    }
+
+    /// Returns the publication with the message expiry interval reduced by
+    /// the time it has been queued, or `None` if the interval has elapsed.
+    ///
+    /// [MQTT5-3.3.2-6] The PUBLISH packet sent to a Client by the Server MUST contain
+    /// a Message Expiry Interval set to the received value minus the time that
+    /// the Application Message has been waiting in the Server.
+    fn publication_at(self, now: SystemTime) -> Option<proto::Publication> {
+        let mut publication = self.publication;
+        if let Some(interval) = publication.properties.message_expiry_interval {
+            let waited = now
+                .duration_since(self.queued_at)
+                .map_or(0, |waited| waited.as_secs());
+            let waited = u32::try_from(waited).unwrap_or(u32::max_value());
+            if waited >= interval {
+                return None;
+            }
+            publication.properties.message_expiry_interval = Some(interval - waited);
+        }
+        Some(publication)
+    }
+}
+
+/// A change made to the persisted part of a session, named after
+/// the packet or the operation which made it.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum SessionChange {
+    AuthId(AuthId),
+    LastActive(SystemTime),
+    SessionExpiryInterval(Option<Duration>),
+    Subscribed(String, Subscription),
+    Unsubscribed(String),
+
+    /// A publication joined the back of the queue.
+    Queued(proto::Publication, SystemTime),
+
+    /// A publication left the front of the queue, either sent, expired or dropped.
+    Dequeued,
+
+    /// A publication was sent to the client and waits to be acknowledged.
+    Sent(Publish),
+
+    /// A QoS 2 publication of the client waits to be released.
+    Publish(proto::Publish),
+    PubRel(proto::PacketIdentifier),
+
+    PubAck(proto::PacketIdentifier),
+    PubAck0(proto::PacketIdentifier),
+    PubRec(proto::PacketIdentifier),
+    PubComp(proto::PacketIdentifier),
+}
+
+/// Changes recorded for a journaled session.
+///
+/// They are not a part of the session state, so sessions compare equal regardless of them.
+#[derive(Clone, Debug, Default)]
+struct Journal(Option<Vec<SessionChange>>);
+
+impl PartialEq for Journal {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
 #[derive(Debug)]
 pub enum Session {
     Transient(ConnectedSession),
@@ -596,16 +1205,29 @@ pub enum Session {
 }
 
 impl Session {
-    pub fn new_transient(auth_id: AuthId, connreq: ConnReq) -> Self {
-        let state = SessionState::new(connreq.client_id().clone());
+    pub fn new_transient(auth_id: AuthId, connreq: ConnReq, config: SessionConfig) -> Self {
+        let state = SessionState::new(connreq.client_id().clone(), config);
         let (connect, handle) = connreq.into_parts();
-        let connected = ConnectedSession::new(auth_id, state, connect.will, handle);
+        let connected =
+            ConnectedSession::new(auth_id, state, connect.will, handle, connect.protocol_level);
         Self::Transient(connected)
     }
 
+    /// Creates a session which outlives the connection.
+    ///
+    /// The identity of the client and the session expiry interval of a MQTT 5.0 client
+    /// replace the ones stored in `state`.
     pub fn new_persistent(auth_id: AuthId, connreq: ConnReq, state: SessionState) -> Self {
         let (connect, handle) = connreq.into_parts();
-        let connected = ConnectedSession::new(auth_id, state, connect.will, handle);
+        let state = state.with_auth_id(auth_id.clone());
+        let state = if connect.protocol_level == mqtt3::PROTOCOL_LEVEL_V5 {
+            let interval = connect.properties.session_expiry_interval.unwrap_or(0);
+            state.with_session_expiry_interval(Some(Duration::from_secs(u64::from(interval))))
+        } else {
+            state.with_session_expiry_interval(None)
+        };
+        let connected =
+            ConnectedSession::new(auth_id, state, connect.will, handle, connect.protocol_level);
         Self::Persistent(connected)
     }
 
@@ -633,12 +1255,22 @@ impl Session {
         }
     }
 
-    pub fn auth_id(&self) -> Result<&AuthId, Error> {
+    /// The protocol level of a connected client.
+    pub fn protocol_level(&self) -> Option<u8> {
         match self {
-            Self::Transient(connected) => Ok(connected.auth_id()),
-            Self::Persistent(connected) => Ok(connected.auth_id()),
-            Self::Offline(_offline) => Err(Error::SessionOffline),
-            Self::Disconnecting(disconnecting) => Ok(disconnecting.auth_id()),
+            Self::Transient(connected) => Some(connected.protocol_level()),
+            Self::Persistent(connected) => Some(connected.protocol_level()),
+            Self::Offline(_) => None,
+            Self::Disconnecting(_) => None,
+        }
+    }
+
+    pub fn auth_id(&self) -> &AuthId {
+        match self {
+            Self::Transient(connected) => connected.auth_id(),
+            Self::Persistent(connected) => connected.auth_id(),
+            Self::Offline(offline) => offline.auth_id(),
+            Self::Disconnecting(disconnecting) => disconnecting.auth_id(),
         }
     }
 
@@ -651,6 +1283,44 @@ impl Session {
         }
     }
 
+    /// Applies updated session limits. Disconnecting sessions are left as is.
+    pub fn update_config(&mut self, config: SessionConfig) {
+        match self {
+            Self::Transient(connected) => connected.state.set_config(config),
+            Self::Persistent(connected) => connected.state.set_config(config),
+            Self::Offline(offline) => offline.state.set_config(config),
+            Self::Disconnecting(_) => (),
+        }
+    }
+
+    /// State of a session which outlives its connection, which is the part
+    /// of the broker state that is persisted.
+    pub(crate) fn persisted_state_mut(&mut self) -> Option<&mut SessionState> {
+        match self {
+            Self::Persistent(connected) => Some(&mut connected.state),
+            Self::Offline(offline) => Some(&mut offline.state),
+            Self::Transient(_) | Self::Disconnecting(_) => None,
+        }
+    }
+
+    pub fn queued_count(&self) -> Option<usize> {
+        match self {
+            Self::Transient(connected) => Some(connected.state().queued_count()),
+            Self::Persistent(connected) => Some(connected.state().queued_count()),
+            Self::Offline(offline) => Some(offline.state().queued_count()),
+            Self::Disconnecting(_) => None,
+        }
+    }
+
+    pub fn inflight(&self, packet_identifier: proto::PacketIdentifier) -> Option<&proto::Publish> {
+        match self {
+            Self::Transient(connected) => connected.state().inflight(packet_identifier),
+            Self::Persistent(connected) => connected.state().inflight(packet_identifier),
+            Self::Offline(offline) => offline.state().inflight(packet_identifier),
+            Self::Disconnecting(_) => None,
+        }
+    }
+
     pub fn subscriptions(&self) -> Option<&HashMap<String, Subscription>> {
         let state = match self {
             Self::Transient(connected) => Some(connected.state()),
@@ -728,10 +1398,7 @@ impl Session {
         }
     }
 
-    pub fn publish_to(
-        &mut self,
-        publication: &proto::Publication,
-    ) -> Result<Option<ClientEvent>, Error> {
+    pub fn publish_to(&mut self, publication: &proto::Publication) -> Result<Delivery, Error> {
         match self {
             Self::Transient(connected) => connected.publish_to(publication.to_owned()),
             Self::Persistent(connected) => connected.publish_to(publication.to_owned()),
@@ -740,6 +1407,23 @@ impl Session {
         }
     }
 
+    pub fn publish_to_shared(
+        &mut self,
+        publication: &proto::Publication,
+        max_qos: proto::QoS,
+    ) -> Result<Delivery, Error> {
+        match self {
+            Self::Transient(connected) => {
+                connected.publish_to_shared(publication.to_owned(), max_qos)
+            }
+            Self::Persistent(connected) => {
+                connected.publish_to_shared(publication.to_owned(), max_qos)
+            }
+            Self::Offline(offline) => offline.publish_to_shared(publication.to_owned(), max_qos),
+            Self::Disconnecting(_) => Err(Error::SessionOffline),
+        }
+    }
+
     pub fn subscribe_to(
         &mut self,
         subscribe_to: proto::SubscribeTo,
@@ -774,6 +1458,16 @@ impl Session {
     }
 }
 
+/// Properties of an incoming PUBLISH packet that are forwarded to subscribers.
+///
+/// Topic aliases are scoped to a connection and subscription identifiers
+/// are set per subscriber, so neither is kept.
+fn into_publication_properties(mut properties: proto::Properties) -> proto::Properties {
+    properties.topic_alias = None;
+    properties.subscription_identifiers.clear();
+    properties
+}
+
 #[derive(Clone)]
 pub(crate) struct IdentifiersInUse(pub(crate) Box<[usize; PacketIdentifiers::SIZE]>);
 
@@ -875,6 +1569,13 @@ impl PacketIdentifiers {
         Ok(current)
     }
 
+    /// Marks an identifier reserved, as `reserve` did when it returned it.
+    fn restore(&mut self, packet_identifier: proto::PacketIdentifier) {
+        let (block, mask) = self.entry(packet_identifier);
+        *block |= mask;
+        self.previous = packet_identifier;
+    }
+
     fn discard(&mut self, packet_identifier: proto::PacketIdentifier) {
         let (block, mask) = self.entry(packet_identifier);
         *block &= !mask;
@@ -909,7 +1610,7 @@ impl Default for PacketIdentifiers {
 
 #[cfg(test)]
 pub(crate) mod tests {
-    use std::time::Duration;
+    use std::time::{Duration, SystemTime};
 
     use matches::assert_matches;
     use tokio::sync::mpsc;
@@ -919,8 +1620,10 @@ pub(crate) mod tests {
 
     use crate::{
         auth::AuthId,
-        session::{PacketIdentifiers, Session, SessionState},
-        ClientId, ConnReq, ConnectionHandle, Error,
+        configuration::QueueFullAction,
+        session::{Delivery, DropReason, PacketIdentifiers, Session, SessionConfig, SessionState},
+        subscription::Subscription,
+        ClientEvent, ClientId, ConnReq, ConnectionHandle, Error, Publish,
     };
 
     fn connection_handle() -> ConnectionHandle {
@@ -938,6 +1641,7 @@ pub(crate) mod tests {
             keep_alive: Duration::default(),
             protocol_name: PROTOCOL_NAME.to_string(),
             protocol_level: PROTOCOL_LEVEL,
+            properties: proto::Properties::default(),
         }
     }
 
@@ -949,7 +1653,7 @@ pub(crate) mod tests {
         let handle1 = connection_handle();
         let req1 = ConnReq::new(client_id, connect1, None, handle1);
         let auth_id = "auth-id1".into();
-        let mut session = Session::new_transient(auth_id, req1);
+        let mut session = Session::new_transient(auth_id, req1, SessionConfig::default());
         let subscribe_to = proto::SubscribeTo {
             topic_filter: "topic/new".to_string(),
             qos: proto::QoS::AtMostOnce,
@@ -999,7 +1703,7 @@ pub(crate) mod tests {
         let handle1 = connection_handle();
         let req1 = ConnReq::new(client_id, connect1, None, handle1);
         let auth_id = "auth-id1".into();
-        let mut session = Session::new_transient(auth_id, req1);
+        let mut session = Session::new_transient(auth_id, req1, SessionConfig::default());
         let subscribe_to = proto::SubscribeTo {
             topic_filter: "topic/#/#".to_string(),
             qos: proto::QoS::AtMostOnce,
@@ -1019,7 +1723,7 @@ pub(crate) mod tests {
         let handle1 = connection_handle();
         let req1 = ConnReq::new(client_id, connect1, None, handle1);
         let auth_id = AuthId::Anonymous;
-        let mut session = Session::new_transient(auth_id, req1);
+        let mut session = Session::new_transient(auth_id, req1, SessionConfig::default());
 
         let subscribe_to = proto::SubscribeTo {
             topic_filter: "topic/new".to_string(),
@@ -1033,6 +1737,7 @@ pub(crate) mod tests {
         let unsubscribe = proto::Unsubscribe {
             packet_identifier: proto::PacketIdentifier::new(1).unwrap(),
             unsubscribe_from: vec!["topic/different".to_string()],
+            properties: proto::Properties::default(),
         };
         session.unsubscribe(&unsubscribe).unwrap();
 
@@ -1050,6 +1755,7 @@ pub(crate) mod tests {
         let unsubscribe = proto::Unsubscribe {
             packet_identifier: proto::PacketIdentifier::new(24).unwrap(),
             unsubscribe_from: vec!["topic/new".to_string()],
+            properties: proto::Properties::default(),
         };
         let unsuback = session.unsubscribe(&unsubscribe).unwrap();
         assert_eq!(
@@ -1069,7 +1775,8 @@ pub(crate) mod tests {
     fn test_offline_subscribe_to() {
         let id = "id1".to_string();
         let client_id = ClientId::from(id);
-        let mut session = Session::new_offline(SessionState::new(client_id));
+        let mut session =
+            Session::new_offline(SessionState::new(client_id, SessionConfig::default()));
 
         let subscribe_to = proto::SubscribeTo {
             topic_filter: "topic/new".to_string(),
@@ -1083,16 +1790,239 @@ pub(crate) mod tests {
     fn test_offline_unsubscribe() {
         let id = "id1".to_string();
         let client_id = ClientId::from(id);
-        let mut session = Session::new_offline(SessionState::new(client_id));
+        let mut session =
+            Session::new_offline(SessionState::new(client_id, SessionConfig::default()));
 
         let unsubscribe = proto::Unsubscribe {
             packet_identifier: proto::PacketIdentifier::new(24).unwrap(),
             unsubscribe_from: vec!["topic/new".to_string()],
+            properties: proto::Properties::default(),
         };
         let result = session.unsubscribe(&unsubscribe);
         assert_matches!(result, Err(Error::SessionOffline));
     }
 
+    fn session_with_limits(config: SessionConfig) -> SessionState {
+        let mut state = SessionState::new("id1".into(), config);
+        let filter = "topic/#".parse().unwrap();
+        state.update_subscription(
+            "topic/#".into(),
+            Subscription::new(filter, proto::QoS::AtLeastOnce),
+        );
+        state
+    }
+
+    fn publication(payload: &'static str) -> proto::Publication {
+        proto::Publication {
+            topic_name: "topic/new".to_string(),
+            qos: proto::QoS::AtLeastOnce,
+            retain: false,
+            payload: payload.into(),
+            properties: proto::Properties::default(),
+        }
+    }
+
+    fn queued_payloads(state: &SessionState) -> Vec<&[u8]> {
+        state
+            .waiting_to_be_sent
+            .iter()
+            .map(|queued| queued.publication.payload.as_ref())
+            .collect()
+    }
+
+    #[test]
+    fn test_queue_drop_new_when_max_count_reached() {
+        let config = SessionConfig::new(None, Some(2), None, QueueFullAction::DropNew);
+        let mut state = session_with_limits(config);
+
+        for payload in &["1", "2", "3"] {
+            state.queue_publish(publication(*payload)).unwrap();
+        }
+
+        assert_eq!(queued_payloads(&state), vec![&b"1"[..], &b"2"[..]]);
+    }
+
+    #[test]
+    fn test_queue_drop_old_when_max_count_reached() {
+        let config = SessionConfig::new(None, Some(2), None, QueueFullAction::DropOld);
+        let mut state = session_with_limits(config);
+
+        for payload in &["1", "2", "3"] {
+            state.queue_publish(publication(*payload)).unwrap();
+        }
+
+        assert_eq!(queued_payloads(&state), vec![&b"2"[..], &b"3"[..]]);
+    }
+
+    #[test]
+    fn test_queue_drop_old_when_max_total_space_reached() {
+        let config = SessionConfig::new(None, None, Some(5), QueueFullAction::DropOld);
+        let mut state = session_with_limits(config);
+
+        for payload in &["11", "22", "333"] {
+            state.queue_publish(publication(*payload)).unwrap();
+        }
+
+        assert_eq!(queued_payloads(&state), vec![&b"22"[..], &b"333"[..]]);
+    }
+
+    #[test]
+    fn test_queue_disconnect_when_full() {
+        let config = SessionConfig::new(None, Some(1), None, QueueFullAction::Disconnect);
+        let mut state = session_with_limits(config);
+
+        state.queue_publish(publication("1")).unwrap();
+        let result = state.queue_publish(publication("2"));
+
+        assert_matches!(result, Err(Error::SessionQueueFull));
+        assert_eq!(queued_payloads(&state), vec![&b"1"[..]]);
+    }
+
+    #[test]
+    fn test_dequeue_drops_expired_and_reduces_expiry_interval() {
+        let mut state = session_with_limits(SessionConfig::default());
+
+        for (payload, interval) in &[("1", 5), ("2", 60)] {
+            let mut publication = publication(*payload);
+            publication.properties.message_expiry_interval = Some(*interval);
+            state.queue_publish(publication).unwrap();
+        }
+        let queued_at = SystemTime::now() - Duration::from_secs(10);
+        for queued in &mut state.waiting_to_be_sent {
+            queued.queued_at = queued_at;
+        }
+
+        let event = state.try_publish().unwrap();
+
+        assert_matches!(
+            event,
+            Some(ClientEvent::PublishTo(Publish::QoS12(_, publish)))
+                if publish.payload == "2"
+                    && publish.properties.message_expiry_interval.map_or(false, |interval| interval <= 50)
+        );
+        assert_eq!(state.queued_count(), 0);
+    }
+
+    #[test]
+    fn test_offline_queue_drops_new_instead_of_disconnecting() {
+        let config = SessionConfig::new(None, Some(1), None, QueueFullAction::Disconnect);
+        let mut session = Session::new_offline(session_with_limits(config));
+
+        let delivery = session.publish_to(&publication("1")).unwrap();
+        assert_matches!(delivery, Delivery::Queued(dropped) if dropped.is_empty());
+        let delivery = session.publish_to(&publication("2")).unwrap();
+        assert_matches!(delivery, Delivery::Dropped(DropReason::QueueFull));
+        let delivery = session.publish_to(&publication("3")).unwrap();
+        assert_matches!(delivery, Delivery::Dropped(DropReason::QueueFull));
+
+        match session {
+            Session::Offline(offline) => {
+                assert_eq!(queued_payloads(offline.state()), vec![&b"1"[..]])
+            }
+            _ => panic!("session must be offline"),
+        }
+    }
+
+    #[test]
+    fn test_queue_drops_message_exceeding_max_size() {
+        let config = SessionConfig::new(Some(2), None, None, QueueFullAction::Disconnect);
+        let mut state = session_with_limits(config);
+
+        state.queue_publish(publication("123")).unwrap();
+        state.queue_publish(publication("12")).unwrap();
+
+        assert_eq!(queued_payloads(&state), vec![&b"12"[..]]);
+    }
+
+    #[test]
+    fn test_queue_reports_dropped_publications() {
+        let config = SessionConfig::new(Some(2), Some(1), None, QueueFullAction::DropNew);
+        let mut state = session_with_limits(config);
+
+        let delivery = state.queue_publish(publication("123")).unwrap();
+        assert_matches!(delivery, Delivery::Dropped(DropReason::TooLarge));
+        let delivery = state.queue_publish(publication("1")).unwrap();
+        assert_matches!(delivery, Delivery::Queued(dropped) if dropped.is_empty());
+        let delivery = state.queue_publish(publication("2")).unwrap();
+        assert_matches!(delivery, Delivery::Dropped(DropReason::QueueFull));
+
+        let config = SessionConfig::new(None, Some(1), None, QueueFullAction::DropOld);
+        let mut state = session_with_limits(config);
+
+        state.queue_publish(publication("1")).unwrap();
+        let delivery = state.queue_publish(publication("2")).unwrap();
+        assert_matches!(delivery, Delivery::Queued(dropped) if dropped == vec![publication("1")]);
+    }
+
+    #[test]
+    fn test_publish_to_queues_when_inflight_window_is_full() {
+        let config = SessionConfig::default().with_max_inflight_messages(1);
+        let mut state = session_with_limits(config);
+
+        let delivery = state.publish_to(publication("1")).unwrap();
+        assert_matches!(delivery, Delivery::Send(ClientEvent::PublishTo(_)));
+
+        let delivery = state.publish_to(publication("2")).unwrap();
+        assert_matches!(delivery, Delivery::Queued(dropped) if dropped.is_empty());
+        assert_eq!(queued_payloads(&state), vec![&b"2"[..]]);
+    }
+
+    #[test]
+    fn test_journal_replays_to_state() {
+        let config = SessionConfig::default().with_max_inflight_messages(1);
+        let mut state = session_with_limits(config);
+        let mut replayed = state.clone();
+        state.start_journal();
+
+        let filter = "other/#".parse().unwrap();
+        state.update_subscription(
+            "other/#".into(),
+            Subscription::new(filter, proto::QoS::ExactlyOnce),
+        );
+
+        let id = match state.publish_to(publication("1")).unwrap() {
+            Delivery::Send(ClientEvent::PublishTo(Publish::QoS12(id, _))) => id,
+            delivery => panic!("unexpected delivery {:?}", delivery),
+        };
+        let delivery = state.publish_to(publication("2")).unwrap();
+        assert_matches!(delivery, Delivery::Queued(_));
+
+        let puback = proto::PubAck {
+            packet_identifier: id,
+            reason_code: proto::ReasonCode::SUCCESS,
+            properties: proto::Properties::default(),
+        };
+        let event = state.handle_puback(&puback).unwrap();
+        assert_matches!(event, Some(ClientEvent::PublishTo(_)));
+
+        for id in 7..9 {
+            let publish = proto::Publish {
+                packet_identifier_dup_qos: proto::PacketIdentifierDupQoS::ExactlyOnce(
+                    proto::PacketIdentifier::new(id).unwrap(),
+                    false,
+                ),
+                retain: false,
+                topic_name: "topic/new".to_string(),
+                payload: "payload".into(),
+                properties: proto::Properties::default(),
+            };
+            state.handle_publish(publish).unwrap();
+        }
+        let pubrel = proto::PubRel {
+            packet_identifier: proto::PacketIdentifier::new(7).unwrap(),
+            reason_code: proto::ReasonCode::SUCCESS,
+            properties: proto::Properties::default(),
+        };
+        state.handle_pubrel(&pubrel).unwrap();
+        state.remove_subscription("topic/#");
+
+        for change in state.take_changes() {
+            replayed.apply(change);
+        }
+        assert_eq!(replayed, state);
+        assert!(state.take_changes().is_empty());
+    }
+
     #[test]
     fn packet_identifiers() {
         #[cfg(target_pointer_width = "32")]
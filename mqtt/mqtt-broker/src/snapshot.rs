@@ -1,34 +1,60 @@
-use tokio::sync::mpsc::{self, Receiver, Sender};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 use tracing::{info, warn};
 
-use crate::persist::Persist;
+use crate::metrics;
+use crate::persist::{Change, Persist};
 use crate::{BrokerState, Error};
 
+/// sets the number of states waiting to be persisted above which new ones are refused
+const MAX_PENDING_STATES: usize = 5;
+
 enum Event {
     State(BrokerState),
+    Changes(Vec<Change>),
     Shutdown,
 }
 
+/// Sends states and changes to the snapshotter.
+///
+/// States and changes share a channel so that the snapshotter persists them
+/// in the order the broker made them. Only states are limited, as a change
+/// which is not persisted would leave a gap in the journal.
 #[derive(Clone, Debug)]
-pub struct StateSnapshotHandle(Sender<Event>);
+pub struct StateSnapshotHandle {
+    sender: UnboundedSender<Event>,
+    pending: Arc<AtomicUsize>,
+}
 
 impl StateSnapshotHandle {
     pub fn try_send(&mut self, state: BrokerState) -> Result<(), Error> {
-        self.0
-            .try_send(Event::State(state))
-            .map_err(|_| Error::SendSnapshotMessage)?;
-        Ok(())
+        if self.pending.fetch_add(1, Ordering::SeqCst) >= MAX_PENDING_STATES {
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+            return Err(Error::SendSnapshotMessage);
+        }
+
+        self.sender.send(Event::State(state)).map_err(|_| {
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+            Error::SendSnapshotMessage
+        })
+    }
+
+    pub fn send_changes(&mut self, changes: Vec<Change>) -> Result<(), Error> {
+        self.sender
+            .send(Event::Changes(changes))
+            .map_err(|_| Error::SendSnapshotMessage)
     }
 }
 
 #[derive(Debug)]
-pub struct ShutdownHandle(Sender<Event>);
+pub struct ShutdownHandle(UnboundedSender<Event>);
 
 impl ShutdownHandle {
     pub async fn shutdown(&mut self) -> Result<(), Error> {
         self.0
             .send(Event::Shutdown)
-            .await
             .map_err(|_| Error::SendSnapshotMessage)?;
         Ok(())
     }
@@ -36,22 +62,27 @@ impl ShutdownHandle {
 
 pub struct Snapshotter<P> {
     persistor: P,
-    sender: Sender<Event>,
-    events: Receiver<Event>,
+    sender: UnboundedSender<Event>,
+    events: UnboundedReceiver<Event>,
+    pending: Arc<AtomicUsize>,
 }
 
 impl<P> Snapshotter<P> {
     pub fn new(persistor: P) -> Self {
-        let (sender, events) = mpsc::channel(5);
+        let (sender, events) = mpsc::unbounded_channel();
         Snapshotter {
             persistor,
             sender,
             events,
+            pending: Arc::new(AtomicUsize::new(0)),
         }
     }
 
     pub fn snapshot_handle(&self) -> StateSnapshotHandle {
-        StateSnapshotHandle(self.sender.clone())
+        StateSnapshotHandle {
+            sender: self.sender.clone(),
+            pending: self.pending.clone(),
+        }
     }
 
     pub fn shutdown_handle(&self) -> ShutdownHandle {
@@ -64,13 +95,40 @@ where
     P: Persist,
 {
     pub async fn run(mut self) -> P {
-        while let Some(event) = self.events.recv().await {
+        let mut next = None;
+        loop {
+            let event = match next.take() {
+                Some(event) => event,
+                None => match self.events.recv().await {
+                    Some(event) => event,
+                    None => break,
+                },
+            };
+
             match event {
                 Event::State(state) => {
+                    self.pending.fetch_sub(1, Ordering::SeqCst);
+                    let _timer = metrics::SNAPSHOT_DURATION.start_timer();
                     if let Err(e) = self.persistor.store(state).await {
                         warn!(message = "an error occurred persisting state snapshot.", error=%e);
                     }
                 }
+                Event::Changes(mut changes) => {
+                    // changes which arrived in the meantime are appended together
+                    while let Ok(event) = self.events.try_recv() {
+                        match event {
+                            Event::Changes(more) => changes.extend(more),
+                            event => {
+                                next = Some(event);
+                                break;
+                            }
+                        }
+                    }
+
+                    if let Err(e) = self.persistor.append(changes).await {
+                        warn!(message = "an error occurred journaling state changes.", error=%e);
+                    }
+                }
                 Event::Shutdown => {
                     info!("state snapshotter shutting down...");
                     break;
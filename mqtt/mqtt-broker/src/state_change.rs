@@ -7,11 +7,16 @@ use crate::session::Session;
 use crate::{ClientId, Error};
 
 const STATE_CHANGE_QOS: proto::QoS = proto::QoS::AtLeastOnce;
+const STATS_QOS: proto::QoS = proto::QoS::AtMostOnce;
 
 pub enum StateChange<'a> {
     Subscriptions(&'a ClientId, Option<Vec<&'a str>>),
     Connections(Vec<&'a ClientId>),
     Sessions(Vec<&'a ClientId>),
+    /// Broker statistic published under `$SYS/broker/`.
+    Stat(&'static str, String),
+    /// Number of publications queued for a session.
+    SessionQueue(&'a ClientId, usize),
 }
 
 impl<'a> StateChange<'a> {
@@ -51,6 +56,16 @@ impl<'a> StateChange<'a> {
 
         Self::Sessions(sessions)
     }
+
+    pub fn new_stat(name: &'static str, value: impl ToString) -> Self {
+        Self::Stat(name, value.to_string())
+    }
+}
+
+/// Whether a client id can be used as a single level of a topic name, i.e.
+/// it does not contain a level separator or a wildcard.
+pub(crate) fn is_topic_level(client_id: &ClientId) -> bool {
+    !client_id.as_str().is_empty() && !client_id.as_str().contains(&['/', '+', '#'][..])
 }
 
 impl<'a> TryFrom<StateChange<'a>> for proto::Publication {
@@ -70,6 +85,7 @@ impl<'a> TryFrom<StateChange<'a>> for proto::Publication {
                     qos: STATE_CHANGE_QOS,
                     retain: true,
                     payload,
+                    properties: proto::Properties::default(),
                 }
             }
             StateChange::Connections(connections) => proto::Publication {
@@ -77,12 +93,29 @@ impl<'a> TryFrom<StateChange<'a>> for proto::Publication {
                 qos: STATE_CHANGE_QOS,
                 retain: true,
                 payload: serde_json::to_string(&connections)?.into(),
+                properties: proto::Properties::default(),
             },
             StateChange::Sessions(sessions) => proto::Publication {
                 topic_name: "$edgehub/sessions".to_owned(),
                 qos: STATE_CHANGE_QOS,
                 retain: true,
                 payload: serde_json::to_string(&sessions)?.into(),
+                properties: proto::Properties::default(),
+            },
+            StateChange::Stat(name, value) => proto::Publication {
+                topic_name: format!("$SYS/broker/{}", name),
+                qos: STATS_QOS,
+                retain: true,
+                payload: value.into(),
+                properties: proto::Properties::default(),
+            },
+            // not retained, so that the topics of removed sessions do not linger
+            StateChange::SessionQueue(client_id, count) => proto::Publication {
+                topic_name: format!("$SYS/broker/clients/{}/queued", client_id),
+                qos: STATS_QOS,
+                retain: false,
+                payload: count.to_string().into(),
+                properties: proto::Properties::default(),
             },
         })
     }
@@ -98,7 +131,7 @@ mod tests {
 
     use crate::broker::tests::{connection_handle, is_notify_equal, persistent_connect};
     use crate::session::{Session, SessionState};
-    use crate::state_change::{StateChange, STATE_CHANGE_QOS};
+    use crate::state_change::{is_topic_level, StateChange, STATE_CHANGE_QOS, STATS_QOS};
     use crate::subscription::{Subscription, TopicFilter};
     use crate::{AuthId, ClientId, ConnReq};
 
@@ -347,6 +380,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_stats() {
+        let message: proto::Publication = StateChange::new_stat("clients/connected", 3)
+            .try_into()
+            .unwrap();
+
+        assert_eq!("$SYS/broker/clients/connected", message.topic_name);
+        assert_eq!(STATS_QOS, message.qos);
+        assert!(message.retain);
+        assert_eq!(&b"3"[..], &message.payload[..]);
+
+        let client_id: ClientId = "Session".into();
+        let message: proto::Publication =
+            StateChange::SessionQueue(&client_id, 5).try_into().unwrap();
+
+        assert_eq!("$SYS/broker/clients/Session/queued", message.topic_name);
+        assert!(!message.retain);
+        assert_eq!(&b"5"[..], &message.payload[..]);
+    }
+
+    #[test]
+    fn test_client_id_as_topic_level() {
+        assert!(is_topic_level(&"Session".into()));
+        assert!(!is_topic_level(&"".into()));
+        assert!(!is_topic_level(&"devices/1".into()));
+        assert!(!is_topic_level(&"devices+".into()));
+        assert!(!is_topic_level(&"#".into()));
+    }
+
     fn make_session<I, S>(id: &str, subscriptions: I, online: bool) -> Session
     where
         I: IntoIterator<Item = S>,
@@ -406,6 +468,7 @@ mod tests {
             qos,
             retain,
             payload,
+            ..
         } = publication;
 
         assert_eq!(&topic_name, topic);
@@ -0,0 +1,131 @@
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+use mqtt3::proto;
+
+/// Counters of publications flowing through the broker.
+///
+/// Received publications are the ones clients publish to the broker and sent
+/// publications are the ones the broker delivers to subscribed sessions.
+/// Bytes are counted for publication payloads.
+#[derive(Debug)]
+pub(crate) struct BrokerStats {
+    started_at: Instant,
+    messages_received: u64,
+    messages_sent: u64,
+    bytes_received: u64,
+    bytes_sent: u64,
+    last_report: (Instant, u64, u64),
+}
+
+impl Default for BrokerStats {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            started_at: now,
+            messages_received: 0,
+            messages_sent: 0,
+            bytes_received: 0,
+            bytes_sent: 0,
+            last_report: (now, 0, 0),
+        }
+    }
+}
+
+impl BrokerStats {
+    pub fn received(&mut self, publication: &proto::Publication) {
+        self.messages_received += 1;
+        self.bytes_received += payload_len(publication);
+    }
+
+    pub fn sent(&mut self, publication: &proto::Publication) {
+        self.messages_sent += 1;
+        self.bytes_sent += payload_len(publication);
+    }
+
+    /// Takes a report of the counters. Rates are calculated since the previous report.
+    pub fn report(&mut self) -> StatsReport {
+        let now = Instant::now();
+        let (reported_at, received, sent) = self.last_report;
+        let elapsed = now.duration_since(reported_at);
+        self.last_report = (now, self.messages_received, self.messages_sent);
+
+        StatsReport {
+            uptime: now.duration_since(self.started_at),
+            messages_received: self.messages_received,
+            messages_sent: self.messages_sent,
+            messages_received_per_second: per_second(self.messages_received - received, elapsed),
+            messages_sent_per_second: per_second(self.messages_sent - sent, elapsed),
+            bytes_received: self.bytes_received,
+            bytes_sent: self.bytes_sent,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct StatsReport {
+    pub uptime: Duration,
+    pub messages_received: u64,
+    pub messages_sent: u64,
+    pub messages_received_per_second: u128,
+    pub messages_sent_per_second: u128,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+}
+
+fn payload_len(publication: &proto::Publication) -> u64 {
+    u64::try_from(publication.payload.len()).unwrap_or(u64::max_value())
+}
+
+fn per_second(count: u64, elapsed: Duration) -> u128 {
+    match elapsed.as_millis() {
+        0 => 0,
+        millis => u128::from(count) * 1000 / millis,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use mqtt3::proto;
+
+    use crate::stats::{per_second, BrokerStats};
+
+    fn publication(payload: &'static str) -> proto::Publication {
+        proto::Publication {
+            topic_name: "topic".to_string(),
+            qos: proto::QoS::AtMostOnce,
+            retain: false,
+            payload: Bytes::from(payload),
+            properties: proto::Properties::default(),
+        }
+    }
+
+    #[test]
+    fn it_counts_messages_and_bytes() {
+        let mut stats = BrokerStats::default();
+        stats.received(&publication("hello"));
+        stats.sent(&publication("hello"));
+        stats.sent(&publication("hi"));
+
+        let report = stats.report();
+        assert_eq!(1, report.messages_received);
+        assert_eq!(2, report.messages_sent);
+        assert_eq!(5, report.bytes_received);
+        assert_eq!(7, report.bytes_sent);
+
+        stats.received(&publication("again"));
+        let report = stats.report();
+        assert_eq!(2, report.messages_received);
+        assert_eq!(10, report.bytes_received);
+    }
+
+    #[test]
+    fn it_calculates_rate_per_second() {
+        assert_eq!(5, per_second(10, Duration::from_secs(2)));
+        assert_eq!(2500, per_second(5, Duration::from_millis(2)));
+        assert_eq!(0, per_second(5, Duration::from_secs(0)));
+    }
+}
@@ -1,15 +1,17 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::str::FromStr;
 
 use mqtt3::proto;
 use serde::{Deserialize, Serialize};
 
-use crate::Error;
+use crate::{ClientId, Error};
 
 const NUL_CHAR: char = '\0';
 const TOPIC_SEPARATOR: char = '/';
 static MULTILEVEL_WILDCARD: &str = "#";
 static SINGLELEVEL_WILDCARD: &str = "+";
+static SHARED_SUBSCRIPTION_PREFIX: &str = "$share/";
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Subscription {
@@ -83,6 +85,59 @@ impl TopicFilter {
     }
 }
 
+impl TopicFilter {
+    /// Returns `true` if every topic matched by `other` is also matched by this filter.
+    pub(crate) fn covers(&self, other: &TopicFilter) -> bool {
+        let mut segments = self.segments.iter();
+        let mut others = other.segments.iter();
+
+        loop {
+            match (segments.next(), others.next()) {
+                (Some(Segment::MultiLevelWildcard), _) => return true,
+                (Some(Segment::SingleLevelWildcard), Some(Segment::MultiLevelWildcard)) => {
+                    return false
+                }
+                (Some(Segment::SingleLevelWildcard), Some(_)) => (),
+                (Some(Segment::Level(s)), Some(Segment::Level(o))) if s == o => (),
+                (Some(_), Some(_)) => return false,
+                (Some(_), None) => return false,
+                (None, Some(_)) => return false,
+                (None, None) => return true,
+            }
+        }
+    }
+}
+
+/// Returns `true` if the topic filter denotes a MQTT 5.0 shared subscription.
+pub(crate) fn is_shared(topic_filter: &str) -> bool {
+    topic_filter.starts_with(SHARED_SUBSCRIPTION_PREFIX)
+}
+
+/// Splits a `$share/{ShareName}/{filter}` shared subscription into
+/// the share name and the topic filter.
+pub(crate) fn parse_shared(topic_filter: &str) -> Result<(&str, TopicFilter), Error> {
+    let invalid = || Error::InvalidTopicFilter(topic_filter.to_owned());
+
+    let shared = topic_filter
+        .get(SHARED_SUBSCRIPTION_PREFIX.len()..)
+        .ok_or_else(invalid)?;
+    let separator = shared.find(TOPIC_SEPARATOR).ok_or_else(invalid)?;
+    let (share_name, filter) = (&shared[..separator], &shared[separator + 1..]);
+
+    // [MQTT5-4.8.2] The ShareName MUST NOT contain the characters "/", "+" or "#",
+    // but MUST be followed by a "/" character.
+    // This "/" character MUST be followed by a Topic Filter.
+    if share_name.is_empty()
+        || share_name.contains(MULTILEVEL_WILDCARD)
+        || share_name.contains(SINGLELEVEL_WILDCARD)
+    {
+        return Err(invalid());
+    }
+
+    let filter = filter.parse()?;
+    Ok((share_name, filter))
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Segment {
     Level(String),
@@ -159,13 +214,158 @@ impl FromStr for TopicFilter {
     }
 }
 
+/// Index of topic filters to the clients subscribed to them.
+///
+/// Topic filters are stored as a trie of their segments, so finding the
+/// subscribers of a topic only visits the branches its levels can match
+/// instead of testing every filter of every session.
+#[derive(Debug, Default)]
+pub(crate) struct SubscriptionTrie {
+    root: Node,
+    filters: HashMap<ClientId, Vec<TopicFilter>>,
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    subscribers: HashSet<ClientId>,
+    multilevel: HashSet<ClientId>,
+    singlelevel: Option<Box<Node>>,
+    levels: HashMap<String, Node>,
+}
+
+impl SubscriptionTrie {
+    /// Replaces the topic filters indexed for a client.
+    pub(crate) fn update<'a>(
+        &mut self,
+        client_id: &ClientId,
+        filters: impl IntoIterator<Item = &'a TopicFilter>,
+    ) {
+        self.remove(client_id);
+
+        let filters: Vec<_> = filters.into_iter().cloned().collect();
+        for filter in &filters {
+            self.root.insert(client_id, &filter.segments);
+        }
+
+        if !filters.is_empty() {
+            self.filters.insert(client_id.clone(), filters);
+        }
+    }
+
+    /// Removes all topic filters of a client from the index.
+    pub(crate) fn remove(&mut self, client_id: &ClientId) {
+        if let Some(filters) = self.filters.remove(client_id) {
+            for filter in filters {
+                self.root.remove(client_id, &filter.segments);
+            }
+        }
+    }
+
+    /// Returns the clients with at least one topic filter matching the topic.
+    pub(crate) fn matches(&self, topic_name: &str) -> HashSet<ClientId> {
+        let levels: Vec<_> = topic_name.split(TOPIC_SEPARATOR).collect();
+
+        // [MQTT-4.7.2-1] The Server MUST NOT match Topic Filters starting with a
+        // wildcard character (# or +) with Topic Names beginning with a $ character.
+        let wildcards = !levels[0].starts_with('$');
+
+        let mut subscribers = HashSet::new();
+        self.root.collect(&levels, wildcards, &mut subscribers);
+        subscribers
+    }
+}
+
+impl Node {
+    fn insert(&mut self, client_id: &ClientId, segments: &[Segment]) {
+        match segments.split_first() {
+            None => {
+                self.subscribers.insert(client_id.clone());
+            }
+            Some((Segment::MultiLevelWildcard, _)) => {
+                self.multilevel.insert(client_id.clone());
+            }
+            Some((Segment::SingleLevelWildcard, rest)) => self
+                .singlelevel
+                .get_or_insert_with(Box::default)
+                .insert(client_id, rest),
+            Some((Segment::Level(level), rest)) => self
+                .levels
+                .entry(level.clone())
+                .or_default()
+                .insert(client_id, rest),
+        }
+    }
+
+    /// Returns `true` if the node is left without subscribers
+    /// and can be pruned from the trie.
+    fn remove(&mut self, client_id: &ClientId, segments: &[Segment]) -> bool {
+        match segments.split_first() {
+            None => {
+                self.subscribers.remove(client_id);
+            }
+            Some((Segment::MultiLevelWildcard, _)) => {
+                self.multilevel.remove(client_id);
+            }
+            Some((Segment::SingleLevelWildcard, rest)) => {
+                let empty = self
+                    .singlelevel
+                    .as_mut()
+                    .map_or(false, |node| node.remove(client_id, rest));
+                if empty {
+                    self.singlelevel = None;
+                }
+            }
+            Some((Segment::Level(level), rest)) => {
+                let empty = self
+                    .levels
+                    .get_mut(level)
+                    .map_or(false, |node| node.remove(client_id, rest));
+                if empty {
+                    self.levels.remove(level);
+                }
+            }
+        }
+
+        self.is_empty()
+    }
+
+    fn collect(&self, levels: &[&str], wildcards: bool, subscribers: &mut HashSet<ClientId>) {
+        // a multi-level wildcard also matches the parent level
+        if wildcards {
+            subscribers.extend(self.multilevel.iter().cloned());
+        }
+
+        match levels.split_first() {
+            None => subscribers.extend(self.subscribers.iter().cloned()),
+            Some((level, rest)) => {
+                if let Some(node) = self.levels.get(*level) {
+                    node.collect(rest, true, subscribers);
+                }
+                if wildcards {
+                    if let Some(node) = &self.singlelevel {
+                        node.collect(rest, true, subscribers);
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+            && self.multilevel.is_empty()
+            && self.singlelevel.is_none()
+            && self.levels.is_empty()
+    }
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use std::str::FromStr;
 
     use proptest::prelude::*;
 
-    use crate::subscription::{Segment, TopicFilter};
+    use crate::subscription::{parse_shared, Segment, SubscriptionTrie, TopicFilter};
+    use crate::ClientId;
 
     fn filter(segments: Vec<Segment>) -> TopicFilter {
         TopicFilter::new(segments)
@@ -239,17 +439,138 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn shared_subscription_valid() {
+        let (share_name, filter) = parse_shared("$share/group/sport/+").unwrap();
+
+        assert_eq!(share_name, "group");
+        assert_eq!(filter, TopicFilter::from_str("sport/+").unwrap());
+    }
+
+    #[test]
+    fn shared_subscription_invalid() {
+        let cases = vec![
+            "$share/",
+            "$share/group",
+            "$share//sport",
+            "$share/gr+oup/sport",
+            "$share/gr#oup/sport",
+            "$share/group/",
+        ];
+
+        for case in &cases {
+            assert!(parse_shared(case).is_err(), "{}", case);
+        }
+    }
+
     proptest! {
         #[test]
         fn display_roundtrip(filter in crate::proptest::arb_topic_filter()) {
             let string = filter.to_string();
             prop_assert_eq!(filter, string.parse::<TopicFilter>().unwrap());
         }
+
+        #[test]
+        fn trie_matches_like_topic_filter(
+            filter in crate::proptest::arb_topic_filter(),
+            topic in crate::proptest::arb_topic(),
+        ) {
+            let client_id = ClientId::from("client");
+            let mut trie = SubscriptionTrie::default();
+            trie.update(&client_id, &[filter.clone()]);
+
+            prop_assert_eq!(filter.matches(&topic), trie.matches(&topic).contains(&client_id));
+        }
     }
 
     #[test]
-    fn test_topics() {
+    fn topic_filter_covers() {
         let cases = vec![
+            ("#", "a/b", true),
+            ("#", "#", true),
+            ("a/#", "a/+/c", true),
+            ("a/#", "a/#", true),
+            ("a/+", "a/b", true),
+            ("a/+", "a/+", true),
+            ("a/+", "a/#", false),
+            ("a/+", "a/b/c", false),
+            ("a/b", "a/+", false),
+            ("a/b", "a/b", true),
+            ("a/b/#", "a/#", false),
+            ("+/b", "a/c", false),
+        ];
+
+        for (filter, other, expected) in &cases {
+            let filter = TopicFilter::from_str(filter).unwrap();
+            let other = TopicFilter::from_str(other).unwrap();
+            assert_eq!(
+                *expected,
+                filter.covers(&other),
+                "filter \"{}\" covers \"{}\"",
+                filter,
+                other
+            );
+        }
+    }
+
+    #[test]
+    fn trie_matches_topics() {
+        for (filter, topic, expected) in &topic_cases() {
+            let client_id = ClientId::from("client");
+            let mut trie = SubscriptionTrie::default();
+            trie.update(&client_id, &[TopicFilter::from_str(filter).unwrap()]);
+
+            assert_eq!(
+                *expected,
+                trie.matches(topic).contains(&client_id),
+                "filter \"{}\" matches \"{}\"",
+                filter,
+                topic
+            );
+        }
+    }
+
+    #[test]
+    fn trie_matches_each_client_once() {
+        let client1 = ClientId::from("client1");
+        let client2 = ClientId::from("client2");
+        let filters = |filters: &[&str]| -> Vec<TopicFilter> {
+            filters.iter().map(|f| f.parse().unwrap()).collect()
+        };
+
+        let mut trie = SubscriptionTrie::default();
+        trie.update(&client1, &filters(&["sport/#", "sport/+/player1", "news"]));
+        trie.update(&client2, &filters(&["sport/tennis/+"]));
+
+        let subscribers = trie.matches("sport/tennis/player1");
+        assert_eq!(2, subscribers.len());
+        assert!(subscribers.contains(&client1));
+        assert!(subscribers.contains(&client2));
+
+        assert_eq!(1, trie.matches("news").len());
+        assert!(trie.matches("weather").is_empty());
+    }
+
+    #[test]
+    fn trie_update_replaces_filters() {
+        let client_id = ClientId::from("client");
+        let mut trie = SubscriptionTrie::default();
+
+        trie.update(&client_id, &["sport/+".parse().unwrap()]);
+        trie.update(&client_id, &["news/#".parse().unwrap()]);
+
+        assert!(trie.matches("sport/tennis").is_empty());
+        assert!(trie.matches("news/today").contains(&client_id));
+
+        trie.remove(&client_id);
+
+        assert!(trie.matches("news/today").is_empty());
+        assert!(trie.root.is_empty());
+        assert!(trie.filters.is_empty());
+    }
+
+    fn topic_cases() -> Vec<(&'static str, &'static str, bool)> {
+        vec![
             ("#", "blah", true),
             ("blah/#", "blah", true),
             ("blah/blah2/#", "blah", false),
@@ -260,9 +581,13 @@ pub(crate) mod tests {
             ("blah/blah1/blah2", "blah/blah", false),
             ("#", "$SYS/blah", false),
             ("+", "$SYS", false),
-        ];
+            ("$SYS/#", "$SYS/blah", true),
+        ]
+    }
 
-        for (filter, topic, expected) in &cases {
+    #[test]
+    fn test_topics() {
+        for (filter, topic, expected) in &topic_cases() {
             let parsed = TopicFilter::from_str(filter).unwrap();
             assert_eq!(
                 *expected,
@@ -0,0 +1,638 @@
+use std::collections::hash_map::DefaultHasher;
+use std::convert::TryFrom;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self as std_mpsc, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use mqtt3::proto;
+use serde::Serialize;
+use tokio::sync::mpsc::Sender;
+use tracing::{debug, error, warn};
+
+use crate::configuration::{Trace, TraceOutput};
+use crate::session::DropReason;
+use crate::subscription::TopicFilter;
+use crate::{ClientEvent, ClientId, Message, Publish, SystemEvent};
+
+const TRACE_TOPIC_PREFIX: &str = "$SYS/trace/";
+
+/// Number of records waiting to be written to the trace file before new ones are dropped.
+const FILE_QUEUE_CAPACITY: usize = 4096;
+
+/// Time to wait before the trace file writer is restarted after it stopped.
+const FILE_WRITER_RESTART_DELAY: Duration = Duration::from_secs(10);
+
+/// A step in the life of a publication inside the broker.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum TraceEvent {
+    /// A client published the publication to the broker.
+    Received,
+
+    /// The authorizer allowed the client to publish.
+    Authorized,
+
+    /// The authorizer did not allow the client to publish,
+    /// or a subscriber to receive the publication.
+    Denied,
+
+    /// The publication was delivered to a number of sessions.
+    Routed { sessions: usize },
+
+    /// The publication waits in the queue of a session.
+    Queued,
+
+    /// The publication was sent to a subscriber.
+    Sent,
+
+    /// The subscriber acknowledged the publication.
+    Acked,
+
+    /// The publication was dropped instead of being delivered to a session.
+    Dropped { reason: DropReason },
+}
+
+/// A trace event of a publication, along with the client it happened for.
+#[derive(Debug, Serialize)]
+pub(crate) struct TraceRecord<'a> {
+    time: String,
+    #[serde(flatten)]
+    event: TraceEvent,
+    client_id: &'a ClientId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    packet_id: Option<u16>,
+    topic: &'a str,
+    qos: u8,
+}
+
+/// Destination of trace records.
+///
+/// Sinks are called by the broker while it processes a message, so they must not block.
+pub(crate) trait TraceSink: Send {
+    fn record(&mut self, record: &TraceRecord<'_>);
+}
+
+/// Records trace events of the publications selected by the trace settings.
+///
+/// A publication is selected by its topic and by sampling a hash of its topic and payload,
+/// so every event of a publication is recorded or none is without keeping track of publications.
+/// Publications with the same topic and payload are therefore all sampled alike.
+#[derive(Default)]
+pub(crate) struct Tracer {
+    sink: Option<Box<dyn TraceSink>>,
+    filters: Vec<TopicFilter>,
+    sample_rate: f64,
+}
+
+impl Tracer {
+    /// Creates the tracer of the trace settings. Trace records go to the
+    /// broker behind `broker` when they are published to topics.
+    pub fn new(config: Option<&Trace>, broker: &Sender<Message>) -> Self {
+        match config {
+            Some(config) => {
+                let sink: Box<dyn TraceSink> = match config.output() {
+                    TraceOutput::File {
+                        path,
+                        max_file_size,
+                        max_files,
+                    } => Box::new(FileSink::new(path.clone(), *max_file_size, *max_files)),
+                    TraceOutput::Topic => Box::new(TopicSink(broker.clone())),
+                };
+                Self::with_sink(sink, config.sample_rate(), config.topics())
+            }
+            None => Self::default(),
+        }
+    }
+
+    pub fn with_sink(sink: Box<dyn TraceSink>, sample_rate: f64, topics: &[String]) -> Self {
+        let filters = topics
+            .iter()
+            .filter_map(|topic| match topic.parse() {
+                Ok(filter) => Some(filter),
+                Err(e) => {
+                    warn!(message = "ignoring invalid trace topic filter", error = %e);
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            sink: Some(sink),
+            filters,
+            sample_rate,
+        }
+    }
+
+    pub fn publish(&mut self, event: TraceEvent, client_id: &ClientId, publish: &proto::Publish) {
+        let (packet_identifier, qos) = match publish.packet_identifier_dup_qos {
+            proto::PacketIdentifierDupQoS::AtMostOnce => (None, proto::QoS::AtMostOnce),
+            proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, _) => {
+                (Some(packet_identifier), proto::QoS::AtLeastOnce)
+            }
+            proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, _) => {
+                (Some(packet_identifier), proto::QoS::ExactlyOnce)
+            }
+        };
+
+        self.trace(
+            event,
+            client_id,
+            packet_identifier,
+            &publish.topic_name,
+            qos,
+            &publish.payload,
+        );
+    }
+
+    pub fn publication(
+        &mut self,
+        event: TraceEvent,
+        client_id: &ClientId,
+        publication: &proto::Publication,
+    ) {
+        self.trace(
+            event,
+            client_id,
+            None,
+            &publication.topic_name,
+            publication.qos,
+            &publication.payload,
+        );
+    }
+
+    /// Records the publication sent by an event to a client, if it sends one.
+    pub fn sent(&mut self, client_id: &ClientId, event: &ClientEvent) {
+        if let ClientEvent::PublishTo(Publish::QoS0(_, publish))
+        | ClientEvent::PublishTo(Publish::QoS12(_, publish)) = event
+        {
+            self.publish(TraceEvent::Sent, client_id, publish);
+        }
+    }
+
+    fn trace(
+        &mut self,
+        event: TraceEvent,
+        client_id: &ClientId,
+        packet_identifier: Option<proto::PacketIdentifier>,
+        topic_name: &str,
+        qos: proto::QoS,
+        payload: &[u8],
+    ) {
+        if self.sink.is_none() || !self.selects(topic_name, payload) {
+            return;
+        }
+
+        if let Some(sink) = &mut self.sink {
+            sink.record(&TraceRecord {
+                time: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+                event,
+                client_id,
+                packet_id: packet_identifier.map(proto::PacketIdentifier::get),
+                topic: topic_name,
+                qos: qos.into(),
+            });
+        }
+    }
+
+    /// System topics are only traced if a filter asks for them, and the trace topics never are.
+    fn selects(&self, topic_name: &str, payload: &[u8]) -> bool {
+        let matches = if self.filters.is_empty() {
+            !topic_name.starts_with('$')
+        } else {
+            !topic_name.starts_with(TRACE_TOPIC_PREFIX)
+                && self.filters.iter().any(|filter| filter.matches(topic_name))
+        };
+
+        matches && self.samples(topic_name, payload)
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn samples(&self, topic_name: &str, payload: &[u8]) -> bool {
+        if self.sample_rate >= 1.0 {
+            return true;
+        }
+
+        let mut hasher = DefaultHasher::new();
+        topic_name.hash(&mut hasher);
+        payload.hash(&mut hasher);
+        (hasher.finish() as f64 / u64::max_value() as f64) < self.sample_rate
+    }
+}
+
+/// Publishes trace records on behalf of the broker to `$SYS/trace/<client id>`,
+/// with the client id escaped to a single topic level.
+///
+/// Records are dropped while the queue of the broker is full.
+struct TopicSink(Sender<Message>);
+
+impl TraceSink for TopicSink {
+    fn record(&mut self, record: &TraceRecord<'_>) {
+        let payload = match serde_json::to_vec(record) {
+            Ok(payload) => payload,
+            Err(e) => {
+                warn!(message = "failed to serialize trace record", error = %e);
+                return;
+            }
+        };
+
+        let publication = proto::Publication {
+            topic_name: format!("{}{}", TRACE_TOPIC_PREFIX, topic_level(record.client_id)),
+            qos: proto::QoS::AtMostOnce,
+            retain: false,
+            payload: payload.into(),
+            properties: proto::Properties::default(),
+        };
+        let message = Message::System(SystemEvent::PublishTrace(publication));
+        if let Err(e) = self.0.try_send(message) {
+            debug!(message = "dropping trace record", error = %e);
+        }
+    }
+}
+
+/// Writes trace records as JSON lines on a dedicated thread, so that the
+/// broker does not wait for the file system.
+///
+/// Records are dropped while the thread falls behind. If the thread stops
+/// because it failed to write the file, the next record restarts it, but no
+/// more often than every `FILE_WRITER_RESTART_DELAY`. Records are dropped
+/// while it is stopped.
+struct FileSink {
+    path: PathBuf,
+    max_file_size: u64,
+    max_files: u32,
+    sender: SyncSender<String>,
+    restart_at: Option<Instant>,
+}
+
+impl FileSink {
+    fn new(path: PathBuf, max_file_size: u64, max_files: u32) -> Self {
+        let sender = spawn_writer(path.clone(), max_file_size, max_files);
+
+        Self {
+            path,
+            max_file_size,
+            max_files,
+            sender,
+            restart_at: None,
+        }
+    }
+
+    fn restart(&mut self, line: String) {
+        let now = Instant::now();
+        if self.restart_at.map_or(false, |restart_at| now < restart_at) {
+            debug!("trace file writer is stopped. dropping trace record");
+            return;
+        }
+
+        warn!("trace file writer stopped. restarting it");
+        self.sender = spawn_writer(self.path.clone(), self.max_file_size, self.max_files);
+        self.restart_at = Some(now + FILE_WRITER_RESTART_DELAY);
+
+        // Dropped if the writer failed to start again
+        let _ = self.sender.try_send(line);
+    }
+}
+
+impl TraceSink for FileSink {
+    fn record(&mut self, record: &TraceRecord<'_>) {
+        let line = match serde_json::to_string(record) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!(message = "failed to serialize trace record", error = %e);
+                return;
+            }
+        };
+
+        match self.sender.try_send(line) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => {
+                debug!("trace file writer is falling behind. dropping trace record");
+            }
+            Err(TrySendError::Disconnected(line)) => self.restart(line),
+        }
+    }
+}
+
+fn spawn_writer(path: PathBuf, max_file_size: u64, max_files: u32) -> SyncSender<String> {
+    let (sender, receiver) = std_mpsc::sync_channel(FILE_QUEUE_CAPACITY);
+
+    let writer = thread::Builder::new()
+        .name("trace".to_owned())
+        .spawn(move || {
+            if let Err(e) = write_file(&receiver, path, max_file_size, max_files) {
+                error!(message = "failed to write trace file", error = %e);
+            }
+        });
+    if let Err(e) = writer {
+        error!(message = "failed to start trace file writer", error = %e);
+    }
+
+    sender
+}
+
+/// Writes lines until every sender is gone. The file is flushed
+/// whenever no more lines are waiting, which batches writes under load.
+fn write_file(
+    receiver: &Receiver<String>,
+    path: PathBuf,
+    max_file_size: u64,
+    max_files: u32,
+) -> io::Result<()> {
+    let mut file = RotatingFile::open(path, max_file_size, max_files)?;
+    while let Ok(line) = receiver.recv() {
+        file.write_line(&line)?;
+        while let Ok(line) = receiver.try_recv() {
+            file.write_line(&line)?;
+        }
+        file.flush()?;
+    }
+    Ok(())
+}
+
+/// A file which is moved aside once it reaches its maximum size.
+///
+/// `trace.log` is rotated to `trace.log.1`, `trace.log.1` to `trace.log.2` and so on,
+/// up to `max_files` rotated files. A maximum size of zero means the file is never rotated.
+struct RotatingFile {
+    path: PathBuf,
+    max_file_size: u64,
+    max_files: u32,
+    file: BufWriter<File>,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_file_size: u64, max_files: u32) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path,
+            max_file_size,
+            max_files,
+            file: BufWriter::new(file),
+            size,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = u64::try_from(line.len() + 1).unwrap_or(u64::max_value());
+        if self.max_file_size > 0 && self.size > 0 && self.size + len > self.max_file_size {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        for index in (1..self.max_files).rev() {
+            let from = rotated_path(&self.path, index);
+            if from.exists() {
+                fs::rename(from, rotated_path(&self.path, index + 1))?;
+            }
+        }
+        if self.max_files > 0 {
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.file = BufWriter::new(file);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Escapes a client id to a single topic level, so that a client id with `/`, `+` or `#`
+/// does not publish trace records to the topic of another client or to an invalid topic.
+fn topic_level(client_id: &ClientId) -> String {
+    let mut level = String::with_capacity(client_id.as_str().len());
+    for c in client_id.as_str().chars() {
+        match c {
+            '%' | '/' | '+' | '#' => level.push_str(&format!("%{:02X}", u32::from(c))),
+            c => level.push(c),
+        }
+    }
+    level
+}
+
+fn rotated_path(path: &Path, index: u32) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(format!(".{}", index));
+    path.into()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    use bytes::Bytes;
+    use mqtt3::proto;
+    use serde_json::{json, Value};
+
+    use crate::session::DropReason;
+    use crate::trace::{
+        rotated_path, topic_level, RotatingFile, TraceEvent, TraceRecord, TraceSink, Tracer,
+    };
+    use crate::ClientId;
+
+    /// Keeps the records it receives, without their time.
+    #[derive(Clone, Default)]
+    pub(crate) struct RecordingSink(pub(crate) Arc<Mutex<Vec<Value>>>);
+
+    impl TraceSink for RecordingSink {
+        fn record(&mut self, record: &TraceRecord<'_>) {
+            let mut value = serde_json::to_value(record).unwrap();
+            value.as_object_mut().unwrap().remove("time");
+            self.0.lock().unwrap().push(value);
+        }
+    }
+
+    fn publication(topic_name: &str, payload: &str) -> proto::Publication {
+        proto::Publication {
+            topic_name: topic_name.to_owned(),
+            qos: proto::QoS::AtLeastOnce,
+            retain: false,
+            payload: Bytes::from(payload.to_owned()),
+            properties: proto::Properties::default(),
+        }
+    }
+
+    #[test]
+    fn it_serializes_records() {
+        let sink = RecordingSink::default();
+        let mut tracer = Tracer::with_sink(Box::new(sink.clone()), 1.0, &[]);
+        let client_id = ClientId::from("device1");
+
+        let publish = proto::Publish {
+            packet_identifier_dup_qos: proto::PacketIdentifierDupQoS::ExactlyOnce(
+                proto::PacketIdentifier::new(7).unwrap(),
+                false,
+            ),
+            retain: false,
+            topic_name: "topic".to_owned(),
+            payload: Bytes::from("payload"),
+            properties: proto::Properties::default(),
+        };
+        tracer.publish(TraceEvent::Received, &client_id, &publish);
+        tracer.publication(
+            TraceEvent::Routed { sessions: 2 },
+            &client_id,
+            &publication("topic", "payload"),
+        );
+        tracer.publication(
+            TraceEvent::Dropped {
+                reason: DropReason::QueueFull,
+            },
+            &client_id,
+            &publication("topic", "payload"),
+        );
+
+        assert_eq!(
+            *sink.0.lock().unwrap(),
+            vec![
+                json!({"event": "received", "client_id": "device1", "packet_id": 7, "topic": "topic", "qos": 2}),
+                json!({"event": "routed", "sessions": 2, "client_id": "device1", "topic": "topic", "qos": 1}),
+                json!({"event": "dropped", "reason": "queue_full", "client_id": "device1", "topic": "topic", "qos": 1}),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_selects_publications_by_topic() {
+        let sink = RecordingSink::default();
+        let filters = vec!["devices/#".to_owned(), "$edgehub/#".to_owned()];
+        let mut tracer = Tracer::with_sink(Box::new(sink.clone()), 1.0, &filters);
+        let client_id = ClientId::from("device1");
+
+        for topic_name in &[
+            "devices/device1",
+            "modules/module1",
+            "$edgehub/device1/inputs",
+            "$SYS/trace/device1",
+        ] {
+            tracer.publication(
+                TraceEvent::Received,
+                &client_id,
+                &publication(topic_name, "payload"),
+            );
+        }
+
+        let topics = sink
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|record| record["topic"].as_str().unwrap().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(topics, vec!["devices/device1", "$edgehub/device1/inputs"]);
+    }
+
+    #[test]
+    fn it_escapes_client_ids_to_a_topic_level() {
+        assert_eq!(topic_level(&ClientId::from("device1")), "device1");
+        assert_eq!(
+            topic_level(&ClientId::from("a/b+c#d%e")),
+            "a%2Fb%2Bc%23d%25e"
+        );
+    }
+
+    #[test]
+    fn it_leaves_out_system_topics_by_default() {
+        let sink = RecordingSink::default();
+        let mut tracer = Tracer::with_sink(Box::new(sink.clone()), 1.0, &[]);
+        let client_id = ClientId::from("device1");
+
+        tracer.publication(
+            TraceEvent::Received,
+            &client_id,
+            &publication("$SYS/broker/uptime", "1"),
+        );
+        tracer.publication(
+            TraceEvent::Received,
+            &client_id,
+            &publication("devices/device1", "1"),
+        );
+
+        assert_eq!(sink.0.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn it_samples_every_event_of_a_publication_alike() {
+        let sink = RecordingSink::default();
+        let mut tracer = Tracer::with_sink(Box::new(sink.clone()), 0.5, &[]);
+        let client_id = ClientId::from("device1");
+
+        for i in 0..1000 {
+            let publication = publication("topic", &i.to_string());
+            tracer.publication(TraceEvent::Received, &client_id, &publication);
+            tracer.publication(TraceEvent::Acked, &client_id, &publication);
+        }
+
+        let records = sink.0.lock().unwrap();
+        assert!(records.len() > 600 && records.len() < 1400);
+        for pair in records.chunks(2) {
+            assert_eq!(pair[0]["event"], "received");
+            assert_eq!(pair[1]["event"], "acked");
+        }
+    }
+
+    #[test]
+    fn it_traces_nothing_at_zero_sample_rate() {
+        let sink = RecordingSink::default();
+        let mut tracer = Tracer::with_sink(Box::new(sink.clone()), 0.0, &[]);
+        let client_id = ClientId::from("device1");
+
+        for i in 0..100 {
+            let publication = publication("topic", &i.to_string());
+            tracer.publication(TraceEvent::Received, &client_id, &publication);
+        }
+
+        assert!(sink.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_rotates_trace_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace").join("trace.log");
+
+        let mut file = RotatingFile::open(path.clone(), 10, 2).unwrap();
+        for line in &["one", "two", "six", "ten", "sum"] {
+            file.write_line(line).unwrap();
+            file.write_line(line).unwrap();
+        }
+        file.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "sum\nsum\n");
+        assert_eq!(
+            fs::read_to_string(rotated_path(&path, 1)).unwrap(),
+            "ten\nten\n"
+        );
+        assert_eq!(
+            fs::read_to_string(rotated_path(&path, 2)).unwrap(),
+            "six\nsix\n"
+        );
+        assert!(!rotated_path(&path, 3).exists());
+    }
+}
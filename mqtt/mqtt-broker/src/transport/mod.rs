@@ -0,0 +1,576 @@
+use std::{
+    convert::TryFrom,
+    fmt::{Display, Formatter, Result as FmtResult},
+    future::Future,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::{Buf, BufMut};
+use core::mem::MaybeUninit;
+use futures::stream::FuturesUnordered;
+use openssl::{
+    pkcs12::{ParsedPkcs12, Pkcs12},
+    ssl::{HandshakeError, SslAcceptor, SslMethod, SslVerifyMode},
+    x509::X509Name,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    stream::Stream,
+};
+use tokio_openssl::SslStream;
+use tracing::{debug, error, info, warn};
+
+use crate::configuration::{ClientAuth, ClientAuthMode, Transport as TransportConfig};
+use crate::{Certificate, CertificateChain, Error, InitializeBrokerError, PeerCredentials};
+
+#[cfg(unix)]
+mod uds;
+mod websocket;
+
+#[cfg(unix)]
+pub use uds::IncomingUds;
+pub use websocket::{IncomingWs, WsStream};
+
+pub enum TransportBuilder<A> {
+    Tcp(A),
+    Tls(A, SslAcceptor),
+    Ws(A),
+    Wss(A, SslAcceptor),
+    #[cfg(unix)]
+    Uds(PathBuf),
+}
+
+impl<A> TransportBuilder<A>
+where
+    A: ToSocketAddrs,
+{
+    pub async fn build(self) -> Result<Transport, InitializeBrokerError> {
+        match self {
+            TransportBuilder::Tcp(addr) => Transport::new_tcp(addr).await,
+            TransportBuilder::Tls(addr, acceptor) => Transport::new_tls(addr, acceptor).await,
+            TransportBuilder::Ws(addr) => Transport::new_ws(addr).await,
+            TransportBuilder::Wss(addr, acceptor) => Transport::new_wss(addr, acceptor).await,
+            #[cfg(unix)]
+            TransportBuilder::Uds(path) => Transport::new_uds(&path),
+        }
+    }
+}
+
+impl TryFrom<TransportConfig> for TransportBuilder<String> {
+    type Error = InitializeBrokerError;
+
+    fn try_from(transport: TransportConfig) -> Result<Self, Self::Error> {
+        match transport {
+            TransportConfig::Tcp { address } => Ok(Self::Tcp(address)),
+            TransportConfig::Tls {
+                address,
+                certificate,
+                client_auth,
+            } => {
+                let acceptor = tls_acceptor(&certificate, client_auth.as_ref())?;
+                Ok(Self::Tls(address, acceptor))
+            }
+            TransportConfig::Ws { address } => Ok(Self::Ws(address)),
+            TransportConfig::Wss {
+                address,
+                certificate,
+                client_auth,
+            } => {
+                let acceptor = tls_acceptor(&certificate, client_auth.as_ref())?;
+                Ok(Self::Wss(address, acceptor))
+            }
+            #[cfg(unix)]
+            TransportConfig::Uds { path } => Ok(Self::Uds(path)),
+            #[cfg(not(unix))]
+            TransportConfig::Uds { .. } => Err(InitializeBrokerError::UnsupportedTransport("uds")),
+        }
+    }
+}
+
+fn load_identity(certificate: &Path) -> Result<ParsedPkcs12, InitializeBrokerError> {
+    info!("Loading identity from {}", certificate.display());
+    let cert_buffer = std::fs::read(&certificate)
+        .map_err(|e| InitializeBrokerError::LoadIdentity(certificate.to_path_buf(), e))?;
+
+    Pkcs12::from_der(cert_buffer.as_slice())
+        .and_then(|pkcs12| pkcs12.parse(""))
+        .map_err(InitializeBrokerError::DecodeIdentity)
+}
+
+fn tls_acceptor(
+    certificate: &Path,
+    client_auth: Option<&ClientAuth>,
+) -> Result<SslAcceptor, InitializeBrokerError> {
+    let identity = load_identity(certificate)?;
+
+    let mut builder =
+        SslAcceptor::mozilla_intermediate(SslMethod::tls()).map_err(InitializeBrokerError::Tls)?;
+    builder
+        .set_private_key(&identity.pkey)
+        .map_err(InitializeBrokerError::Tls)?;
+    builder
+        .set_certificate(&identity.cert)
+        .map_err(InitializeBrokerError::Tls)?;
+    for cert in identity.chain.into_iter().flatten() {
+        builder
+            .add_extra_chain_cert(cert)
+            .map_err(InitializeBrokerError::Tls)?;
+    }
+
+    if let Some(client_auth) = client_auth {
+        let ca_bundle = client_auth.ca_bundle();
+        info!("Loading client CA bundle from {}", ca_bundle.display());
+
+        let load_ca =
+            |e| InitializeBrokerError::LoadCertificateAuthority(ca_bundle.to_path_buf(), e);
+        builder.set_ca_file(ca_bundle).map_err(load_ca)?;
+        builder.set_client_ca_list(X509Name::load_client_ca_file(ca_bundle).map_err(load_ca)?);
+
+        // client certificates are verified against the trusted CA bundle during
+        // the handshake, so only verified certificates reach the authenticator.
+        let mode = match client_auth.mode() {
+            ClientAuthMode::Optional => SslVerifyMode::PEER,
+            ClientAuthMode::Required => SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+        };
+        builder.set_verify(mode);
+    }
+
+    Ok(builder.build())
+}
+
+pub enum Transport {
+    Tcp(TcpListener),
+    Tls(TcpListener, SslAcceptor),
+    Ws(TcpListener),
+    Wss(TcpListener, SslAcceptor),
+    #[cfg(unix)]
+    Uds(tokio::net::UnixListener),
+}
+
+impl Transport {
+    async fn new_tcp<A>(addr: A) -> Result<Self, InitializeBrokerError>
+    where
+        A: ToSocketAddrs,
+    {
+        let tcp = TcpListener::bind(addr)
+            .await
+            .map_err(InitializeBrokerError::BindServer)?;
+
+        Ok(Transport::Tcp(tcp))
+    }
+
+    async fn new_tls<A>(addr: A, acceptor: SslAcceptor) -> Result<Self, InitializeBrokerError>
+    where
+        A: ToSocketAddrs,
+    {
+        let tcp = TcpListener::bind(addr)
+            .await
+            .map_err(InitializeBrokerError::BindServer)?;
+
+        Ok(Transport::Tls(tcp, acceptor))
+    }
+
+    async fn new_ws<A>(addr: A) -> Result<Self, InitializeBrokerError>
+    where
+        A: ToSocketAddrs,
+    {
+        let tcp = TcpListener::bind(addr)
+            .await
+            .map_err(InitializeBrokerError::BindServer)?;
+
+        Ok(Transport::Ws(tcp))
+    }
+
+    async fn new_wss<A>(addr: A, acceptor: SslAcceptor) -> Result<Self, InitializeBrokerError>
+    where
+        A: ToSocketAddrs,
+    {
+        let tcp = TcpListener::bind(addr)
+            .await
+            .map_err(InitializeBrokerError::BindServer)?;
+
+        Ok(Transport::Wss(tcp, acceptor))
+    }
+
+    #[cfg(unix)]
+    fn new_uds(path: &Path) -> Result<Self, InitializeBrokerError> {
+        // a socket file left behind by a previous run would fail the bind
+        if path.exists() {
+            info!("Removing stale socket file {}", path.display());
+            std::fs::remove_file(path).map_err(InitializeBrokerError::BindServer)?;
+        }
+
+        let listener =
+            tokio::net::UnixListener::bind(path).map_err(InitializeBrokerError::BindServer)?;
+
+        Ok(Transport::Uds(listener))
+    }
+
+    pub fn incoming(self) -> Incoming {
+        match self {
+            Self::Tcp(listener) => Incoming::Tcp(IncomingTcp::new(listener)),
+            Self::Tls(listener, acceptor) => Incoming::Tls(IncomingTls::new(listener, acceptor)),
+            Self::Ws(listener) => Incoming::Ws(IncomingWs::new(listener, None)),
+            Self::Wss(listener, acceptor) => {
+                Incoming::Ws(IncomingWs::new(listener, Some(acceptor)))
+            }
+            #[cfg(unix)]
+            Self::Uds(listener) => Incoming::Uds(IncomingUds::new(listener)),
+        }
+    }
+
+    pub fn local_addr(&self) -> Result<Addr, InitializeBrokerError> {
+        let addr = match self {
+            Self::Tcp(listener) => listener.local_addr().map(Addr::Tcp),
+            Self::Tls(listener, _) => listener.local_addr().map(Addr::Tcp),
+            Self::Ws(listener) => listener.local_addr().map(Addr::Tcp),
+            Self::Wss(listener, _) => listener.local_addr().map(Addr::Tcp),
+            #[cfg(unix)]
+            Self::Uds(listener) => listener.local_addr().map(|addr| Addr::from(&addr)),
+        };
+        addr.map_err(InitializeBrokerError::ConnectionLocalAddress)
+    }
+}
+
+type HandshakeFuture =
+    Pin<Box<dyn Future<Output = Result<SslStream<TcpStream>, HandshakeError<TcpStream>>> + Send>>;
+
+pub enum Incoming {
+    Tcp(IncomingTcp),
+    Tls(IncomingTls),
+    Ws(IncomingWs),
+    #[cfg(unix)]
+    Uds(IncomingUds),
+}
+
+impl Stream for Incoming {
+    type Item = std::io::Result<StreamSelector>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.get_mut() {
+            Self::Tcp(incoming) => Pin::new(incoming).poll_next(cx),
+            Self::Tls(incoming) => Pin::new(incoming).poll_next(cx),
+            Self::Ws(incoming) => Pin::new(incoming).poll_next(cx),
+            #[cfg(unix)]
+            Self::Uds(incoming) => Pin::new(incoming).poll_next(cx),
+        }
+    }
+}
+
+pub struct IncomingTcp {
+    listener: TcpListener,
+}
+
+impl IncomingTcp {
+    fn new(listener: TcpListener) -> Self {
+        Self { listener }
+    }
+}
+
+impl Stream for IncomingTcp {
+    type Item = std::io::Result<StreamSelector>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.listener.poll_accept(cx) {
+            Poll::Ready(Ok((tcp, _))) => match tcp.set_nodelay(true) {
+                Ok(()) => {
+                    debug!("TCP: Accepted connection from client");
+                    Poll::Ready(Some(Ok(StreamSelector::Tcp(tcp))))
+                }
+                Err(err) => {
+                    warn!(
+                        "TCP: Dropping client because failed to setup TCP properties: {}",
+                        err
+                    );
+                    Poll::Ready(Some(Err(err)))
+                }
+            },
+            Poll::Ready(Err(err)) => {
+                error!(
+                    "TCP: Dropping client that failed to completely establish a TCP connection: {}",
+                    err
+                );
+                Poll::Ready(Some(Err(err)))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+pub struct IncomingTls {
+    listener: TcpListener,
+    acceptor: SslAcceptor,
+    connections: FuturesUnordered<HandshakeFuture>,
+}
+
+impl IncomingTls {
+    fn new(listener: TcpListener, acceptor: SslAcceptor) -> Self {
+        Self {
+            listener,
+            acceptor,
+            connections: FuturesUnordered::default(),
+        }
+    }
+}
+
+impl Stream for IncomingTls {
+    type Item = std::io::Result<StreamSelector>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match self.listener.poll_accept(cx) {
+                Poll::Ready(Ok((stream, _))) => match stream.set_nodelay(true) {
+                    Ok(()) => {
+                        let acceptor = self.acceptor.clone();
+                        self.connections.push(Box::pin(async move {
+                            tokio_openssl::accept(&acceptor, stream).await
+                        }));
+                    }
+                    Err(err) => warn!(
+                        "TCP: Dropping client because failed to setup TCP properties: {}",
+                        err
+                    ),
+                },
+                Poll::Ready(Err(err)) => warn!(
+                    "TCP: Dropping client that failed to completely establish a TCP connection: {}",
+                    err
+                ),
+                Poll::Pending => break,
+            }
+        }
+
+        loop {
+            if self.connections.is_empty() {
+                return Poll::Pending;
+            }
+
+            match Pin::new(&mut self.connections).poll_next(cx) {
+                Poll::Ready(Some(Ok(stream))) => {
+                    debug!("TLS: Accepted connection from client");
+                    return Poll::Ready(Some(Ok(StreamSelector::Tls(stream))));
+                }
+
+                Poll::Ready(Some(Err(err))) => warn!(
+                    "TLS: Dropping client that failed to complete a TLS handshake: {}",
+                    err
+                ),
+
+                Poll::Ready(None) => {
+                    debug!("TLS: Shutting down web server");
+                    return Poll::Ready(None);
+                }
+
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+pub enum StreamSelector {
+    Tcp(TcpStream),
+    Tls(SslStream<TcpStream>),
+    Ws(WsStream<TcpStream>),
+    Wss(WsStream<SslStream<TcpStream>>),
+    #[cfg(unix)]
+    Uds(tokio::net::UnixStream),
+}
+
+impl StreamSelector {
+    pub fn peer_addr(&self) -> std::io::Result<Addr> {
+        match self {
+            StreamSelector::Tcp(stream) => stream.peer_addr().map(Addr::Tcp),
+            StreamSelector::Tls(stream) => stream.get_ref().peer_addr().map(Addr::Tcp),
+            StreamSelector::Ws(stream) => stream.get_ref().peer_addr().map(Addr::Tcp),
+            StreamSelector::Wss(stream) => stream.get_ref().get_ref().peer_addr().map(Addr::Tcp),
+            #[cfg(unix)]
+            StreamSelector::Uds(stream) => stream.peer_addr().map(|addr| Addr::from(&addr)),
+        }
+    }
+}
+
+/// Address of a listener or a connected peer.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Addr {
+    Tcp(SocketAddr),
+    Unix(Option<PathBuf>),
+}
+
+#[cfg(unix)]
+impl From<&tokio::net::unix::SocketAddr> for Addr {
+    fn from(addr: &tokio::net::unix::SocketAddr) -> Self {
+        Addr::Unix(addr.as_pathname().map(Path::to_path_buf))
+    }
+}
+
+impl Display for Addr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Addr::Tcp(addr) => write!(f, "{}", addr),
+            Addr::Unix(Some(path)) => write!(f, "unix:{}", path.display()),
+            Addr::Unix(None) => write!(f, "unix:(unnamed)"),
+        }
+    }
+}
+
+pub trait GetPeerCertificate {
+    type Certificate;
+
+    fn peer_certificate(&self) -> Result<Option<Self::Certificate>, Error>;
+}
+
+impl GetPeerCertificate for StreamSelector {
+    type Certificate = CertificateChain;
+
+    fn peer_certificate(&self) -> Result<Option<Self::Certificate>, Error> {
+        match self {
+            StreamSelector::Tcp(_) | StreamSelector::Ws(_) => Ok(None),
+            StreamSelector::Tls(stream) => peer_certificate(stream),
+            StreamSelector::Wss(stream) => peer_certificate(stream.get_ref()),
+            #[cfg(unix)]
+            StreamSelector::Uds(_) => Ok(None),
+        }
+    }
+}
+
+pub trait GetPeerCredentials {
+    fn peer_credentials(&self) -> Result<Option<PeerCredentials>, Error>;
+}
+
+impl GetPeerCredentials for StreamSelector {
+    fn peer_credentials(&self) -> Result<Option<PeerCredentials>, Error> {
+        match self {
+            #[cfg(unix)]
+            StreamSelector::Uds(stream) => uds::peer_credentials(stream)
+                .map(Some)
+                .map_err(Error::PeerCredentials),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Returns a client certificate chain which passed verification during the handshake.
+fn peer_certificate(stream: &SslStream<TcpStream>) -> Result<Option<CertificateChain>, Error> {
+    let ssl = stream.ssl();
+    let certificate = match ssl.peer_certificate() {
+        Some(certificate) => certificate.to_der().map_err(Error::PeerCertificate)?,
+        None => return Ok(None),
+    };
+
+    // on the server side the peer chain does not contain the client certificate
+    let intermediates = ssl
+        .peer_cert_chain()
+        .into_iter()
+        .flatten()
+        .map(|cert| cert.to_der().map(Certificate::from))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::PeerCertificate)?;
+
+    Ok(Some(CertificateChain::new(
+        certificate.into(),
+        intermediates,
+    )))
+}
+
+impl AsyncRead for StreamSelector {
+    #[inline]
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [MaybeUninit<u8>]) -> bool {
+        match self {
+            StreamSelector::Tcp(stream) => stream.prepare_uninitialized_buffer(buf),
+            StreamSelector::Tls(stream) => stream.prepare_uninitialized_buffer(buf),
+            StreamSelector::Ws(stream) => stream.prepare_uninitialized_buffer(buf),
+            StreamSelector::Wss(stream) => stream.prepare_uninitialized_buffer(buf),
+            #[cfg(unix)]
+            StreamSelector::Uds(stream) => stream.prepare_uninitialized_buffer(buf),
+        }
+    }
+
+    #[inline]
+    fn poll_read_buf<B: BufMut>(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut B,
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            StreamSelector::Tcp(stream) => Pin::new(stream).poll_read_buf(cx, buf),
+            StreamSelector::Tls(stream) => Pin::new(stream).poll_read_buf(cx, buf),
+            StreamSelector::Ws(stream) => Pin::new(stream).poll_read_buf(cx, buf),
+            StreamSelector::Wss(stream) => Pin::new(stream).poll_read_buf(cx, buf),
+            #[cfg(unix)]
+            StreamSelector::Uds(stream) => Pin::new(stream).poll_read_buf(cx, buf),
+        }
+    }
+
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            StreamSelector::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            StreamSelector::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+            StreamSelector::Ws(stream) => Pin::new(stream).poll_read(cx, buf),
+            StreamSelector::Wss(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            StreamSelector::Uds(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for StreamSelector {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            StreamSelector::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            StreamSelector::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+            StreamSelector::Ws(stream) => Pin::new(stream).poll_write(cx, buf),
+            StreamSelector::Wss(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            StreamSelector::Uds(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_buf<B: Buf>(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut B,
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            StreamSelector::Tcp(stream) => Pin::new(stream).poll_write_buf(cx, buf),
+            StreamSelector::Tls(stream) => Pin::new(stream).poll_write_buf(cx, buf),
+            StreamSelector::Ws(stream) => Pin::new(stream).poll_write_buf(cx, buf),
+            StreamSelector::Wss(stream) => Pin::new(stream).poll_write_buf(cx, buf),
+            #[cfg(unix)]
+            StreamSelector::Uds(stream) => Pin::new(stream).poll_write_buf(cx, buf),
+        }
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            StreamSelector::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            StreamSelector::Tls(stream) => Pin::new(stream).poll_flush(cx),
+            StreamSelector::Ws(stream) => Pin::new(stream).poll_flush(cx),
+            StreamSelector::Wss(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            StreamSelector::Uds(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            StreamSelector::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            StreamSelector::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+            StreamSelector::Ws(stream) => Pin::new(stream).poll_shutdown(cx),
+            StreamSelector::Wss(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            StreamSelector::Uds(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::{
+    net::{UnixListener, UnixStream},
+    stream::Stream,
+};
+use tracing::{debug, error};
+
+use super::StreamSelector;
+use crate::PeerCredentials;
+
+pub struct IncomingUds {
+    listener: UnixListener,
+}
+
+impl IncomingUds {
+    pub(super) fn new(listener: UnixListener) -> Self {
+        Self { listener }
+    }
+}
+
+impl Stream for IncomingUds {
+    type Item = io::Result<StreamSelector>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match Pin::new(&mut self.listener).poll_next(cx) {
+            Poll::Ready(Some(Ok(stream))) => {
+                debug!("UDS: Accepted connection from client");
+                Poll::Ready(Some(Ok(StreamSelector::Uds(stream))))
+            }
+            Poll::Ready(Some(Err(err))) => {
+                error!(
+                    "UDS: Dropping client that failed to completely establish a connection: {}",
+                    err
+                );
+                Poll::Ready(Some(Err(err)))
+            }
+            Poll::Ready(None) => {
+                debug!("UDS: Shutting down listener");
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Reads credentials of the process on the other end of the socket.
+#[cfg(target_os = "linux")]
+pub(super) fn peer_credentials(stream: &UnixStream) -> io::Result<PeerCredentials> {
+    use std::{convert::TryFrom, mem, os::unix::io::AsRawFd};
+
+    let mut ucred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = libc::socklen_t::try_from(mem::size_of::<libc::ucred>())
+        .expect("ucred size fits into socklen_t");
+
+    // SAFETY: ucred and len are valid for writes and len holds the size of ucred
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&mut ucred as *mut libc::ucred).cast(),
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(PeerCredentials::new(
+        ucred.uid,
+        ucred.gid,
+        u32::try_from(ucred.pid).ok(),
+    ))
+}
+
+/// Reads credentials of the process on the other end of the socket.
+///
+/// Process id is only available on Linux.
+#[cfg(not(target_os = "linux"))]
+pub(super) fn peer_credentials(stream: &UnixStream) -> io::Result<PeerCredentials> {
+    let ucred = stream.peer_cred()?;
+    Ok(PeerCredentials::new(ucred.uid, ucred.gid, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        net::{UnixListener, UnixStream},
+        stream::StreamExt,
+    };
+
+    use super::{peer_credentials, IncomingUds};
+    use crate::transport::{Addr, StreamSelector};
+
+    #[tokio::test]
+    async fn it_accepts_connection_with_peer_credentials() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let path = dir.path().join("broker.sock");
+
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut incoming = IncomingUds::new(listener);
+
+        let client = UnixStream::connect(&path).await.unwrap();
+        let stream = incoming.next().await.unwrap().unwrap();
+
+        let stream = match stream {
+            StreamSelector::Uds(stream) => stream,
+            _ => panic!("expected UDS stream"),
+        };
+
+        let credentials = peer_credentials(&stream).unwrap();
+        assert_eq!(credentials.uid(), unsafe { libc::getuid() });
+        assert_eq!(credentials.gid(), unsafe { libc::getgid() });
+        #[cfg(target_os = "linux")]
+        assert_eq!(credentials.pid(), Some(std::process::id()));
+
+        let local = Addr::from(&client.peer_addr().unwrap());
+        assert_eq!(local, Addr::Unix(Some(path)));
+    }
+}
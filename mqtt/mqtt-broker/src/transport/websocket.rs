@@ -0,0 +1,195 @@
+use std::{
+    error::Error as StdError,
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::stream::FuturesUnordered;
+pub use mqtt3::io_source::WsStream;
+use openssl::ssl::SslAcceptor;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+    stream::Stream,
+};
+use tokio_openssl::SslStream;
+use tokio_tungstenite::tungstenite::{
+    handshake::server::{ErrorResponse, Request, Response},
+    http::{header::SEC_WEBSOCKET_PROTOCOL, HeaderValue, StatusCode},
+    Error as WsError,
+};
+use tracing::{debug, warn};
+
+use super::StreamSelector;
+
+/// [MQTT-6.0.0-3] The WebSocket Sub Protocol name selected and returned
+/// by the Server MUST be "mqtt".
+const MQTT_SUBPROTOCOL: &str = "mqtt";
+
+type HandshakeFuture =
+    Pin<Box<dyn Future<Output = Result<StreamSelector, Box<dyn StdError + Send + Sync>>> + Send>>;
+
+/// Accepts MQTT over WebSockets connections, optionally secured with TLS.
+pub struct IncomingWs {
+    listener: TcpListener,
+    acceptor: Option<SslAcceptor>,
+    connections: FuturesUnordered<HandshakeFuture>,
+}
+
+impl IncomingWs {
+    pub(super) fn new(listener: TcpListener, acceptor: Option<SslAcceptor>) -> Self {
+        Self {
+            listener,
+            acceptor,
+            connections: FuturesUnordered::default(),
+        }
+    }
+
+    fn handshake(&self, stream: TcpStream) -> HandshakeFuture {
+        match self.acceptor.clone() {
+            Some(acceptor) => Box::pin(async move {
+                let stream: SslStream<TcpStream> = tokio_openssl::accept(&acceptor, stream)
+                    .await
+                    .map_err(|e| {
+                    io::Error::new(io::ErrorKind::ConnectionAborted, e.to_string())
+                })?;
+                let stream = accept(stream).await?;
+                Ok(StreamSelector::Wss(stream))
+            }),
+            None => Box::pin(async move {
+                let stream = accept(stream).await?;
+                Ok(StreamSelector::Ws(stream))
+            }),
+        }
+    }
+}
+
+impl Stream for IncomingWs {
+    type Item = io::Result<StreamSelector>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match self.listener.poll_accept(cx) {
+                Poll::Ready(Ok((stream, _))) => match stream.set_nodelay(true) {
+                    Ok(()) => {
+                        let handshake = self.handshake(stream);
+                        self.connections.push(handshake);
+                    }
+                    Err(err) => warn!(
+                        "TCP: Dropping client because failed to setup TCP properties: {}",
+                        err
+                    ),
+                },
+                Poll::Ready(Err(err)) => warn!(
+                    "TCP: Dropping client that failed to completely establish a TCP connection: {}",
+                    err
+                ),
+                Poll::Pending => break,
+            }
+        }
+
+        loop {
+            if self.connections.is_empty() {
+                return Poll::Pending;
+            }
+
+            match Pin::new(&mut self.connections).poll_next(cx) {
+                Poll::Ready(Some(Ok(stream))) => {
+                    debug!("WS: Accepted connection from client");
+                    return Poll::Ready(Some(Ok(stream)));
+                }
+
+                Poll::Ready(Some(Err(err))) => warn!(
+                    "WS: Dropping client that failed to complete a WebSocket handshake: {}",
+                    err
+                ),
+
+                Poll::Ready(None) => {
+                    debug!("WS: Shutting down web server");
+                    return Poll::Ready(None);
+                }
+
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+async fn accept<S>(stream: S) -> Result<WsStream<S>, WsError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let stream = tokio_tungstenite::accept_hdr_async(stream, select_subprotocol).await?;
+    Ok(WsStream::new(stream))
+}
+
+/// Completes the handshake only for clients which ask for the `mqtt` subprotocol.
+fn select_subprotocol(
+    request: &Request,
+    mut response: Response,
+) -> Result<Response, ErrorResponse> {
+    let requested = request
+        .headers()
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|protocol| protocol.trim() == MQTT_SUBPROTOCOL);
+
+    if requested {
+        response.headers_mut().insert(
+            SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static(MQTT_SUBPROTOCOL),
+        );
+        Ok(response)
+    } else {
+        let mut response = ErrorResponse::new(Some(format!(
+            "expected \"{}\" websocket subprotocol",
+            MQTT_SUBPROTOCOL
+        )));
+        *response.status_mut() = StatusCode::BAD_REQUEST;
+        Err(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use matches::assert_matches;
+    use tokio_tungstenite::tungstenite::http::{
+        header::SEC_WEBSOCKET_PROTOCOL, Request, Response, StatusCode,
+    };
+
+    use super::select_subprotocol;
+
+    fn request(protocols: Option<&str>) -> Request<()> {
+        let mut request = Request::builder();
+        if let Some(protocols) = protocols {
+            request = request.header(SEC_WEBSOCKET_PROTOCOL, protocols);
+        }
+        request.body(()).unwrap()
+    }
+
+    #[test]
+    fn it_selects_mqtt_subprotocol() {
+        let response = select_subprotocol(&request(Some("mqttv3.1, mqtt")), Response::new(()))
+            .expect("handshake should succeed");
+
+        assert_eq!(response.headers()[SEC_WEBSOCKET_PROTOCOL], "mqtt");
+    }
+
+    #[test]
+    fn it_rejects_missing_subprotocol() {
+        let result = select_subprotocol(&request(None), Response::new(()));
+
+        assert_matches!(result, Err(response) if response.status() == StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn it_rejects_unknown_subprotocol() {
+        let result = select_subprotocol(&request(Some("chat")), Response::new(()));
+
+        assert_matches!(result, Err(response) if response.status() == StatusCode::BAD_REQUEST);
+    }
+}
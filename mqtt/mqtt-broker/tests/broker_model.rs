@@ -268,7 +268,7 @@ pub fn arb_broker_event() -> impl Strategy<Value = BrokerEvent> {
             |id| arb_connect(id).prop_map(|p| BrokerEvent::ConnReq(client_id(&p.client_id), p))
         ),
         arb_client_id_weighted()
-            .prop_map(|id| BrokerEvent::Disconnect(client_id(&id), proto::Disconnect)),
+            .prop_map(|id| BrokerEvent::Disconnect(client_id(&id), proto::Disconnect::default())),
         arb_client_id_weighted().prop_flat_map(
             |id| arb_subscribe().prop_map(move |p| BrokerEvent::Subscribe(client_id(&id), p))
         ),
@@ -20,7 +20,9 @@ use tokio_io_timeout::TimeoutStream;
 use tokio_util::codec::Framed;
 
 use mqtt3::{
-    proto::{ClientId, Connect, Packet, PacketCodec, Publication, Publish, QoS, SubscribeTo},
+    proto::{
+        ClientId, Connect, Packet, PacketCodec, Properties, Publication, Publish, QoS, SubscribeTo,
+    },
     Client, Event, PublishError, PublishHandle, ReceivedPublication, ShutdownHandle,
     UpdateSubscriptionHandle, PROTOCOL_LEVEL, PROTOCOL_NAME,
 };
@@ -59,6 +61,7 @@ impl TestClient {
             qos: QoS::AtMostOnce,
             retain,
             payload: payload.into(),
+            properties: Properties::default(),
         })
         .await
         .expect("couldn't publish")
@@ -75,6 +78,7 @@ impl TestClient {
             qos: QoS::AtLeastOnce,
             retain,
             payload: payload.into(),
+            properties: Properties::default(),
         })
         .await
         .expect("couldn't publish")
@@ -91,6 +95,7 @@ impl TestClient {
             qos: QoS::ExactlyOnce,
             retain,
             payload: payload.into(),
+            properties: Properties::default(),
         })
         .await
         .expect("couldn't publish")
@@ -339,6 +344,7 @@ impl PacketStream {
                 keep_alive: Duration::from_secs(30),
                 protocol_name: PROTOCOL_NAME.into(),
                 protocol_level: PROTOCOL_LEVEL,
+                properties: Properties::default(),
             })
             .await;
         client
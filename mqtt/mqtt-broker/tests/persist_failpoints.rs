@@ -1,6 +1,11 @@
+use std::collections::HashMap;
+
 use fail::FailScenario;
 
-use mqtt_broker::{BrokerState, FilePersistor, Persist, PersistError, VersionedFileFormat};
+use mqtt_broker::{
+    proptest::arb_change, BrokerState, Change, ClientId, FilePersistor, Persist, PersistError,
+    RetainedPublication, SessionState, VersionedFileFormat, WalPersistor,
+};
 use proptest::collection::vec;
 use proptest::prelude::*;
 use tempfile::TempDir;
@@ -22,6 +27,29 @@ const FAILPOINTS: &[&str] = &[
     "filepersistor.store.spawn_blocking",
 ];
 
+const WAL_FAILPOINTS: &[&str] = &[
+    "walpersistor.append.spawn_blocking",
+    "walpersistor.append.serialize",
+    "walpersistor.append.createdir",
+    "walpersistor.append.fileopen",
+    "walpersistor.append.write",
+    "walpersistor.append.torn_write",
+    "walpersistor.append.sync",
+    "walpersistor.reset.spawn_blocking",
+    "walpersistor.load.spawn_blocking",
+    "walpersistor.load.log_fileopen",
+    "walpersistor.load.log_read",
+    "walpersistor.load.snapshot_fileopen",
+    "walpersistor.load.snapshot_deserialize",
+    "walpersistor.readdir",
+    "walpersistor.prune.unlink",
+    "walpersistor.snapshot.createdir",
+    "walpersistor.snapshot.fileopen",
+    "walpersistor.snapshot.serialize",
+    "walpersistor.snapshot.sync",
+    "walpersistor.snapshot.filerename",
+];
+
 #[derive(Clone, Debug)]
 enum Op {
     Load,
@@ -30,6 +58,16 @@ enum Op {
     RemoveFailpoint(&'static str),
 }
 
+/// Operations on the log, whose states are made by the changes appended.
+#[derive(Clone, Debug)]
+enum WalOp {
+    Load,
+    Store,
+    Append(Vec<Change>),
+    AddFailpoint(&'static str),
+    RemoveFailpoint(&'static str),
+}
+
 fn arb_op() -> impl Strategy<Value = Op> {
     prop_oneof![
         Just(Op::Load),
@@ -39,6 +77,16 @@ fn arb_op() -> impl Strategy<Value = Op> {
     ]
 }
 
+fn arb_wal_op() -> impl Strategy<Value = WalOp> {
+    prop_oneof![
+        Just(WalOp::Load),
+        Just(WalOp::Store),
+        vec(arb_change(), 0..3).prop_map(WalOp::Append),
+        proptest::sample::select(WAL_FAILPOINTS).prop_map(|f| WalOp::AddFailpoint(f)),
+        proptest::sample::select(WAL_FAILPOINTS).prop_map(|f| WalOp::RemoveFailpoint(f)),
+    ]
+}
+
 fn tear_down_failpoints() {
     for (name, _) in fail::list() {
         fail::remove(name);
@@ -75,6 +123,120 @@ async fn test_persistor(count: usize, ops: Vec<Op>) {
     assert!(state.is_some());
 }
 
+type Model = (
+    HashMap<String, RetainedPublication>,
+    HashMap<ClientId, SessionState>,
+);
+
+fn normalize(state: BrokerState) -> Model {
+    let (retained, sessions) = state.into_parts();
+    let sessions = sessions
+        .into_iter()
+        .map(|session| (session.client_id().clone(), session))
+        .collect();
+    (retained, sessions)
+}
+
+fn denormalize(model: &Model) -> BrokerState {
+    let (retained, sessions) = model.clone();
+    BrokerState::new(retained, sessions.into_iter().map(|(_, s)| s).collect())
+}
+
+fn apply(model: &mut Model, change: Change) {
+    match change {
+        Change::RetainedStored(topic, retained) => {
+            model.0.insert(topic, retained);
+        }
+        Change::RetainedRemoved(topic) => {
+            model.0.remove(&topic);
+        }
+        Change::SessionStored(session) => {
+            model.1.insert(session.client_id().clone(), session);
+        }
+        Change::SessionRemoved(client_id) => {
+            model.1.remove(&client_id);
+        }
+        Change::Session(client_id, change) => {
+            let session = model.1.get_mut(&client_id).expect("unknown session");
+            session.apply(change);
+        }
+    }
+}
+
+async fn test_wal_persistor(compaction_threshold: usize, ops: Vec<WalOp>) {
+    let tmp_dir = TempDir::new().unwrap();
+    let path = tmp_dir.path().to_owned();
+    let mut persistor =
+        WalPersistor::new(path.clone()).with_compaction_threshold(compaction_threshold);
+
+    // Make sure we've stored at least one state
+    tear_down_failpoints();
+    persistor.store(BrokerState::default()).await.unwrap();
+
+    // the state of the broker, which journals every change whether it is persisted or not
+    let mut truth = normalize(BrokerState::default());
+
+    // States which may be on disk. After a failed append the persistor
+    // drops changes until it stores a full state successfully.
+    let mut candidates = vec![truth.clone()];
+    let mut broken = false;
+
+    // process the operations
+    for op in ops {
+        match op {
+            WalOp::Load => {
+                if let Ok(state) = persistor.load().await {
+                    let state = state.map_or_else(Model::default, normalize);
+                    assert!(candidates.contains(&state));
+                    truth = state.clone();
+                    candidates = vec![state];
+                    broken = false;
+                }
+            }
+            WalOp::Store => {
+                let result = persistor.store(denormalize(&truth)).await;
+                if !broken {
+                    assert!(result.is_ok());
+                } else if result.is_ok() {
+                    candidates = vec![truth.clone()];
+                    broken = false;
+                } else {
+                    candidates.push(truth.clone());
+                }
+            }
+            WalOp::Append(changes) => {
+                let previous = truth.clone();
+                for change in changes.clone() {
+                    apply(&mut truth, change);
+                }
+
+                let result = persistor.append(changes).await;
+                if broken {
+                    assert!(result.is_ok());
+                } else if result.is_ok() {
+                    candidates = vec![truth.clone()];
+                } else {
+                    // the batch may or may not have made it to disk
+                    candidates = vec![previous, truth.clone()];
+                    broken = true;
+                }
+            }
+            WalOp::AddFailpoint(f) => fail::cfg(f, "return").unwrap(),
+            WalOp::RemoveFailpoint(f) => fail::remove(f),
+        }
+    }
+
+    // clear the failpoints and ensure we load one of the expected states
+    tear_down_failpoints();
+    let state = normalize(persistor.load().await.unwrap().unwrap());
+    assert!(candidates.contains(&state));
+
+    // a fresh persistor sees the same state on disk
+    let mut persistor = WalPersistor::new(path);
+    let reloaded = normalize(persistor.load().await.unwrap().unwrap());
+    assert_eq!(state, reloaded);
+}
+
 #[test]
 fn test_failpoints_smoketest() {
     let scenario = FailScenario::setup();
@@ -110,4 +272,16 @@ proptest! {
             .block_on(test_persistor(count, ops));
         scenario.teardown();
     }
+
+    #[test]
+    fn test_wal_failpoints(compaction_threshold in 1usize..5, ops in vec(arb_wal_op(), 0..50)) {
+        let scenario = FailScenario::setup();
+        tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(test_wal_persistor(compaction_threshold, ops));
+        scenario.teardown();
+    }
 }
@@ -6,7 +6,8 @@ use matches::assert_matches;
 use mqtt3::{
     proto::{
         ClientId, ConnAck, Connect, ConnectReturnCode, ConnectionRefusedReason, Packet,
-        PacketIdentifier, PacketIdentifierDupQoS, PingReq, PubAck, Publication, Publish, QoS,
+        PacketIdentifier, PacketIdentifierDupQoS, PingReq, Properties, PubAck, Publication,
+        Publish, QoS, ReasonCode,
     },
     Event, ReceivedPublication, PROTOCOL_LEVEL, PROTOCOL_NAME,
 };
@@ -354,6 +355,7 @@ async fn will_message() {
             qos: QoS::AtLeastOnce,
             retain: false,
             payload: "will_msg_a".into(),
+            properties: Properties::default(),
         })
         .build();
 
@@ -615,6 +617,7 @@ async fn duplicate_connect_packet_connection_dropped() {
             keep_alive: Duration::from_secs(30),
             protocol_name: PROTOCOL_NAME.into(),
             protocol_level: PROTOCOL_LEVEL,
+            properties: Properties::default(),
         })
         .await;
 
@@ -622,7 +625,8 @@ async fn duplicate_connect_packet_connection_dropped() {
         client.next().await,
         Some(Packet::ConnAck(ConnAck {
             return_code: ConnectReturnCode::Accepted,
-            session_present: false
+            session_present: false,
+            properties: Properties::default(),
         }))
     );
 
@@ -635,6 +639,7 @@ async fn duplicate_connect_packet_connection_dropped() {
             keep_alive: Duration::from_secs(30),
             protocol_name: PROTOCOL_NAME.into(),
             protocol_level: PROTOCOL_LEVEL,
+            properties: Properties::default(),
         })
         .await;
 
@@ -660,6 +665,7 @@ async fn wrong_protocol_name_connection_dropped() {
             will: None,
             keep_alive: Duration::from_secs(30),
             protocol_level: PROTOCOL_LEVEL,
+            properties: Properties::default(),
         })
         .await;
 
@@ -685,6 +691,7 @@ async fn wrong_protocol_version_rejected() {
             will: None,
             keep_alive: Duration::from_secs(30),
             protocol_name: PROTOCOL_NAME.into(),
+            properties: Properties::default(),
         })
         .await;
 
@@ -727,6 +734,7 @@ async fn qos1_puback_should_be_in_order() {
             retain: false,
             topic_name: "topic/A".into(),
             payload: Bytes::from("qos 1"),
+            properties: Properties::default(),
         })
         .await;
 
@@ -739,6 +747,7 @@ async fn qos1_puback_should_be_in_order() {
             retain: false,
             topic_name: "topic/A".into(),
             payload: Bytes::from("qos 1"),
+            properties: Properties::default(),
         })
         .await;
 
@@ -751,27 +760,31 @@ async fn qos1_puback_should_be_in_order() {
             retain: false,
             topic_name: "topic/A".into(),
             payload: Bytes::from("qos 1"),
+            properties: Properties::default(),
         })
         .await;
 
     assert_eq!(
         client.next().await,
         Some(Packet::PubAck(PubAck {
-            packet_identifier: PacketIdentifier::new(1).unwrap()
-        }))
+            packet_identifier: PacketIdentifier::new(1).unwrap(), reason_code: ReasonCode::SUCCESS, properties: Properties::default(), }))
     );
 
     assert_eq!(
         client.next().await,
         Some(Packet::PubAck(PubAck {
-            packet_identifier: PacketIdentifier::new(2).unwrap()
+            packet_identifier: PacketIdentifier::new(2).unwrap(),
+            reason_code: ReasonCode::SUCCESS,
+            properties: Properties::default(),
         }))
     );
 
     assert_eq!(
         client.next().await,
         Some(Packet::PubAck(PubAck {
-            packet_identifier: PacketIdentifier::new(3).unwrap()
+            packet_identifier: PacketIdentifier::new(3).unwrap(),
+            reason_code: ReasonCode::SUCCESS,
+            properties: Properties::default(),
         }))
     );
 }
@@ -0,0 +1,171 @@
+use std::convert::TryInto;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use futures_util::future;
+use mqtt3::{proto, Event};
+use tokio::time::{Duration, Instant};
+
+use crate::connection::{self, parse_arg, Connection};
+use crate::{parse_qos, timeout, Error};
+
+/// Every payload starts with the time it was sent at, in nanoseconds since the start of the run.
+const TIMESTAMP_SIZE: usize = 8;
+
+pub fn command() -> App<'static, 'static> {
+    SubCommand::with_name("bench")
+        .about("Measures throughput and latency of publications between two clients")
+        .args(&connection::args())
+        .arg(
+            Arg::with_name("topic")
+                .short("t")
+                .long("topic")
+                .value_name("TOPIC")
+                .takes_value(true)
+                .default_value("mqtt-cli/bench"),
+        )
+        .arg(
+            Arg::with_name("qos")
+                .short("q")
+                .long("qos")
+                .value_name("QOS")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("count")
+                .short("n")
+                .long("count")
+                .value_name("COUNT")
+                .help("Number of messages to publish")
+                .takes_value(true)
+                .default_value("1000"),
+        )
+        .arg(
+            Arg::with_name("size")
+                .long("size")
+                .value_name("BYTES")
+                .help("Payload size of each message, at least 8 bytes")
+                .takes_value(true)
+                .default_value("64"),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .value_name("SECONDS")
+                .help("Time to wait for each acknowledgement or received message")
+                .takes_value(true)
+                .default_value("10"),
+        )
+}
+
+pub async fn run(matches: &ArgMatches<'_>) -> Result<(), Error> {
+    let topic_name = matches.value_of("topic").unwrap_or_default();
+    let qos = parse_qos(matches.value_of("qos").unwrap_or_default())?;
+    let count: usize = parse_arg(matches, "count")?;
+    let size: usize = parse_arg(matches, "size")?;
+    if size < TIMESTAMP_SIZE {
+        return Err(Error::InvalidArgument("size", size.to_string()));
+    }
+    let timeout_duration = Duration::from_secs(parse_arg(matches, "timeout")?);
+
+    let mut subscriber = connection::client(matches, "-sub")?;
+    let subscribe_to = proto::SubscribeTo {
+        topic_filter: topic_name.to_owned(),
+        qos,
+    };
+    subscriber
+        .subscribe(subscribe_to)
+        .map_err(Error::Subscribe)?;
+    let (mut subscriber, mut events) = Connection::spawn(subscriber)?;
+
+    let subscribed = async {
+        while let Some(event) = events.recv().await {
+            if let Event::SubscriptionUpdates(_) = event {
+                break;
+            }
+        }
+    };
+    subscriber
+        .until_closed(timeout(timeout_duration, subscribed))
+        .await??;
+
+    let (mut publisher, _) = Connection::spawn(connection::client(matches, "-pub")?)?;
+    let mut publish_handle = publisher.publish_handle();
+    let start = Instant::now();
+
+    let publish = async {
+        for _ in 0..count {
+            let mut payload = vec![0; size];
+            payload[..TIMESTAMP_SIZE].copy_from_slice(&nanos_since(start).to_be_bytes());
+
+            let publication = proto::Publication {
+                topic_name: topic_name.to_owned(),
+                qos,
+                retain: false,
+                payload: payload.into(),
+                properties: proto::Properties::default(),
+            };
+            timeout(timeout_duration, publish_handle.publish(publication))
+                .await?
+                .map_err(Error::Publish)?;
+        }
+        Ok::<_, Error>(start.elapsed())
+    };
+
+    // Messages lost at QoS 0 are reported instead of failing the run.
+    let receive = async {
+        let mut latencies = Vec::with_capacity(count);
+        while latencies.len() < count {
+            match tokio::time::timeout(timeout_duration, events.recv()).await {
+                Ok(Some(Event::Publication(publication))) => {
+                    if let Some(timestamp) = publication.payload.get(..TIMESTAMP_SIZE) {
+                        let sent = u64::from_be_bytes(timestamp.try_into().expect("8 bytes"));
+                        latencies.push(Duration::from_nanos(nanos_since(start) - sent));
+                    }
+                }
+                Ok(Some(_)) => {}
+                Ok(None) | Err(_) => break,
+            }
+        }
+        (latencies, start.elapsed())
+    };
+
+    let (sent, (latencies, received)) = publisher
+        .until_closed(subscriber.until_closed(future::join(publish, receive)))
+        .await??;
+    let sent = sent?;
+
+    println!(
+        "sent     {} messages of {} bytes in {:.3}s: {}",
+        count,
+        size,
+        sent.as_secs_f64(),
+        rate(count, size, sent)
+    );
+    println!(
+        "received {} messages in {:.3}s: {}",
+        latencies.len(),
+        received.as_secs_f64(),
+        rate(latencies.len(), size, received)
+    );
+    if let (Some(min), Some(max)) = (latencies.iter().min(), latencies.iter().max()) {
+        let avg = latencies.iter().sum::<Duration>() / latencies.len() as u32;
+        println!("latency  min {:?}, avg {:?}, max {:?}", min, avg, max);
+    }
+
+    publisher.shutdown().await?;
+    subscriber.shutdown().await
+}
+
+fn nanos_since(start: Instant) -> u64 {
+    start.elapsed().as_nanos() as u64
+}
+
+fn rate(count: usize, size: usize, duration: Duration) -> String {
+    let seconds = duration.as_secs_f64();
+    format!(
+        "{:.0} msg/s, {:.2} MB/s",
+        count as f64 / seconds,
+        (count * size) as f64 / seconds / 1_000_000.0
+    )
+}
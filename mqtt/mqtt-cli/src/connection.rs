@@ -0,0 +1,334 @@
+//! Connection settings shared by all subcommands.
+
+use std::{future::Future, io, path::PathBuf, pin::Pin, time::Duration};
+
+use clap::{Arg, ArgMatches};
+use futures_util::{
+    future::{self, Either},
+    StreamExt,
+};
+use mqtt3::io_source::{TcpSource, TlsConfig, TlsSource, WebSocketSource};
+use mqtt3::{proto, Client, Event, IoSource, PublishHandle, ShutdownHandle};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::mpsc,
+    task::JoinHandle,
+};
+
+use crate::{parse_qos, Error};
+
+const DEFAULT_SERVER: &str = "localhost:1883";
+
+/// Arguments to connect to the server, accepted by every subcommand.
+pub fn args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("server")
+            .short("s")
+            .long("server")
+            .value_name("ADDRESS")
+            .takes_value(true)
+            .help("Server to connect to, as host:port or a tcp, tls, ws, wss or unix URL")
+            .default_value(DEFAULT_SERVER),
+        Arg::with_name("ca-file")
+            .long("ca-file")
+            .value_name("FILE")
+            .takes_value(true)
+            .help("PEM file with CA certificates to trust in addition to the system ones"),
+        Arg::with_name("cert-file")
+            .long("cert-file")
+            .value_name("FILE")
+            .takes_value(true)
+            .help("PEM file with the client certificate to authenticate with")
+            .requires("key-file"),
+        Arg::with_name("key-file")
+            .long("key-file")
+            .value_name("FILE")
+            .takes_value(true)
+            .help("PEM file with the private key of the client certificate")
+            .requires("cert-file"),
+        Arg::with_name("client-id")
+            .short("i")
+            .long("client-id")
+            .value_name("ID")
+            .takes_value(true)
+            .help("Client identifier, generated by the server if not given"),
+        Arg::with_name("persistent")
+            .long("persistent")
+            .help("Resumes the existing session of the client instead of starting a clean one")
+            .requires("client-id"),
+        Arg::with_name("username")
+            .short("u")
+            .long("username")
+            .value_name("USERNAME")
+            .takes_value(true),
+        Arg::with_name("password")
+            .short("P")
+            .long("password")
+            .value_name("PASSWORD")
+            .takes_value(true),
+        Arg::with_name("keep-alive")
+            .short("k")
+            .long("keep-alive")
+            .value_name("SECONDS")
+            .takes_value(true)
+            .default_value("30"),
+        Arg::with_name("will-topic")
+            .long("will-topic")
+            .value_name("TOPIC")
+            .takes_value(true)
+            .help("Topic of the will the server publishes if the client disconnects unexpectedly"),
+        Arg::with_name("will-message")
+            .long("will-message")
+            .value_name("MESSAGE")
+            .takes_value(true)
+            .requires("will-topic"),
+        Arg::with_name("will-qos")
+            .long("will-qos")
+            .value_name("QOS")
+            .takes_value(true)
+            .default_value("0"),
+        Arg::with_name("will-retain").long("will-retain"),
+    ]
+}
+
+/// The transport of a connection, chosen by the server address.
+#[derive(Debug)]
+pub enum Source {
+    Tcp(TcpSource),
+    Tls(TlsSource),
+    WebSocket(WebSocketSource),
+    #[cfg(unix)]
+    Unix(mqtt3::io_source::UnixSource),
+}
+
+impl Source {
+    fn new(server: &str, tls: TlsConfig, password: Option<String>) -> Result<Self, Error> {
+        let (scheme, address) = match server.find("://") {
+            Some(index) => (&server[..index], &server[index + 3..]),
+            None => ("tcp", server),
+        };
+        if address.is_empty() {
+            return Err(Error::InvalidServer(server.to_owned()));
+        }
+
+        let source = match scheme {
+            "tcp" | "mqtt" => Source::Tcp(TcpSource::new(address).with_password(password)),
+            "tls" | "ssl" | "mqtts" => {
+                Source::Tls(TlsSource::new(address, tls).with_password(password))
+            }
+            "ws" | "wss" => Source::WebSocket(
+                WebSocketSource::new(server)
+                    .with_tls_config(tls)
+                    .with_password(password),
+            ),
+            #[cfg(unix)]
+            "unix" => {
+                Source::Unix(mqtt3::io_source::UnixSource::new(address).with_password(password))
+            }
+            _ => return Err(Error::InvalidServer(server.to_owned())),
+        };
+        Ok(source)
+    }
+}
+
+pub trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T> Stream for T where T: AsyncRead + AsyncWrite + Send + Unpin {}
+
+type ConnectFuture =
+    Pin<Box<dyn Future<Output = io::Result<(Box<dyn Stream>, Option<String>)>> + Send>>;
+
+impl IoSource for Source {
+    type Io = Box<dyn Stream>;
+    type Error = io::Error;
+    type Future = ConnectFuture;
+
+    fn connect(&mut self) -> Self::Future {
+        match self {
+            Source::Tcp(source) => boxed(source.connect()),
+            Source::Tls(source) => boxed(source.connect()),
+            Source::WebSocket(source) => boxed(source.connect()),
+            #[cfg(unix)]
+            Source::Unix(source) => boxed(source.connect()),
+        }
+    }
+}
+
+fn boxed<F, S>(connect: F) -> ConnectFuture
+where
+    F: Future<Output = io::Result<(S, Option<String>)>> + Send + 'static,
+    S: Stream + 'static,
+{
+    Box::pin(async move {
+        let (stream, password) = connect.await?;
+        let stream: Box<dyn Stream> = Box::new(stream);
+        Ok((stream, password))
+    })
+}
+
+/// Creates a client from the connection arguments. `client_id_suffix` is appended
+/// to the client identifier, if any, so that a subcommand can connect more than one client.
+pub fn client(matches: &ArgMatches<'_>, client_id_suffix: &str) -> Result<Client<Source>, Error> {
+    let mut tls = TlsConfig::default();
+    if let Some(path) = matches.value_of("ca-file") {
+        tls = tls.with_ca_certificates(read_file(path)?);
+    }
+    if let (Some(cert), Some(key)) = (matches.value_of("cert-file"), matches.value_of("key-file")) {
+        tls = tls.with_client_certificate(read_file(cert)?, read_file(key)?);
+    }
+
+    let server = matches.value_of("server").unwrap_or(DEFAULT_SERVER);
+    let password = matches.value_of("password").map(ToOwned::to_owned);
+    let source = Source::new(server, tls, password)?;
+
+    let username = matches.value_of("username").map(ToOwned::to_owned);
+    let keep_alive = Duration::from_secs(parse_arg(matches, "keep-alive")?);
+    let will = match matches.value_of("will-topic") {
+        Some(topic) => Some(proto::Publication {
+            topic_name: topic.to_owned(),
+            qos: parse_qos(matches.value_of("will-qos").unwrap_or("0"))?,
+            retain: matches.is_present("will-retain"),
+            payload: matches
+                .value_of("will-message")
+                .unwrap_or_default()
+                .to_owned()
+                .into(),
+            properties: proto::Properties::default(),
+        }),
+        None => None,
+    };
+
+    let max_reconnect_back_off = Duration::from_secs(5);
+    let client_id = matches
+        .value_of("client-id")
+        .map(|client_id| format!("{}{}", client_id, client_id_suffix));
+    let client = match client_id {
+        Some(client_id) if matches.is_present("persistent") => Client::from_state(
+            client_id,
+            username,
+            will,
+            source,
+            max_reconnect_back_off,
+            keep_alive,
+        ),
+        client_id => Client::new(
+            client_id,
+            username,
+            will,
+            source,
+            max_reconnect_back_off,
+            keep_alive,
+        ),
+    };
+    Ok(client)
+}
+
+/// A client polled in the background.
+///
+/// The first error of the client closes the connection instead of reconnecting,
+/// so that connectivity problems are reported right away.
+pub struct Connection {
+    publish: PublishHandle,
+    shutdown: ShutdownHandle,
+    client: JoinHandle<Option<Error>>,
+}
+
+impl Connection {
+    /// Starts polling the client. Events of the client are sent to the returned receiver.
+    pub fn spawn(
+        mut client: Client<Source>,
+    ) -> Result<(Self, mpsc::UnboundedReceiver<Event>), Error> {
+        let publish = client.publish_handle().map_err(Error::Publish)?;
+        let shutdown = client.shutdown_handle().map_err(Error::Shutdown)?;
+
+        let (events_send, events_recv) = mpsc::unbounded_channel();
+        let client = tokio::spawn(async move {
+            while let Some(event) = client.next().await {
+                match event {
+                    Ok(event) => {
+                        log::debug!("{:?}", event);
+                        let _ = events_send.send(event);
+                    }
+                    Err(e) => return Some(Error::Client(e)),
+                }
+            }
+            None
+        });
+
+        let connection = Self {
+            publish,
+            shutdown,
+            client,
+        };
+        Ok((connection, events_recv))
+    }
+
+    pub fn publish_handle(&self) -> PublishHandle {
+        self.publish.clone()
+    }
+
+    /// Waits for `future`, unless the client fails first.
+    pub async fn until_closed<F>(&mut self, future: F) -> Result<F::Output, Error>
+    where
+        F: Future,
+    {
+        futures_util::pin_mut!(future);
+        match future::select(future, &mut self.client).await {
+            Either::Left((output, _)) => Ok(output),
+            Either::Right((result, _)) => Err(client_error(result)),
+        }
+    }
+
+    /// Disconnects from the server and waits for the client to stop.
+    pub async fn shutdown(mut self) -> Result<(), Error> {
+        self.shutdown.shutdown().await.map_err(Error::Shutdown)?;
+        match self.client.await {
+            Ok(None) => Ok(()),
+            result => Err(client_error(result)),
+        }
+    }
+}
+
+fn client_error(result: Result<Option<Error>, tokio::task::JoinError>) -> Error {
+    match result {
+        Ok(Some(e)) => e,
+        Ok(None) => Error::ClientStopped,
+        Err(e) => panic!("client task failed: {}", e),
+    }
+}
+
+/// Parses the value of an argument which has a default.
+pub fn parse_arg<T>(matches: &ArgMatches<'_>, name: &'static str) -> Result<T, Error>
+where
+    T: std::str::FromStr,
+{
+    let value = matches.value_of(name).unwrap_or_default();
+    value
+        .parse()
+        .map_err(|_| Error::InvalidArgument(name, value.to_owned()))
+}
+
+pub fn read_file(path: &str) -> Result<Vec<u8>, Error> {
+    std::fs::read(path).map_err(|e| Error::ReadFile(PathBuf::from(path), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use matches::assert_matches;
+    use mqtt3::io_source::TlsConfig;
+
+    use super::Source;
+    use crate::Error;
+
+    #[test]
+    fn it_selects_transport_by_scheme() {
+        let source = |server| Source::new(server, TlsConfig::default(), None);
+
+        assert_matches!(source("localhost:1883"), Ok(Source::Tcp(_)));
+        assert_matches!(source("tcp://localhost:1883"), Ok(Source::Tcp(_)));
+        assert_matches!(source("tls://localhost:8883"), Ok(Source::Tls(_)));
+        assert_matches!(source("wss://localhost/mqtt"), Ok(Source::WebSocket(_)));
+        assert_matches!(source("http://localhost"), Err(Error::InvalidServer(_)));
+        assert_matches!(source("tcp://"), Err(Error::InvalidServer(_)));
+    }
+}
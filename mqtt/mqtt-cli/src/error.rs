@@ -0,0 +1,39 @@
+use std::{io, path::PathBuf};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(
+        "Invalid server address {0:?}. Expected host:port or a tcp, tls, ws, wss or unix URL."
+    )]
+    InvalidServer(String),
+
+    #[error("Invalid QoS {0:?}. Expected 0, 1 or 2.")]
+    InvalidQoS(String),
+
+    #[error("Invalid value {1:?} for {0}.")]
+    InvalidArgument(&'static str, String),
+
+    #[error("Unable to read {0}.")]
+    ReadFile(PathBuf, #[source] io::Error),
+
+    #[error("Unable to read the payload from stdin.")]
+    ReadStdin(#[source] io::Error),
+
+    #[error("Unable to publish.")]
+    Publish(#[source] mqtt3::PublishError),
+
+    #[error("Unable to subscribe.")]
+    Subscribe(#[source] mqtt3::UpdateSubscriptionError),
+
+    #[error("Unable to shut down the client.")]
+    Shutdown(#[source] mqtt3::ShutdownError),
+
+    #[error("Client failed.")]
+    Client(#[source] mqtt3::Error),
+
+    #[error("Client stopped unexpectedly.")]
+    ClientStopped,
+
+    #[error("Timed out after {0:?}.")]
+    Timeout(std::time::Duration),
+}
@@ -0,0 +1,133 @@
+use std::str::FromStr;
+
+use mqtt3::ReceivedPublication;
+use serde_json::{json, Value};
+
+use crate::Error;
+
+/// How the `sub` subcommand prints received publications.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// The payload as UTF-8 text, with invalid sequences replaced.
+    Text,
+
+    /// A JSON object per publication with its topic, flags and payload.
+    Json,
+
+    /// The payload as lowercase hex.
+    Hex,
+}
+
+impl OutputFormat {
+    pub const VALUES: &'static [&'static str] = &["text", "json", "hex"];
+
+    /// Formats a publication as a single line. `verbose` prefixes the text
+    /// and hex formats with the topic, which the JSON format always contains.
+    pub fn format(self, publication: &ReceivedPublication, verbose: bool) -> String {
+        let payload = match self {
+            OutputFormat::Text => String::from_utf8_lossy(&publication.payload).into_owned(),
+            OutputFormat::Hex => hex(&publication.payload),
+            OutputFormat::Json => return json(publication).to_string(),
+        };
+
+        if verbose {
+            format!("{} {}", publication.topic_name, payload)
+        } else {
+            payload
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "hex" => Ok(OutputFormat::Hex),
+            _ => Err(Error::InvalidArgument("format", s.to_owned())),
+        }
+    }
+}
+
+/// JSON payloads are embedded as is, other UTF-8 payloads as strings
+/// and binary payloads as hex strings.
+fn json(publication: &ReceivedPublication) -> Value {
+    let mut value = json!({
+        "topic": publication.topic_name,
+        "qos": u8::from(publication.qos),
+        "retain": publication.retain,
+        "dup": publication.dup,
+    });
+
+    value["payload"] = if let Ok(payload) = serde_json::from_slice::<Value>(&publication.payload) {
+        payload
+    } else if let Ok(payload) = std::str::from_utf8(&publication.payload) {
+        Value::String(payload.to_owned())
+    } else {
+        value["payload_encoding"] = "hex".into();
+        Value::String(hex(&publication.payload))
+    };
+
+    value
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use mqtt3::{proto::QoS, ReceivedPublication};
+    use serde_json::json;
+
+    use super::{json, OutputFormat};
+
+    fn publication(payload: &'static [u8]) -> ReceivedPublication {
+        ReceivedPublication {
+            topic_name: "devices/device1/messages".to_owned(),
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            retain: true,
+            payload: Bytes::from(payload),
+            ack: None,
+        }
+    }
+
+    #[test]
+    fn it_formats_text_and_hex() {
+        let publication = publication(b"hi\xff");
+
+        assert_eq!(OutputFormat::Text.format(&publication, false), "hi\u{fffd}");
+        assert_eq!(OutputFormat::Hex.format(&publication, false), "6869ff");
+        assert_eq!(
+            OutputFormat::Hex.format(&publication, true),
+            "devices/device1/messages 6869ff"
+        );
+    }
+
+    #[test]
+    fn it_embeds_json_payloads() {
+        assert_eq!(
+            json(&publication(br#"{"temperature":21.5}"#)),
+            json!({
+                "topic": "devices/device1/messages",
+                "qos": 1,
+                "retain": true,
+                "dup": false,
+                "payload": { "temperature": 21.5 },
+            })
+        );
+    }
+
+    #[test]
+    fn it_encodes_other_payloads_as_strings() {
+        assert_eq!(json(&publication(b"hello"))["payload"], "hello");
+
+        let value = json(&publication(b"\x00\xff"));
+        assert_eq!(value["payload"], "00ff");
+        assert_eq!(value["payload_encoding"], "hex");
+    }
+}
@@ -0,0 +1,107 @@
+use std::{error::Error as StdError, future::Future, time::Duration};
+
+use clap::{crate_description, crate_version, App, AppSettings};
+use mqtt3::proto::QoS;
+
+mod bench;
+mod connection;
+mod error;
+mod format;
+mod publish;
+mod subscribe;
+
+pub use error::Error;
+
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(env_logger::Env::new().filter_or("MQTT_LOG", "warn")).init();
+
+    let matches = create_app().get_matches();
+    let result = match matches.subcommand() {
+        ("pub", Some(matches)) => publish::run(matches).await,
+        ("sub", Some(matches)) => subscribe::run(matches).await,
+        ("bench", Some(matches)) => bench::run(matches).await,
+        _ => unreachable!("subcommand is required"),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        let mut source = e.source();
+        while let Some(e) = source {
+            eprintln!("caused by: {}", e);
+            source = e.source();
+        }
+        std::process::exit(1);
+    }
+}
+
+fn create_app() -> App<'static, 'static> {
+    App::new("mqtt")
+        .version(crate_version!())
+        .about(crate_description!())
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .after_help("Set MQTT_LOG=debug to log the events of the client.")
+        .subcommand(publish::command())
+        .subcommand(subscribe::command())
+        .subcommand(bench::command())
+}
+
+pub fn parse_qos(qos: &str) -> Result<QoS, Error> {
+    match qos {
+        "0" => Ok(QoS::AtMostOnce),
+        "1" => Ok(QoS::AtLeastOnce),
+        "2" => Ok(QoS::ExactlyOnce),
+        _ => Err(Error::InvalidQoS(qos.to_owned())),
+    }
+}
+
+/// Fails with [`Error::Timeout`] if `future` does not complete within `duration`.
+pub async fn timeout<F>(duration: Duration, future: F) -> Result<F::Output, Error>
+where
+    F: Future,
+{
+    tokio::time::timeout(duration, future)
+        .await
+        .map_err(|_| Error::Timeout(duration))
+}
+
+#[cfg(test)]
+mod tests {
+    use matches::assert_matches;
+    use mqtt3::proto::QoS;
+
+    use super::{create_app, parse_qos};
+    use crate::Error;
+
+    #[test]
+    fn it_parses_qos() {
+        assert_matches!(parse_qos("0"), Ok(QoS::AtMostOnce));
+        assert_matches!(parse_qos("1"), Ok(QoS::AtLeastOnce));
+        assert_matches!(parse_qos("2"), Ok(QoS::ExactlyOnce));
+        assert_matches!(parse_qos("3"), Err(Error::InvalidQoS(qos)) if qos == "3");
+    }
+
+    #[test]
+    fn it_accepts_connection_args_for_each_subcommand() {
+        for subcommand in &["pub", "sub", "bench"] {
+            let matches = create_app()
+                .get_matches_from_safe(vec![
+                    "mqtt",
+                    subcommand,
+                    "--server",
+                    "tls://localhost:8883",
+                    "-u",
+                    "user",
+                    "-t",
+                    "topic",
+                ])
+                .unwrap();
+
+            let (_, matches) = matches.subcommand();
+            let matches = matches.unwrap();
+            assert_eq!(matches.value_of("server"), Some("tls://localhost:8883"));
+            assert_eq!(matches.value_of("username"), Some("user"));
+            assert_eq!(matches.value_of("qos"), Some("0"));
+        }
+    }
+}
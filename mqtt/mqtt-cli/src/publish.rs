@@ -0,0 +1,125 @@
+use std::io::Read;
+
+use bytes::Bytes;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use mqtt3::proto;
+use tokio::time::{self, Duration};
+
+use crate::connection::{self, parse_arg, read_file, Connection};
+use crate::{parse_qos, timeout, Error};
+
+pub fn command() -> App<'static, 'static> {
+    SubCommand::with_name("pub")
+        .about("Publishes a message, read from stdin if neither --message nor --file is given")
+        .args(&connection::args())
+        .arg(
+            Arg::with_name("topic")
+                .short("t")
+                .long("topic")
+                .value_name("TOPIC")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("message")
+                .short("m")
+                .long("message")
+                .value_name("MESSAGE")
+                .takes_value(true)
+                .conflicts_with("file"),
+        )
+        .arg(
+            Arg::with_name("file")
+                .short("f")
+                .long("file")
+                .value_name("FILE")
+                .help("File to publish as the payload")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("qos")
+                .short("q")
+                .long("qos")
+                .value_name("QOS")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("retain")
+                .short("r")
+                .long("retain")
+                .help("Asks the server to retain the message"),
+        )
+        .arg(
+            Arg::with_name("count")
+                .short("n")
+                .long("count")
+                .value_name("COUNT")
+                .help("Number of times to publish the message")
+                .takes_value(true)
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("interval")
+                .long("interval")
+                .value_name("MILLISECONDS")
+                .help("Delay between two publications")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .value_name("SECONDS")
+                .help("Time to wait for the server to acknowledge each publication")
+                .takes_value(true)
+                .default_value("10"),
+        )
+}
+
+pub async fn run(matches: &ArgMatches<'_>) -> Result<(), Error> {
+    let topic_name = matches.value_of("topic").expect("topic is required");
+    let qos = parse_qos(matches.value_of("qos").unwrap_or_default())?;
+    let retain = matches.is_present("retain");
+    let count: usize = parse_arg(matches, "count")?;
+    let interval = Duration::from_millis(parse_arg(matches, "interval")?);
+    let timeout_duration = Duration::from_secs(parse_arg(matches, "timeout")?);
+
+    let payload: Bytes = match (matches.value_of("message"), matches.value_of("file")) {
+        (Some(message), _) => message.to_owned().into(),
+        (None, Some(path)) => read_file(path)?.into(),
+        (None, None) => {
+            let mut payload = Vec::new();
+            std::io::stdin()
+                .read_to_end(&mut payload)
+                .map_err(Error::ReadStdin)?;
+            payload.into()
+        }
+    };
+
+    let client = connection::client(matches, "")?;
+    let (mut connection, _) = Connection::spawn(client)?;
+    let mut publish_handle = connection.publish_handle();
+
+    for i in 0..count {
+        if i > 0 {
+            time::delay_for(interval).await;
+        }
+
+        let publication = proto::Publication {
+            topic_name: topic_name.to_owned(),
+            qos,
+            retain,
+            payload: payload.clone(),
+            properties: proto::Properties::default(),
+        };
+        let publish = timeout(timeout_duration, publish_handle.publish(publication));
+        connection
+            .until_closed(publish)
+            .await??
+            .map_err(Error::Publish)?;
+        log::info!("published {} bytes to {}", payload.len(), topic_name);
+    }
+
+    connection.shutdown().await
+}
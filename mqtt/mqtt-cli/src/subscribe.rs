@@ -0,0 +1,118 @@
+use std::io::Write;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use futures_util::future::{self, Either};
+use mqtt3::{proto, Event, SubscriptionUpdateEvent};
+
+use crate::connection::{self, parse_arg, Connection};
+use crate::format::OutputFormat;
+use crate::{parse_qos, Error};
+
+pub fn command() -> App<'static, 'static> {
+    SubCommand::with_name("sub")
+        .about("Subscribes to topics and prints the received messages until interrupted")
+        .args(&connection::args())
+        .arg(
+            Arg::with_name("topic")
+                .short("t")
+                .long("topic")
+                .value_name("FILTER")
+                .help("Topic filter to subscribe to, can be given multiple times")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("qos")
+                .short("q")
+                .long("qos")
+                .value_name("QOS")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .short("F")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Output format of the messages")
+                .takes_value(true)
+                .possible_values(OutputFormat::VALUES)
+                .default_value("text"),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .help("Prints the topic of each message before its payload"),
+        )
+        .arg(
+            Arg::with_name("count")
+                .short("C")
+                .long("count")
+                .value_name("COUNT")
+                .help("Exits after receiving this many messages")
+                .takes_value(true),
+        )
+}
+
+pub async fn run(matches: &ArgMatches<'_>) -> Result<(), Error> {
+    let qos = parse_qos(matches.value_of("qos").unwrap_or_default())?;
+    let format: OutputFormat = parse_arg(matches, "format")?;
+    let verbose = matches.is_present("verbose");
+    let count: Option<usize> = match matches.value_of("count") {
+        Some(_) => Some(parse_arg(matches, "count")?),
+        None => None,
+    };
+
+    let mut client = connection::client(matches, "")?;
+    for topic_filter in matches.values_of("topic").expect("topic is required") {
+        let subscribe_to = proto::SubscribeTo {
+            topic_filter: topic_filter.to_owned(),
+            qos,
+        };
+        client.subscribe(subscribe_to).map_err(Error::Subscribe)?;
+    }
+
+    let (mut connection, mut events) = Connection::spawn(client)?;
+    let interrupt = tokio::signal::ctrl_c();
+    futures_util::pin_mut!(interrupt);
+
+    let mut received = 0;
+    while count.map_or(true, |count| received < count) {
+        let event = match connection
+            .until_closed(future::select(events.recv(), &mut interrupt))
+            .await?
+        {
+            Either::Left((Some(event), _)) => event,
+            Either::Left((None, _)) | Either::Right(_) => break,
+        };
+
+        match event {
+            Event::NewConnection { reset_session } => {
+                log::info!("connected, session reset: {}", reset_session);
+            }
+            Event::SubscriptionUpdates(updates) => {
+                for update in updates {
+                    if let SubscriptionUpdateEvent::Subscribe(subscribe_to) = update {
+                        log::info!(
+                            "subscribed to {} with {:?}",
+                            subscribe_to.topic_filter,
+                            subscribe_to.qos
+                        );
+                    }
+                }
+            }
+            Event::Publication(publication) => {
+                let stdout = std::io::stdout();
+                let mut stdout = stdout.lock();
+                let _ = writeln!(stdout, "{}", format.format(&publication, verbose));
+                let _ = stdout.flush();
+                received += 1;
+            }
+        }
+    }
+
+    connection.shutdown().await
+}
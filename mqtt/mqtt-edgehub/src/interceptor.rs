@@ -0,0 +1,49 @@
+use mqtt3::proto;
+use mqtt_broker::{Intercept, Interceptor, InterceptorContext};
+
+use crate::translation::{
+    translate_incoming_publish, translate_incoming_subscribe, translate_incoming_unsubscribe,
+    translate_outgoing_publish,
+};
+
+/// Translates the topics of IoT Hub device SDKs, which lack a client id,
+/// into topics which include one, and back for outgoing publications.
+#[derive(Debug, Default)]
+pub struct TranslationInterceptor;
+
+impl Interceptor for TranslationInterceptor {
+    fn on_incoming_publish(
+        &self,
+        context: &mut InterceptorContext<'_>,
+        publish: proto::Publish,
+    ) -> Intercept<proto::Publish> {
+        let client_id = context.client_id().as_str();
+        Intercept::Continue(translate_incoming_publish(client_id, publish))
+    }
+
+    fn on_subscribe(
+        &self,
+        context: &mut InterceptorContext<'_>,
+        subscribe: proto::Subscribe,
+    ) -> Intercept<proto::Subscribe> {
+        let client_id = context.client_id().as_str();
+        Intercept::Continue(translate_incoming_subscribe(client_id, subscribe))
+    }
+
+    fn on_unsubscribe(
+        &self,
+        context: &mut InterceptorContext<'_>,
+        unsubscribe: proto::Unsubscribe,
+    ) -> Intercept<proto::Unsubscribe> {
+        let client_id = context.client_id().as_str();
+        Intercept::Continue(translate_incoming_unsubscribe(client_id, unsubscribe))
+    }
+
+    fn on_outgoing_publish(
+        &self,
+        _context: &mut InterceptorContext<'_>,
+        publish: proto::Publish,
+    ) -> Intercept<proto::Publish> {
+        Intercept::Continue(translate_outgoing_publish(publish))
+    }
+}
@@ -1,5 +1,7 @@
+mod interceptor;
 mod translation;
 
+pub use crate::interceptor::TranslationInterceptor;
 pub use crate::translation::{
     translate_incoming_publish, translate_incoming_subscribe, translate_incoming_unsubscribe,
     translate_outgoing_publish,
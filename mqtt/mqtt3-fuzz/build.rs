@@ -16,6 +16,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             mqtt3::proto::Packet::ConnAck(mqtt3::proto::ConnAck {
                 session_present: true,
                 return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                properties: mqtt3::proto::Properties::default(),
             }),
         ),
         (
@@ -28,16 +29,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     qos: mqtt3::proto::QoS::ExactlyOnce,
                     retain: true,
                     payload: b"\x00\x01\x02\xFF\xFE\xFD"[..].into(),
+                    properties: mqtt3::proto::Properties::default(),
                 }),
                 client_id: mqtt3::proto::ClientId::IdWithExistingSession("id".to_string()),
                 keep_alive: std::time::Duration::from_secs(5),
                 protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                 protocol_level: mqtt3::PROTOCOL_LEVEL,
+                properties: mqtt3::proto::Properties::default(),
             }),
         ),
         (
             "disconnect",
-            mqtt3::proto::Packet::Disconnect(mqtt3::proto::Disconnect),
+            mqtt3::proto::Packet::Disconnect(mqtt3::proto::Disconnect::default()),
         ),
         (
             "pingreq",
@@ -51,12 +54,16 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             "puback",
             mqtt3::proto::Packet::PubAck(mqtt3::proto::PubAck {
                 packet_identifier: mqtt3::proto::PacketIdentifier::new(5).unwrap(),
+                reason_code: mqtt3::proto::ReasonCode::SUCCESS,
+                properties: mqtt3::proto::Properties::default(),
             }),
         ),
         (
             "pubcomp",
             mqtt3::proto::Packet::PubComp(mqtt3::proto::PubComp {
                 packet_identifier: mqtt3::proto::PacketIdentifier::new(5).unwrap(),
+                reason_code: mqtt3::proto::ReasonCode::SUCCESS,
+                properties: mqtt3::proto::Properties::default(),
             }),
         ),
         (
@@ -69,18 +76,23 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 retain: true,
                 topic_name: "publish-topic".to_string(),
                 payload: b"\x00\x01\x02\xFF\xFE\xFD"[..].into(),
+                properties: mqtt3::proto::Properties::default(),
             }),
         ),
         (
             "pubrec",
             mqtt3::proto::Packet::PubRec(mqtt3::proto::PubRec {
                 packet_identifier: mqtt3::proto::PacketIdentifier::new(5).unwrap(),
+                reason_code: mqtt3::proto::ReasonCode::SUCCESS,
+                properties: mqtt3::proto::Properties::default(),
             }),
         ),
         (
             "pubrel",
             mqtt3::proto::Packet::PubRel(mqtt3::proto::PubRel {
                 packet_identifier: mqtt3::proto::PacketIdentifier::new(5).unwrap(),
+                reason_code: mqtt3::proto::ReasonCode::SUCCESS,
+                properties: mqtt3::proto::Properties::default(),
             }),
         ),
         (
@@ -91,6 +103,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     mqtt3::proto::SubAckQos::Success(mqtt3::proto::QoS::ExactlyOnce),
                     mqtt3::proto::SubAckQos::Failure,
                 ],
+                properties: mqtt3::proto::Properties::default(),
             }),
         ),
         (
@@ -101,12 +114,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     topic_filter: "subscribe-topic".to_string(),
                     qos: mqtt3::proto::QoS::ExactlyOnce,
                 }],
+                properties: mqtt3::proto::Properties::default(),
             }),
         ),
         (
             "unsuback",
             mqtt3::proto::Packet::UnsubAck(mqtt3::proto::UnsubAck {
                 packet_identifier: mqtt3::proto::PacketIdentifier::new(5).unwrap(),
+                reason_codes: vec![],
+                properties: mqtt3::proto::Properties::default(),
             }),
         ),
         (
@@ -114,27 +130,133 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             mqtt3::proto::Packet::Unsubscribe(mqtt3::proto::Unsubscribe {
                 packet_identifier: mqtt3::proto::PacketIdentifier::new(5).unwrap(),
                 unsubscribe_from: vec!["unsubscribe-topic".to_string()],
+                properties: mqtt3::proto::Properties::default(),
             }),
         ),
     ];
 
-    for (filename, packet) in packets {
-        let file = std::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(in_dir.join(filename))?;
-        let mut file = std::io::BufWriter::new(file);
+    let properties = mqtt3::proto::Properties {
+        message_expiry_interval: Some(60),
+        content_type: Some("application/octet-stream".to_string()),
+        correlation_data: Some(vec![0x00, 0x01, 0x02]),
+        user_properties: vec![("key".to_string(), "value".to_string())],
+        ..Default::default()
+    };
 
-        let mut codec: mqtt3::proto::PacketCodec = Default::default();
+    let packets_v5 = vec![
+        (
+            "auth",
+            mqtt3::proto::Packet::Auth(mqtt3::proto::Auth {
+                reason_code: mqtt3::proto::ReasonCode::CONTINUE_AUTHENTICATION,
+                properties: mqtt3::proto::Properties {
+                    authentication_method: Some("method".to_string()),
+                    authentication_data: Some(vec![0xFF, 0xFE, 0xFD]),
+                    ..Default::default()
+                },
+            }),
+        ),
+        (
+            "connect-properties",
+            mqtt3::proto::Packet::Connect(mqtt3::proto::Connect {
+                username: None,
+                password: None,
+                will: Some(mqtt3::proto::Publication {
+                    topic_name: "will-topic".to_string(),
+                    qos: mqtt3::proto::QoS::AtLeastOnce,
+                    retain: false,
+                    payload: b"\x00\x01\x02\xFF\xFE\xFD"[..].into(),
+                    properties: mqtt3::proto::Properties {
+                        will_delay_interval: Some(5),
+                        ..properties.clone()
+                    },
+                }),
+                client_id: mqtt3::proto::ClientId::ServerGenerated,
+                keep_alive: std::time::Duration::from_secs(5),
+                protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
+                protocol_level: mqtt3::PROTOCOL_LEVEL_V5,
+                properties: mqtt3::proto::Properties {
+                    session_expiry_interval: Some(3600),
+                    receive_maximum: Some(10),
+                    ..Default::default()
+                },
+            }),
+        ),
+        (
+            "puback-reason-code",
+            mqtt3::proto::Packet::PubAck(mqtt3::proto::PubAck {
+                packet_identifier: mqtt3::proto::PacketIdentifier::new(5).unwrap(),
+                reason_code: mqtt3::proto::ReasonCode::NOT_AUTHORIZED,
+                properties: mqtt3::proto::Properties {
+                    reason_string: Some("not authorized".to_string()),
+                    ..Default::default()
+                },
+            }),
+        ),
+        (
+            "publish-properties",
+            mqtt3::proto::Packet::Publish(mqtt3::proto::Publish {
+                packet_identifier_dup_qos: mqtt3::proto::PacketIdentifierDupQoS::AtLeastOnce(
+                    mqtt3::proto::PacketIdentifier::new(5).unwrap(),
+                    false,
+                ),
+                retain: false,
+                topic_name: "publish-topic".to_string(),
+                payload: b"\x00\x01\x02\xFF\xFE\xFD"[..].into(),
+                properties: mqtt3::proto::Properties {
+                    topic_alias: Some(1),
+                    ..properties
+                },
+            }),
+        ),
+        (
+            "unsuback-reason-codes",
+            mqtt3::proto::Packet::UnsubAck(mqtt3::proto::UnsubAck {
+                packet_identifier: mqtt3::proto::PacketIdentifier::new(5).unwrap(),
+                reason_codes: vec![
+                    mqtt3::proto::ReasonCode::SUCCESS,
+                    mqtt3::proto::ReasonCode::NO_SUBSCRIPTION_EXISTED,
+                ],
+                properties: mqtt3::proto::Properties::default(),
+            }),
+        ),
+    ];
 
-        let mut bytes = bytes::BytesMut::new();
+    // Every input starts with a byte that selects the protocol level the fuzzer decodes with.
+    for (filename, packet) in packets.clone() {
+        write_input(&in_dir, filename, 0, mqtt3::PROTOCOL_LEVEL, packet)?;
+    }
 
-        codec.encode(packet, &mut bytes)?;
+    for (filename, packet) in packets.into_iter().chain(packets_v5) {
+        let filename = format!("v5-{}", filename);
+        write_input(&in_dir, &filename, 1, mqtt3::PROTOCOL_LEVEL_V5, packet)?;
+    }
 
-        file.write_all(&bytes)?;
+    Ok(())
+}
 
-        file.flush()?;
-    }
+fn write_input(
+    in_dir: &std::path::Path,
+    filename: &str,
+    selector: u8,
+    protocol_level: u8,
+    packet: mqtt3::proto::Packet,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .open(in_dir.join(filename))?;
+    let mut file = std::io::BufWriter::new(file);
+
+    let mut codec = mqtt3::proto::PacketCodec::new(protocol_level);
+
+    let mut bytes = bytes::BytesMut::new();
+
+    codec.encode(packet, &mut bytes)?;
+
+    file.write_all(&[selector])?;
+    file.write_all(&bytes)?;
+
+    file.flush()?;
 
     Ok(())
 }
@@ -4,7 +4,13 @@ use tokio_util::codec::{Decoder, Encoder};
 
 fn main() {
     afl::fuzz(true, |data| {
-        let mut codec: mqtt3::proto::PacketCodec = Default::default();
+        // The first byte selects the protocol level the codec starts with.
+        let (protocol_level, data) = match data.split_first() {
+            Some((selector, data)) => (protocol_level(*selector), data),
+            None => return,
+        };
+
+        let mut codec = mqtt3::proto::PacketCodec::new(protocol_level);
 
         let mut bytes: bytes::BytesMut = data.into();
 
@@ -36,3 +42,11 @@ fn main() {
         }
     })
 }
+
+fn protocol_level(selector: u8) -> u8 {
+    if selector & 1 == 0 {
+        mqtt3::PROTOCOL_LEVEL
+    } else {
+        mqtt3::PROTOCOL_LEVEL_V5
+    }
+}
@@ -134,6 +134,7 @@ fn main() {
                         qos,
                         retain: false,
                         payload,
+                        properties: mqtt3::proto::Properties::default(),
                     })
                     .await;
                 let () = result.expect("couldn't publish");
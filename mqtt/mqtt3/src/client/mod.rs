@@ -4,8 +4,11 @@ mod connect;
 
 mod ping;
 
+mod store;
+pub use store::{FilePublicationStore, PublicationStore};
+
 mod publish;
-pub use publish::{PublishError, PublishHandle};
+pub use publish::{Ack, AckError, PublishError, PublishHandle};
 
 mod subscriptions;
 pub use subscriptions::{UpdateSubscriptionError, UpdateSubscriptionHandle};
@@ -150,6 +153,48 @@ where
         })
     }
 
+    /// Persists the QoS 1 and QoS 2 publications of this client in the given store until the server acknowledges them.
+    ///
+    /// Publications already in the store, left over from an earlier client that used the same store, are redelivered
+    /// with the DUP flag set once the client connects, or released if the server already received them. Combine this
+    /// with [`Client::from_state`] so that the server recognizes the redelivered packets as belonging to the resumed session.
+    ///
+    /// The store is accessed while the client is polled, see [`PublicationStore`].
+    pub fn with_publication_store<S>(mut self, store: S) -> Result<Self, Error>
+    where
+        S: PublicationStore + 'static,
+    {
+        if let ClientState::Up {
+            packet_identifiers,
+            publish,
+            ..
+        } = &mut self.0
+        {
+            publish.set_store(Box::new(store), packet_identifiers)?;
+        }
+
+        Ok(self)
+    }
+
+    /// Switches the client to manual acknowledgement mode.
+    ///
+    /// QoS 1 and QoS 2 publications from the server are then yielded with an [`Ack`] in [`ReceivedPublication::ack`],
+    /// and their PUBACK or PUBREC is only sent once the application calls [`Ack::ack`]. QoS 2 publications are yielded
+    /// as soon as they are received rather than when the server releases them. If an `Ack` is dropped without being used,
+    /// the publication stays unacknowledged and the server redelivers it when the session is resumed on a new connection.
+    /// Until then, it keeps its slot in the receive window.
+    ///
+    /// The client holds back further QoS 1 and QoS 2 publications while `receive_window` publications are waiting to be
+    /// acknowledged. It keeps reading the other packets from the server in the meantime, so the application can wait for
+    /// its own publications to complete before it acknowledges the ones it received. A window of 0 is treated as 1.
+    pub fn with_manual_acks(mut self, receive_window: usize) -> Self {
+        if let ClientState::Up { publish, .. } = &mut self.0 {
+            publish.set_manual_acks(receive_window);
+        }
+
+        self
+    }
+
     /// Queues a message to be published to the server
     pub fn publish(
         &mut self,
@@ -397,8 +442,9 @@ where
                         } else {
                             match std::pin::Pin::new(&mut framed).poll_ready(cx) {
                                 std::task::Poll::Ready(Ok(())) => {
-                                    let packet =
-                                        crate::proto::Packet::Disconnect(crate::proto::Disconnect);
+                                    let packet = crate::proto::Packet::Disconnect(
+                                        crate::proto::Disconnect::default(),
+                                    );
                                     match std::pin::Pin::new(&mut framed).start_send(packet) {
                                         Ok(()) => *sent_disconnect = true,
 
@@ -530,6 +576,10 @@ pub struct ReceivedPublication {
     pub qos: crate::proto::QoS,
     pub retain: bool,
     pub payload: bytes::Bytes,
+
+    /// Set for QoS 1 and QoS 2 publications if the client is in manual acknowledgement mode.
+    /// See [`Client::with_manual_acks`].
+    pub ack: Option<Ack>,
 }
 
 #[derive(Clone, Debug)]
@@ -640,18 +690,26 @@ where
 
         let mut continue_loop = false;
 
-        let mut packet = match std::pin::Pin::new(&mut *framed).poll_next(cx) {
-            std::task::Poll::Ready(Some(packet)) => {
-                let packet = packet.map_err(Error::DecodePacket)?;
+        // Publications from the server are held back while the receive window is full, until the application
+        // acknowledges some of the publications it received. Polling `publish` below registers for the wake-up when it does.
+        let mut packet = if let Some(packet) = publish.next_held_back() {
+            // May have more held back packets after this one, so keep looping
+            continue_loop = true;
+            Some(packet)
+        } else {
+            match std::pin::Pin::new(&mut *framed).poll_next(cx) {
+                std::task::Poll::Ready(Some(packet)) => {
+                    let packet = packet.map_err(Error::DecodePacket)?;
 
-                // May have more packets after this one, so keep looping
-                continue_loop = true;
-                Some(packet)
-            }
-            std::task::Poll::Ready(None) => {
-                return std::task::Poll::Ready(Err(Error::ServerClosedConnection))
+                    // May have more packets after this one, so keep looping
+                    continue_loop = true;
+                    publish.hold_back(packet)
+                }
+                std::task::Poll::Ready(None) => {
+                    return std::task::Poll::Ready(Err(Error::ServerClosedConnection))
+                }
+                std::task::Poll::Pending => None,
             }
-            std::task::Poll::Pending => None,
         };
 
         let mut new_packets_to_be_sent = vec![];
@@ -732,6 +790,13 @@ impl PacketIdentifiers {
         Ok(current)
     }
 
+    /// Marks a packet identifier restored from a publication store as in use.
+    fn restore(&mut self, packet_identifier: crate::proto::PacketIdentifier) {
+        let (block, mask) = self.entry(packet_identifier);
+        *block |= mask;
+        self.previous = packet_identifier;
+    }
+
     fn discard(&mut self, packet_identifier: crate::proto::PacketIdentifier) {
         let (block, mask) = self.entry(packet_identifier);
         *block &= !mask;
@@ -771,6 +836,7 @@ pub enum Error {
     EncodePacket(crate::proto::EncodeError),
     PacketIdentifiersExhausted,
     PingTimer(tokio::time::Error),
+    PublicationStore(std::io::Error),
     ServerClosedConnection,
     SubAckDoesNotContainEnoughQoS(crate::proto::PacketIdentifier, usize, usize),
     SubscriptionDowngraded(String, crate::proto::QoS, crate::proto::QoS),
@@ -805,7 +871,7 @@ impl Error {
                 std::io::ErrorKind::TimedOut | std::io::ErrorKind::WriteZero => true,
                 _ => false,
             },
-            Error::ServerClosedConnection => true,
+            Error::PublicationStore(_) | Error::ServerClosedConnection => true,
             _ => false,
         }
     }
@@ -833,6 +899,9 @@ impl std::fmt::Display for Error {
 			Error::PingTimer(err) =>
 				write!(f, "ping timer failed: {}", err),
 
+			Error::PublicationStore(err) =>
+				write!(f, "could not access publication store: {}", err),
+
 			Error::ServerClosedConnection =>
 				write!(f, "connection closed by server"),
 
@@ -863,6 +932,7 @@ impl std::error::Error for Error {
             Error::EncodePacket(err) => Some(err),
             Error::PacketIdentifiersExhausted => None,
             Error::PingTimer(err) => Some(err),
+            Error::PublicationStore(err) => Some(err),
             Error::ServerClosedConnection => None,
             Error::SubAckDoesNotContainEnoughQoS(_, _, _) => None,
             Error::SubscriptionDowngraded(_, _, _) => None,
@@ -14,15 +14,45 @@ pub(super) struct State {
     >,
 
     /// Holds the identifiers of PUBREC packets sent by us, waiting for a corresponding PUBREL,
-    /// and the contents of the original PUBLISH packet for which we sent the PUBREC
-    waiting_to_be_released:
-        std::collections::BTreeMap<crate::proto::PacketIdentifier, crate::ReceivedPublication>,
+    /// and the contents of the original PUBLISH packet for which we sent the PUBREC.
+    /// The contents are `None` if the publication was already given to the application in manual acknowledgement mode.
+    waiting_to_be_released: std::collections::BTreeMap<
+        crate::proto::PacketIdentifier,
+        Option<crate::ReceivedPublication>,
+    >,
 
     /// Holds PUBLISH packets sent by us, waiting for a corresponding PUBCOMP
     waiting_to_be_completed: std::collections::BTreeMap<
         crate::proto::PacketIdentifier,
         (futures_channel::oneshot::Sender<()>, crate::proto::Publish),
     >,
+
+    /// Persists the PUBLISH packets in waiting_to_be_acked and waiting_to_be_completed, if set
+    store: Option<Box<dyn super::PublicationStore>>,
+
+    /// Set in manual acknowledgement mode
+    manual_acks: Option<ManualAcks>,
+}
+
+#[derive(Debug)]
+struct ManualAcks {
+    ack_send: futures_channel::mpsc::UnboundedSender<AckRequest>,
+    ack_recv: futures_channel::mpsc::UnboundedReceiver<AckRequest>,
+
+    /// Holds the identifiers and QoS of PUBLISH packets given to the application, waiting for it to acknowledge them
+    waiting_for_ack: std::collections::BTreeMap<crate::proto::PacketIdentifier, crate::proto::QoS>,
+
+    /// Holds the identifiers of publications in waiting_for_ack that the application dropped without acknowledging them.
+    /// They keep their slot in the receive window, as the server keeps them inflight until they are redelivered on a new connection.
+    dropped: std::collections::BTreeSet<crate::proto::PacketIdentifier>,
+
+    receive_window: usize,
+
+    /// Holds QoS 1 and QoS 2 PUBLISH packets read from the server while the receive window is full
+    held_back: std::collections::VecDeque<crate::proto::Publish>,
+
+    /// Incremented when the session is reset, so that acks of publications from an earlier session are ignored
+    session: u64,
 }
 
 impl State {
@@ -45,39 +75,43 @@ impl State {
         let mut publication_received = None;
 
         match packet.take() {
-            Some(crate::proto::Packet::PubAck(crate::proto::PubAck { packet_identifier })) => {
-                match self.waiting_to_be_acked.remove(&packet_identifier) {
-                    Some((ack_sender, _)) => {
-                        packet_identifiers.discard(packet_identifier);
+            Some(crate::proto::Packet::PubAck(crate::proto::PubAck {
+                packet_identifier, ..
+            })) => match self.waiting_to_be_acked.remove(&packet_identifier) {
+                Some((ack_sender, _)) => {
+                    packet_identifiers.discard(packet_identifier);
+                    self.remove_stored(packet_identifier);
 
-                        match ack_sender.send(()) {
+                    match ack_sender.send(()) {
 						Ok(()) => (),
 						Err(()) => log::debug!("could not send ack for publish request because ack receiver has been dropped"),
 					}
-                    }
-                    None => log::warn!("ignoring PUBACK for a PUBLISH we never sent"),
                 }
-            }
+                None => log::warn!("ignoring PUBACK for a PUBLISH we never sent"),
+            },
 
-            Some(crate::proto::Packet::PubComp(crate::proto::PubComp { packet_identifier })) => {
-                match self.waiting_to_be_completed.remove(&packet_identifier) {
-                    Some((ack_sender, _)) => {
-                        packet_identifiers.discard(packet_identifier);
+            Some(crate::proto::Packet::PubComp(crate::proto::PubComp {
+                packet_identifier,
+                ..
+            })) => match self.waiting_to_be_completed.remove(&packet_identifier) {
+                Some((ack_sender, _)) => {
+                    packet_identifiers.discard(packet_identifier);
+                    self.remove_stored(packet_identifier);
 
-                        match ack_sender.send(()) {
+                    match ack_sender.send(()) {
 						Ok(()) => (),
 						Err(()) => log::debug!("could not send ack for publish request because ack receiver has been dropped"),
 					}
-                    }
-                    None => log::warn!("ignoring PUBCOMP for a PUBREL we never sent"),
                 }
-            }
+                None => log::warn!("ignoring PUBCOMP for a PUBREL we never sent"),
+            },
 
             Some(crate::proto::Packet::Publish(crate::proto::Publish {
                 packet_identifier_dup_qos,
                 retain,
                 topic_name,
                 payload,
+                ..
             })) => match packet_identifier_dup_qos {
                 crate::proto::PacketIdentifierDupQoS::AtMostOnce => {
                     publication_received = Some(crate::ReceivedPublication {
@@ -86,9 +120,26 @@ impl State {
                         qos: crate::proto::QoS::AtMostOnce,
                         retain,
                         payload,
+                        ack: None,
                     });
                 }
 
+                crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, dup)
+                    if self.manual_acks.is_some() =>
+                {
+                    publication_received = self.wait_for_ack(
+                        packet_identifier,
+                        crate::ReceivedPublication {
+                            topic_name,
+                            dup,
+                            qos: crate::proto::QoS::AtLeastOnce,
+                            retain,
+                            payload,
+                            ack: None,
+                        },
+                    );
+                }
+
                 crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, dup) => {
                     publication_received = Some(crate::ReceivedPublication {
                         topic_name,
@@ -96,13 +147,37 @@ impl State {
                         qos: crate::proto::QoS::AtLeastOnce,
                         retain,
                         payload,
+                        ack: None,
                     });
 
                     packets_waiting_to_be_sent.push(crate::proto::Packet::PubAck(
-                        crate::proto::PubAck { packet_identifier },
+                        crate::proto::PubAck {
+                            packet_identifier,
+                            reason_code: crate::proto::ReasonCode::SUCCESS,
+                            properties: crate::proto::Properties::default(),
+                        },
                     ));
                 }
 
+                crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, dup)
+                    if self.manual_acks.is_some()
+                        && !self.waiting_to_be_released.contains_key(&packet_identifier) =>
+                {
+                    // The PUBREC is only sent once the application acknowledges the publication,
+                    // so the publication is given to the application right away.
+                    publication_received = self.wait_for_ack(
+                        packet_identifier,
+                        crate::ReceivedPublication {
+                            topic_name,
+                            dup,
+                            qos: crate::proto::QoS::ExactlyOnce,
+                            retain,
+                            payload,
+                            ack: None,
+                        },
+                    );
+                }
+
                 crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, dup) => {
                     match self.waiting_to_be_released.entry(packet_identifier) {
                         std::collections::btree_map::Entry::Occupied(_) =>
@@ -117,52 +192,130 @@ impl State {
                         std::collections::btree_map::Entry::Vacant(entry) => {
                             // ExactlyOnce publications should only be sent to the client when the corresponding PUBREL is received.
                             // Otherwise the server might send the PUBLISH again after a session reset and we would have no way of knowing we should ignore it.
-                            entry.insert(crate::ReceivedPublication {
+                            entry.insert(Some(crate::ReceivedPublication {
                                 topic_name,
                                 dup,
                                 qos: crate::proto::QoS::ExactlyOnce,
                                 retain,
                                 payload,
-                            });
+                                ack: None,
+                            }));
                         }
                     }
 
                     packets_waiting_to_be_sent.push(crate::proto::Packet::PubRec(
-                        crate::proto::PubRec { packet_identifier },
+                        crate::proto::PubRec {
+                            packet_identifier,
+                            reason_code: crate::proto::ReasonCode::SUCCESS,
+                            properties: crate::proto::Properties::default(),
+                        },
                     ));
                 }
             },
 
-            Some(crate::proto::Packet::PubRec(crate::proto::PubRec { packet_identifier })) => {
+            Some(crate::proto::Packet::PubRec(crate::proto::PubRec {
+                packet_identifier, ..
+            })) => {
                 match self.waiting_to_be_acked.remove(&packet_identifier) {
                     Some((ack_sender, packet)) => {
                         self.waiting_to_be_completed
                             .insert(packet_identifier, (ack_sender, packet));
+                        self.release_stored(packet_identifier);
                     }
                     None => log::warn!("ignoring PUBREC for a PUBLISH we never sent"),
                 }
 
                 packets_waiting_to_be_sent.push(crate::proto::Packet::PubRel(
-                    crate::proto::PubRel { packet_identifier },
+                    crate::proto::PubRel {
+                        packet_identifier,
+                        reason_code: crate::proto::ReasonCode::SUCCESS,
+                        properties: crate::proto::Properties::default(),
+                    },
                 ));
             }
 
-            Some(crate::proto::Packet::PubRel(crate::proto::PubRel { packet_identifier })) => {
+            Some(crate::proto::Packet::PubRel(crate::proto::PubRel {
+                packet_identifier, ..
+            })) => {
                 if let Some(publication) = self.waiting_to_be_released.remove(&packet_identifier) {
                     packet_identifiers.discard(packet_identifier);
-                    publication_received = Some(publication);
+                    publication_received = publication;
                 } else {
                     log::warn!("ignoring PUBREL for a PUBREC we never sent");
                 }
 
                 packets_waiting_to_be_sent.push(crate::proto::Packet::PubComp(
-                    crate::proto::PubComp { packet_identifier },
+                    crate::proto::PubComp {
+                        packet_identifier,
+                        reason_code: crate::proto::ReasonCode::SUCCESS,
+                        properties: crate::proto::Properties::default(),
+                    },
                 ));
             }
 
             other => *packet = other,
         }
 
+        if let Some(manual_acks) = &mut self.manual_acks {
+            while let std::task::Poll::Ready(Some(AckRequest {
+                packet_identifier,
+                session,
+                ack,
+            })) = std::pin::Pin::new(&mut manual_acks.ack_recv).poll_next(cx)
+            {
+                if session != manual_acks.session {
+                    log::debug!(
+                        "ignoring ack for publication {} from a session that has been reset",
+                        packet_identifier,
+                    );
+                    continue;
+                }
+
+                if !ack {
+                    // The application dropped the publication without acknowledging it.
+                    // The server redelivers it once the session is resumed on a new connection.
+                    if manual_acks.waiting_for_ack.contains_key(&packet_identifier) {
+                        manual_acks.dropped.insert(packet_identifier);
+                    }
+                    continue;
+                }
+
+                let qos = match manual_acks.waiting_for_ack.remove(&packet_identifier) {
+                    Some(qos) => qos,
+                    None => {
+                        log::warn!("ignoring ack for a publication we are not waiting on");
+                        continue;
+                    }
+                };
+
+                match qos {
+                    crate::proto::QoS::AtMostOnce => (),
+
+                    crate::proto::QoS::AtLeastOnce => {
+                        packets_waiting_to_be_sent.push(crate::proto::Packet::PubAck(
+                            crate::proto::PubAck {
+                                packet_identifier,
+                                reason_code: crate::proto::ReasonCode::SUCCESS,
+                                properties: crate::proto::Properties::default(),
+                            },
+                        ));
+                    }
+
+                    crate::proto::QoS::ExactlyOnce => {
+                        self.waiting_to_be_released.insert(packet_identifier, None);
+
+                        packets_waiting_to_be_sent.push(crate::proto::Packet::PubRec(
+                            crate::proto::PubRec {
+                                packet_identifier,
+                                reason_code: crate::proto::ReasonCode::SUCCESS,
+                                properties: crate::proto::Properties::default(),
+                            },
+                        ));
+                    }
+                }
+            }
+        }
+
         while let std::task::Poll::Ready(Some(publish_request)) =
             std::pin::Pin::new(&mut self.publish_request_recv).poll_next(cx)
         {
@@ -184,6 +337,7 @@ impl State {
                             retain: publication.retain,
                             topic_name: publication.topic_name,
                             payload: publication.payload,
+                            properties: publication.properties,
                         },
                     ));
 
@@ -206,33 +360,42 @@ impl State {
                         }
                     };
 
-                    let packet = crate::proto::Packet::Publish(crate::proto::Publish {
+                    let stored_packet = crate::proto::Publish {
                         packet_identifier_dup_qos:
                             crate::proto::PacketIdentifierDupQoS::AtLeastOnce(
                                 packet_identifier,
-                                false,
+                                true,
                             ),
                         retain: publication.retain,
                         topic_name: publication.topic_name.clone(),
                         payload: publication.payload.clone(),
+                        properties: publication.properties.clone(),
+                    };
+
+                    if let Err(err) = self.store(&stored_packet) {
+                        packet_identifiers.discard(packet_identifier);
+                        self.publish_requests_waiting_to_be_sent
+                            .push_front(PublishRequest {
+                                publication,
+                                ack_sender,
+                            });
+                        return Err(err);
+                    }
+
+                    let packet = crate::proto::Packet::Publish(crate::proto::Publish {
+                        packet_identifier_dup_qos:
+                            crate::proto::PacketIdentifierDupQoS::AtLeastOnce(
+                                packet_identifier,
+                                false,
+                            ),
+                        retain: publication.retain,
+                        topic_name: publication.topic_name,
+                        payload: publication.payload,
+                        properties: publication.properties,
                     });
 
-                    self.waiting_to_be_acked.insert(
-                        packet_identifier,
-                        (
-                            ack_sender,
-                            crate::proto::Publish {
-                                packet_identifier_dup_qos:
-                                    crate::proto::PacketIdentifierDupQoS::AtLeastOnce(
-                                        packet_identifier,
-                                        true,
-                                    ),
-                                retain: publication.retain,
-                                topic_name: publication.topic_name,
-                                payload: publication.payload,
-                            },
-                        ),
-                    );
+                    self.waiting_to_be_acked
+                        .insert(packet_identifier, (ack_sender, stored_packet));
 
                     packets_waiting_to_be_sent.push(packet);
                 }
@@ -250,33 +413,42 @@ impl State {
                         }
                     };
 
-                    let packet = crate::proto::Packet::Publish(crate::proto::Publish {
+                    let stored_packet = crate::proto::Publish {
                         packet_identifier_dup_qos:
                             crate::proto::PacketIdentifierDupQoS::ExactlyOnce(
                                 packet_identifier,
-                                false,
+                                true,
                             ),
                         retain: publication.retain,
                         topic_name: publication.topic_name.clone(),
                         payload: publication.payload.clone(),
+                        properties: publication.properties.clone(),
+                    };
+
+                    if let Err(err) = self.store(&stored_packet) {
+                        packet_identifiers.discard(packet_identifier);
+                        self.publish_requests_waiting_to_be_sent
+                            .push_front(PublishRequest {
+                                publication,
+                                ack_sender,
+                            });
+                        return Err(err);
+                    }
+
+                    let packet = crate::proto::Packet::Publish(crate::proto::Publish {
+                        packet_identifier_dup_qos:
+                            crate::proto::PacketIdentifierDupQoS::ExactlyOnce(
+                                packet_identifier,
+                                false,
+                            ),
+                        retain: publication.retain,
+                        topic_name: publication.topic_name,
+                        payload: publication.payload,
+                        properties: publication.properties,
                     });
 
-                    self.waiting_to_be_acked.insert(
-                        packet_identifier,
-                        (
-                            ack_sender,
-                            crate::proto::Publish {
-                                packet_identifier_dup_qos:
-                                    crate::proto::PacketIdentifierDupQoS::ExactlyOnce(
-                                        packet_identifier,
-                                        true,
-                                    ),
-                                retain: publication.retain,
-                                topic_name: publication.topic_name,
-                                payload: publication.payload,
-                            },
-                        ),
-                    );
+                    self.waiting_to_be_acked
+                        .insert(packet_identifier, (ack_sender, stored_packet));
 
                     packets_waiting_to_be_sent.push(packet);
                 }
@@ -291,8 +463,32 @@ impl State {
         reset_session: bool,
         packet_identifiers: &mut super::PacketIdentifiers,
     ) -> impl Iterator<Item = crate::proto::Packet> + 'a {
+        if let Some(manual_acks) = &mut self.manual_acks {
+            // The server redelivers the publications the application dropped, and the ones held back, if the session is resumed
+            for packet_identifier in std::mem::take(&mut manual_acks.dropped) {
+                manual_acks.waiting_for_ack.remove(&packet_identifier);
+            }
+            manual_acks.held_back.clear();
+        }
+
         if reset_session {
+            // The server does not expect acks for publications of the old session any more
+            if let Some(manual_acks) = &mut self.manual_acks {
+                manual_acks.waiting_for_ack.clear();
+                manual_acks.session += 1;
+            }
+
             // Move all waiting_to_be_completed back to waiting_to_be_acked since we must restart the ExactlyOnce protocol flow
+            if let Some(store) = &mut self.store {
+                for (_, packet) in self.waiting_to_be_completed.values() {
+                    if let Err(err) = store.insert(packet) {
+                        log::warn!(
+                            "could not restore PUBLISH packet in publication store: {}",
+                            err
+                        );
+                    }
+                }
+            }
             self.waiting_to_be_acked
                 .append(&mut self.waiting_to_be_completed);
 
@@ -309,13 +505,25 @@ impl State {
                 self.waiting_to_be_released
                     .keys()
                     .map(|&packet_identifier| {
-                        crate::proto::Packet::PubRec(crate::proto::PubRec { packet_identifier })
+                        crate::proto::Packet::PubRec(crate::proto::PubRec {
+                            packet_identifier,
+                            reason_code: crate::proto::ReasonCode::SUCCESS,
+                            properties: crate::proto::Properties::default(),
+                        })
                     }),
             )
             .chain(
+                // The server already received these, so the flow resumes with PUBREL rather than
+                // with a duplicate PUBLISH the server could deliver a second time.
                 self.waiting_to_be_completed
-                    .values()
-                    .map(|(_, packet)| crate::proto::Packet::Publish(packet.clone())),
+                    .keys()
+                    .map(|&packet_identifier| {
+                        crate::proto::Packet::PubRel(crate::proto::PubRel {
+                            packet_identifier,
+                            reason_code: crate::proto::ReasonCode::SUCCESS,
+                            properties: crate::proto::Properties::default(),
+                        })
+                    }),
             )
     }
 
@@ -342,6 +550,185 @@ impl State {
     pub(super) fn publish_handle(&self) -> PublishHandle {
         PublishHandle(self.publish_request_send.clone())
     }
+
+    /// Switches to manual acknowledgement mode.
+    pub(super) fn set_manual_acks(&mut self, receive_window: usize) {
+        let (ack_send, ack_recv) = futures_channel::mpsc::unbounded();
+
+        self.manual_acks = Some(ManualAcks {
+            ack_send,
+            ack_recv,
+            waiting_for_ack: Default::default(),
+            dropped: Default::default(),
+            receive_window: std::cmp::max(receive_window, 1),
+            held_back: Default::default(),
+            session: 0,
+        });
+    }
+
+    /// Whether so many received publications are waiting for the application to acknowledge them
+    /// that no more publications should be read from the server.
+    pub(super) fn is_receive_window_full(&self) -> bool {
+        self.manual_acks.as_ref().map_or(false, |manual_acks| {
+            manual_acks.waiting_for_ack.len() >= manual_acks.receive_window
+        })
+    }
+
+    /// Holds back a QoS 1 or QoS 2 PUBLISH packet read from the server while the receive window is full,
+    /// so that the client keeps reading the other packets the server sends, like the acks of its own publications.
+    ///
+    /// Returns the packet if it does not need to be held back.
+    pub(super) fn hold_back(
+        &mut self,
+        packet: crate::proto::Packet,
+    ) -> Option<crate::proto::Packet> {
+        let manual_acks = match &mut self.manual_acks {
+            Some(manual_acks) => manual_acks,
+            None => return Some(packet),
+        };
+
+        match packet {
+            crate::proto::Packet::Publish(publish)
+                if publish.packet_identifier_dup_qos
+                    != crate::proto::PacketIdentifierDupQoS::AtMostOnce
+                    && (manual_acks.waiting_for_ack.len() >= manual_acks.receive_window
+                        || !manual_acks.held_back.is_empty()) =>
+            {
+                manual_acks.held_back.push_back(publish);
+                None
+            }
+
+            packet => Some(packet),
+        }
+    }
+
+    /// Returns the PUBLISH packet that was held back the longest, once the receive window has room for it.
+    pub(super) fn next_held_back(&mut self) -> Option<crate::proto::Packet> {
+        if self.is_receive_window_full() {
+            return None;
+        }
+
+        let manual_acks = self.manual_acks.as_mut()?;
+        manual_acks
+            .held_back
+            .pop_front()
+            .map(crate::proto::Packet::Publish)
+    }
+
+    /// Attaches an ack to a publication received in manual acknowledgement mode.
+    ///
+    /// Returns `None` if the publication is a redelivery of one that the application has not acknowledged yet,
+    /// since the application already has it.
+    fn wait_for_ack(
+        &mut self,
+        packet_identifier: crate::proto::PacketIdentifier,
+        mut publication: crate::ReceivedPublication,
+    ) -> Option<crate::ReceivedPublication> {
+        let manual_acks = self
+            .manual_acks
+            .as_mut()
+            .expect("only called in manual acknowledgement mode");
+
+        match manual_acks.waiting_for_ack.entry(packet_identifier) {
+            std::collections::btree_map::Entry::Occupied(_) => {
+                log::debug!(
+                    "ignoring redelivered PUBLISH {} that is still waiting for an ack",
+                    packet_identifier,
+                );
+                None
+            }
+
+            std::collections::btree_map::Entry::Vacant(entry) => {
+                entry.insert(publication.qos);
+                publication.ack = Some(Ack {
+                    ack_send: Some(manual_acks.ack_send.clone()),
+                    packet_identifier,
+                    session: manual_acks.session,
+                });
+                Some(publication)
+            }
+        }
+    }
+
+    /// Persists PUBLISH packets in the given store from now on.
+    ///
+    /// Packets already in the store are queued to be redelivered with the DUP flag set, or released if the server
+    /// already received them, and their packet identifiers are reserved.
+    pub(super) fn set_store(
+        &mut self,
+        mut store: Box<dyn super::PublicationStore>,
+        packet_identifiers: &mut super::PacketIdentifiers,
+    ) -> Result<(), super::Error> {
+        for (mut packet, released) in store.load().map_err(super::Error::PublicationStore)? {
+            match &mut packet.packet_identifier_dup_qos {
+                crate::proto::PacketIdentifierDupQoS::AtMostOnce => {
+                    log::warn!("ignoring stored AtMostOnce PUBLISH packet")
+                }
+
+                crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, dup)
+                | crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, dup) => {
+                    let packet_identifier = *packet_identifier;
+                    *dup = true;
+
+                    // Nobody is waiting for the ack of a publication from a previous run of the client,
+                    // so the ack receiver is dropped right away.
+                    let (ack_sender, _) = futures_channel::oneshot::channel();
+                    if released {
+                        self.waiting_to_be_completed
+                            .insert(packet_identifier, (ack_sender, packet));
+                    } else {
+                        self.waiting_to_be_acked
+                            .insert(packet_identifier, (ack_sender, packet));
+                    }
+                }
+            }
+        }
+
+        // Restored in order, so that new packet identifiers continue after the highest stored one
+        let stored: std::collections::BTreeSet<_> = self
+            .waiting_to_be_acked
+            .keys()
+            .chain(self.waiting_to_be_completed.keys())
+            .copied()
+            .collect();
+        for packet_identifier in stored {
+            packet_identifiers.restore(packet_identifier);
+        }
+
+        self.store = Some(store);
+        Ok(())
+    }
+
+    fn store(&mut self, packet: &crate::proto::Publish) -> Result<(), super::Error> {
+        match &mut self.store {
+            Some(store) => store.insert(packet).map_err(super::Error::PublicationStore),
+            None => Ok(()),
+        }
+    }
+
+    fn release_stored(&mut self, packet_identifier: crate::proto::PacketIdentifier) {
+        if let Some(store) = &mut self.store {
+            if let Err(err) = store.release(packet_identifier) {
+                log::warn!(
+                    "could not mark PUBLISH packet {} as released in publication store: {}",
+                    packet_identifier,
+                    err,
+                );
+            }
+        }
+    }
+
+    fn remove_stored(&mut self, packet_identifier: crate::proto::PacketIdentifier) {
+        if let Some(store) = &mut self.store {
+            if let Err(err) = store.remove(packet_identifier) {
+                log::warn!(
+                    "could not remove PUBLISH packet {} from publication store: {}",
+                    packet_identifier,
+                    err,
+                );
+            }
+        }
+    }
 }
 
 impl Default for State {
@@ -356,6 +743,9 @@ impl Default for State {
             waiting_to_be_acked: Default::default(),
             waiting_to_be_released: Default::default(),
             waiting_to_be_completed: Default::default(),
+
+            store: None,
+            manual_acks: None,
         }
     }
 }
@@ -386,6 +776,76 @@ impl PublishHandle {
     }
 }
 
+/// Acknowledges a publication received in manual acknowledgement mode.
+///
+/// See [`crate::Client::with_manual_acks`].
+#[derive(Debug)]
+pub struct Ack {
+    ack_send: Option<futures_channel::mpsc::UnboundedSender<AckRequest>>,
+    packet_identifier: crate::proto::PacketIdentifier,
+    session: u64,
+}
+
+impl Ack {
+    /// Tells the client that the publication has been processed, so it sends the PUBACK or PUBREC to the server.
+    pub fn ack(mut self) -> Result<(), AckError> {
+        self.send(true)
+    }
+
+    fn send(&mut self, ack: bool) -> Result<(), AckError> {
+        match self.ack_send.take() {
+            Some(ack_send) => ack_send
+                .unbounded_send(AckRequest {
+                    packet_identifier: self.packet_identifier,
+                    session: self.session,
+                    ack,
+                })
+                .map_err(|_| AckError::ClientDoesNotExist),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Ack {
+    fn drop(&mut self) {
+        // Leaves the publication unacknowledged, so that it is given to the application again once the server redelivers it.
+        // Nothing to do if the client does not exist any more.
+        let _ = self.send(false);
+    }
+}
+
+impl PartialEq for Ack {
+    fn eq(&self, other: &Self) -> bool {
+        self.packet_identifier == other.packet_identifier && self.session == other.session
+    }
+}
+
+impl Eq for Ack {}
+
+#[derive(Clone, Copy, Debug)]
+struct AckRequest {
+    packet_identifier: crate::proto::PacketIdentifier,
+    session: u64,
+
+    /// False if the application dropped the publication without acknowledging it
+    ack: bool,
+}
+
+#[derive(Debug)]
+pub enum AckError {
+    ClientDoesNotExist,
+}
+
+impl std::fmt::Display for AckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AckError::ClientDoesNotExist => write!(f, "client does not exist"),
+        }
+    }
+}
+
+impl std::error::Error for AckError {}
+
 #[derive(Debug)]
 pub enum PublishError {
     ClientDoesNotExist,
@@ -432,11 +892,12 @@ impl PublishRequest {
             retain: publication.retain,
             topic_name: publication.topic_name,
             payload: publication.payload,
+            properties: publication.properties,
         };
 
         let mut counter = crate::proto::ByteCounter::new();
         let encode_result = packet
-            .encode(&mut counter)
+            .encode(&mut counter, crate::PROTOCOL_LEVEL)
             .and_then(|()| crate::proto::encode_remaining_length(counter.0, &mut counter));
 
         let publication = crate::proto::Publication {
@@ -444,6 +905,7 @@ impl PublishRequest {
             qos: publication.qos,
             retain: publication.retain,
             payload: packet.payload,
+            properties: packet.properties,
         };
 
         match encode_result {
@@ -455,3 +917,191 @@ impl PublishRequest {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::client::publish::State;
+    use crate::client::PacketIdentifiers;
+    use crate::proto::{
+        Packet, PacketIdentifier, PacketIdentifierDupQoS, Properties, PubAck, PubComp, PubRec,
+        PubRel, Publish, ReasonCode,
+    };
+
+    fn publish(packet_identifier_dup_qos: PacketIdentifierDupQoS) -> Option<Packet> {
+        Some(Packet::Publish(Publish {
+            packet_identifier_dup_qos,
+            retain: false,
+            topic_name: "topic".to_owned(),
+            payload: "payload".into(),
+            properties: Properties::default(),
+        }))
+    }
+
+    fn poll(
+        state: &mut State,
+        mut packet: Option<Packet>,
+    ) -> (Vec<Packet>, Option<crate::ReceivedPublication>) {
+        let mut cx = std::task::Context::from_waker(futures_util::task::noop_waker_ref());
+        let mut packet_identifiers: PacketIdentifiers = Default::default();
+        state
+            .poll(&mut cx, &mut packet, &mut packet_identifiers)
+            .unwrap()
+    }
+
+    #[test]
+    fn manual_acks_at_least_once() {
+        let packet_identifier = PacketIdentifier::new(1).unwrap();
+        let mut state: State = Default::default();
+        state.set_manual_acks(1);
+
+        let (packets, publication) = poll(
+            &mut state,
+            publish(PacketIdentifierDupQoS::AtLeastOnce(
+                packet_identifier,
+                false,
+            )),
+        );
+        assert!(packets.is_empty());
+        assert!(state.is_receive_window_full());
+
+        // Redelivery of a publication the application has not acknowledged yet is not given to it again
+        let (packets, redelivered) = poll(
+            &mut state,
+            publish(PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, true)),
+        );
+        assert!(packets.is_empty());
+        assert!(redelivered.is_none());
+
+        publication.unwrap().ack.unwrap().ack().unwrap();
+
+        let (packets, _) = poll(&mut state, None);
+        assert_eq!(
+            packets,
+            vec![Packet::PubAck(PubAck {
+                packet_identifier,
+                reason_code: ReasonCode::SUCCESS,
+                properties: Properties::default(),
+            })]
+        );
+        assert!(!state.is_receive_window_full());
+    }
+
+    #[test]
+    fn manual_acks_exactly_once() {
+        let packet_identifier = PacketIdentifier::new(1).unwrap();
+        let mut state: State = Default::default();
+        state.set_manual_acks(10);
+
+        let (packets, publication) = poll(
+            &mut state,
+            publish(PacketIdentifierDupQoS::ExactlyOnce(
+                packet_identifier,
+                false,
+            )),
+        );
+        assert!(packets.is_empty());
+
+        publication.unwrap().ack.unwrap().ack().unwrap();
+
+        let (packets, _) = poll(&mut state, None);
+        assert_eq!(
+            packets,
+            vec![Packet::PubRec(PubRec {
+                packet_identifier,
+                reason_code: ReasonCode::SUCCESS,
+                properties: Properties::default(),
+            })]
+        );
+
+        // The publication was already given to the application, so PUBREL only completes the flow
+        let (packets, publication) = poll(
+            &mut state,
+            Some(Packet::PubRel(PubRel {
+                packet_identifier,
+                reason_code: ReasonCode::SUCCESS,
+                properties: Properties::default(),
+            })),
+        );
+        assert_eq!(
+            packets,
+            vec![Packet::PubComp(PubComp {
+                packet_identifier,
+                reason_code: ReasonCode::SUCCESS,
+                properties: Properties::default(),
+            })]
+        );
+        assert!(publication.is_none());
+    }
+
+    #[test]
+    fn manual_acks_hold_back_publications_while_window_is_full() {
+        let mut state: State = Default::default();
+        state.set_manual_acks(1);
+
+        let (_, publication) = poll(
+            &mut state,
+            publish(PacketIdentifierDupQoS::AtLeastOnce(
+                PacketIdentifier::new(1).unwrap(),
+                false,
+            )),
+        );
+        assert!(state.is_receive_window_full());
+
+        // Further publications are held back, but acks of the client's own publications are not
+        let held_back = publish(PacketIdentifierDupQoS::ExactlyOnce(
+            PacketIdentifier::new(2).unwrap(),
+            false,
+        ));
+        assert_eq!(state.hold_back(held_back.clone().unwrap()), None);
+        let puback = Packet::PubAck(PubAck {
+            packet_identifier: PacketIdentifier::new(3).unwrap(),
+            reason_code: ReasonCode::SUCCESS,
+            properties: Properties::default(),
+        });
+        assert_eq!(state.hold_back(puback.clone()), Some(puback));
+        let at_most_once = publish(PacketIdentifierDupQoS::AtMostOnce).unwrap();
+        assert_eq!(state.hold_back(at_most_once.clone()), Some(at_most_once));
+        assert_eq!(state.next_held_back(), None);
+
+        publication.unwrap().ack.unwrap().ack().unwrap();
+        let _ = poll(&mut state, None);
+
+        assert_eq!(state.next_held_back(), held_back);
+        assert_eq!(state.next_held_back(), None);
+    }
+
+    #[test]
+    fn manual_acks_dropped_ack() {
+        let packet_identifier = PacketIdentifier::new(1).unwrap();
+        let mut state: State = Default::default();
+        state.set_manual_acks(1);
+
+        let (_, publication) = poll(
+            &mut state,
+            publish(PacketIdentifierDupQoS::AtLeastOnce(
+                packet_identifier,
+                false,
+            )),
+        );
+        drop(publication);
+
+        // The publication keeps its slot in the receive window, like it stays inflight in the server
+        let (packets, _) = poll(&mut state, None);
+        assert!(packets.is_empty());
+        assert!(state.is_receive_window_full());
+
+        let mut packet_identifiers: PacketIdentifiers = Default::default();
+        let packets: Vec<_> = state
+            .new_connection(false, &mut packet_identifiers)
+            .collect();
+        assert!(packets.is_empty());
+        assert!(!state.is_receive_window_full());
+
+        // The server redelivers the publication, which is given to the application again
+        let (_, redelivered) = poll(
+            &mut state,
+            publish(PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, true)),
+        );
+        assert!(redelivered.unwrap().dup);
+    }
+}
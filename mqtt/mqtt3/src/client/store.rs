@@ -0,0 +1,301 @@
+/// Persists the QoS 1 and QoS 2 PUBLISH packets sent by a [`crate::Client`] until the server acknowledges them.
+///
+/// The client inserts every such packet before sending it for the first time, marks a QoS 2 packet as released once
+/// the server sent PUBREC for it, and removes the packet once the corresponding PUBACK or PUBCOMP is received.
+/// The packet identifier of a stored packet stays reserved for as long as the packet is in the store.
+///
+/// When a client is given a store that already contains packets, for example because the process restarted before
+/// the server acknowledged them, the client redelivers them as soon as it is connected. Packets the server has not
+/// received yet are sent again with the DUP flag set, and released packets are resumed with PUBREL, so that the server
+/// does not get a QoS 2 publication twice.
+///
+/// The client calls the store from [`crate::Client`]'s `poll_next`, so a store that does blocking I/O blocks the task
+/// polling the client until the I/O completes.
+pub trait PublicationStore: std::fmt::Debug + Send {
+    /// Returns all PUBLISH packets in the store, each with whether it was released.
+    fn load(&mut self) -> std::io::Result<Vec<(crate::proto::Publish, bool)>>;
+
+    /// Stores a PUBLISH packet, replacing any stored packet with the same packet identifier.
+    fn insert(&mut self, publish: &crate::proto::Publish) -> std::io::Result<()>;
+
+    /// Marks the stored QoS 2 PUBLISH packet with the given packet identifier as released, i.e. received by the server.
+    fn release(&mut self, packet_identifier: crate::proto::PacketIdentifier)
+        -> std::io::Result<()>;
+
+    /// Removes the PUBLISH packet with the given packet identifier from the store, if it exists.
+    fn remove(&mut self, packet_identifier: crate::proto::PacketIdentifier) -> std::io::Result<()>;
+}
+
+/// A [`PublicationStore`] that keeps each PUBLISH packet in its own file in a directory.
+///
+/// Packets are saved in the MQTT 5 wire format so that their properties are preserved, and a released packet is
+/// followed by the PUBREL packet the client resumes its flow with. A file is written completely and synced, together
+/// with the directory, before it replaces the previous one, so a crash never leaves a partial packet behind nor brings
+/// back a packet which was already released.
+///
+/// All file operations are synchronous, so the store blocks the task polling the client while it writes a packet.
+#[derive(Debug)]
+pub struct FilePublicationStore {
+    dir: std::path::PathBuf,
+}
+
+impl FilePublicationStore {
+    const EXTENSION: &'static str = "publish";
+
+    /// Opens the store in the given directory, creating the directory if it does not exist.
+    pub fn open(dir: impl Into<std::path::PathBuf>) -> std::io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(FilePublicationStore { dir })
+    }
+
+    fn path(&self, packet_identifier: crate::proto::PacketIdentifier) -> std::path::PathBuf {
+        self.dir
+            .join(format!("{}.{}", packet_identifier.get(), Self::EXTENSION))
+    }
+
+    fn write(
+        &self,
+        packet_identifier: crate::proto::PacketIdentifier,
+        bytes: &[u8],
+    ) -> std::io::Result<()> {
+        use std::io::Write;
+
+        let path = self.path(packet_identifier);
+        let temp_path = path.with_extension("tmp");
+
+        let mut file = std::fs::File::create(&temp_path)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        std::fs::rename(temp_path, path)?;
+
+        // The rename is only durable once the directory is synced as well
+        #[cfg(unix)]
+        std::fs::File::open(&self.dir)?.sync_all()?;
+
+        Ok(())
+    }
+}
+
+impl PublicationStore for FilePublicationStore {
+    fn load(&mut self) -> std::io::Result<Vec<(crate::proto::Publish, bool)>> {
+        use tokio_util::codec::Decoder;
+
+        let mut publications = vec![];
+
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .map_or(true, |extension| extension != Self::EXTENSION)
+            {
+                continue;
+            }
+
+            let mut src = bytes::BytesMut::from(&std::fs::read(&path)?[..]);
+            let mut codec = crate::proto::PacketCodec::new(crate::PROTOCOL_LEVEL_V5);
+            let invalid_data = |message: String| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{} {}", path.display(), message),
+                )
+            };
+
+            let publish = match codec.decode(&mut src) {
+                Ok(Some(crate::proto::Packet::Publish(publish))) => publish,
+                Ok(_) => {
+                    return Err(invalid_data(
+                        "does not contain a PUBLISH packet".to_string(),
+                    ))
+                }
+                Err(err) => return Err(invalid_data(format!("could not be decoded: {}", err))),
+            };
+
+            let released = match codec.decode(&mut src) {
+                Ok(Some(crate::proto::Packet::PubRel(pubrel)))
+                    if Some(pubrel.packet_identifier) == packet_identifier(&publish) =>
+                {
+                    true
+                }
+                Ok(None) => false,
+                Ok(_) => {
+                    return Err(invalid_data(
+                        "contains a packet other than the PUBREL of its PUBLISH packet".to_string(),
+                    ))
+                }
+                Err(err) => return Err(invalid_data(format!("could not be decoded: {}", err))),
+            };
+
+            publications.push((publish, released));
+        }
+
+        Ok(publications)
+    }
+
+    fn insert(&mut self, publish: &crate::proto::Publish) -> std::io::Result<()> {
+        use tokio_util::codec::Encoder;
+
+        let packet_identifier = match packet_identifier(publish) {
+            Some(packet_identifier) => packet_identifier,
+            None => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "AtMostOnce PUBLISH packets cannot be stored",
+                ))
+            }
+        };
+
+        let mut bytes = bytes::BytesMut::new();
+        crate::proto::PacketCodec::new(crate::PROTOCOL_LEVEL_V5)
+            .encode(crate::proto::Packet::Publish(publish.clone()), &mut bytes)
+            .map_err(|err| {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, err.to_string())
+            })?;
+
+        self.write(packet_identifier, &bytes)
+    }
+
+    fn release(
+        &mut self,
+        packet_identifier: crate::proto::PacketIdentifier,
+    ) -> std::io::Result<()> {
+        use tokio_util::codec::Encoder;
+
+        let mut bytes = bytes::BytesMut::from(&std::fs::read(self.path(packet_identifier))?[..]);
+        crate::proto::PacketCodec::new(crate::PROTOCOL_LEVEL_V5)
+            .encode(
+                crate::proto::Packet::PubRel(crate::proto::PubRel {
+                    packet_identifier,
+                    reason_code: crate::proto::ReasonCode::SUCCESS,
+                    properties: crate::proto::Properties::default(),
+                }),
+                &mut bytes,
+            )
+            .map_err(|err| {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, err.to_string())
+            })?;
+
+        self.write(packet_identifier, &bytes)
+    }
+
+    fn remove(&mut self, packet_identifier: crate::proto::PacketIdentifier) -> std::io::Result<()> {
+        match std::fs::remove_file(self.path(packet_identifier)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn packet_identifier(publish: &crate::proto::Publish) -> Option<crate::proto::PacketIdentifier> {
+    match publish.packet_identifier_dup_qos {
+        crate::proto::PacketIdentifierDupQoS::AtMostOnce => None,
+        crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, _)
+        | crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, _) => {
+            Some(packet_identifier)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::store::{FilePublicationStore, PublicationStore};
+    use crate::client::{publish, PacketIdentifiers};
+    use crate::proto::{
+        Packet, PacketIdentifier, PacketIdentifierDupQoS, Properties, PubRel, Publish, QoS,
+        ReasonCode,
+    };
+
+    fn publish_packet(packet_identifier: u16, qos: QoS) -> Publish {
+        let packet_identifier = PacketIdentifier::new(packet_identifier).unwrap();
+        let packet_identifier_dup_qos = match qos {
+            QoS::AtMostOnce => PacketIdentifierDupQoS::AtMostOnce,
+            QoS::AtLeastOnce => PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, false),
+            QoS::ExactlyOnce => PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, false),
+        };
+
+        Publish {
+            packet_identifier_dup_qos,
+            retain: false,
+            topic_name: format!("topic/{}", packet_identifier),
+            payload: "payload".into(),
+            properties: Properties::default(),
+        }
+    }
+
+    #[test]
+    fn file_store_persists_publications() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut store = FilePublicationStore::open(dir.path()).unwrap();
+        store.insert(&publish_packet(1, QoS::AtLeastOnce)).unwrap();
+        store.insert(&publish_packet(2, QoS::ExactlyOnce)).unwrap();
+        store.insert(&publish_packet(3, QoS::AtLeastOnce)).unwrap();
+        store.remove(PacketIdentifier::new(2).unwrap()).unwrap();
+        store.remove(PacketIdentifier::new(4).unwrap()).unwrap();
+        assert!(store.insert(&publish_packet(5, QoS::AtMostOnce)).is_err());
+        store.insert(&publish_packet(6, QoS::ExactlyOnce)).unwrap();
+        store.release(PacketIdentifier::new(6).unwrap()).unwrap();
+        assert!(store.release(PacketIdentifier::new(8).unwrap()).is_err());
+
+        let mut store = FilePublicationStore::open(dir.path()).unwrap();
+        let mut publications = store.load().unwrap();
+        publications.sort_by(|(p1, _), (p2, _)| p1.topic_name.cmp(&p2.topic_name));
+        assert_eq!(
+            publications,
+            vec![
+                (publish_packet(1, QoS::AtLeastOnce), false),
+                (publish_packet(3, QoS::AtLeastOnce), false),
+                (publish_packet(6, QoS::ExactlyOnce), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn stored_publications_are_redelivered_as_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut store = FilePublicationStore::open(dir.path()).unwrap();
+        store.insert(&publish_packet(7, QoS::AtLeastOnce)).unwrap();
+        store.insert(&publish_packet(3, QoS::ExactlyOnce)).unwrap();
+        store.insert(&publish_packet(5, QoS::ExactlyOnce)).unwrap();
+        store.release(PacketIdentifier::new(5).unwrap()).unwrap();
+
+        let mut state: publish::State = Default::default();
+        let mut packet_identifiers: PacketIdentifiers = Default::default();
+        state
+            .set_store(Box::new(store), &mut packet_identifiers)
+            .unwrap();
+
+        let packets: Vec<_> = state
+            .new_connection(false, &mut packet_identifiers)
+            .collect();
+        assert_eq!(
+            packets,
+            vec![
+                Packet::Publish(Publish {
+                    packet_identifier_dup_qos: PacketIdentifierDupQoS::ExactlyOnce(
+                        PacketIdentifier::new(3).unwrap(),
+                        true
+                    ),
+                    ..publish_packet(3, QoS::ExactlyOnce)
+                }),
+                Packet::Publish(Publish {
+                    packet_identifier_dup_qos: PacketIdentifierDupQoS::AtLeastOnce(
+                        PacketIdentifier::new(7).unwrap(),
+                        true
+                    ),
+                    ..publish_packet(7, QoS::AtLeastOnce)
+                }),
+                // Released publications resume with PUBREL instead of being published again
+                Packet::PubRel(PubRel {
+                    packet_identifier: PacketIdentifier::new(5).unwrap(),
+                    reason_code: ReasonCode::SUCCESS,
+                    properties: Properties::default(),
+                }),
+            ]
+        );
+
+        // Packet identifiers of stored publications stay reserved
+        assert_eq!(packet_identifiers.reserve().unwrap().get(), 8);
+    }
+}
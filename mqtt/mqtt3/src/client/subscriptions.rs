@@ -32,6 +32,7 @@ impl State {
             Some(crate::proto::Packet::SubAck(crate::proto::SubAck {
                 packet_identifier,
                 qos,
+                ..
             })) => match self.subscription_updates_waiting_to_be_acked.pop_front() {
                 Some((
                     packet_identifier_waiting_to_be_acked,
@@ -146,57 +147,58 @@ impl State {
                 }
             },
 
-            Some(crate::proto::Packet::UnsubAck(crate::proto::UnsubAck { packet_identifier })) => {
-                match self.subscription_updates_waiting_to_be_acked.pop_front() {
-                    Some((
-                        packet_identifier_waiting_to_be_acked,
-                        BatchedSubscriptionUpdate::Unsubscribe(unsubscribe_from),
-                    )) => {
-                        if packet_identifier != packet_identifier_waiting_to_be_acked {
-                            self.subscription_updates_waiting_to_be_acked.push_front((
-                                packet_identifier_waiting_to_be_acked,
-                                BatchedSubscriptionUpdate::Unsubscribe(unsubscribe_from),
-                            ));
-                            return Err(super::Error::UnexpectedUnsubAck(
-                                packet_identifier,
-                                super::UnexpectedSubUnsubAckReason::Expected(
-                                    packet_identifier_waiting_to_be_acked,
-                                ),
-                            ));
-                        }
-
-                        packet_identifiers.discard(packet_identifier);
-
-                        for topic_filter in unsubscribe_from {
-                            log::debug!("Unsubscribed from {}", topic_filter);
-                            self.subscriptions.remove(&topic_filter);
-                            subscription_updates
-                                .push(super::SubscriptionUpdateEvent::Unsubscribe(topic_filter));
-                        }
-                    }
-
-                    Some((
-                        packet_identifier_waiting_to_be_acked,
-                        subscribe @ BatchedSubscriptionUpdate::Subscribe(_),
-                    )) => {
-                        self.subscription_updates_waiting_to_be_acked
-                            .push_front((packet_identifier_waiting_to_be_acked, subscribe));
+            Some(crate::proto::Packet::UnsubAck(crate::proto::UnsubAck {
+                packet_identifier,
+                ..
+            })) => match self.subscription_updates_waiting_to_be_acked.pop_front() {
+                Some((
+                    packet_identifier_waiting_to_be_acked,
+                    BatchedSubscriptionUpdate::Unsubscribe(unsubscribe_from),
+                )) => {
+                    if packet_identifier != packet_identifier_waiting_to_be_acked {
+                        self.subscription_updates_waiting_to_be_acked.push_front((
+                            packet_identifier_waiting_to_be_acked,
+                            BatchedSubscriptionUpdate::Unsubscribe(unsubscribe_from),
+                        ));
                         return Err(super::Error::UnexpectedUnsubAck(
                             packet_identifier,
-                            super::UnexpectedSubUnsubAckReason::ExpectedSubAck(
+                            super::UnexpectedSubUnsubAckReason::Expected(
                                 packet_identifier_waiting_to_be_acked,
                             ),
                         ));
                     }
 
-                    None => {
-                        return Err(super::Error::UnexpectedUnsubAck(
-                            packet_identifier,
-                            super::UnexpectedSubUnsubAckReason::DidNotExpect,
-                        ))
+                    packet_identifiers.discard(packet_identifier);
+
+                    for topic_filter in unsubscribe_from {
+                        log::debug!("Unsubscribed from {}", topic_filter);
+                        self.subscriptions.remove(&topic_filter);
+                        subscription_updates
+                            .push(super::SubscriptionUpdateEvent::Unsubscribe(topic_filter));
                     }
                 }
-            }
+
+                Some((
+                    packet_identifier_waiting_to_be_acked,
+                    subscribe @ BatchedSubscriptionUpdate::Subscribe(_),
+                )) => {
+                    self.subscription_updates_waiting_to_be_acked
+                        .push_front((packet_identifier_waiting_to_be_acked, subscribe));
+                    return Err(super::Error::UnexpectedUnsubAck(
+                        packet_identifier,
+                        super::UnexpectedSubUnsubAckReason::ExpectedSubAck(
+                            packet_identifier_waiting_to_be_acked,
+                        ),
+                    ));
+                }
+
+                None => {
+                    return Err(super::Error::UnexpectedUnsubAck(
+                        packet_identifier,
+                        super::UnexpectedSubUnsubAckReason::DidNotExpect,
+                    ))
+                }
+            },
 
             other => *packet = other,
         }
@@ -294,6 +296,7 @@ impl State {
                         let mut packet = crate::proto::Subscribe {
                             packet_identifier,
                             subscribe_to: vec![],
+                            properties: crate::proto::Properties::default(),
                         };
 
                         while let Some(subscribe_to) = pending_subscriptions.pop_front() {
@@ -341,6 +344,7 @@ impl State {
                         let mut packet = crate::proto::Unsubscribe {
                             packet_identifier,
                             unsubscribe_from: vec![],
+                            properties: crate::proto::Properties::default(),
                         };
 
                         while let Some(unsubscribe_from) = pending_unsubscriptions.pop_front() {
@@ -448,6 +452,7 @@ impl State {
                     crate::proto::Subscribe {
                         packet_identifier,
                         subscribe_to: subscriptions_waiting_to_be_acked,
+                        properties: crate::proto::Properties::default(),
                     },
                 )))
             }
@@ -462,6 +467,7 @@ impl State {
                             crate::proto::Packet::Subscribe(crate::proto::Subscribe {
                                 packet_identifier: *packet_identifier,
                                 subscribe_to: subscribe_to.clone(),
+                                properties: crate::proto::Properties::default(),
                             })
                         }
 
@@ -469,6 +475,7 @@ impl State {
                             crate::proto::Packet::Unsubscribe(crate::proto::Unsubscribe {
                                 packet_identifier: *packet_identifier,
                                 unsubscribe_from: unsubscribe_from.clone(),
+                                properties: crate::proto::Properties::default(),
                             })
                         }
                     },
@@ -534,6 +541,7 @@ impl SubscriptionUpdate {
         let mut packet = crate::proto::Subscribe {
             packet_identifier: crate::proto::PacketIdentifier::max_value(),
             subscribe_to: vec![],
+            properties: crate::proto::Properties::default(),
         };
 
         let subscribe_to = match try_append_subscription(&mut packet, subscribe_to) {
@@ -557,6 +565,7 @@ impl SubscriptionUpdate {
         let mut packet = crate::proto::Unsubscribe {
             packet_identifier: crate::proto::PacketIdentifier::max_value(),
             unsubscribe_from: vec![],
+            properties: crate::proto::Properties::default(),
         };
 
         let unsubscribe_from = match try_append_unsubscription(&mut packet, unsubscribe_from) {
@@ -677,7 +686,7 @@ fn try_append_subscription(
     packet.subscribe_to.push(subscribe_to);
     let mut counter = crate::proto::ByteCounter::new();
     match packet
-        .encode(&mut counter)
+        .encode(&mut counter, crate::PROTOCOL_LEVEL)
         .and_then(|()| crate::proto::encode_remaining_length(counter.0, &mut counter))
     {
         Ok(_) => Ok(()),
@@ -703,7 +712,7 @@ fn try_append_unsubscription(
     packet.unsubscribe_from.push(unsubscribe_from);
     let mut counter = crate::proto::ByteCounter::new();
     match packet
-        .encode(&mut counter)
+        .encode(&mut counter, crate::PROTOCOL_LEVEL)
         .and_then(|()| crate::proto::encode_remaining_length(counter.0, &mut counter))
     {
         Ok(_) => Ok(()),
@@ -0,0 +1,89 @@
+/*!
+ * Ready-made [`crate::IoSource`] implementations.
+ *
+ * Each source is behind a cargo feature:
+ *
+ * | Source              | Feature     | Transport                                            |
+ * |---------------------|-------------|------------------------------------------------------|
+ * | [`TcpSource`]       | `tcp`       | Plain TCP                                            |
+ * | [`TlsSource`]       | `tls`       | TLS with optional custom CA and client certificate   |
+ * | [`WebSocketSource`] | `websocket` | WebSockets, secured with TLS for `wss://` URLs       |
+ * | [`UnixSource`]      | `uds`       | Unix domain sockets                                  |
+ *
+ * Sources resolve the address of the server again every time the client reconnects, so a server that moves to
+ * a new IP address is picked up on the next connection.
+ *
+ * The password of each connection is taken from a [`PasswordProvider`]. A static password can be given
+ * as an `Option<String>`, and [`SasToken`] (feature `sas`) generates a fresh shared access signature
+ * for every connection.
+ */
+
+#[cfg(feature = "sas")]
+mod sas;
+#[cfg(feature = "sas")]
+pub use sas::SasToken;
+
+#[cfg(feature = "tcp")]
+mod tcp;
+#[cfg(feature = "tcp")]
+pub use tcp::TcpSource;
+
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(feature = "tls")]
+pub use tls::{TlsConfig, TlsSource};
+
+#[cfg(all(unix, feature = "uds"))]
+mod unix;
+#[cfg(all(unix, feature = "uds"))]
+pub use unix::UnixSource;
+
+#[cfg(feature = "websocket")]
+mod websocket;
+#[cfg(feature = "websocket")]
+pub use websocket::{WebSocketSource, WsStream};
+
+/// The connection future of the sources in this module.
+pub type ConnectFuture<Io> = std::pin::Pin<
+    Box<dyn std::future::Future<Output = std::io::Result<(Io, Option<String>)>> + Send>,
+>;
+
+/// Provides the password for each new connection to the server.
+pub trait PasswordProvider: Send {
+    /// Returns the password to connect with, if any.
+    fn password(&mut self) -> std::io::Result<Option<String>>;
+}
+
+impl PasswordProvider for Option<String> {
+    fn password(&mut self) -> std::io::Result<Option<String>> {
+        Ok(self.clone())
+    }
+}
+
+impl PasswordProvider for String {
+    fn password(&mut self) -> std::io::Result<Option<String>> {
+        Ok(Some(self.clone()))
+    }
+}
+
+/// Returns the host part of a `host:port` address, without the brackets around an IPv6 address.
+#[cfg(feature = "tls")]
+fn host(address: &str) -> &str {
+    let host = match address.rfind(':') {
+        Some(index) if !address[index..].contains(']') => &address[..index],
+        _ => address,
+    };
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod tests {
+    #[test]
+    fn host() {
+        assert_eq!(super::host("example.com:8883"), "example.com");
+        assert_eq!(super::host("example.com"), "example.com");
+        assert_eq!(super::host("127.0.0.1:8883"), "127.0.0.1");
+        assert_eq!(super::host("[::1]:8883"), "::1");
+        assert_eq!(super::host("[::1]"), "::1");
+    }
+}
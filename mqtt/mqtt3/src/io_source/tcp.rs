@@ -0,0 +1,56 @@
+/// Connects to the server over plain TCP.
+pub struct TcpSource {
+    address: String,
+    password: Box<dyn super::PasswordProvider>,
+}
+
+impl TcpSource {
+    /// Creates a source for the server at the given `host:port` address.
+    pub fn new(address: impl Into<String>) -> Self {
+        TcpSource {
+            address: address.into(),
+            password: Box::new(None),
+        }
+    }
+
+    /// Connects with the passwords of the given provider.
+    pub fn with_password<P>(mut self, password: P) -> Self
+    where
+        P: super::PasswordProvider + 'static,
+    {
+        self.password = Box::new(password);
+        self
+    }
+}
+
+impl std::fmt::Debug for TcpSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TcpSource")
+            .field("address", &self.address)
+            .finish()
+    }
+}
+
+impl crate::IoSource for TcpSource {
+    type Io = tokio::net::TcpStream;
+    type Error = std::io::Error;
+    type Future = super::ConnectFuture<Self::Io>;
+
+    fn connect(&mut self) -> Self::Future {
+        let address = self.address.clone();
+        let password = self.password.password();
+
+        Box::pin(async move {
+            let password = password?;
+            let stream = connect(&address).await?;
+            Ok((stream, password))
+        })
+    }
+}
+
+/// Resolves the address and connects to the first of its IP addresses that accepts the connection.
+pub(super) async fn connect(address: &str) -> std::io::Result<tokio::net::TcpStream> {
+    let stream = tokio::net::TcpStream::connect(address).await?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
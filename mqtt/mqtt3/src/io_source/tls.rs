@@ -0,0 +1,132 @@
+/// TLS settings of a [`TlsSource`] or of a [`super::WebSocketSource`] with a `wss://` URL.
+///
+/// The server certificate is verified against the system trust store and any CA certificates given here.
+#[derive(Clone, Debug, Default)]
+pub struct TlsConfig {
+    ca_certificates: Option<Vec<u8>>,
+    client_certificate: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl TlsConfig {
+    /// Also trusts the PEM-encoded CA certificates.
+    pub fn with_ca_certificates(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.ca_certificates = Some(pem.into());
+        self
+    }
+
+    /// Authenticates with the PEM-encoded client certificate and private key.
+    ///
+    /// `certificate_chain` starts with the client certificate, optionally followed by intermediate certificates.
+    pub fn with_client_certificate(
+        mut self,
+        certificate_chain: impl Into<Vec<u8>>,
+        private_key: impl Into<Vec<u8>>,
+    ) -> Self {
+        self.client_certificate = Some((certificate_chain.into(), private_key.into()));
+        self
+    }
+
+    fn connector(&self) -> Result<openssl::ssl::SslConnector, openssl::error::ErrorStack> {
+        let mut builder = openssl::ssl::SslConnector::builder(openssl::ssl::SslMethod::tls())?;
+
+        if let Some(ca_certificates) = &self.ca_certificates {
+            for certificate in openssl::x509::X509::stack_from_pem(ca_certificates)? {
+                builder.cert_store_mut().add_cert(certificate)?;
+            }
+        }
+
+        if let Some((certificate_chain, private_key)) = &self.client_certificate {
+            let mut certificates =
+                openssl::x509::X509::stack_from_pem(certificate_chain)?.into_iter();
+            if let Some(certificate) = certificates.next() {
+                builder.set_certificate(&certificate)?;
+            }
+            for certificate in certificates {
+                builder.add_extra_chain_cert(certificate)?;
+            }
+
+            let private_key = openssl::pkey::PKey::private_key_from_pem(private_key)?;
+            builder.set_private_key(&private_key)?;
+            builder.check_private_key()?;
+        }
+
+        Ok(builder.build())
+    }
+
+    /// Creates the configuration of a new connection. Certificates are loaded anew for every connection.
+    pub(super) fn configure(&self) -> std::io::Result<openssl::ssl::ConnectConfiguration> {
+        self.connector()
+            .and_then(|connector| connector.configure())
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))
+    }
+}
+
+/// Connects to the server over TLS.
+pub struct TlsSource {
+    address: String,
+    config: TlsConfig,
+    password: Box<dyn super::PasswordProvider>,
+}
+
+impl TlsSource {
+    /// Creates a source for the server at the given `host:port` address.
+    /// The host is also the name the server certificate is verified against.
+    pub fn new(address: impl Into<String>, config: TlsConfig) -> Self {
+        TlsSource {
+            address: address.into(),
+            config,
+            password: Box::new(None),
+        }
+    }
+
+    /// Connects with the passwords of the given provider.
+    pub fn with_password<P>(mut self, password: P) -> Self
+    where
+        P: super::PasswordProvider + 'static,
+    {
+        self.password = Box::new(password);
+        self
+    }
+}
+
+impl std::fmt::Debug for TlsSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsSource")
+            .field("address", &self.address)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl crate::IoSource for TlsSource {
+    type Io = tokio_openssl::SslStream<tokio::net::TcpStream>;
+    type Error = std::io::Error;
+    type Future = super::ConnectFuture<Self::Io>;
+
+    fn connect(&mut self) -> Self::Future {
+        let address = self.address.clone();
+        let configuration = self.config.configure();
+        let password = self.password.password();
+
+        Box::pin(async move {
+            let configuration = configuration?;
+            let password = password?;
+            let stream = super::tcp::connect(&address).await?;
+            let stream = handshake(configuration, super::host(&address), stream).await?;
+            Ok((stream, password))
+        })
+    }
+}
+
+pub(super) async fn handshake<S>(
+    configuration: openssl::ssl::ConnectConfiguration,
+    domain: &str,
+    stream: S,
+) -> std::io::Result<tokio_openssl::SslStream<S>>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + std::fmt::Debug + Unpin,
+{
+    tokio_openssl::connect(configuration, domain, stream)
+        .await
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::ConnectionAborted, err.to_string()))
+}
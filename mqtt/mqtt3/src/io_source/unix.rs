@@ -0,0 +1,49 @@
+/// Connects to the server over a Unix domain socket.
+pub struct UnixSource {
+    path: std::path::PathBuf,
+    password: Box<dyn super::PasswordProvider>,
+}
+
+impl UnixSource {
+    /// Creates a source for the server listening on the socket at the given path.
+    pub fn new(path: impl Into<std::path::PathBuf>) -> Self {
+        UnixSource {
+            path: path.into(),
+            password: Box::new(None),
+        }
+    }
+
+    /// Connects with the passwords of the given provider.
+    pub fn with_password<P>(mut self, password: P) -> Self
+    where
+        P: super::PasswordProvider + 'static,
+    {
+        self.password = Box::new(password);
+        self
+    }
+}
+
+impl std::fmt::Debug for UnixSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UnixSource")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl crate::IoSource for UnixSource {
+    type Io = tokio::net::UnixStream;
+    type Error = std::io::Error;
+    type Future = super::ConnectFuture<Self::Io>;
+
+    fn connect(&mut self) -> Self::Future {
+        let path = self.path.clone();
+        let password = self.password.password();
+
+        Box::pin(async move {
+            let password = password?;
+            let stream = tokio::net::UnixStream::connect(path).await?;
+            Ok((stream, password))
+        })
+    }
+}
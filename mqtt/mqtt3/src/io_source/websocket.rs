@@ -0,0 +1,347 @@
+/// [MQTT-6.0.0-3] The Client MUST include "mqtt" in the list of WebSocket Sub Protocols it offers.
+const MQTT_SUBPROTOCOL: &str = "mqtt";
+
+/// Connects to the server over a WebSocket, secured with TLS for `wss://` URLs.
+pub struct WebSocketSource {
+    url: String,
+    config: super::TlsConfig,
+    password: Box<dyn super::PasswordProvider>,
+}
+
+impl WebSocketSource {
+    /// Creates a source for the given `ws://` or `wss://` URL, such as `wss://example.com:443/mqtt`.
+    pub fn new(url: impl Into<String>) -> Self {
+        WebSocketSource {
+            url: url.into(),
+            config: Default::default(),
+            password: Box::new(None),
+        }
+    }
+
+    /// Secures `wss://` connections with the given settings.
+    pub fn with_tls_config(mut self, config: super::TlsConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Connects with the passwords of the given provider.
+    pub fn with_password<P>(mut self, password: P) -> Self
+    where
+        P: super::PasswordProvider + 'static,
+    {
+        self.password = Box::new(password);
+        self
+    }
+}
+
+impl std::fmt::Debug for WebSocketSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebSocketSource")
+            .field("url", &self.url)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl crate::IoSource for WebSocketSource {
+    type Io = WsStream<MaybeTlsStream>;
+    type Error = std::io::Error;
+    type Future = super::ConnectFuture<Self::Io>;
+
+    fn connect(&mut self) -> Self::Future {
+        let target = Target::parse(&self.url);
+        let configuration = match &target {
+            Ok(target) if target.secure => self.config.configure().map(Some),
+            _ => Ok(None),
+        };
+        let password = self.password.password();
+
+        Box::pin(async move {
+            let target = target?;
+            let configuration = configuration?;
+            let password = password?;
+
+            let stream = super::tcp::connect(&target.address).await?;
+            let stream = match configuration {
+                Some(configuration) => MaybeTlsStream::Tls(
+                    super::tls::handshake(configuration, &target.host, stream).await?,
+                ),
+                None => MaybeTlsStream::Plain(stream),
+            };
+
+            let stream = handshake(target.request()?, stream).await?;
+            Ok((stream, password))
+        })
+    }
+}
+
+/// The server a WebSocket URL points to.
+#[derive(Debug)]
+struct Target {
+    secure: bool,
+    host: String,
+    address: String,
+    uri: tokio_tungstenite::tungstenite::http::Uri,
+}
+
+impl Target {
+    fn parse(url: &str) -> std::io::Result<Self> {
+        fn invalid(url: &str, reason: &str) -> std::io::Error {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid WebSocket URL {:?}: {}", url, reason),
+            )
+        }
+
+        let uri: tokio_tungstenite::tungstenite::http::Uri =
+            url.parse().map_err(|_| invalid(url, "malformed URL"))?;
+
+        let (secure, default_port) = match uri.scheme_str() {
+            Some("ws") => (false, 80),
+            Some("wss") => (true, 443),
+            _ => return Err(invalid(url, "scheme must be ws or wss")),
+        };
+        let host = uri
+            .host()
+            .ok_or_else(|| invalid(url, "missing host"))?
+            .to_owned();
+        let address = format!("{}:{}", host, uri.port_u16().unwrap_or(default_port));
+
+        Ok(Target {
+            secure,
+            host: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_owned(),
+            address,
+            uri,
+        })
+    }
+
+    /// The opening handshake request, offering the `mqtt` subprotocol.
+    fn request(
+        &self,
+    ) -> std::io::Result<tokio_tungstenite::tungstenite::handshake::client::Request> {
+        tokio_tungstenite::tungstenite::handshake::client::Request::builder()
+            .uri(self.uri.clone())
+            .header(
+                tokio_tungstenite::tungstenite::http::header::SEC_WEBSOCKET_PROTOCOL,
+                MQTT_SUBPROTOCOL,
+            )
+            .body(())
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))
+    }
+}
+
+async fn handshake<S>(
+    request: tokio_tungstenite::tungstenite::handshake::client::Request,
+    stream: S,
+) -> std::io::Result<WsStream<S>>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let (stream, _) = tokio_tungstenite::client_async(request, stream)
+        .await
+        .map_err(into_io_error)?;
+    Ok(WsStream::new(stream))
+}
+
+/// A TCP stream, optionally secured with TLS.
+#[derive(Debug)]
+pub enum MaybeTlsStream {
+    Plain(tokio::net::TcpStream),
+    Tls(tokio_openssl::SslStream<tokio::net::TcpStream>),
+}
+
+impl tokio::io::AsyncRead for MaybeTlsStream {
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut [u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => std::pin::Pin::new(stream).poll_read(cx, buf),
+            MaybeTlsStream::Tls(stream) => std::pin::Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl tokio::io::AsyncWrite for MaybeTlsStream {
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => std::pin::Pin::new(stream).poll_write(cx, buf),
+            MaybeTlsStream::Tls(stream) => std::pin::Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => std::pin::Pin::new(stream).poll_flush(cx),
+            MaybeTlsStream::Tls(stream) => std::pin::Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => std::pin::Pin::new(stream).poll_shutdown(cx),
+            MaybeTlsStream::Tls(stream) => std::pin::Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Exposes binary frames of a WebSocket connection as a byte stream
+/// the MQTT codec can read packets from and write packets to.
+///
+/// Used by both ends of the connection, so it is also available to servers built on this crate.
+#[derive(Debug)]
+pub struct WsStream<S> {
+    inner: tokio_tungstenite::WebSocketStream<S>,
+    pending: bytes::Bytes,
+}
+
+impl<S> WsStream<S> {
+    pub fn new(inner: tokio_tungstenite::WebSocketStream<S>) -> Self {
+        WsStream {
+            inner,
+            pending: bytes::Bytes::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        self.inner.get_ref()
+    }
+}
+
+impl<S> tokio::io::AsyncRead for WsStream<S>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut [u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        use bytes::Buf;
+        use futures_core::Stream;
+        use tokio_tungstenite::tungstenite::Message;
+
+        loop {
+            if !self.pending.is_empty() {
+                let len = std::cmp::min(buf.len(), self.pending.len());
+                buf[..len].copy_from_slice(&self.pending[..len]);
+                self.pending.advance(len);
+                return std::task::Poll::Ready(Ok(len));
+            }
+
+            match futures_core::ready!(std::pin::Pin::new(&mut self.inner).poll_next(cx)) {
+                Some(Ok(Message::Binary(data))) => self.pending = bytes::Bytes::from(data),
+                Some(Ok(Message::Close(_))) | None => return std::task::Poll::Ready(Ok(0)),
+                Some(Ok(Message::Text(_))) => {
+                    // [MQTT-6.0.0-1] If a Client or Server receives data in any other type of
+                    // data frame it MUST close the Network Connection.
+                    return std::task::Poll::Ready(Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "MQTT control packets must be sent in binary data frames",
+                    )));
+                }
+                Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => (),
+                Some(Err(err)) => return std::task::Poll::Ready(Err(into_io_error(err))),
+            }
+        }
+    }
+}
+
+impl<S> tokio::io::AsyncWrite for WsStream<S>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        use futures_sink::Sink;
+
+        futures_core::ready!(std::pin::Pin::new(&mut self.inner).poll_ready(cx))
+            .map_err(into_io_error)?;
+
+        std::pin::Pin::new(&mut self.inner)
+            .start_send(tokio_tungstenite::tungstenite::Message::Binary(
+                buf.to_vec(),
+            ))
+            .map_err(into_io_error)?;
+
+        std::task::Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        use futures_sink::Sink;
+
+        std::pin::Pin::new(&mut self.inner)
+            .poll_flush(cx)
+            .map_err(into_io_error)
+    }
+
+    fn poll_shutdown(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        use futures_sink::Sink;
+
+        std::pin::Pin::new(&mut self.inner)
+            .poll_close(cx)
+            .map_err(into_io_error)
+    }
+}
+
+fn into_io_error(err: tokio_tungstenite::tungstenite::Error) -> std::io::Error {
+    match err {
+        tokio_tungstenite::tungstenite::Error::Io(err) => err,
+        tokio_tungstenite::tungstenite::Error::ConnectionClosed
+        | tokio_tungstenite::tungstenite::Error::AlreadyClosed => {
+            std::io::Error::new(std::io::ErrorKind::ConnectionAborted, err)
+        }
+        err => std::io::Error::new(std::io::ErrorKind::Other, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Target;
+
+    #[test]
+    fn target_of_url() {
+        let target = Target::parse("wss://example.com/mqtt").unwrap();
+        assert!(target.secure);
+        assert_eq!(target.host, "example.com");
+        assert_eq!(target.address, "example.com:443");
+
+        let request = target.request().unwrap();
+        assert_eq!(request.uri(), "wss://example.com/mqtt");
+        assert_eq!(
+            request.headers()[tokio_tungstenite::tungstenite::http::header::SEC_WEBSOCKET_PROTOCOL],
+            "mqtt"
+        );
+
+        let target = Target::parse("ws://[::1]:8080").unwrap();
+        assert!(!target.secure);
+        assert_eq!(target.host, "::1");
+        assert_eq!(target.address, "[::1]:8080");
+
+        assert!(Target::parse("tcp://example.com").is_err());
+        assert!(Target::parse("ws://").is_err());
+    }
+}
@@ -10,11 +10,14 @@ use serde::{Deserialize, Serialize};
 
 mod packet;
 pub use packet::{
-    ConnAck, Connect, Disconnect, Packet, PacketCodec, PacketIdentifierDupQoS, PingReq, PingResp,
-    PubAck, PubComp, PubRec, PubRel, Publication, Publish, QoS, SubAck, SubAckQos, Subscribe,
-    SubscribeTo, UnsubAck, Unsubscribe,
+    Auth, ConnAck, Connect, Disconnect, Packet, PacketCodec, PacketIdentifierDupQoS, PingReq,
+    PingResp, PubAck, PubComp, PubRec, PubRel, Publication, Publish, QoS, SubAck, SubAckQos,
+    Subscribe, SubscribeTo, UnsubAck, Unsubscribe,
 };
 
+mod properties;
+pub use properties::Properties;
+
 pub(crate) use packet::PacketMeta;
 
 /// The client ID
@@ -83,6 +86,78 @@ impl From<ConnectReturnCode> for u8 {
     }
 }
 
+impl ConnectReturnCode {
+    /// Converts a CONNACK reason code of protocol level 5 into a return code.
+    ///
+    /// Ref: 3.2.2.2 Connect Reason Code (MQTT 5.0)
+    pub(crate) fn from_reason_code(code: u8) -> Self {
+        match code {
+            0x00 => ConnectReturnCode::Accepted,
+            0x84 => {
+                ConnectReturnCode::Refused(ConnectionRefusedReason::UnacceptableProtocolVersion)
+            }
+            0x85 => ConnectReturnCode::Refused(ConnectionRefusedReason::IdentifierRejected),
+            0x88 => ConnectReturnCode::Refused(ConnectionRefusedReason::ServerUnavailable),
+            0x86 => ConnectReturnCode::Refused(ConnectionRefusedReason::BadUserNameOrPassword),
+            0x87 => ConnectReturnCode::Refused(ConnectionRefusedReason::NotAuthorized),
+            code => ConnectReturnCode::Refused(ConnectionRefusedReason::Other(code)),
+        }
+    }
+
+    /// Converts this return code into a CONNACK reason code of protocol level 5.
+    pub(crate) fn into_reason_code(self) -> u8 {
+        match self {
+            ConnectReturnCode::Accepted => 0x00,
+            ConnectReturnCode::Refused(ConnectionRefusedReason::UnacceptableProtocolVersion) => {
+                0x84
+            }
+            ConnectReturnCode::Refused(ConnectionRefusedReason::IdentifierRejected) => 0x85,
+            ConnectReturnCode::Refused(ConnectionRefusedReason::ServerUnavailable) => 0x88,
+            ConnectReturnCode::Refused(ConnectionRefusedReason::BadUserNameOrPassword) => 0x86,
+            ConnectReturnCode::Refused(ConnectionRefusedReason::NotAuthorized) => 0x87,
+            ConnectReturnCode::Refused(ConnectionRefusedReason::Other(code)) => code,
+        }
+    }
+}
+
+/// The reason code of a PUBACK, PUBREC, PUBREL, PUBCOMP, UNSUBACK or AUTH packet
+///
+/// Reason codes are only sent on the wire with protocol level 5.
+///
+/// Ref: 2.4 Reason Code (MQTT 5.0)
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde1", derive(Deserialize, Serialize))]
+pub struct ReasonCode(pub u8);
+
+impl ReasonCode {
+    pub const SUCCESS: ReasonCode = ReasonCode(0x00);
+    pub const DISCONNECT_WITH_WILL_MESSAGE: ReasonCode = ReasonCode(0x04);
+    pub const NO_MATCHING_SUBSCRIBERS: ReasonCode = ReasonCode(0x10);
+    pub const NO_SUBSCRIPTION_EXISTED: ReasonCode = ReasonCode(0x11);
+    pub const CONTINUE_AUTHENTICATION: ReasonCode = ReasonCode(0x18);
+    pub const RE_AUTHENTICATE: ReasonCode = ReasonCode(0x19);
+    pub const UNSPECIFIED_ERROR: ReasonCode = ReasonCode(0x80);
+    pub const IMPLEMENTATION_SPECIFIC_ERROR: ReasonCode = ReasonCode(0x83);
+    pub const NOT_AUTHORIZED: ReasonCode = ReasonCode(0x87);
+    pub const TOPIC_NAME_INVALID: ReasonCode = ReasonCode(0x90);
+    pub const PACKET_IDENTIFIER_IN_USE: ReasonCode = ReasonCode(0x91);
+    pub const PACKET_IDENTIFIER_NOT_FOUND: ReasonCode = ReasonCode(0x92);
+    pub const TOPIC_ALIAS_INVALID: ReasonCode = ReasonCode(0x94);
+    pub const QUOTA_EXCEEDED: ReasonCode = ReasonCode(0x97);
+    pub const PAYLOAD_FORMAT_INVALID: ReasonCode = ReasonCode(0x99);
+
+    /// Reason codes of 0x80 or greater indicate failure.
+    pub fn is_failure(self) -> bool {
+        self.0 >= 0x80
+    }
+}
+
+impl Default for ReasonCode {
+    fn default() -> Self {
+        ReasonCode::SUCCESS
+    }
+}
+
 /// A tokio decoder of MQTT-format strings.
 ///
 /// Strings are prefixed with a two-byte big-endian length and are encoded as utf-8.
@@ -280,6 +355,7 @@ impl std::ops::AddAssign<u16> for PacketIdentifier {
 pub enum DecodeError {
     ConnectReservedSet,
     ConnectZeroLengthIdWithExistingSession,
+    DuplicateProperty(u8),
     IncompletePacket,
     Io(std::io::Error),
     PublishDupAtMostOnce,
@@ -292,7 +368,9 @@ pub enum DecodeError {
         flags: u8,
         remaining_length: usize,
     },
+    UnrecognizedProperty(u8),
     UnrecognizedProtocolLevel(u8),
+    UnrecognizedSubscriptionOptions(u8),
     UnrecognizedProtocolName(String),
     UnrecognizedQoS(u8),
     ZeroPacketIdentifier,
@@ -308,6 +386,11 @@ impl std::fmt::Display for DecodeError {
                 f,
                 "a zero length client_id was received without the clean session flag set"
             ),
+            DecodeError::DuplicateProperty(identifier) => write!(
+                f,
+                "property 0x{:02X} is included more than once",
+                identifier
+            ),
             DecodeError::IncompletePacket => write!(f, "packet is truncated"),
             DecodeError::Io(err) => write!(f, "I/O error: {}", err),
             DecodeError::NoTopics => write!(f, "expected at least one topic but there were none"),
@@ -332,9 +415,15 @@ impl std::fmt::Display for DecodeError {
 					flags,
 					remaining_length,
 				),
+            DecodeError::UnrecognizedProperty(identifier) => {
+                write!(f, "could not identify property 0x{:02X}", identifier)
+            }
             DecodeError::UnrecognizedProtocolLevel(level) => {
                 write!(f, "unexpected protocol level {:?}", level)
             }
+            DecodeError::UnrecognizedSubscriptionOptions(options) => {
+                write!(f, "could not parse subscription options 0x{:02X}", options)
+            }
             DecodeError::UnrecognizedProtocolName(name) => {
                 write!(f, "unexpected protocol name {:?}", name)
             }
@@ -350,6 +439,7 @@ impl std::error::Error for DecodeError {
         match self {
             DecodeError::ConnectReservedSet => None,
             DecodeError::ConnectZeroLengthIdWithExistingSession => None,
+            DecodeError::DuplicateProperty(_) => None,
             DecodeError::IncompletePacket => None,
             DecodeError::Io(err) => Some(err),
             DecodeError::NoTopics => None,
@@ -358,7 +448,9 @@ impl std::error::Error for DecodeError {
             DecodeError::StringNotUtf8(err) => Some(err),
             DecodeError::UnrecognizedConnAckFlags(_) => None,
             DecodeError::UnrecognizedPacket { .. } => None,
+            DecodeError::UnrecognizedProperty(_) => None,
             DecodeError::UnrecognizedProtocolLevel(_) => None,
+            DecodeError::UnrecognizedSubscriptionOptions(_) => None,
             DecodeError::UnrecognizedProtocolName(_) => None,
             DecodeError::UnrecognizedQoS(_) => None,
             DecodeError::ZeroPacketIdentifier => None,
@@ -374,6 +466,7 @@ impl From<std::io::Error> for DecodeError {
 
 #[derive(Debug)]
 pub enum EncodeError {
+    BinaryTooLarge(usize),
     Io(std::io::Error),
     KeepAliveTooHigh(std::time::Duration),
     RemainingLengthTooHigh(usize),
@@ -385,6 +478,7 @@ impl EncodeError {
     pub fn is_user_error(&self) -> bool {
         #[allow(clippy::match_same_arms)]
         match self {
+            EncodeError::BinaryTooLarge(_) => true,
             EncodeError::Io(_) => false,
             EncodeError::KeepAliveTooHigh(_) => true,
             EncodeError::RemainingLengthTooHigh(_) => true,
@@ -397,6 +491,11 @@ impl EncodeError {
 impl std::fmt::Display for EncodeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            EncodeError::BinaryTooLarge(len) => write!(
+                f,
+                "binary data of length {} is too large to be encoded",
+                len
+            ),
             EncodeError::Io(err) => write!(f, "I/O error: {}", err),
             EncodeError::KeepAliveTooHigh(keep_alive) => {
                 write!(f, "keep-alive {:?} is too high", keep_alive)
@@ -420,6 +519,7 @@ impl std::error::Error for EncodeError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         #[allow(clippy::match_same_arms)]
         match self {
+            EncodeError::BinaryTooLarge(_) => None,
             EncodeError::Io(err) => Some(err),
             EncodeError::KeepAliveTooHigh(_) => None,
             EncodeError::RemainingLengthTooHigh(_) => None,
@@ -442,6 +542,8 @@ pub(crate) trait ByteBuf {
 
     fn put_u16_bytes(&mut self, n: u16);
 
+    fn put_u32_bytes(&mut self, n: u32);
+
     fn put_packet_identifier_bytes(&mut self, packet_identifier: PacketIdentifier) {
         self.put_u16_bytes(packet_identifier.0);
     }
@@ -462,6 +564,10 @@ impl ByteBuf for bytes::BytesMut {
         self.put_u16(n);
     }
 
+    fn put_u32_bytes(&mut self, n: u32) {
+        self.put_u32(n);
+    }
+
     fn put_slice_bytes(&mut self, src: &[u8]) {
         self.put_slice(src);
     }
@@ -486,6 +592,10 @@ impl ByteBuf for ByteCounter {
         self.0 += std::mem::size_of::<u16>();
     }
 
+    fn put_u32_bytes(&mut self, _: u32) {
+        self.0 += std::mem::size_of::<u32>();
+    }
+
     fn put_slice_bytes(&mut self, src: &[u8]) {
         self.0 += src.len();
     }
@@ -496,6 +606,7 @@ trait BufMutExt {
 
     fn try_get_u8(&mut self) -> Result<u8, DecodeError>;
     fn try_get_u16_be(&mut self) -> Result<u16, DecodeError>;
+    fn try_get_u32_be(&mut self) -> Result<u32, DecodeError>;
     fn try_get_packet_identifier(&mut self) -> Result<PacketIdentifier, DecodeError>;
 }
 
@@ -521,6 +632,14 @@ impl BufMutExt for bytes::BytesMut {
         Ok(self.get_u16())
     }
 
+    fn try_get_u32_be(&mut self) -> Result<u32, DecodeError> {
+        if self.len() < std::mem::size_of::<u32>() {
+            return Err(DecodeError::IncompletePacket);
+        }
+
+        Ok(self.get_u32())
+    }
+
     fn try_get_packet_identifier(&mut self) -> Result<PacketIdentifier, DecodeError> {
         if self.len() < std::mem::size_of::<u16>() {
             return Err(DecodeError::IncompletePacket);
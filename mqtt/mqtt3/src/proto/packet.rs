@@ -10,6 +10,9 @@ use crate::proto::{BufMutExt, ByteBuf};
 /// An MQTT packet
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Packet {
+    /// Ref: 3.15 AUTH - Authentication exchange (MQTT 5.0)
+    Auth(Auth),
+
     /// Ref: 3.2 CONNACK – Acknowledge connection request
     ConnAck(ConnAck),
 
@@ -58,29 +61,107 @@ pub(crate) trait PacketMeta: Sized {
     /// The packet type for this kind of packet
     const PACKET_TYPE: u8;
 
-    /// Decodes this packet from the given buffer
-    fn decode(flags: u8, src: bytes::BytesMut) -> Result<Self, super::DecodeError>;
+    /// Decodes this packet from the given buffer, using the wire format of the given protocol level
+    fn decode(
+        flags: u8,
+        src: bytes::BytesMut,
+        protocol_level: u8,
+    ) -> Result<Self, super::DecodeError>;
 
-    /// Encodes the variable header and payload corresponding to this packet into the given buffer.
+    /// Encodes the variable header and payload corresponding to this packet into the given buffer,
+    /// using the wire format of the given protocol level.
     /// The buffer is expected to already have the packet type and body length encoded into it,
     /// and to have reserved enough space to put the bytes of this packet directly into the buffer.
-    fn encode<B>(&self, dst: &mut B) -> Result<(), super::EncodeError>
+    fn encode<B>(&self, dst: &mut B, protocol_level: u8) -> Result<(), super::EncodeError>
     where
         B: ByteBuf;
 }
 
+/// Ref: 3.15 AUTH - Authentication exchange (MQTT 5.0)
+///
+/// This packet only exists in protocol level 5.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Auth {
+    pub reason_code: super::ReasonCode,
+    pub properties: super::Properties,
+}
+
+impl PacketMeta for Auth {
+    const PACKET_TYPE: u8 = 0xF0;
+
+    fn decode(
+        flags: u8,
+        mut src: bytes::BytesMut,
+        protocol_level: u8,
+    ) -> Result<Self, super::DecodeError> {
+        if flags != 0 || !is_v5(protocol_level) {
+            return Err(super::DecodeError::UnrecognizedPacket {
+                packet_type: Self::PACKET_TYPE,
+                flags,
+                remaining_length: src.len(),
+            });
+        }
+
+        // [MQTT5-3.15.2.1] The Reason Code and Property Length can be omitted
+        // if the Reason Code is 0x00 (Success) and there are no Properties.
+        let reason_code = if src.is_empty() {
+            super::ReasonCode::SUCCESS
+        } else {
+            super::ReasonCode(src.get_u8())
+        };
+
+        let properties = if src.is_empty() {
+            super::Properties::default()
+        } else {
+            super::Properties::decode(&mut src)?
+        };
+
+        Ok(Auth {
+            reason_code,
+            properties,
+        })
+    }
+
+    fn encode<B>(&self, dst: &mut B, _: u8) -> Result<(), super::EncodeError>
+    where
+        B: ByteBuf,
+    {
+        let Auth {
+            reason_code,
+            properties,
+        } = self;
+
+        if *reason_code != super::ReasonCode::SUCCESS || !properties.is_empty() {
+            dst.put_u8_bytes(reason_code.0);
+            properties.encode(dst)?;
+        }
+
+        Ok(())
+    }
+}
+
 /// Ref: 3.2 CONNACK – Acknowledge connection request
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct ConnAck {
     pub session_present: bool,
     pub return_code: super::ConnectReturnCode,
+    pub properties: super::Properties,
 }
 
 impl PacketMeta for ConnAck {
     const PACKET_TYPE: u8 = 0x20;
 
-    fn decode(flags: u8, mut src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
-        if flags != 0 || src.len() != (std::mem::size_of::<u8>() + std::mem::size_of::<u8>()) {
+    fn decode(
+        flags: u8,
+        mut src: bytes::BytesMut,
+        protocol_level: u8,
+    ) -> Result<Self, super::DecodeError> {
+        let is_valid_len = if is_v5(protocol_level) {
+            src.len() >= std::mem::size_of::<u8>() + std::mem::size_of::<u8>()
+        } else {
+            src.len() == std::mem::size_of::<u8>() + std::mem::size_of::<u8>()
+        };
+        if flags != 0 || !is_valid_len {
             return Err(super::DecodeError::UnrecognizedPacket {
                 packet_type: Self::PACKET_TYPE,
                 flags,
@@ -97,21 +178,37 @@ impl PacketMeta for ConnAck {
             }
         };
 
-        let return_code: super::ConnectReturnCode = src.get_u8().into();
+        let (return_code, properties) = if is_v5(protocol_level) {
+            let return_code = super::ConnectReturnCode::from_reason_code(src.get_u8());
+
+            // A server that only speaks protocol level 4 refuses the connection
+            // with a CONNACK that has no properties.
+            let properties = if src.is_empty() {
+                super::Properties::default()
+            } else {
+                super::Properties::decode(&mut src)?
+            };
+
+            (return_code, properties)
+        } else {
+            (src.get_u8().into(), super::Properties::default())
+        };
 
         Ok(ConnAck {
             session_present,
             return_code,
+            properties,
         })
     }
 
-    fn encode<B>(&self, dst: &mut B) -> Result<(), super::EncodeError>
+    fn encode<B>(&self, dst: &mut B, protocol_level: u8) -> Result<(), super::EncodeError>
     where
         B: ByteBuf,
     {
         let ConnAck {
             session_present,
             return_code,
+            properties,
         } = self;
         if *session_present {
             dst.put_u8_bytes(0x01);
@@ -119,13 +216,20 @@ impl PacketMeta for ConnAck {
             dst.put_u8_bytes(0x00);
         }
 
-        dst.put_u8_bytes((*return_code).into());
+        if is_v5(protocol_level) {
+            dst.put_u8_bytes(return_code.into_reason_code());
+            properties.encode(dst)?;
+        } else {
+            dst.put_u8_bytes((*return_code).into());
+        }
 
         Ok(())
     }
 }
 
 /// Ref: 3.1 CONNECT – Client requests a connection to a Server
+///
+/// With protocol level 5 the clean session flag of `client_id` is the Clean Start flag.
 #[derive(Clone, Eq, PartialEq)]
 pub struct Connect {
     pub username: Option<String>,
@@ -135,6 +239,7 @@ pub struct Connect {
     pub keep_alive: Duration,
     pub protocol_name: String,
     pub protocol_level: u8,
+    pub properties: super::Properties,
 }
 
 impl std::fmt::Debug for Connect {
@@ -146,6 +251,7 @@ impl std::fmt::Debug for Connect {
             .field("keep_alive", &self.keep_alive)
             .field("protocol_name", &self.protocol_name)
             .field("protocol_level", &self.protocol_level)
+            .field("properties", &self.properties)
             .finish()
     }
 }
@@ -153,7 +259,8 @@ impl std::fmt::Debug for Connect {
 impl PacketMeta for Connect {
     const PACKET_TYPE: u8 = 0x10;
 
-    fn decode(flags: u8, mut src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
+    /// The protocol level of the CONNECT packet is read from the packet itself.
+    fn decode(flags: u8, mut src: bytes::BytesMut, _: u8) -> Result<Self, super::DecodeError> {
         if flags != 0 {
             return Err(super::DecodeError::UnrecognizedPacket {
                 packet_type: Self::PACKET_TYPE,
@@ -178,11 +285,17 @@ impl PacketMeta for Connect {
 
         let keep_alive = Duration::from_secs(u64::from(src.try_get_u16_be()?));
 
+        let properties = if is_v5(protocol_level) {
+            super::Properties::decode(&mut src)?
+        } else {
+            super::Properties::default()
+        };
+
         let client_id = super::Utf8StringDecoder::default()
             .decode(&mut src)?
             .ok_or(super::DecodeError::IncompletePacket)?;
         let client_id = if client_id == "" {
-            if connect_flags & 0x02 == 0 {
+            if connect_flags & 0x02 == 0 && !is_v5(protocol_level) {
                 return Err(super::DecodeError::ConnectZeroLengthIdWithExistingSession);
             }
             super::ClientId::ServerGenerated
@@ -195,6 +308,12 @@ impl PacketMeta for Connect {
         let will = if connect_flags & 0x04 == 0 {
             None
         } else {
+            let properties = if is_v5(protocol_level) {
+                super::Properties::decode(&mut src)?
+            } else {
+                super::Properties::default()
+            };
+
             let topic_name = super::Utf8StringDecoder::default()
                 .decode(&mut src)?
                 .ok_or(super::DecodeError::IncompletePacket)?;
@@ -219,6 +338,7 @@ impl PacketMeta for Connect {
                 qos,
                 retain,
                 payload,
+                properties,
             })
         };
 
@@ -250,10 +370,12 @@ impl PacketMeta for Connect {
             keep_alive,
             protocol_name,
             protocol_level,
+            properties,
         })
     }
 
-    fn encode<B>(&self, dst: &mut B) -> Result<(), super::EncodeError>
+    /// The CONNECT packet is encoded using its own protocol level.
+    fn encode<B>(&self, dst: &mut B, _: u8) -> Result<(), super::EncodeError>
     where
         B: ByteBuf,
     {
@@ -265,6 +387,7 @@ impl PacketMeta for Connect {
             keep_alive,
             protocol_name,
             protocol_level,
+            properties,
         } = self;
 
         super::encode_utf8_str(protocol_name, dst)?;
@@ -306,6 +429,10 @@ impl PacketMeta for Connect {
                 .map_err(|_| super::EncodeError::KeepAliveTooHigh(*keep_alive))?,
         );
 
+        if is_v5(*protocol_level) {
+            properties.encode(dst)?;
+        }
+
         match client_id {
             super::ClientId::ServerGenerated => super::encode_utf8_str("", dst)?,
             super::ClientId::IdWithCleanSession(id)
@@ -313,6 +440,10 @@ impl PacketMeta for Connect {
         }
 
         if let Some(will) = will {
+            if is_v5(*protocol_level) {
+                will.properties.encode(dst)?;
+            }
+
             super::encode_utf8_str(&will.topic_name, dst)?;
 
             let will_len = will.payload.len();
@@ -338,14 +469,23 @@ impl PacketMeta for Connect {
 }
 
 /// Ref: 3.14 DISCONNECT - Disconnect notification
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct Disconnect;
+///
+/// The reason code and properties only exist in protocol level 5.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Disconnect {
+    pub reason_code: super::ReasonCode,
+    pub properties: super::Properties,
+}
 
 impl PacketMeta for Disconnect {
     const PACKET_TYPE: u8 = 0xE0;
 
-    fn decode(flags: u8, src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
-        if flags != 0 || !src.is_empty() {
+    fn decode(
+        flags: u8,
+        mut src: bytes::BytesMut,
+        protocol_level: u8,
+    ) -> Result<Self, super::DecodeError> {
+        if flags != 0 || (!src.is_empty() && !is_v5(protocol_level)) {
             return Err(super::DecodeError::UnrecognizedPacket {
                 packet_type: Self::PACKET_TYPE,
                 flags,
@@ -353,13 +493,42 @@ impl PacketMeta for Disconnect {
             });
         }
 
-        Ok(Disconnect)
+        // [MQTT5-3.14.2.1] The Reason Code and Property Length can be omitted
+        // if the Reason Code is 0x00 (Normal disconnection) and there are no Properties.
+        let reason_code = if src.is_empty() {
+            super::ReasonCode::SUCCESS
+        } else {
+            super::ReasonCode(src.get_u8())
+        };
+
+        let properties = if src.is_empty() {
+            super::Properties::default()
+        } else {
+            super::Properties::decode(&mut src)?
+        };
+
+        Ok(Disconnect {
+            reason_code,
+            properties,
+        })
     }
 
-    fn encode<B>(&self, _: &mut B) -> Result<(), super::EncodeError>
+    fn encode<B>(&self, dst: &mut B, protocol_level: u8) -> Result<(), super::EncodeError>
     where
         B: ByteBuf,
     {
+        let Disconnect {
+            reason_code,
+            properties,
+        } = self;
+
+        if is_v5(protocol_level)
+            && (*reason_code != super::ReasonCode::SUCCESS || !properties.is_empty())
+        {
+            dst.put_u8_bytes(reason_code.0);
+            properties.encode(dst)?;
+        }
+
         Ok(())
     }
 }
@@ -371,7 +540,7 @@ pub struct PingReq;
 impl PacketMeta for PingReq {
     const PACKET_TYPE: u8 = 0xC0;
 
-    fn decode(flags: u8, src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
+    fn decode(flags: u8, src: bytes::BytesMut, _: u8) -> Result<Self, super::DecodeError> {
         if flags != 0 || !src.is_empty() {
             return Err(super::DecodeError::UnrecognizedPacket {
                 packet_type: Self::PACKET_TYPE,
@@ -383,7 +552,7 @@ impl PacketMeta for PingReq {
         Ok(PingReq)
     }
 
-    fn encode<B>(&self, _: &mut B) -> Result<(), super::EncodeError>
+    fn encode<B>(&self, _: &mut B, _: u8) -> Result<(), super::EncodeError>
     where
         B: ByteBuf,
     {
@@ -398,7 +567,7 @@ pub struct PingResp;
 impl PacketMeta for PingResp {
     const PACKET_TYPE: u8 = 0xD0;
 
-    fn decode(flags: u8, src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
+    fn decode(flags: u8, src: bytes::BytesMut, _: u8) -> Result<Self, super::DecodeError> {
         if flags != 0 || !src.is_empty() {
             return Err(super::DecodeError::UnrecognizedPacket {
                 packet_type: Self::PACKET_TYPE,
@@ -410,7 +579,7 @@ impl PacketMeta for PingResp {
         Ok(PingResp)
     }
 
-    fn encode<B>(&self, _: &mut B) -> Result<(), super::EncodeError>
+    fn encode<B>(&self, _: &mut B, _: u8) -> Result<(), super::EncodeError>
     where
         B: ByteBuf,
     {
@@ -422,32 +591,44 @@ impl PacketMeta for PingResp {
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct PubAck {
     pub packet_identifier: super::PacketIdentifier,
+    pub reason_code: super::ReasonCode,
+    pub properties: super::Properties,
 }
 
 impl PacketMeta for PubAck {
     const PACKET_TYPE: u8 = 0x40;
 
-    fn decode(flags: u8, mut src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
-        if flags != 0 || src.len() != std::mem::size_of::<u16>() {
-            return Err(super::DecodeError::UnrecognizedPacket {
-                packet_type: Self::PACKET_TYPE,
-                flags,
-                remaining_length: src.len(),
-            });
-        }
+    fn decode(
+        flags: u8,
+        src: bytes::BytesMut,
+        protocol_level: u8,
+    ) -> Result<Self, super::DecodeError> {
+        let (packet_identifier, reason_code, properties) =
+            decode_ack(Self::PACKET_TYPE, 0, flags, src, protocol_level)?;
 
-        let packet_identifier = src.get_packet_identifier()?;
-
-        Ok(PubAck { packet_identifier })
+        Ok(PubAck {
+            packet_identifier,
+            reason_code,
+            properties,
+        })
     }
 
-    fn encode<B>(&self, dst: &mut B) -> Result<(), super::EncodeError>
+    fn encode<B>(&self, dst: &mut B, protocol_level: u8) -> Result<(), super::EncodeError>
     where
         B: ByteBuf,
     {
-        let PubAck { packet_identifier } = self;
-        dst.put_packet_identifier_bytes(*packet_identifier);
-        Ok(())
+        let PubAck {
+            packet_identifier,
+            reason_code,
+            properties,
+        } = self;
+        encode_ack(
+            *packet_identifier,
+            *reason_code,
+            properties,
+            dst,
+            protocol_level,
+        )
     }
 }
 
@@ -456,32 +637,44 @@ impl PacketMeta for PubAck {
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct PubComp {
     pub packet_identifier: super::PacketIdentifier,
+    pub reason_code: super::ReasonCode,
+    pub properties: super::Properties,
 }
 
 impl PacketMeta for PubComp {
     const PACKET_TYPE: u8 = 0x70;
 
-    fn decode(flags: u8, mut src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
-        if flags != 0 || src.len() != std::mem::size_of::<u16>() {
-            return Err(super::DecodeError::UnrecognizedPacket {
-                packet_type: Self::PACKET_TYPE,
-                flags,
-                remaining_length: src.len(),
-            });
-        }
-
-        let packet_identifier = src.get_packet_identifier()?;
+    fn decode(
+        flags: u8,
+        src: bytes::BytesMut,
+        protocol_level: u8,
+    ) -> Result<Self, super::DecodeError> {
+        let (packet_identifier, reason_code, properties) =
+            decode_ack(Self::PACKET_TYPE, 0, flags, src, protocol_level)?;
 
-        Ok(PubComp { packet_identifier })
+        Ok(PubComp {
+            packet_identifier,
+            reason_code,
+            properties,
+        })
     }
 
-    fn encode<B>(&self, dst: &mut B) -> Result<(), super::EncodeError>
+    fn encode<B>(&self, dst: &mut B, protocol_level: u8) -> Result<(), super::EncodeError>
     where
         B: ByteBuf,
     {
-        let PubComp { packet_identifier } = self;
-        dst.put_packet_identifier_bytes(*packet_identifier);
-        Ok(())
+        let PubComp {
+            packet_identifier,
+            reason_code,
+            properties,
+        } = self;
+        encode_ack(
+            *packet_identifier,
+            *reason_code,
+            properties,
+            dst,
+            protocol_level,
+        )
     }
 }
 
@@ -495,12 +688,17 @@ pub struct Publish {
     #[cfg_attr(feature = "serde1", serde(serialize_with = "serialize_bytes"))]
     #[cfg_attr(feature = "serde1", serde(deserialize_with = "deserialize_bytes"))]
     pub payload: bytes::Bytes,
+    pub properties: super::Properties,
 }
 
 impl PacketMeta for Publish {
     const PACKET_TYPE: u8 = 0x30;
 
-    fn decode(flags: u8, mut src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
+    fn decode(
+        flags: u8,
+        mut src: bytes::BytesMut,
+        protocol_level: u8,
+    ) -> Result<Self, super::DecodeError> {
         let dup = (flags & 0x08) != 0;
         let retain = (flags & 0x01) != 0;
 
@@ -526,6 +724,12 @@ impl PacketMeta for Publish {
             qos => return Err(super::DecodeError::UnrecognizedQoS(qos)),
         };
 
+        let properties = if is_v5(protocol_level) {
+            super::Properties::decode(&mut src)?
+        } else {
+            super::Properties::default()
+        };
+
         let payload = src.freeze();
 
         Ok(Publish {
@@ -533,10 +737,11 @@ impl PacketMeta for Publish {
             retain,
             topic_name,
             payload,
+            properties,
         })
     }
 
-    fn encode<B>(&self, dst: &mut B) -> Result<(), super::EncodeError>
+    fn encode<B>(&self, dst: &mut B, protocol_level: u8) -> Result<(), super::EncodeError>
     where
         B: ByteBuf,
     {
@@ -546,6 +751,7 @@ impl PacketMeta for Publish {
             retain: _,
             topic_name,
             payload,
+            properties,
         } = self;
 
         super::encode_utf8_str(topic_name, dst)?;
@@ -558,6 +764,10 @@ impl PacketMeta for Publish {
             }
         }
 
+        if is_v5(protocol_level) {
+            properties.encode(dst)?;
+        }
+
         dst.put_slice_bytes(&payload);
 
         Ok(())
@@ -569,32 +779,44 @@ impl PacketMeta for Publish {
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct PubRec {
     pub packet_identifier: super::PacketIdentifier,
+    pub reason_code: super::ReasonCode,
+    pub properties: super::Properties,
 }
 
 impl PacketMeta for PubRec {
     const PACKET_TYPE: u8 = 0x50;
 
-    fn decode(flags: u8, mut src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
-        if flags != 0 || src.len() != std::mem::size_of::<u16>() {
-            return Err(super::DecodeError::UnrecognizedPacket {
-                packet_type: Self::PACKET_TYPE,
-                flags,
-                remaining_length: src.len(),
-            });
-        }
-
-        let packet_identifier = src.get_packet_identifier()?;
+    fn decode(
+        flags: u8,
+        src: bytes::BytesMut,
+        protocol_level: u8,
+    ) -> Result<Self, super::DecodeError> {
+        let (packet_identifier, reason_code, properties) =
+            decode_ack(Self::PACKET_TYPE, 0, flags, src, protocol_level)?;
 
-        Ok(PubRec { packet_identifier })
+        Ok(PubRec {
+            packet_identifier,
+            reason_code,
+            properties,
+        })
     }
 
-    fn encode<B>(&self, dst: &mut B) -> Result<(), super::EncodeError>
+    fn encode<B>(&self, dst: &mut B, protocol_level: u8) -> Result<(), super::EncodeError>
     where
         B: ByteBuf,
     {
-        let PubRec { packet_identifier } = self;
-        dst.put_packet_identifier_bytes(*packet_identifier);
-        Ok(())
+        let PubRec {
+            packet_identifier,
+            reason_code,
+            properties,
+        } = self;
+        encode_ack(
+            *packet_identifier,
+            *reason_code,
+            properties,
+            dst,
+            protocol_level,
+        )
     }
 }
 
@@ -603,32 +825,44 @@ impl PacketMeta for PubRec {
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct PubRel {
     pub packet_identifier: super::PacketIdentifier,
+    pub reason_code: super::ReasonCode,
+    pub properties: super::Properties,
 }
 
 impl PacketMeta for PubRel {
     const PACKET_TYPE: u8 = 0x60;
 
-    fn decode(flags: u8, mut src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
-        if flags != 2 || src.len() != std::mem::size_of::<u16>() {
-            return Err(super::DecodeError::UnrecognizedPacket {
-                packet_type: Self::PACKET_TYPE,
-                flags,
-                remaining_length: src.len(),
-            });
-        }
-
-        let packet_identifier = src.get_packet_identifier()?;
+    fn decode(
+        flags: u8,
+        src: bytes::BytesMut,
+        protocol_level: u8,
+    ) -> Result<Self, super::DecodeError> {
+        let (packet_identifier, reason_code, properties) =
+            decode_ack(Self::PACKET_TYPE, 2, flags, src, protocol_level)?;
 
-        Ok(PubRel { packet_identifier })
+        Ok(PubRel {
+            packet_identifier,
+            reason_code,
+            properties,
+        })
     }
 
-    fn encode<B>(&self, dst: &mut B) -> Result<(), super::EncodeError>
+    fn encode<B>(&self, dst: &mut B, protocol_level: u8) -> Result<(), super::EncodeError>
     where
         B: ByteBuf,
     {
-        let PubRel { packet_identifier } = self;
-        dst.put_packet_identifier_bytes(*packet_identifier);
-        Ok(())
+        let PubRel {
+            packet_identifier,
+            reason_code,
+            properties,
+        } = self;
+        encode_ack(
+            *packet_identifier,
+            *reason_code,
+            properties,
+            dst,
+            protocol_level,
+        )
     }
 }
 
@@ -637,12 +871,17 @@ impl PacketMeta for PubRel {
 pub struct SubAck {
     pub packet_identifier: super::PacketIdentifier,
     pub qos: Vec<SubAckQos>,
+    pub properties: super::Properties,
 }
 
 impl PacketMeta for SubAck {
     const PACKET_TYPE: u8 = 0x90;
 
-    fn decode(flags: u8, mut src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
+    fn decode(
+        flags: u8,
+        mut src: bytes::BytesMut,
+        protocol_level: u8,
+    ) -> Result<Self, super::DecodeError> {
         if flags != 0 || src.len() < std::mem::size_of::<u16>() {
             return Err(super::DecodeError::UnrecognizedPacket {
                 packet_type: Self::PACKET_TYPE,
@@ -653,6 +892,12 @@ impl PacketMeta for SubAck {
 
         let packet_identifier = src.get_packet_identifier()?;
 
+        let properties = if is_v5(protocol_level) {
+            super::Properties::decode(&mut src)?
+        } else {
+            super::Properties::default()
+        };
+
         let qos: Result<Vec<_>, _> = src
             .iter()
             .map(|&qos| match qos {
@@ -660,6 +905,8 @@ impl PacketMeta for SubAck {
                 0x01 => Ok(SubAckQos::Success(QoS::AtLeastOnce)),
                 0x02 => Ok(SubAckQos::Success(QoS::ExactlyOnce)),
                 0x80 => Ok(SubAckQos::Failure),
+                // Protocol level 5 has a range of failure reason codes
+                qos if qos > 0x80 && is_v5(protocol_level) => Ok(SubAckQos::Failure),
                 qos => Err(super::DecodeError::UnrecognizedQoS(qos)),
             })
             .collect();
@@ -672,20 +919,26 @@ impl PacketMeta for SubAck {
         Ok(SubAck {
             packet_identifier,
             qos,
+            properties,
         })
     }
 
-    fn encode<B>(&self, dst: &mut B) -> Result<(), super::EncodeError>
+    fn encode<B>(&self, dst: &mut B, protocol_level: u8) -> Result<(), super::EncodeError>
     where
         B: ByteBuf,
     {
         let SubAck {
             packet_identifier,
             qos,
+            properties,
         } = self;
 
         dst.put_packet_identifier_bytes(*packet_identifier);
 
+        if is_v5(protocol_level) {
+            properties.encode(dst)?;
+        }
+
         for &qos in qos {
             dst.put_u8_bytes(qos.into());
         }
@@ -699,12 +952,17 @@ impl PacketMeta for SubAck {
 pub struct Subscribe {
     pub packet_identifier: super::PacketIdentifier,
     pub subscribe_to: Vec<SubscribeTo>,
+    pub properties: super::Properties,
 }
 
 impl PacketMeta for Subscribe {
     const PACKET_TYPE: u8 = 0x80;
 
-    fn decode(flags: u8, mut src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
+    fn decode(
+        flags: u8,
+        mut src: bytes::BytesMut,
+        protocol_level: u8,
+    ) -> Result<Self, super::DecodeError> {
         if flags != 2 || src.len() < std::mem::size_of::<u16>() {
             return Err(super::DecodeError::UnrecognizedPacket {
                 packet_type: Self::PACKET_TYPE,
@@ -715,13 +973,34 @@ impl PacketMeta for Subscribe {
 
         let packet_identifier = src.get_packet_identifier()?;
 
+        let properties = if is_v5(protocol_level) {
+            super::Properties::decode(&mut src)?
+        } else {
+            super::Properties::default()
+        };
+
         let mut subscribe_to = vec![];
 
         while !src.is_empty() {
             let topic_filter = super::Utf8StringDecoder::default()
                 .decode(&mut src)?
                 .ok_or(super::DecodeError::IncompletePacket)?;
-            let qos = match src.try_get_u8()? {
+
+            let options = src.try_get_u8()?;
+            let qos = if is_v5(protocol_level) {
+                // [MQTT5-3.8.3-5] The Server MUST treat a SUBSCRIBE packet as malformed
+                // if any of Reserved bits in the Payload are non-zero.
+                //
+                // The No Local, Retain As Published and Retain Handling options are not retained.
+                if options & 0xC0 != 0 {
+                    return Err(super::DecodeError::UnrecognizedSubscriptionOptions(options));
+                }
+                options & 0x03
+            } else {
+                options
+            };
+
+            let qos = match qos {
                 0x00 => QoS::AtMostOnce,
                 0x01 => QoS::AtLeastOnce,
                 0x02 => QoS::ExactlyOnce,
@@ -737,20 +1016,26 @@ impl PacketMeta for Subscribe {
         Ok(Subscribe {
             packet_identifier,
             subscribe_to,
+            properties,
         })
     }
 
-    fn encode<B>(&self, dst: &mut B) -> Result<(), super::EncodeError>
+    fn encode<B>(&self, dst: &mut B, protocol_level: u8) -> Result<(), super::EncodeError>
     where
         B: ByteBuf,
     {
         let Subscribe {
             packet_identifier,
             subscribe_to,
+            properties,
         } = self;
 
         dst.put_packet_identifier_bytes(*packet_identifier);
 
+        if is_v5(protocol_level) {
+            properties.encode(dst)?;
+        }
+
         for SubscribeTo { topic_filter, qos } in subscribe_to {
             super::encode_utf8_str(topic_filter, dst)?;
             dst.put_u8_bytes((*qos).into());
@@ -761,16 +1046,29 @@ impl PacketMeta for Subscribe {
 }
 
 /// Ref: 3.11 UNSUBACK – Unsubscribe acknowledgement
+///
+/// `reason_codes` are only sent with protocol level 5, one per topic filter of the UNSUBSCRIBE packet.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct UnsubAck {
     pub packet_identifier: super::PacketIdentifier,
+    pub reason_codes: Vec<super::ReasonCode>,
+    pub properties: super::Properties,
 }
 
 impl PacketMeta for UnsubAck {
     const PACKET_TYPE: u8 = 0xB0;
 
-    fn decode(flags: u8, mut src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
-        if flags != 0 || src.len() != std::mem::size_of::<u16>() {
+    fn decode(
+        flags: u8,
+        mut src: bytes::BytesMut,
+        protocol_level: u8,
+    ) -> Result<Self, super::DecodeError> {
+        let is_valid_len = if is_v5(protocol_level) {
+            src.len() >= std::mem::size_of::<u16>()
+        } else {
+            src.len() == std::mem::size_of::<u16>()
+        };
+        if flags != 0 || !is_valid_len {
             return Err(super::DecodeError::UnrecognizedPacket {
                 packet_type: Self::PACKET_TYPE,
                 flags,
@@ -780,15 +1078,41 @@ impl PacketMeta for UnsubAck {
 
         let packet_identifier = src.get_packet_identifier()?;
 
-        Ok(UnsubAck { packet_identifier })
+        let (reason_codes, properties) = if is_v5(protocol_level) {
+            let properties = super::Properties::decode(&mut src)?;
+            let reason_codes = src.iter().map(|&code| super::ReasonCode(code)).collect();
+            (reason_codes, properties)
+        } else {
+            (vec![], super::Properties::default())
+        };
+
+        Ok(UnsubAck {
+            packet_identifier,
+            reason_codes,
+            properties,
+        })
     }
 
-    fn encode<B>(&self, dst: &mut B) -> Result<(), super::EncodeError>
+    fn encode<B>(&self, dst: &mut B, protocol_level: u8) -> Result<(), super::EncodeError>
     where
         B: ByteBuf,
     {
-        let UnsubAck { packet_identifier } = self;
+        let UnsubAck {
+            packet_identifier,
+            reason_codes,
+            properties,
+        } = self;
+
         dst.put_packet_identifier_bytes(*packet_identifier);
+
+        if is_v5(protocol_level) {
+            properties.encode(dst)?;
+
+            for reason_code in reason_codes {
+                dst.put_u8_bytes(reason_code.0);
+            }
+        }
+
         Ok(())
     }
 }
@@ -798,12 +1122,17 @@ impl PacketMeta for UnsubAck {
 pub struct Unsubscribe {
     pub packet_identifier: super::PacketIdentifier,
     pub unsubscribe_from: Vec<String>,
+    pub properties: super::Properties,
 }
 
 impl PacketMeta for Unsubscribe {
     const PACKET_TYPE: u8 = 0xA0;
 
-    fn decode(flags: u8, mut src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
+    fn decode(
+        flags: u8,
+        mut src: bytes::BytesMut,
+        protocol_level: u8,
+    ) -> Result<Self, super::DecodeError> {
         if flags != 2 || src.len() < std::mem::size_of::<u16>() {
             return Err(super::DecodeError::UnrecognizedPacket {
                 packet_type: Self::PACKET_TYPE,
@@ -814,6 +1143,12 @@ impl PacketMeta for Unsubscribe {
 
         let packet_identifier = src.get_packet_identifier()?;
 
+        let properties = if is_v5(protocol_level) {
+            super::Properties::decode(&mut src)?
+        } else {
+            super::Properties::default()
+        };
+
         let mut unsubscribe_from = vec![];
 
         while !src.is_empty() {
@@ -831,20 +1166,26 @@ impl PacketMeta for Unsubscribe {
         Ok(Unsubscribe {
             packet_identifier,
             unsubscribe_from,
+            properties,
         })
     }
 
-    fn encode<B>(&self, dst: &mut B) -> Result<(), super::EncodeError>
+    fn encode<B>(&self, dst: &mut B, protocol_level: u8) -> Result<(), super::EncodeError>
     where
         B: ByteBuf,
     {
         let Unsubscribe {
             packet_identifier,
             unsubscribe_from,
+            properties,
         } = self;
 
         dst.put_packet_identifier_bytes(*packet_identifier);
 
+        if is_v5(protocol_level) {
+            properties.encode(dst)?;
+        }
+
         for unsubscribe_from in unsubscribe_from {
             super::encode_utf8_str(unsubscribe_from, dst)?;
         }
@@ -853,6 +1194,90 @@ impl PacketMeta for Unsubscribe {
     }
 }
 
+fn is_v5(protocol_level: u8) -> bool {
+    protocol_level == crate::PROTOCOL_LEVEL_V5
+}
+
+/// Decodes the variable header shared by PUBACK, PUBREC, PUBREL and PUBCOMP.
+fn decode_ack(
+    packet_type: u8,
+    expected_flags: u8,
+    flags: u8,
+    mut src: bytes::BytesMut,
+    protocol_level: u8,
+) -> Result<
+    (
+        super::PacketIdentifier,
+        super::ReasonCode,
+        super::Properties,
+    ),
+    super::DecodeError,
+> {
+    let is_valid_len = if is_v5(protocol_level) {
+        src.len() >= std::mem::size_of::<u16>()
+    } else {
+        src.len() == std::mem::size_of::<u16>()
+    };
+    if flags != expected_flags || !is_valid_len {
+        return Err(super::DecodeError::UnrecognizedPacket {
+            packet_type,
+            flags,
+            remaining_length: src.len(),
+        });
+    }
+
+    let packet_identifier = src.get_packet_identifier()?;
+
+    // [MQTT5-3.4.2.1] The Reason Code and Property Length can be omitted
+    // if the Reason Code is 0x00 (Success) and there are no Properties.
+    let reason_code = if src.is_empty() {
+        super::ReasonCode::SUCCESS
+    } else {
+        super::ReasonCode(src.get_u8())
+    };
+
+    let properties = if src.is_empty() {
+        super::Properties::default()
+    } else {
+        super::Properties::decode(&mut src)?
+    };
+
+    if !src.is_empty() {
+        return Err(super::DecodeError::UnrecognizedPacket {
+            packet_type,
+            flags,
+            remaining_length: src.len(),
+        });
+    }
+
+    Ok((packet_identifier, reason_code, properties))
+}
+
+/// Encodes the variable header shared by PUBACK, PUBREC, PUBREL and PUBCOMP.
+fn encode_ack<B>(
+    packet_identifier: super::PacketIdentifier,
+    reason_code: super::ReasonCode,
+    properties: &super::Properties,
+    dst: &mut B,
+    protocol_level: u8,
+) -> Result<(), super::EncodeError>
+where
+    B: ByteBuf,
+{
+    dst.put_packet_identifier_bytes(packet_identifier);
+
+    if is_v5(protocol_level)
+        && (reason_code != super::ReasonCode::SUCCESS || !properties.is_empty())
+    {
+        dst.put_u8_bytes(reason_code.0);
+        if !properties.is_empty() {
+            properties.encode(dst)?;
+        }
+    }
+
+    Ok(())
+}
+
 #[allow(clippy::doc_markdown)]
 /// A combination of the packet identifier, dup flag and QoS that only allows valid combinations of these three properties.
 /// Used in [`Packet::Publish`]
@@ -865,6 +1290,8 @@ pub enum PacketIdentifierDupQoS {
 }
 
 /// A subscription request.
+///
+/// With protocol level 5 only the maximum QoS of the subscription options is retained.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct SubscribeTo {
     pub topic_filter: String,
@@ -919,14 +1346,39 @@ pub struct Publication {
     #[cfg_attr(feature = "serde1", serde(serialize_with = "serialize_bytes"))]
     #[cfg_attr(feature = "serde1", serde(deserialize_with = "deserialize_bytes"))]
     pub payload: bytes::Bytes,
+    pub properties: super::Properties,
 }
 
 /// A tokio codec that encodes and decodes MQTT packets.
 ///
+/// The codec starts with the wire format of the given protocol level
+/// and switches to the protocol level of any CONNECT packet it decodes or encodes.
+///
 /// Ref: 2 MQTT Control Packet format
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct PacketCodec {
     decoder_state: PacketDecoderState,
+    protocol_level: u8,
+}
+
+impl PacketCodec {
+    pub fn new(protocol_level: u8) -> Self {
+        PacketCodec {
+            decoder_state: PacketDecoderState::default(),
+            protocol_level,
+        }
+    }
+
+    /// The protocol level whose wire format is currently used.
+    pub fn protocol_level(&self) -> u8 {
+        self.protocol_level
+    }
+}
+
+impl Default for PacketCodec {
+    fn default() -> Self {
+        PacketCodec::new(crate::PROTOCOL_LEVEL)
+    }
 }
 
 #[derive(Debug)]
@@ -997,25 +1449,83 @@ impl tokio_util::codec::Decoder for PacketCodec {
 
         let packet_type = first_byte & 0xF0;
         let flags = first_byte & 0x0F;
+        let protocol_level = self.protocol_level;
         match packet_type {
-            ConnAck::PACKET_TYPE => Ok(Some(Packet::ConnAck(ConnAck::decode(flags, src)?))),
-            Connect::PACKET_TYPE => Ok(Some(Packet::Connect(Connect::decode(flags, src)?))),
-            Disconnect::PACKET_TYPE => {
-                Ok(Some(Packet::Disconnect(Disconnect::decode(flags, src)?)))
-            }
-            PingReq::PACKET_TYPE => Ok(Some(Packet::PingReq(PingReq::decode(flags, src)?))),
-            PingResp::PACKET_TYPE => Ok(Some(Packet::PingResp(PingResp::decode(flags, src)?))),
-            PubAck::PACKET_TYPE => Ok(Some(Packet::PubAck(PubAck::decode(flags, src)?))),
-            PubComp::PACKET_TYPE => Ok(Some(Packet::PubComp(PubComp::decode(flags, src)?))),
-            Publish::PACKET_TYPE => Ok(Some(Packet::Publish(Publish::decode(flags, src)?))),
-            PubRec::PACKET_TYPE => Ok(Some(Packet::PubRec(PubRec::decode(flags, src)?))),
-            PubRel::PACKET_TYPE => Ok(Some(Packet::PubRel(PubRel::decode(flags, src)?))),
-            SubAck::PACKET_TYPE => Ok(Some(Packet::SubAck(SubAck::decode(flags, src)?))),
-            Subscribe::PACKET_TYPE => Ok(Some(Packet::Subscribe(Subscribe::decode(flags, src)?))),
-            UnsubAck::PACKET_TYPE => Ok(Some(Packet::UnsubAck(UnsubAck::decode(flags, src)?))),
-            Unsubscribe::PACKET_TYPE => {
-                Ok(Some(Packet::Unsubscribe(Unsubscribe::decode(flags, src)?)))
+            Auth::PACKET_TYPE => Ok(Some(Packet::Auth(Auth::decode(
+                flags,
+                src,
+                protocol_level,
+            )?))),
+            ConnAck::PACKET_TYPE => Ok(Some(Packet::ConnAck(ConnAck::decode(
+                flags,
+                src,
+                protocol_level,
+            )?))),
+            Connect::PACKET_TYPE => {
+                let connect = Connect::decode(flags, src, protocol_level)?;
+                self.protocol_level = connect.protocol_level;
+                Ok(Some(Packet::Connect(connect)))
             }
+            Disconnect::PACKET_TYPE => Ok(Some(Packet::Disconnect(Disconnect::decode(
+                flags,
+                src,
+                protocol_level,
+            )?))),
+            PingReq::PACKET_TYPE => Ok(Some(Packet::PingReq(PingReq::decode(
+                flags,
+                src,
+                protocol_level,
+            )?))),
+            PingResp::PACKET_TYPE => Ok(Some(Packet::PingResp(PingResp::decode(
+                flags,
+                src,
+                protocol_level,
+            )?))),
+            PubAck::PACKET_TYPE => Ok(Some(Packet::PubAck(PubAck::decode(
+                flags,
+                src,
+                protocol_level,
+            )?))),
+            PubComp::PACKET_TYPE => Ok(Some(Packet::PubComp(PubComp::decode(
+                flags,
+                src,
+                protocol_level,
+            )?))),
+            Publish::PACKET_TYPE => Ok(Some(Packet::Publish(Publish::decode(
+                flags,
+                src,
+                protocol_level,
+            )?))),
+            PubRec::PACKET_TYPE => Ok(Some(Packet::PubRec(PubRec::decode(
+                flags,
+                src,
+                protocol_level,
+            )?))),
+            PubRel::PACKET_TYPE => Ok(Some(Packet::PubRel(PubRel::decode(
+                flags,
+                src,
+                protocol_level,
+            )?))),
+            SubAck::PACKET_TYPE => Ok(Some(Packet::SubAck(SubAck::decode(
+                flags,
+                src,
+                protocol_level,
+            )?))),
+            Subscribe::PACKET_TYPE => Ok(Some(Packet::Subscribe(Subscribe::decode(
+                flags,
+                src,
+                protocol_level,
+            )?))),
+            UnsubAck::PACKET_TYPE => Ok(Some(Packet::UnsubAck(UnsubAck::decode(
+                flags,
+                src,
+                protocol_level,
+            )?))),
+            Unsubscribe::PACKET_TYPE => Ok(Some(Packet::Unsubscribe(Unsubscribe::decode(
+                flags,
+                src,
+                protocol_level,
+            )?))),
             packet_type => Err(super::DecodeError::UnrecognizedPacket {
                 packet_type,
                 flags,
@@ -1032,14 +1542,20 @@ impl tokio_util::codec::Encoder for PacketCodec {
     fn encode(&mut self, item: Self::Item, dst: &mut bytes::BytesMut) -> Result<(), Self::Error> {
         dst.reserve(std::mem::size_of::<u8>() + 4 * std::mem::size_of::<u8>());
 
+        if let Packet::Connect(packet) = &item {
+            self.protocol_level = packet.protocol_level;
+        }
+        let protocol_level = self.protocol_level;
+
         match &item {
-            Packet::ConnAck(packet) => encode_packet(packet, 0, dst),
-            Packet::Connect(packet) => encode_packet(packet, 0, dst),
-            Packet::Disconnect(packet) => encode_packet(packet, 0, dst),
-            Packet::PingReq(packet) => encode_packet(packet, 0, dst),
-            Packet::PingResp(packet) => encode_packet(packet, 0, dst),
-            Packet::PubAck(packet) => encode_packet(packet, 0, dst),
-            Packet::PubComp(packet) => encode_packet(packet, 0, dst),
+            Packet::Auth(packet) => encode_packet(packet, 0, dst, protocol_level),
+            Packet::ConnAck(packet) => encode_packet(packet, 0, dst, protocol_level),
+            Packet::Connect(packet) => encode_packet(packet, 0, dst, protocol_level),
+            Packet::Disconnect(packet) => encode_packet(packet, 0, dst, protocol_level),
+            Packet::PingReq(packet) => encode_packet(packet, 0, dst, protocol_level),
+            Packet::PingResp(packet) => encode_packet(packet, 0, dst, protocol_level),
+            Packet::PubAck(packet) => encode_packet(packet, 0, dst, protocol_level),
+            Packet::PubComp(packet) => encode_packet(packet, 0, dst, protocol_level),
             Packet::Publish(packet) => {
                 let mut flags = match packet.packet_identifier_dup_qos {
                     PacketIdentifierDupQoS::AtMostOnce => 0x00,
@@ -1051,14 +1567,14 @@ impl tokio_util::codec::Encoder for PacketCodec {
                 if packet.retain {
                     flags |= 0x01;
                 };
-                encode_packet(packet, flags, dst)
+                encode_packet(packet, flags, dst, protocol_level)
             }
-            Packet::PubRec(packet) => encode_packet(packet, 0, dst),
-            Packet::PubRel(packet) => encode_packet(packet, 0x02, dst),
-            Packet::SubAck(packet) => encode_packet(packet, 0, dst),
-            Packet::Subscribe(packet) => encode_packet(packet, 0x02, dst),
-            Packet::UnsubAck(packet) => encode_packet(packet, 0, dst),
-            Packet::Unsubscribe(packet) => encode_packet(packet, 0x02, dst),
+            Packet::PubRec(packet) => encode_packet(packet, 0, dst, protocol_level),
+            Packet::PubRel(packet) => encode_packet(packet, 0x02, dst, protocol_level),
+            Packet::SubAck(packet) => encode_packet(packet, 0, dst, protocol_level),
+            Packet::Subscribe(packet) => encode_packet(packet, 0x02, dst, protocol_level),
+            Packet::UnsubAck(packet) => encode_packet(packet, 0, dst, protocol_level),
+            Packet::Unsubscribe(packet) => encode_packet(packet, 0x02, dst, protocol_level),
         }
     }
 }
@@ -1067,12 +1583,13 @@ fn encode_packet<P>(
     packet: &P,
     flags: u8,
     dst: &mut bytes::BytesMut,
+    protocol_level: u8,
 ) -> Result<(), super::EncodeError>
 where
     P: PacketMeta,
 {
     let mut counter = super::ByteCounter::new();
-    packet.encode(&mut counter)?;
+    packet.encode(&mut counter, protocol_level)?;
     let body_len = counter.0;
 
     dst.reserve(
@@ -1083,7 +1600,7 @@ where
 
     dst.put_u8(<P as PacketMeta>::PACKET_TYPE | flags);
     super::encode_remaining_length(body_len, dst)?;
-    packet.encode(dst)?;
+    packet.encode(dst, protocol_level)?;
 
     Ok(())
 }
@@ -1103,3 +1620,179 @@ where
 {
     Vec::<u8>::deserialize(deserializer).map(bytes::Bytes::from)
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use super::{
+        Auth, Connect, Disconnect, Packet, PacketCodec, PacketIdentifierDupQoS, PubAck,
+        Publication, Publish, QoS,
+    };
+    use crate::proto::{ClientId, DecodeError, PacketIdentifier, Properties, ReasonCode};
+
+    fn roundtrip(protocol_level: u8, packet: Packet) {
+        let mut bytes = bytes::BytesMut::new();
+        PacketCodec::new(protocol_level)
+            .encode(packet.clone(), &mut bytes)
+            .unwrap();
+
+        let decoded = PacketCodec::new(protocol_level)
+            .decode(&mut bytes)
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded, packet);
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn connect_v5_roundtrip() {
+        roundtrip(
+            crate::PROTOCOL_LEVEL_V5,
+            Packet::Connect(Connect {
+                username: Some("username".to_string()),
+                password: None,
+                will: Some(Publication {
+                    topic_name: "will".to_string(),
+                    qos: QoS::AtLeastOnce,
+                    retain: false,
+                    payload: bytes::Bytes::from("will"),
+                    properties: Properties {
+                        will_delay_interval: Some(10),
+                        content_type: Some("text/plain".to_string()),
+                        ..Properties::default()
+                    },
+                }),
+                client_id: ClientId::ServerGenerated,
+                keep_alive: std::time::Duration::from_secs(30),
+                protocol_name: crate::PROTOCOL_NAME.to_string(),
+                protocol_level: crate::PROTOCOL_LEVEL_V5,
+                properties: Properties {
+                    session_expiry_interval: Some(3600),
+                    topic_alias_maximum: Some(5),
+                    ..Properties::default()
+                },
+            }),
+        );
+    }
+
+    #[test]
+    fn publish_v5_roundtrip() {
+        roundtrip(
+            crate::PROTOCOL_LEVEL_V5,
+            Packet::Publish(Publish {
+                packet_identifier_dup_qos: PacketIdentifierDupQoS::AtLeastOnce(
+                    PacketIdentifier::new(1).unwrap(),
+                    false,
+                ),
+                retain: true,
+                topic_name: "topic".to_string(),
+                payload: bytes::Bytes::from("payload"),
+                properties: Properties {
+                    message_expiry_interval: Some(60),
+                    user_properties: vec![("key".to_string(), "value".to_string())],
+                    ..Properties::default()
+                },
+            }),
+        );
+    }
+
+    #[test]
+    fn puback_v5_reason_code_roundtrip() {
+        roundtrip(
+            crate::PROTOCOL_LEVEL_V5,
+            Packet::PubAck(PubAck {
+                packet_identifier: PacketIdentifier::new(1).unwrap(),
+                reason_code: ReasonCode::NOT_AUTHORIZED,
+                properties: Properties::default(),
+            }),
+        );
+    }
+
+    #[test]
+    fn puback_v5_success_is_encoded_without_reason_code() {
+        let mut bytes = bytes::BytesMut::new();
+        PacketCodec::new(crate::PROTOCOL_LEVEL_V5)
+            .encode(
+                Packet::PubAck(PubAck {
+                    packet_identifier: PacketIdentifier::new(1).unwrap(),
+                    reason_code: ReasonCode::SUCCESS,
+                    properties: Properties::default(),
+                }),
+                &mut bytes,
+            )
+            .unwrap();
+
+        assert_eq!(&*bytes, &[0x40, 0x02, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn disconnect_v5_reason_code_roundtrip() {
+        roundtrip(
+            crate::PROTOCOL_LEVEL_V5,
+            Packet::Disconnect(Disconnect {
+                reason_code: ReasonCode::DISCONNECT_WITH_WILL_MESSAGE,
+                properties: Properties::default(),
+            }),
+        );
+        roundtrip(
+            crate::PROTOCOL_LEVEL_V5,
+            Packet::Disconnect(Disconnect::default()),
+        );
+        roundtrip(
+            crate::PROTOCOL_LEVEL,
+            Packet::Disconnect(Disconnect::default()),
+        );
+    }
+
+    #[test]
+    fn auth_is_rejected_before_v5() {
+        let mut bytes = bytes::BytesMut::from(&[0xF0, 0x00][..]);
+
+        let err = PacketCodec::default().decode(&mut bytes).unwrap_err();
+        if let DecodeError::UnrecognizedPacket { .. } = err {
+        } else {
+            panic!("{:?}", err);
+        }
+
+        roundtrip(crate::PROTOCOL_LEVEL_V5, Packet::Auth(Auth::default()));
+    }
+
+    #[test]
+    fn subscribe_v5_rejects_reserved_option_bits() {
+        let mut bytes =
+            bytes::BytesMut::from(&[0x82, 0x07, 0x00, 0x01, 0x00, 0x00, 0x01, b'a', 0xC1][..]);
+
+        let err = PacketCodec::new(crate::PROTOCOL_LEVEL_V5)
+            .decode(&mut bytes)
+            .unwrap_err();
+        if let DecodeError::UnrecognizedSubscriptionOptions(0xC1) = err {
+        } else {
+            panic!("{:?}", err);
+        }
+    }
+
+    #[test]
+    fn codec_switches_to_protocol_level_of_connect() {
+        let mut codec = PacketCodec::default();
+        let mut bytes = bytes::BytesMut::new();
+        PacketCodec::default()
+            .encode(
+                Packet::Connect(Connect {
+                    username: None,
+                    password: None,
+                    will: None,
+                    client_id: ClientId::IdWithCleanSession("id".to_string()),
+                    keep_alive: std::time::Duration::from_secs(30),
+                    protocol_name: crate::PROTOCOL_NAME.to_string(),
+                    protocol_level: crate::PROTOCOL_LEVEL_V5,
+                    properties: Properties::default(),
+                }),
+                &mut bytes,
+            )
+            .unwrap();
+
+        codec.decode(&mut bytes).unwrap().unwrap();
+        assert_eq!(codec.protocol_level(), crate::PROTOCOL_LEVEL_V5);
+    }
+}
@@ -0,0 +1,507 @@
+use std::convert::TryFrom;
+
+#[cfg(feature = "serde1")]
+use serde::{Deserialize, Serialize};
+use tokio_util::codec::Decoder;
+
+use crate::proto::{BufMutExt, ByteBuf};
+
+const PAYLOAD_FORMAT_INDICATOR: u8 = 0x01;
+const MESSAGE_EXPIRY_INTERVAL: u8 = 0x02;
+const CONTENT_TYPE: u8 = 0x03;
+const RESPONSE_TOPIC: u8 = 0x08;
+const CORRELATION_DATA: u8 = 0x09;
+const SUBSCRIPTION_IDENTIFIER: u8 = 0x0B;
+const SESSION_EXPIRY_INTERVAL: u8 = 0x11;
+const ASSIGNED_CLIENT_IDENTIFIER: u8 = 0x12;
+const SERVER_KEEP_ALIVE: u8 = 0x13;
+const AUTHENTICATION_METHOD: u8 = 0x15;
+const AUTHENTICATION_DATA: u8 = 0x16;
+const REQUEST_PROBLEM_INFORMATION: u8 = 0x17;
+const WILL_DELAY_INTERVAL: u8 = 0x18;
+const REQUEST_RESPONSE_INFORMATION: u8 = 0x19;
+const RESPONSE_INFORMATION: u8 = 0x1A;
+const SERVER_REFERENCE: u8 = 0x1C;
+const REASON_STRING: u8 = 0x1F;
+const RECEIVE_MAXIMUM: u8 = 0x21;
+const TOPIC_ALIAS_MAXIMUM: u8 = 0x22;
+const TOPIC_ALIAS: u8 = 0x23;
+const MAXIMUM_QOS: u8 = 0x24;
+const RETAIN_AVAILABLE: u8 = 0x25;
+const USER_PROPERTY: u8 = 0x26;
+const MAXIMUM_PACKET_SIZE: u8 = 0x27;
+const WILDCARD_SUBSCRIPTION_AVAILABLE: u8 = 0x28;
+const SUBSCRIPTION_IDENTIFIER_AVAILABLE: u8 = 0x29;
+const SHARED_SUBSCRIPTION_AVAILABLE: u8 = 0x2A;
+
+/// MQTT 5.0 properties of a packet.
+///
+/// Properties are only encoded and decoded when the protocol level is 5.
+/// Which properties are allowed in which packet is not enforced by the codec.
+///
+/// Ref: 2.2.2 Properties (MQTT 5.0)
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde1", derive(Deserialize, Serialize))]
+pub struct Properties {
+    pub payload_format_indicator: Option<u8>,
+    pub message_expiry_interval: Option<u32>,
+    pub content_type: Option<String>,
+    pub response_topic: Option<String>,
+    pub correlation_data: Option<Vec<u8>>,
+    pub subscription_identifiers: Vec<u32>,
+    pub session_expiry_interval: Option<u32>,
+    pub assigned_client_identifier: Option<String>,
+    pub server_keep_alive: Option<u16>,
+    pub authentication_method: Option<String>,
+    pub authentication_data: Option<Vec<u8>>,
+    pub request_problem_information: Option<u8>,
+    pub will_delay_interval: Option<u32>,
+    pub request_response_information: Option<u8>,
+    pub response_information: Option<String>,
+    pub server_reference: Option<String>,
+    pub reason_string: Option<String>,
+    pub receive_maximum: Option<u16>,
+    pub topic_alias_maximum: Option<u16>,
+    pub topic_alias: Option<u16>,
+    pub maximum_qos: Option<u8>,
+    pub retain_available: Option<u8>,
+    pub user_properties: Vec<(String, String)>,
+    pub maximum_packet_size: Option<u32>,
+    pub wildcard_subscription_available: Option<u8>,
+    pub subscription_identifier_available: Option<u8>,
+    pub shared_subscription_available: Option<u8>,
+}
+
+impl Properties {
+    pub fn is_empty(&self) -> bool {
+        self == &Properties::default()
+    }
+
+    /// Decodes a property length followed by the properties.
+    pub(crate) fn decode(src: &mut bytes::BytesMut) -> Result<Self, super::DecodeError> {
+        let len = super::RemainingLengthDecoder::default()
+            .decode(src)?
+            .ok_or(super::DecodeError::IncompletePacket)?;
+        if src.len() < len {
+            return Err(super::DecodeError::IncompletePacket);
+        }
+        let mut src = src.split_to(len);
+
+        let mut properties = Properties::default();
+
+        while !src.is_empty() {
+            let identifier = src.try_get_u8()?;
+            match identifier {
+                PAYLOAD_FORMAT_INDICATOR => set_once(
+                    &mut properties.payload_format_indicator,
+                    identifier,
+                    src.try_get_u8()?,
+                )?,
+                MESSAGE_EXPIRY_INTERVAL => set_once(
+                    &mut properties.message_expiry_interval,
+                    identifier,
+                    src.try_get_u32_be()?,
+                )?,
+                CONTENT_TYPE => set_once(
+                    &mut properties.content_type,
+                    identifier,
+                    decode_string(&mut src)?,
+                )?,
+                RESPONSE_TOPIC => set_once(
+                    &mut properties.response_topic,
+                    identifier,
+                    decode_string(&mut src)?,
+                )?,
+                CORRELATION_DATA => set_once(
+                    &mut properties.correlation_data,
+                    identifier,
+                    decode_binary(&mut src)?,
+                )?,
+                SUBSCRIPTION_IDENTIFIER => {
+                    let subscription_identifier = super::RemainingLengthDecoder::default()
+                        .decode(&mut src)?
+                        .ok_or(super::DecodeError::IncompletePacket)?;
+                    let subscription_identifier = u32::try_from(subscription_identifier)
+                        .map_err(|_| super::DecodeError::RemainingLengthTooHigh)?;
+                    properties
+                        .subscription_identifiers
+                        .push(subscription_identifier);
+                }
+                SESSION_EXPIRY_INTERVAL => set_once(
+                    &mut properties.session_expiry_interval,
+                    identifier,
+                    src.try_get_u32_be()?,
+                )?,
+                ASSIGNED_CLIENT_IDENTIFIER => set_once(
+                    &mut properties.assigned_client_identifier,
+                    identifier,
+                    decode_string(&mut src)?,
+                )?,
+                SERVER_KEEP_ALIVE => set_once(
+                    &mut properties.server_keep_alive,
+                    identifier,
+                    src.try_get_u16_be()?,
+                )?,
+                AUTHENTICATION_METHOD => set_once(
+                    &mut properties.authentication_method,
+                    identifier,
+                    decode_string(&mut src)?,
+                )?,
+                AUTHENTICATION_DATA => set_once(
+                    &mut properties.authentication_data,
+                    identifier,
+                    decode_binary(&mut src)?,
+                )?,
+                REQUEST_PROBLEM_INFORMATION => set_once(
+                    &mut properties.request_problem_information,
+                    identifier,
+                    src.try_get_u8()?,
+                )?,
+                WILL_DELAY_INTERVAL => set_once(
+                    &mut properties.will_delay_interval,
+                    identifier,
+                    src.try_get_u32_be()?,
+                )?,
+                REQUEST_RESPONSE_INFORMATION => set_once(
+                    &mut properties.request_response_information,
+                    identifier,
+                    src.try_get_u8()?,
+                )?,
+                RESPONSE_INFORMATION => set_once(
+                    &mut properties.response_information,
+                    identifier,
+                    decode_string(&mut src)?,
+                )?,
+                SERVER_REFERENCE => set_once(
+                    &mut properties.server_reference,
+                    identifier,
+                    decode_string(&mut src)?,
+                )?,
+                REASON_STRING => set_once(
+                    &mut properties.reason_string,
+                    identifier,
+                    decode_string(&mut src)?,
+                )?,
+                RECEIVE_MAXIMUM => set_once(
+                    &mut properties.receive_maximum,
+                    identifier,
+                    src.try_get_u16_be()?,
+                )?,
+                TOPIC_ALIAS_MAXIMUM => set_once(
+                    &mut properties.topic_alias_maximum,
+                    identifier,
+                    src.try_get_u16_be()?,
+                )?,
+                TOPIC_ALIAS => set_once(
+                    &mut properties.topic_alias,
+                    identifier,
+                    src.try_get_u16_be()?,
+                )?,
+                MAXIMUM_QOS => {
+                    set_once(&mut properties.maximum_qos, identifier, src.try_get_u8()?)?
+                }
+                RETAIN_AVAILABLE => set_once(
+                    &mut properties.retain_available,
+                    identifier,
+                    src.try_get_u8()?,
+                )?,
+                USER_PROPERTY => {
+                    let name = decode_string(&mut src)?;
+                    let value = decode_string(&mut src)?;
+                    properties.user_properties.push((name, value));
+                }
+                MAXIMUM_PACKET_SIZE => set_once(
+                    &mut properties.maximum_packet_size,
+                    identifier,
+                    src.try_get_u32_be()?,
+                )?,
+                WILDCARD_SUBSCRIPTION_AVAILABLE => set_once(
+                    &mut properties.wildcard_subscription_available,
+                    identifier,
+                    src.try_get_u8()?,
+                )?,
+                SUBSCRIPTION_IDENTIFIER_AVAILABLE => set_once(
+                    &mut properties.subscription_identifier_available,
+                    identifier,
+                    src.try_get_u8()?,
+                )?,
+                SHARED_SUBSCRIPTION_AVAILABLE => set_once(
+                    &mut properties.shared_subscription_available,
+                    identifier,
+                    src.try_get_u8()?,
+                )?,
+                identifier => return Err(super::DecodeError::UnrecognizedProperty(identifier)),
+            }
+        }
+
+        Ok(properties)
+    }
+
+    /// Encodes the property length followed by the properties.
+    pub(crate) fn encode<B>(&self, dst: &mut B) -> Result<(), super::EncodeError>
+    where
+        B: ByteBuf,
+    {
+        let mut counter = super::ByteCounter::new();
+        self.encode_inner(&mut counter)?;
+        super::encode_remaining_length(counter.0, dst)?;
+        self.encode_inner(dst)
+    }
+
+    fn encode_inner<B>(&self, dst: &mut B) -> Result<(), super::EncodeError>
+    where
+        B: ByteBuf,
+    {
+        let Properties {
+            payload_format_indicator,
+            message_expiry_interval,
+            content_type,
+            response_topic,
+            correlation_data,
+            subscription_identifiers,
+            session_expiry_interval,
+            assigned_client_identifier,
+            server_keep_alive,
+            authentication_method,
+            authentication_data,
+            request_problem_information,
+            will_delay_interval,
+            request_response_information,
+            response_information,
+            server_reference,
+            reason_string,
+            receive_maximum,
+            topic_alias_maximum,
+            topic_alias,
+            maximum_qos,
+            retain_available,
+            user_properties,
+            maximum_packet_size,
+            wildcard_subscription_available,
+            subscription_identifier_available,
+            shared_subscription_available,
+        } = self;
+
+        encode_u8(PAYLOAD_FORMAT_INDICATOR, *payload_format_indicator, dst);
+        encode_u32(MESSAGE_EXPIRY_INTERVAL, *message_expiry_interval, dst);
+        encode_string(CONTENT_TYPE, content_type.as_deref(), dst)?;
+        encode_string(RESPONSE_TOPIC, response_topic.as_deref(), dst)?;
+        encode_binary(CORRELATION_DATA, correlation_data.as_deref(), dst)?;
+        for subscription_identifier in subscription_identifiers {
+            dst.put_u8_bytes(SUBSCRIPTION_IDENTIFIER);
+            super::encode_remaining_length(*subscription_identifier as usize, dst)?;
+        }
+        encode_u32(SESSION_EXPIRY_INTERVAL, *session_expiry_interval, dst);
+        encode_string(
+            ASSIGNED_CLIENT_IDENTIFIER,
+            assigned_client_identifier.as_deref(),
+            dst,
+        )?;
+        encode_u16(SERVER_KEEP_ALIVE, *server_keep_alive, dst);
+        encode_string(AUTHENTICATION_METHOD, authentication_method.as_deref(), dst)?;
+        encode_binary(AUTHENTICATION_DATA, authentication_data.as_deref(), dst)?;
+        encode_u8(
+            REQUEST_PROBLEM_INFORMATION,
+            *request_problem_information,
+            dst,
+        );
+        encode_u32(WILL_DELAY_INTERVAL, *will_delay_interval, dst);
+        encode_u8(
+            REQUEST_RESPONSE_INFORMATION,
+            *request_response_information,
+            dst,
+        );
+        encode_string(RESPONSE_INFORMATION, response_information.as_deref(), dst)?;
+        encode_string(SERVER_REFERENCE, server_reference.as_deref(), dst)?;
+        encode_string(REASON_STRING, reason_string.as_deref(), dst)?;
+        encode_u16(RECEIVE_MAXIMUM, *receive_maximum, dst);
+        encode_u16(TOPIC_ALIAS_MAXIMUM, *topic_alias_maximum, dst);
+        encode_u16(TOPIC_ALIAS, *topic_alias, dst);
+        encode_u8(MAXIMUM_QOS, *maximum_qos, dst);
+        encode_u8(RETAIN_AVAILABLE, *retain_available, dst);
+        for (name, value) in user_properties {
+            dst.put_u8_bytes(USER_PROPERTY);
+            super::encode_utf8_str(name, dst)?;
+            super::encode_utf8_str(value, dst)?;
+        }
+        encode_u32(MAXIMUM_PACKET_SIZE, *maximum_packet_size, dst);
+        encode_u8(
+            WILDCARD_SUBSCRIPTION_AVAILABLE,
+            *wildcard_subscription_available,
+            dst,
+        );
+        encode_u8(
+            SUBSCRIPTION_IDENTIFIER_AVAILABLE,
+            *subscription_identifier_available,
+            dst,
+        );
+        encode_u8(
+            SHARED_SUBSCRIPTION_AVAILABLE,
+            *shared_subscription_available,
+            dst,
+        );
+
+        Ok(())
+    }
+}
+
+/// [MQTT5-2.2.2.2] It is a Protocol Error to include most properties more than once.
+fn set_once<T>(
+    property: &mut Option<T>,
+    identifier: u8,
+    value: T,
+) -> Result<(), super::DecodeError> {
+    if property.is_some() {
+        return Err(super::DecodeError::DuplicateProperty(identifier));
+    }
+
+    *property = Some(value);
+    Ok(())
+}
+
+fn decode_string(src: &mut bytes::BytesMut) -> Result<String, super::DecodeError> {
+    super::Utf8StringDecoder::default()
+        .decode(src)?
+        .ok_or(super::DecodeError::IncompletePacket)
+}
+
+pub(crate) fn decode_binary(src: &mut bytes::BytesMut) -> Result<Vec<u8>, super::DecodeError> {
+    let len = usize::from(src.try_get_u16_be()?);
+    if src.len() < len {
+        return Err(super::DecodeError::IncompletePacket);
+    }
+
+    Ok(src.split_to(len).to_vec())
+}
+
+fn encode_u8<B>(identifier: u8, value: Option<u8>, dst: &mut B)
+where
+    B: ByteBuf,
+{
+    if let Some(value) = value {
+        dst.put_u8_bytes(identifier);
+        dst.put_u8_bytes(value);
+    }
+}
+
+fn encode_u16<B>(identifier: u8, value: Option<u16>, dst: &mut B)
+where
+    B: ByteBuf,
+{
+    if let Some(value) = value {
+        dst.put_u8_bytes(identifier);
+        dst.put_u16_bytes(value);
+    }
+}
+
+fn encode_u32<B>(identifier: u8, value: Option<u32>, dst: &mut B)
+where
+    B: ByteBuf,
+{
+    if let Some(value) = value {
+        dst.put_u8_bytes(identifier);
+        dst.put_u32_bytes(value);
+    }
+}
+
+fn encode_string<B>(
+    identifier: u8,
+    value: Option<&str>,
+    dst: &mut B,
+) -> Result<(), super::EncodeError>
+where
+    B: ByteBuf,
+{
+    if let Some(value) = value {
+        dst.put_u8_bytes(identifier);
+        super::encode_utf8_str(value, dst)?;
+    }
+
+    Ok(())
+}
+
+fn encode_binary<B>(
+    identifier: u8,
+    value: Option<&[u8]>,
+    dst: &mut B,
+) -> Result<(), super::EncodeError>
+where
+    B: ByteBuf,
+{
+    if let Some(value) = value {
+        dst.put_u8_bytes(identifier);
+        encode_binary_data(value, dst)?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn encode_binary_data<B>(value: &[u8], dst: &mut B) -> Result<(), super::EncodeError>
+where
+    B: ByteBuf,
+{
+    let len = value.len();
+    dst.put_u16_bytes(u16::try_from(len).map_err(|_| super::EncodeError::BinaryTooLarge(len))?);
+    dst.put_slice_bytes(value);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Properties;
+
+    #[test]
+    fn properties_roundtrip() {
+        let properties = Properties {
+            message_expiry_interval: Some(60),
+            content_type: Some("application/json".to_string()),
+            correlation_data: Some(vec![0x00, 0xFF]),
+            subscription_identifiers: vec![1, 268_435_455],
+            topic_alias: Some(3),
+            reason_string: Some("reason".to_string()),
+            user_properties: vec![
+                ("a".to_string(), "1".to_string()),
+                ("a".to_string(), "2".to_string()),
+            ],
+            ..Properties::default()
+        };
+
+        let mut bytes = bytes::BytesMut::new();
+        properties.encode(&mut bytes).unwrap();
+
+        let decoded = Properties::decode(&mut bytes).unwrap();
+        assert_eq!(decoded, properties);
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn empty_properties_encode_to_zero_length() {
+        let mut bytes = bytes::BytesMut::new();
+        Properties::default().encode(&mut bytes).unwrap();
+
+        assert_eq!(&*bytes, &[0x00]);
+    }
+
+    #[test]
+    fn duplicate_property_is_rejected() {
+        let mut bytes = bytes::BytesMut::from(&[0x06, 0x23, 0x00, 0x01, 0x23, 0x00, 0x02][..]);
+
+        let err = Properties::decode(&mut bytes).unwrap_err();
+        if let super::super::DecodeError::DuplicateProperty(0x23) = err {
+        } else {
+            panic!("{:?}", err);
+        }
+    }
+
+    #[test]
+    fn unknown_property_is_rejected() {
+        let mut bytes = bytes::BytesMut::from(&[0x02, 0x7F, 0x00][..]);
+
+        let err = Properties::decode(&mut bytes).unwrap_err();
+        if let super::super::DecodeError::UnrecognizedProperty(0x7F) = err {
+        } else {
+            panic!("{:?}", err);
+        }
+    }
+}
@@ -20,11 +20,13 @@ fn server_publishes_at_most_once() {
                 keep_alive: std::time::Duration::from_secs(4),
                 protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                 protocol_level: mqtt3::PROTOCOL_LEVEL,
+                properties: mqtt3::proto::Properties::default(),
             },
         )),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(mqtt3::proto::ConnAck {
             session_present: false,
             return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+            properties: mqtt3::proto::Properties::default(),
         })),
         common::TestConnectionStep::Receives(mqtt3::proto::Packet::Subscribe(
             mqtt3::proto::Subscribe {
@@ -33,6 +35,7 @@ fn server_publishes_at_most_once() {
                     topic_filter: "topic1".to_owned(),
                     qos: mqtt3::proto::QoS::AtMostOnce,
                 }],
+                properties: mqtt3::proto::Properties::default(),
             },
         )),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::SubAck(mqtt3::proto::SubAck {
@@ -40,6 +43,7 @@ fn server_publishes_at_most_once() {
             qos: vec![mqtt3::proto::SubAckQos::Success(
                 mqtt3::proto::QoS::AtMostOnce,
             )],
+            properties: mqtt3::proto::Properties::default(),
         })),
         common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(mqtt3::proto::PingReq)),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::PingResp(mqtt3::proto::PingResp)),
@@ -48,6 +52,7 @@ fn server_publishes_at_most_once() {
             retain: false,
             topic_name: "topic1".to_owned(),
             payload: [0x01, 0x02, 0x03][..].into(),
+            properties: mqtt3::proto::Properties::default(),
         })),
         common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(mqtt3::proto::PingReq)),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::PingResp(mqtt3::proto::PingResp)),
@@ -87,6 +92,7 @@ fn server_publishes_at_most_once() {
                 qos: mqtt3::proto::QoS::AtMostOnce,
                 retain: false,
                 payload: [0x01, 0x02, 0x03][..].into(),
+                ack: None,
             }),
         ],
     );
@@ -114,11 +120,13 @@ fn server_publishes_at_least_once() {
                 keep_alive: std::time::Duration::from_secs(4),
                 protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                 protocol_level: mqtt3::PROTOCOL_LEVEL,
+                properties: mqtt3::proto::Properties::default(),
             },
         )),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(mqtt3::proto::ConnAck {
             session_present: false,
             return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+            properties: mqtt3::proto::Properties::default(),
         })),
         common::TestConnectionStep::Receives(mqtt3::proto::Packet::Subscribe(
             mqtt3::proto::Subscribe {
@@ -127,6 +135,7 @@ fn server_publishes_at_least_once() {
                     topic_filter: "topic1".to_owned(),
                     qos: mqtt3::proto::QoS::AtLeastOnce,
                 }],
+                properties: mqtt3::proto::Properties::default(),
             },
         )),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::SubAck(mqtt3::proto::SubAck {
@@ -134,6 +143,7 @@ fn server_publishes_at_least_once() {
             qos: vec![mqtt3::proto::SubAckQos::Success(
                 mqtt3::proto::QoS::AtLeastOnce,
             )],
+            properties: mqtt3::proto::Properties::default(),
         })),
         common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(mqtt3::proto::PingReq)),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::PingResp(mqtt3::proto::PingResp)),
@@ -145,9 +155,12 @@ fn server_publishes_at_least_once() {
             retain: false,
             topic_name: "topic1".to_owned(),
             payload: [0x01, 0x02, 0x03][..].into(),
+            properties: mqtt3::proto::Properties::default(),
         })),
         common::TestConnectionStep::Receives(mqtt3::proto::Packet::PubAck(mqtt3::proto::PubAck {
             packet_identifier: mqtt3::proto::PacketIdentifier::new(2).unwrap(),
+            reason_code: mqtt3::proto::ReasonCode::SUCCESS,
+            properties: mqtt3::proto::Properties::default(),
         })),
         common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(mqtt3::proto::PingReq)),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::PingResp(mqtt3::proto::PingResp)),
@@ -187,6 +200,7 @@ fn server_publishes_at_least_once() {
                 qos: mqtt3::proto::QoS::AtLeastOnce,
                 retain: false,
                 payload: [0x01, 0x02, 0x03][..].into(),
+                ack: None,
             }),
         ],
     );
@@ -215,12 +229,14 @@ fn server_publishes_at_least_once_with_reconnect_before_publish() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: false,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
         ],
@@ -236,12 +252,14 @@ fn server_publishes_at_least_once_with_reconnect_before_publish() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: true,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::Subscribe(
@@ -251,6 +269,7 @@ fn server_publishes_at_least_once_with_reconnect_before_publish() {
                         topic_filter: "topic1".to_owned(),
                         qos: mqtt3::proto::QoS::AtLeastOnce,
                     }],
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::SubAck(mqtt3::proto::SubAck {
@@ -258,6 +277,7 @@ fn server_publishes_at_least_once_with_reconnect_before_publish() {
                 qos: vec![mqtt3::proto::SubAckQos::Success(
                     mqtt3::proto::QoS::AtLeastOnce,
                 )],
+                properties: mqtt3::proto::Properties::default(),
             })),
         ],
         vec![
@@ -272,12 +292,14 @@ fn server_publishes_at_least_once_with_reconnect_before_publish() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: true,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(
@@ -295,11 +317,14 @@ fn server_publishes_at_least_once_with_reconnect_before_publish() {
                     retain: false,
                     topic_name: "topic1".to_owned(),
                     payload: [0x01, 0x02, 0x03][..].into(),
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::PubAck(
                 mqtt3::proto::PubAck {
                     packet_identifier: mqtt3::proto::PacketIdentifier::new(1).unwrap(),
+                    reason_code: mqtt3::proto::ReasonCode::SUCCESS,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(
@@ -351,6 +376,7 @@ fn server_publishes_at_least_once_with_reconnect_before_publish() {
                 qos: mqtt3::proto::QoS::AtLeastOnce,
                 retain: false,
                 payload: [0x01, 0x02, 0x03][..].into(),
+                ack: None,
             }),
         ],
     );
@@ -379,12 +405,14 @@ fn server_publishes_at_least_once_with_reconnect_before_ack() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: false,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
         ],
@@ -400,12 +428,14 @@ fn server_publishes_at_least_once_with_reconnect_before_ack() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: true,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::Subscribe(
@@ -415,6 +445,7 @@ fn server_publishes_at_least_once_with_reconnect_before_ack() {
                         topic_filter: "topic1".to_owned(),
                         qos: mqtt3::proto::QoS::AtLeastOnce,
                     }],
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::SubAck(mqtt3::proto::SubAck {
@@ -422,6 +453,7 @@ fn server_publishes_at_least_once_with_reconnect_before_ack() {
                 qos: vec![mqtt3::proto::SubAckQos::Success(
                     mqtt3::proto::QoS::AtLeastOnce,
                 )],
+                properties: mqtt3::proto::Properties::default(),
             })),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::Publish(
                 mqtt3::proto::Publish {
@@ -432,6 +464,7 @@ fn server_publishes_at_least_once_with_reconnect_before_ack() {
                     retain: false,
                     topic_name: "topic1".to_owned(),
                     payload: [0x01, 0x02, 0x03][..].into(),
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
         ],
@@ -447,12 +480,14 @@ fn server_publishes_at_least_once_with_reconnect_before_ack() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: true,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(
@@ -470,11 +505,14 @@ fn server_publishes_at_least_once_with_reconnect_before_ack() {
                     retain: false,
                     topic_name: "topic1".to_owned(),
                     payload: [0x01, 0x02, 0x03][..].into(),
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::PubAck(
                 mqtt3::proto::PubAck {
                     packet_identifier: mqtt3::proto::PacketIdentifier::new(1).unwrap(),
+                    reason_code: mqtt3::proto::ReasonCode::SUCCESS,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(
@@ -523,6 +561,7 @@ fn server_publishes_at_least_once_with_reconnect_before_ack() {
                 qos: mqtt3::proto::QoS::AtLeastOnce,
                 retain: false,
                 payload: [0x01, 0x02, 0x03][..].into(),
+                ack: None,
             }),
             mqtt3::Event::NewConnection {
                 reset_session: false,
@@ -533,6 +572,7 @@ fn server_publishes_at_least_once_with_reconnect_before_ack() {
                 qos: mqtt3::proto::QoS::AtLeastOnce,
                 retain: false,
                 payload: [0x01, 0x02, 0x03][..].into(),
+                ack: None,
             }),
         ],
     );
@@ -560,11 +600,13 @@ fn should_reject_invalid_publications() {
                 keep_alive: std::time::Duration::from_secs(4),
                 protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                 protocol_level: mqtt3::PROTOCOL_LEVEL,
+                properties: mqtt3::proto::Properties::default(),
             },
         )),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(mqtt3::proto::ConnAck {
             session_present: false,
             return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+            properties: mqtt3::proto::Properties::default(),
         })),
         common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(mqtt3::proto::PingReq)),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::PingResp(mqtt3::proto::PingResp)),
@@ -588,6 +630,7 @@ fn should_reject_invalid_publications() {
         qos: mqtt3::proto::QoS::AtMostOnce,
         retain: false,
         payload: Default::default(),
+        properties: mqtt3::proto::Properties::default(),
     });
 
     common::verify_client_events(
@@ -21,12 +21,14 @@ fn server_generated_id_must_always_resubscribe() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: false,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::Subscribe(
@@ -46,6 +48,7 @@ fn server_generated_id_must_always_resubscribe() {
                             qos: mqtt3::proto::QoS::ExactlyOnce,
                         },
                     ],
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::SubAck(mqtt3::proto::SubAck {
@@ -55,6 +58,7 @@ fn server_generated_id_must_always_resubscribe() {
                     mqtt3::proto::SubAckQos::Success(mqtt3::proto::QoS::AtLeastOnce),
                     mqtt3::proto::SubAckQos::Success(mqtt3::proto::QoS::ExactlyOnce),
                 ],
+                properties: mqtt3::proto::Properties::default(),
             })),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(
                 mqtt3::proto::PingReq,
@@ -73,12 +77,14 @@ fn server_generated_id_must_always_resubscribe() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: false,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::Subscribe(
@@ -98,6 +104,7 @@ fn server_generated_id_must_always_resubscribe() {
                             qos: mqtt3::proto::QoS::ExactlyOnce,
                         },
                     ],
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::SubAck(mqtt3::proto::SubAck {
@@ -107,6 +114,7 @@ fn server_generated_id_must_always_resubscribe() {
                     mqtt3::proto::SubAckQos::Success(mqtt3::proto::QoS::AtLeastOnce),
                     mqtt3::proto::SubAckQos::Success(mqtt3::proto::QoS::ExactlyOnce),
                 ],
+                properties: mqtt3::proto::Properties::default(),
             })),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(
                 mqtt3::proto::PingReq,
@@ -228,12 +236,14 @@ fn client_id_should_not_resubscribe_when_session_is_present() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: false,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::Subscribe(
@@ -253,6 +263,7 @@ fn client_id_should_not_resubscribe_when_session_is_present() {
                             qos: mqtt3::proto::QoS::ExactlyOnce,
                         },
                     ],
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::SubAck(mqtt3::proto::SubAck {
@@ -262,6 +273,7 @@ fn client_id_should_not_resubscribe_when_session_is_present() {
                     mqtt3::proto::SubAckQos::Success(mqtt3::proto::QoS::AtLeastOnce),
                     mqtt3::proto::SubAckQos::Success(mqtt3::proto::QoS::ExactlyOnce),
                 ],
+                properties: mqtt3::proto::Properties::default(),
             })),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(
                 mqtt3::proto::PingReq,
@@ -282,6 +294,7 @@ fn client_id_should_not_resubscribe_when_session_is_present() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
@@ -291,6 +304,7 @@ fn client_id_should_not_resubscribe_when_session_is_present() {
                     // So this second session will still have `session_present == false`
                     session_present: false,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::Subscribe(
@@ -310,6 +324,7 @@ fn client_id_should_not_resubscribe_when_session_is_present() {
                             qos: mqtt3::proto::QoS::ExactlyOnce,
                         },
                     ],
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::SubAck(mqtt3::proto::SubAck {
@@ -319,6 +334,7 @@ fn client_id_should_not_resubscribe_when_session_is_present() {
                     mqtt3::proto::SubAckQos::Success(mqtt3::proto::QoS::AtLeastOnce),
                     mqtt3::proto::SubAckQos::Success(mqtt3::proto::QoS::ExactlyOnce),
                 ],
+                properties: mqtt3::proto::Properties::default(),
             })),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(
                 mqtt3::proto::PingReq,
@@ -339,12 +355,14 @@ fn client_id_should_not_resubscribe_when_session_is_present() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: true,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    properties: mqtt3::proto::Properties::default(),
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(
@@ -450,11 +468,13 @@ fn should_combine_pending_subscription_updates() {
                 keep_alive: std::time::Duration::from_secs(4),
                 protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                 protocol_level: mqtt3::PROTOCOL_LEVEL,
+                properties: mqtt3::proto::Properties::default(),
             },
         )),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(mqtt3::proto::ConnAck {
             session_present: false,
             return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+            properties: mqtt3::proto::Properties::default(),
         })),
         common::TestConnectionStep::Receives(mqtt3::proto::Packet::Subscribe(
             mqtt3::proto::Subscribe {
@@ -469,6 +489,7 @@ fn should_combine_pending_subscription_updates() {
                         qos: mqtt3::proto::QoS::ExactlyOnce,
                     },
                 ],
+                properties: mqtt3::proto::Properties::default(),
             },
         )),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::SubAck(mqtt3::proto::SubAck {
@@ -477,6 +498,7 @@ fn should_combine_pending_subscription_updates() {
                 mqtt3::proto::SubAckQos::Success(mqtt3::proto::QoS::AtLeastOnce),
                 mqtt3::proto::SubAckQos::Success(mqtt3::proto::QoS::ExactlyOnce),
             ],
+            properties: mqtt3::proto::Properties::default(),
         })),
         common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(mqtt3::proto::PingReq)),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::PingResp(mqtt3::proto::PingResp)),
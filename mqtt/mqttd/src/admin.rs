@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::convert::{Infallible, TryFrom};
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+
+use hyper::body::{Bytes, HttpBody};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use mqtt3::proto;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use mqtt_broker::{
+    Admin, AdminError, BrokerHandle, ClientId, Error, InitializeBrokerError, StateSnapshotHandle,
+};
+
+/// Serves the administrative API of the broker.
+///
+/// * `GET /sessions` - lists sessions.
+/// * `POST /sessions/disconnect?client_id=<id>` - drops the connection of a client.
+/// * `DELETE /sessions?client_id=<id>` - removes an offline session.
+/// * `GET /retained` - lists retained messages with base64 encoded payloads.
+/// * `PUT /retained?topic=<topic>&qos=<qos>` - retains the request body on a topic.
+///   Bodies above the maximum message size of sessions are refused.
+/// * `DELETE /retained[?topic=<topic>]` - removes one or all retained messages
+///   of clients.
+/// * `POST /snapshot` - persists the broker state.
+///
+/// The API bypasses authorization of clients. If a token is configured, every
+/// request must carry it in an `Authorization: Bearer <token>` header. Without
+/// a token the API refuses to listen on anything but a loopback address.
+///
+/// Binds the listener right away, so that the broker fails to start if the
+/// API cannot be served, and returns the future serving the requests.
+pub fn serve(
+    config: &Admin,
+    max_message_size: u64,
+    broker_handle: BrokerHandle,
+    snapshot_handle: StateSnapshotHandle,
+) -> Result<impl Future<Output = ()>, InitializeBrokerError> {
+    let address = config.address();
+    if config.token().is_none() && !address.ip().is_loopback() {
+        return Err(InitializeBrokerError::UnprotectedAdmin(address));
+    }
+
+    let server = Server::try_bind(&address)
+        .map_err(|e| InitializeBrokerError::BindServer(io::Error::new(io::ErrorKind::Other, e)))?;
+
+    let token: Option<Arc<str>> = config.token().map(Into::into);
+    let make_service = make_service_fn(move |_| {
+        let broker_handle = broker_handle.clone();
+        let snapshot_handle = snapshot_handle.clone();
+        let token = token.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let authorized = token
+                    .as_ref()
+                    .map_or(true, |token| is_authorized(&request, token));
+                let broker_handle = broker_handle.clone();
+                let snapshot_handle = snapshot_handle.clone();
+                async move {
+                    if authorized {
+                        handle_request(request, max_message_size, broker_handle, snapshot_handle)
+                            .await
+                    } else {
+                        warn!("refused admin request without a valid token");
+                        Ok(Response::builder()
+                            .status(StatusCode::UNAUTHORIZED)
+                            .header(header::WWW_AUTHENTICATE, "Bearer")
+                            .body(Body::empty())
+                            .expect("response must be valid"))
+                    }
+                }
+            }))
+        }
+    });
+
+    info!("Serving admin API on address {}", address);
+    Ok(async move {
+        if let Err(e) = server.serve(make_service).await {
+            warn!(message = "admin listener exited with an error", error=%e);
+        }
+    })
+}
+
+const BEARER: &str = "Bearer ";
+
+/// Checks the bearer token of a request in constant time, so that the time
+/// it takes does not tell how much of the token matched.
+fn is_authorized(request: &Request<Body>, token: &str) -> bool {
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| value.starts_with(BEARER))
+        .map(|value| &value[BEARER.len()..]);
+
+    match provided {
+        Some(provided) => {
+            provided.len() == token.len()
+                && provided
+                    .bytes()
+                    .zip(token.bytes())
+                    .fold(0, |diff, (a, b)| diff | (a ^ b))
+                    == 0
+        }
+        None => false,
+    }
+}
+
+async fn handle_request(
+    request: Request<Body>,
+    max_message_size: u64,
+    mut broker_handle: BrokerHandle,
+    snapshot_handle: StateSnapshotHandle,
+) -> Result<Response<Body>, Infallible> {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let query: HashMap<String, String> = request
+        .uri()
+        .query()
+        .map(|query| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let response = match (method, path.as_str()) {
+        (Method::GET, "/sessions") => json(broker_handle.sessions().await),
+        (Method::POST, "/sessions/disconnect") => match client_id(&query) {
+            Some(client_id) => admin(broker_handle.disconnect_client(client_id).await),
+            None => status(StatusCode::BAD_REQUEST),
+        },
+        (Method::DELETE, "/sessions") => match client_id(&query) {
+            Some(client_id) => admin(broker_handle.purge_session(client_id).await),
+            None => status(StatusCode::BAD_REQUEST),
+        },
+        (Method::GET, "/retained") => json(broker_handle.retained().await),
+        (Method::PUT, "/retained") => {
+            let topic = query.get("topic").cloned();
+            let qos = query
+                .get("qos")
+                .map_or(Some(proto::QoS::AtMostOnce), |qos| qos_from_str(qos));
+            match (topic, qos) {
+                (Some(topic), Some(qos)) => match read_body(request, max_message_size).await {
+                    Ok(payload) => admin(broker_handle.set_retained(topic, qos, payload).await),
+                    Err(BodyError::TooLarge) => {
+                        warn!("refused retained message above the maximum message size");
+                        status(StatusCode::PAYLOAD_TOO_LARGE)
+                    }
+                    Err(BodyError::Read(e)) => {
+                        warn!(message = "failed to read request body", error=%e);
+                        status(StatusCode::BAD_REQUEST)
+                    }
+                },
+                _ => status(StatusCode::BAD_REQUEST),
+            }
+        }
+        (Method::DELETE, "/retained") => {
+            let topic = query.get("topic").cloned();
+            json(
+                broker_handle
+                    .clear_retained(topic)
+                    .await
+                    .map(|removed| Removed { removed }),
+            )
+        }
+        (Method::POST, "/snapshot") => json(broker_handle.snapshot(snapshot_handle).await),
+        _ => status(StatusCode::NOT_FOUND),
+    };
+
+    Ok(response)
+}
+
+#[derive(Debug)]
+enum BodyError {
+    TooLarge,
+    Read(hyper::Error),
+}
+
+/// Buffers the body of a request, refusing it as soon as it is known to be
+/// larger than `max_size` bytes. A `max_size` of zero means no limit.
+async fn read_body(request: Request<Body>, max_size: u64) -> Result<Bytes, BodyError> {
+    let too_large = |len: u64| max_size != 0 && len > max_size;
+
+    let content_length = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+    if content_length.map_or(false, too_large) {
+        return Err(BodyError::TooLarge);
+    }
+
+    let mut body = request.into_body();
+    let mut payload = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(BodyError::Read)?;
+        let len = u64::try_from(payload.len() + chunk.len()).unwrap_or(u64::max_value());
+        if too_large(len) {
+            return Err(BodyError::TooLarge);
+        }
+        payload.extend_from_slice(&chunk);
+    }
+    Ok(payload.into())
+}
+
+#[derive(Serialize)]
+struct Removed {
+    removed: usize,
+}
+
+fn client_id(query: &HashMap<String, String>) -> Option<ClientId> {
+    query.get("client_id").map(|id| ClientId::from(id.as_str()))
+}
+
+fn qos_from_str(qos: &str) -> Option<proto::QoS> {
+    match qos {
+        "0" => Some(proto::QoS::AtMostOnce),
+        "1" => Some(proto::QoS::AtLeastOnce),
+        "2" => Some(proto::QoS::ExactlyOnce),
+        _ => None,
+    }
+}
+
+fn admin(result: Result<Result<(), AdminError>, Error>) -> Response<Body> {
+    match result {
+        Ok(Ok(())) => status(StatusCode::NO_CONTENT),
+        Ok(Err(e @ AdminError::NoSession(_))) => error(StatusCode::NOT_FOUND, &e),
+        Ok(Err(e @ AdminError::InvalidTopic(_))) => error(StatusCode::BAD_REQUEST, &e),
+        Ok(Err(e)) => error(StatusCode::CONFLICT, &e),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e),
+    }
+}
+
+fn json<T: Serialize>(result: Result<T, Error>) -> Response<Body> {
+    match result.map(|value| serde_json::to_vec(&value)) {
+        Ok(Ok(body)) => Response::builder()
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .expect("response must be valid"),
+        Ok(Err(e)) => error(StatusCode::INTERNAL_SERVER_ERROR, &e),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e),
+    }
+}
+
+fn error(code: StatusCode, e: &dyn std::error::Error) -> Response<Body> {
+    warn!(message = "admin request failed", error=%e);
+    Response::builder()
+        .status(code)
+        .body(Body::from(e.to_string()))
+        .expect("response must be valid")
+}
+
+fn status(code: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(code)
+        .body(Body::empty())
+        .expect("response must be valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::body::Bytes;
+    use hyper::{header, Body, Request};
+
+    use super::{read_body, BodyError};
+
+    fn request(body: &'static str, content_length: Option<usize>) -> Request<Body> {
+        let mut request = Request::put("/retained?topic=topic");
+        if let Some(content_length) = content_length {
+            request = request.header(header::CONTENT_LENGTH, content_length);
+        }
+        request.body(Body::from(body)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn read_body_up_to_max_size() {
+        let payload = read_body(request("payload", Some(7)), 7).await.unwrap();
+        assert_eq!(payload, Bytes::from("payload"));
+
+        let payload = read_body(request("payload", None), 0).await.unwrap();
+        assert_eq!(payload, Bytes::from("payload"));
+    }
+
+    #[tokio::test]
+    async fn read_body_refuses_body_above_max_size() {
+        let payload = read_body(request("", Some(8)), 7).await;
+        assert!(matches!(payload, Err(BodyError::TooLarge)));
+
+        let payload = read_body(request("too large", None), 7).await;
+        assert!(matches!(payload, Err(BodyError::TooLarge)));
+    }
+}
@@ -0,0 +1,180 @@
+use std::sync::RwLock;
+
+use mqtt_broker::{
+    AclAuthorizer, AclError, Activity, AuthId, Authentication, Authenticator, Authorization,
+    Authorizer, BrokerConfig, CertificateAuthenticateError, CertificateAuthenticator, ClientId,
+    Credentials, PasswordAuthenticator, PasswordFile, PasswordFileError,
+    PeerCredentialsAuthenticator,
+};
+
+/// Authenticator selected by the `authentication` section of the broker configuration.
+///
+/// Clients connecting with a client certificate or with peer credentials are
+/// identified by the certificate or by the configured user ids, all other
+/// clients by username and password. Clients whose transport credentials
+/// identify nobody are treated as anonymous clients.
+///
+/// The settings, including the users of the password file, are loaded again
+/// when the broker configuration is updated.
+pub struct ConfiguredAuthenticator(RwLock<Methods>);
+
+struct Methods {
+    basic: BasicAuthenticator,
+    certificate: Option<CertificateAuthenticator>,
+    peer_credentials: PeerCredentialsAuthenticator,
+}
+
+enum BasicAuthenticator {
+    /// No password file configured. Every client is anonymous, if allowed.
+    Anonymous {
+        allowed: bool,
+    },
+    Password(PasswordAuthenticator),
+}
+
+impl ConfiguredAuthenticator {
+    pub fn from_config(config: &Authentication) -> Result<Self, PasswordFileError> {
+        Ok(Self(RwLock::new(Methods::from_config(config)?)))
+    }
+}
+
+impl Methods {
+    fn from_config(config: &Authentication) -> Result<Self, PasswordFileError> {
+        let basic = match config.password_file() {
+            None => BasicAuthenticator::Anonymous {
+                allowed: config.allow_anonymous(),
+            },
+            Some(path) => {
+                let file = PasswordFile::load(path)?;
+                BasicAuthenticator::Password(PasswordAuthenticator::new(
+                    file,
+                    config.allow_anonymous(),
+                ))
+            }
+        };
+
+        let certificate = config
+            .certificate_identity()
+            .map(CertificateAuthenticator::new);
+
+        let peer_credentials = PeerCredentialsAuthenticator::new(
+            config
+                .peer_credentials()
+                .iter()
+                .map(|peer| (peer.uid(), peer.identity().to_string())),
+        );
+
+        Ok(Self {
+            basic,
+            certificate,
+            peer_credentials,
+        })
+    }
+
+    fn authenticate(&self, credentials: Credentials) -> Result<Option<AuthId>, AuthenticateError> {
+        let auth_id = match credentials {
+            Credentials::ClientCertificate(chain) => match &self.certificate {
+                Some(certificate) => {
+                    certificate.authenticate(Credentials::ClientCertificate(chain))?
+                }
+                None => None,
+            },
+            Credentials::PeerCredentials(peer) => self
+                .peer_credentials
+                .authenticate(Credentials::PeerCredentials(peer))
+                .unwrap_or_else(|never| match never {}),
+            credentials => return self.authenticate_basic(credentials),
+        };
+
+        match auth_id {
+            Some(auth_id) => Ok(Some(auth_id)),
+            None => self.authenticate_basic(Credentials::Basic(None, None)),
+        }
+    }
+
+    fn authenticate_basic(
+        &self,
+        credentials: Credentials,
+    ) -> Result<Option<AuthId>, AuthenticateError> {
+        match &self.basic {
+            BasicAuthenticator::Anonymous { allowed: true } => Ok(Some(AuthId::Anonymous)),
+            BasicAuthenticator::Anonymous { allowed: false } => Ok(None),
+            BasicAuthenticator::Password(password) => Ok(password.authenticate(credentials)?),
+        }
+    }
+}
+
+impl Authenticator for ConfiguredAuthenticator {
+    type Error = AuthenticateError;
+
+    fn authenticate(&self, credentials: Credentials) -> Result<Option<AuthId>, Self::Error> {
+        self.0
+            .read()
+            .expect("authentication methods")
+            .authenticate(credentials)
+    }
+
+    /// Replaces the settings with the ones of the updated configuration.
+    /// The settings in effect are kept if the password file cannot be loaded.
+    fn update_config(&self, config: &BrokerConfig) -> Result<(), Self::Error> {
+        let methods = Methods::from_config(config.authentication())?;
+        *self.0.write().expect("authentication methods") = methods;
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthenticateError {
+    #[error("An error occurred verifying client password.")]
+    Password(#[from] PasswordFileError),
+
+    #[error("An error occurred verifying client certificate.")]
+    Certificate(#[from] CertificateAuthenticateError),
+}
+
+/// Authorizer selected by the `authorization` section of the broker configuration.
+pub enum ConfiguredAuthorizer {
+    AllowAll,
+    Acl(AclAuthorizer),
+}
+
+impl ConfiguredAuthorizer {
+    pub fn from_config(config: &BrokerConfig) -> Result<Self, AclError> {
+        match config.authorization() {
+            None | Some(Authorization::AllowAll) => Ok(Self::AllowAll),
+            Some(Authorization::Acl { path }) => Ok(Self::Acl(AclAuthorizer::from_file(path)?)),
+        }
+    }
+}
+
+impl Authorizer for ConfiguredAuthorizer {
+    type Error = AclError;
+
+    fn authorize(&self, activity: Activity) -> Result<bool, Self::Error> {
+        match self {
+            Self::AllowAll => Ok(true),
+            Self::Acl(acl) => acl.authorize(activity),
+        }
+    }
+
+    fn max_inflight_messages(&self, auth_id: &AuthId, client_id: &ClientId) -> Option<usize> {
+        match self {
+            Self::AllowAll => None,
+            Self::Acl(acl) => acl.max_inflight_messages(auth_id, client_id),
+        }
+    }
+
+    fn reload(&mut self) -> Result<(), Self::Error> {
+        match self {
+            Self::AllowAll => Ok(()),
+            Self::Acl(acl) => acl.reload(),
+        }
+    }
+
+    /// Replaces the policy with the one the updated configuration selects.
+    /// The policy in effect is kept if the new one cannot be loaded.
+    fn update_config(&mut self, config: &BrokerConfig) -> Result<(), Self::Error> {
+        *self = Self::from_config(config)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,635 @@
+//! Forwards publications between the local broker and a remote one.
+//!
+//! A bridge is a client of both brokers. Upstream it is a `mqtt3::Client`
+//! which reconnects on its own. Locally it talks to the broker through a
+//! `BrokerHandle` the same way the connection of a local client does,
+//! with a persistent session. Publications going out are only acknowledged
+//! to the local broker once the remote broker accepted them, so while the
+//! upstream is offline they wait in the bridge's session on the local broker.
+//!
+//! Publications the bridge receives from the remote broker are tagged with
+//! the `$bridge` user property, so that the bridge does not forward them
+//! back when the topic is bridged both ways.
+
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::future::{self, Either};
+use futures_util::pin_mut;
+use futures_util::stream::StreamExt;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tracing::{debug, info, warn};
+
+use mqtt3::io_source::{TcpSource, TlsConfig, TlsSource};
+use mqtt3::{proto, IoSource, PublishError, PublishHandle};
+use mqtt_broker::{
+    AuthId, BridgeConfig, BridgeTls, BridgeTopic, BrokerHandle, ClientEvent, ClientId, ConnReq,
+    ConnectionHandle, Error, Message, Publish, TopicFilter,
+};
+
+const LOCAL_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Name of the user property which tags the publications a bridge injected
+/// into the local broker. The value is the name of the bridge.
+const BRIDGE_PROPERTY: &str = "$bridge";
+
+pub struct Bridge {
+    config: BridgeConfig,
+    mapper: Arc<TopicMapper>,
+    broker_handle: BrokerHandle,
+}
+
+impl Bridge {
+    pub fn new(config: BridgeConfig, broker_handle: BrokerHandle) -> Result<Self, Error> {
+        let mapper = TopicMapper::new(config.topics())?;
+        Ok(Self {
+            config,
+            mapper: Arc::new(mapper),
+            broker_handle,
+        })
+    }
+
+    pub async fn run(self) {
+        let name = self.config.name().to_owned();
+        info!("starting bridge {} to {}", name, self.config.address());
+
+        let address = self.config.address().to_owned();
+        let password = self.config.password().map(ToOwned::to_owned);
+        match self.config.tls().map(tls_config) {
+            Some(Ok(tls_config)) => {
+                let io_source = TlsSource::new(address, tls_config).with_password(password);
+                self.run_with(io_source).await;
+            }
+            Some(Err(e)) => {
+                warn!(message = "bridge failed to load its TLS settings", error = %e);
+            }
+            None => {
+                let io_source = TcpSource::new(address).with_password(password);
+                self.run_with(io_source).await;
+            }
+        }
+
+        info!("bridge {} stopped", name);
+    }
+
+    async fn run_with<IoS>(self, io_source: IoS)
+    where
+        IoS: IoSource,
+        <IoS as IoSource>::Io: Unpin,
+        <IoS as IoSource>::Error: std::fmt::Display,
+        <IoS as IoSource>::Future: Unpin,
+    {
+        let Bridge {
+            config,
+            mapper,
+            broker_handle,
+        } = self;
+        let client_id = ClientId::from(format!("$bridge/{}", config.name()));
+
+        let mut client = upstream_client(&config, io_source);
+        for topic in config.topics().iter().filter(|t| t.direction().is_in()) {
+            let subscribe_to = proto::SubscribeTo {
+                topic_filter: format!("{}{}", topic.remote_prefix(), topic.pattern()),
+                qos: topic.qos(),
+            };
+            if let Err(e) = client.subscribe(subscribe_to) {
+                warn!(message = "bridge failed to subscribe upstream", error = %e);
+            }
+        }
+
+        let publish_handle = match client.publish_handle() {
+            Ok(publish_handle) => publish_handle,
+            Err(e) => {
+                warn!(message = "bridge failed to get upstream publish handle", error = %e);
+                return;
+            }
+        };
+        let mut shutdown_handle = match client.shutdown_handle() {
+            Ok(shutdown_handle) => shutdown_handle,
+            Err(e) => {
+                warn!(message = "bridge failed to get upstream shutdown handle", error = %e);
+                return;
+            }
+        };
+
+        let local = Local {
+            name: config.name().to_owned(),
+            client_id: client_id.clone(),
+            broker_handle: broker_handle.clone(),
+            mapper: mapper.clone(),
+        };
+        let rx = match local.connect(config.topics()).await {
+            Ok(rx) => rx,
+            Err(e) => {
+                warn!(message = "bridge failed to connect to the local broker", error = %e);
+                return;
+            }
+        };
+
+        let upstream = Upstream {
+            name: config.name().to_owned(),
+            client_id,
+            broker_handle,
+            mapper,
+        };
+
+        // Both halves stop together, so that the bridge never forwards
+        // publications one way only.
+        let local_run = local.run(rx, config.topics(), publish_handle);
+        let upstream_run = upstream.run(client);
+        pin_mut!(local_run, upstream_run);
+        match future::select(local_run, upstream_run).await {
+            Either::Left(((), upstream_run)) => {
+                if let Err(e) = shutdown_handle.shutdown().await {
+                    warn!(message = "bridge failed to shut down upstream client", error = %e);
+                }
+                upstream_run.await;
+            }
+            Either::Right(((), _)) => {
+                if let Err(e) = local.send(ClientEvent::DropConnection).await {
+                    warn!(message = "bridge failed to disconnect from the local broker", error = %e);
+                }
+            }
+        }
+    }
+}
+
+fn upstream_client<IoS>(config: &BridgeConfig, io_source: IoS) -> mqtt3::Client<IoS>
+where
+    IoS: IoSource,
+{
+    let username = config.username().map(ToOwned::to_owned);
+    match config.client_id() {
+        Some(client_id) if !config.clean_session() => mqtt3::Client::from_state(
+            client_id.to_owned(),
+            username,
+            None,
+            io_source,
+            config.max_reconnect_back_off(),
+            config.keep_alive(),
+        ),
+        client_id => mqtt3::Client::new(
+            client_id.map(ToOwned::to_owned),
+            username,
+            None,
+            io_source,
+            config.max_reconnect_back_off(),
+            config.keep_alive(),
+        ),
+    }
+}
+
+fn tls_config(tls: &BridgeTls) -> Result<TlsConfig, std::io::Error> {
+    let mut tls_config = TlsConfig::default();
+    if let Some(ca_bundle) = tls.ca_bundle() {
+        tls_config = tls_config.with_ca_certificates(fs::read(ca_bundle)?);
+    }
+    if let Some(client_certificate) = tls.client_certificate() {
+        tls_config = tls_config.with_client_certificate(
+            fs::read(client_certificate.certificate())?,
+            fs::read(client_certificate.private_key())?,
+        );
+    }
+    Ok(tls_config)
+}
+
+/// The bridge as a client of the local broker.
+struct Local {
+    name: String,
+    client_id: ClientId,
+    broker_handle: BrokerHandle,
+    mapper: Arc<TopicMapper>,
+}
+
+impl Local {
+    async fn connect(&self, topics: &[BridgeTopic]) -> Result<UnboundedReceiver<Message>, Error> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let connect = proto::Connect {
+            username: None,
+            password: None,
+            will: None,
+            client_id: proto::ClientId::IdWithExistingSession(self.client_id.to_string()),
+            keep_alive: Duration::default(),
+            protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
+            protocol_level: mqtt3::PROTOCOL_LEVEL,
+            properties: proto::Properties::default(),
+        };
+        let handle = ConnectionHandle::from_sender(tx);
+        let connreq = ConnReq::new(self.client_id.clone(), connect, None, handle)
+            .with_auth_id(AuthId::from_identity(self.client_id.to_string()));
+        self.send(ClientEvent::ConnReq(connreq)).await?;
+
+        let subscribe_to: Vec<_> = topics
+            .iter()
+            .filter(|t| t.direction().is_out())
+            .map(|topic| proto::SubscribeTo {
+                topic_filter: format!("{}{}", topic.local_prefix(), topic.pattern()),
+                qos: topic.qos(),
+            })
+            .collect();
+        if !subscribe_to.is_empty() {
+            let subscribe = proto::Subscribe {
+                packet_identifier: proto::PacketIdentifier::new(1).expect("1 is non-zero"),
+                subscribe_to,
+                properties: proto::Properties::default(),
+            };
+            self.send(ClientEvent::Subscribe(subscribe)).await?;
+        }
+
+        Ok(rx)
+    }
+
+    async fn run(
+        &self,
+        mut rx: UnboundedReceiver<Message>,
+        topics: &[BridgeTopic],
+        publish_handle: PublishHandle,
+    ) {
+        // The broker redelivers the publications left unacknowledged
+        // in the bridge's session once the bridge reconnects.
+        while self.process(&mut rx, &publish_handle).await {
+            info!(
+                "bridge {} lost the local connection, reconnecting...",
+                self.client_id
+            );
+            tokio::time::delay_for(LOCAL_RECONNECT_DELAY).await;
+            rx = match self.connect(topics).await {
+                Ok(rx) => rx,
+                Err(e) => {
+                    warn!(message = "bridge failed to connect to the local broker", error = %e);
+                    return;
+                }
+            };
+        }
+    }
+
+    /// Processes the events of one local connection.
+    /// Returns `false` if the local broker refused the bridge.
+    async fn process(
+        &self,
+        rx: &mut UnboundedReceiver<Message>,
+        publish_handle: &PublishHandle,
+    ) -> bool {
+        while let Some(message) = rx.recv().await {
+            let event = match message {
+                Message::Client(_, event) => event,
+                Message::System(_) => continue,
+            };
+
+            let result = match event {
+                ClientEvent::ConnAck(connack) => {
+                    if let proto::ConnectReturnCode::Refused(reason) = connack.return_code {
+                        warn!(
+                            "local broker refused bridge {}: {:?}",
+                            self.client_id, reason
+                        );
+                        return false;
+                    }
+                    Ok(())
+                }
+                ClientEvent::PublishTo(publish) => {
+                    self.forward(publish, publish_handle.clone());
+                    Ok(())
+                }
+                ClientEvent::PubRel(pubrel) => {
+                    let pubcomp = proto::PubComp {
+                        packet_identifier: pubrel.packet_identifier,
+                        reason_code: proto::ReasonCode::SUCCESS,
+                        properties: proto::Properties::default(),
+                    };
+                    self.send(ClientEvent::PubComp(pubcomp)).await
+                }
+                ClientEvent::PubRec(pubrec) => {
+                    let pubrel = proto::PubRel {
+                        packet_identifier: pubrec.packet_identifier,
+                        reason_code: proto::ReasonCode::SUCCESS,
+                        properties: proto::Properties::default(),
+                    };
+                    self.send(ClientEvent::PubRel(pubrel)).await
+                }
+                ClientEvent::DropConnection | ClientEvent::Disconnect(_) => return true,
+                _ => Ok(()),
+            };
+
+            if let Err(e) = result {
+                warn!(message = "bridge failed to process a local event", error = %e);
+            }
+        }
+        true
+    }
+
+    /// Publishes a publication of the local broker to the remote one and
+    /// acknowledges it to the local broker once it has been published.
+    ///
+    /// The upstream client keeps a publication until the remote broker
+    /// accepted it, reconnecting as needed. It only fails to publish if the
+    /// publication can never be sent, which is acknowledged and dropped, or
+    /// if the client has stopped. The bridge stops along with the client and
+    /// the publication stays in the bridge's session on the local broker.
+    fn forward(&self, publish: Publish, mut publish_handle: PublishHandle) {
+        let (ack, publish) = match publish {
+            Publish::QoS0(id, publish) => (ClientEvent::PubAck0(id), publish),
+            Publish::QoS12(_, publish) => match ack(&publish) {
+                Some(ack) => (ack, publish),
+                None => return,
+            },
+        };
+
+        let remote_topic = if self.is_injected(&publish) {
+            None
+        } else {
+            self.mapper.map_out(&publish.topic_name)
+        };
+        let publication = remote_topic.map(|topic_name| proto::Publication {
+            topic_name,
+            qos: qos(&publish.packet_identifier_dup_qos),
+            retain: publish.retain,
+            payload: publish.payload,
+            properties: proto::Properties::default(),
+        });
+
+        let mut broker_handle = self.broker_handle.clone();
+        let message = Message::Client(self.client_id.clone(), ack);
+        tokio::spawn(async move {
+            if let Some(publication) = publication {
+                debug!(
+                    "bridge forwarding publication to {}",
+                    publication.topic_name
+                );
+                match publish_handle.publish(publication).await {
+                    Ok(()) => (),
+                    Err(e @ PublishError::EncodePacket(..)) => {
+                        warn!(message = "bridge dropped a publication it cannot publish upstream", error = %e);
+                    }
+                    Err(e @ PublishError::ClientDoesNotExist) => {
+                        warn!(message = "bridge failed to publish upstream", error = %e);
+                        return;
+                    }
+                }
+            }
+
+            if let Err(e) = broker_handle.send(message).await {
+                warn!(message = "bridge failed to acknowledge a local publication", error = %e);
+            }
+        });
+    }
+
+    /// Returns whether this bridge injected the publication into the local broker.
+    fn is_injected(&self, publish: &proto::Publish) -> bool {
+        publish
+            .properties
+            .user_properties
+            .iter()
+            .any(|(name, value)| name == BRIDGE_PROPERTY && value == &self.name)
+    }
+
+    async fn send(&self, event: ClientEvent) -> Result<(), Error> {
+        let message = Message::Client(self.client_id.clone(), event);
+        self.broker_handle.clone().send(message).await
+    }
+}
+
+/// The bridge as a client of the remote broker.
+struct Upstream {
+    name: String,
+    client_id: ClientId,
+    broker_handle: BrokerHandle,
+    mapper: Arc<TopicMapper>,
+}
+
+impl Upstream {
+    async fn run<IoS>(mut self, mut client: mqtt3::Client<IoS>)
+    where
+        IoS: IoSource,
+        <IoS as IoSource>::Io: Unpin,
+        <IoS as IoSource>::Error: std::fmt::Display,
+        <IoS as IoSource>::Future: Unpin,
+    {
+        let mut packet_identifier = proto::PacketIdentifier::max_value();
+
+        while let Some(event) = client.next().await {
+            match event {
+                Ok(mqtt3::Event::NewConnection { reset_session }) => {
+                    info!(
+                        "bridge {} connected upstream, session reset: {}",
+                        self.client_id, reset_session
+                    );
+                }
+                Ok(mqtt3::Event::Publication(publication)) => {
+                    let local_topic = match self.mapper.map_in(&publication.topic_name) {
+                        Some(local_topic) => local_topic,
+                        None => continue,
+                    };
+
+                    packet_identifier += 1;
+                    let packet_identifier_dup_qos = match publication.qos {
+                        proto::QoS::AtMostOnce => proto::PacketIdentifierDupQoS::AtMostOnce,
+                        proto::QoS::AtLeastOnce => {
+                            proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, false)
+                        }
+                        proto::QoS::ExactlyOnce => {
+                            proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, false)
+                        }
+                    };
+
+                    // The local broker sends the publication back to the bridge
+                    // if the topic is also forwarded the other way.
+                    let mut properties = proto::Properties::default();
+                    properties
+                        .user_properties
+                        .push((BRIDGE_PROPERTY.to_owned(), self.name.clone()));
+
+                    let publish = proto::Publish {
+                        packet_identifier_dup_qos,
+                        retain: publication.retain,
+                        topic_name: local_topic,
+                        payload: publication.payload,
+                        properties,
+                    };
+
+                    let message =
+                        Message::Client(self.client_id.clone(), ClientEvent::PublishFrom(publish));
+                    if let Err(e) = self.broker_handle.send(message).await {
+                        warn!(message = "bridge failed to publish locally", error = %e);
+                    }
+                }
+                Ok(mqtt3::Event::SubscriptionUpdates(updates)) => {
+                    debug!(
+                        "bridge {} subscriptions updated: {:?}",
+                        self.client_id, updates
+                    );
+                }
+                Err(e) => {
+                    warn!(message = "bridge upstream connection failed", error = %e);
+                }
+            }
+        }
+    }
+}
+
+/// Returns the acknowledgement the local broker expects for a QoS 1 or 2 publication.
+fn ack(publish: &proto::Publish) -> Option<ClientEvent> {
+    match publish.packet_identifier_dup_qos {
+        proto::PacketIdentifierDupQoS::AtMostOnce => None,
+        proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, _) => {
+            Some(ClientEvent::PubAck(proto::PubAck {
+                packet_identifier,
+                reason_code: proto::ReasonCode::SUCCESS,
+                properties: proto::Properties::default(),
+            }))
+        }
+        proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, _) => {
+            Some(ClientEvent::PubRec(proto::PubRec {
+                packet_identifier,
+                reason_code: proto::ReasonCode::SUCCESS,
+                properties: proto::Properties::default(),
+            }))
+        }
+    }
+}
+
+fn qos(packet_identifier_dup_qos: &proto::PacketIdentifierDupQoS) -> proto::QoS {
+    match packet_identifier_dup_qos {
+        proto::PacketIdentifierDupQoS::AtMostOnce => proto::QoS::AtMostOnce,
+        proto::PacketIdentifierDupQoS::AtLeastOnce(..) => proto::QoS::AtLeastOnce,
+        proto::PacketIdentifierDupQoS::ExactlyOnce(..) => proto::QoS::ExactlyOnce,
+    }
+}
+
+/// Rewrites topics between the local and the remote broker.
+#[derive(Debug)]
+struct TopicMapper {
+    rules: Vec<TopicRule>,
+}
+
+#[derive(Debug)]
+struct TopicRule {
+    filter: TopicFilter,
+    topic: BridgeTopic,
+}
+
+impl TopicMapper {
+    fn new(topics: &[BridgeTopic]) -> Result<Self, Error> {
+        let rules = topics
+            .iter()
+            .map(|topic| {
+                let filter = topic.pattern().parse()?;
+                Ok(TopicRule {
+                    filter,
+                    topic: topic.clone(),
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(Self { rules })
+    }
+
+    /// Returns the remote topic a publication on a local topic is forwarded to.
+    fn map_out(&self, local_topic: &str) -> Option<String> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.topic.direction().is_out())
+            .find_map(|rule| {
+                rewrite(
+                    &rule.filter,
+                    local_topic,
+                    rule.topic.local_prefix(),
+                    rule.topic.remote_prefix(),
+                )
+            })
+    }
+
+    /// Returns the local topic a publication on a remote topic is forwarded to.
+    fn map_in(&self, remote_topic: &str) -> Option<String> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.topic.direction().is_in())
+            .find_map(|rule| {
+                rewrite(
+                    &rule.filter,
+                    remote_topic,
+                    rule.topic.remote_prefix(),
+                    rule.topic.local_prefix(),
+                )
+            })
+    }
+}
+
+fn rewrite(filter: &TopicFilter, topic: &str, from: &str, to: &str) -> Option<String> {
+    if !topic.starts_with(from) {
+        return None;
+    }
+
+    let topic = &topic[from.len()..];
+    if filter.matches(topic) {
+        Some(format!("{}{}", to, topic))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mqtt_broker::BridgeTopic;
+
+    use super::TopicMapper;
+
+    fn topics(topics: serde_json::Value) -> Vec<BridgeTopic> {
+        serde_json::from_value(topics).unwrap()
+    }
+
+    #[test]
+    fn it_maps_out_with_prefixes() {
+        let mapper = TopicMapper::new(&topics(serde_json::json!([
+            {
+                "pattern": "telemetry/#",
+                "direction": "out",
+                "qos": 1,
+                "local_prefix": "local/",
+                "remote_prefix": "child/"
+            }
+        ])))
+        .unwrap();
+
+        assert_eq!(
+            mapper.map_out("local/telemetry/temp"),
+            Some("child/telemetry/temp".to_string())
+        );
+        assert_eq!(mapper.map_out("telemetry/temp"), None);
+        assert_eq!(mapper.map_out("local/commands/reboot"), None);
+        assert_eq!(mapper.map_in("child/telemetry/temp"), None);
+    }
+
+    #[test]
+    fn it_maps_in_and_both() {
+        let mapper = TopicMapper::new(&topics(serde_json::json!([
+            { "pattern": "commands/+", "direction": "in", "qos": 0, "local_prefix": "parent/" },
+            { "pattern": "twin/#", "direction": "both", "qos": 1 }
+        ])))
+        .unwrap();
+
+        assert_eq!(
+            mapper.map_in("commands/reboot"),
+            Some("parent/commands/reboot".to_string())
+        );
+        assert_eq!(mapper.map_in("commands/reboot/now"), None);
+        assert_eq!(mapper.map_out("parent/commands/reboot"), None);
+        assert_eq!(
+            mapper.map_in("twin/reported"),
+            Some("twin/reported".to_string())
+        );
+        assert_eq!(
+            mapper.map_out("twin/reported"),
+            Some("twin/reported".to_string())
+        );
+    }
+
+    #[test]
+    fn it_refuses_invalid_pattern() {
+        let result = TopicMapper::new(&topics(serde_json::json!([
+            { "pattern": "commands/#/reboot", "direction": "in", "qos": 0 }
+        ])));
+
+        assert!(result.is_err());
+    }
+}
@@ -2,6 +2,12 @@ use std::fmt;
 
 use mqtt_broker::Error;
 
+pub mod admin;
+pub mod auth;
+pub mod bridge;
+pub mod metrics;
+pub mod passwd;
+pub mod reload;
 pub mod shutdown;
 pub mod snapshot;
 
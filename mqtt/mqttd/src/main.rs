@@ -1,13 +1,31 @@
-use std::{convert::TryInto, env, io};
+use std::{
+    env,
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+};
 
-use clap::{crate_description, crate_name, crate_version, App, Arg};
+use clap::{
+    crate_description, crate_name, crate_version, App, AppSettings, Arg, ArgMatches, SubCommand,
+};
 use futures_util::pin_mut;
 use mqtt_broker::*;
-use tokio::time::{Duration, Instant};
+use mqtt_edgehub::TranslationInterceptor;
+use tokio::{
+    sync::mpsc,
+    time::{Duration, Instant},
+};
 use tracing::{info, warn, Level};
 use tracing_subscriber::{fmt, EnvFilter};
 
-use mqttd::{shutdown, snapshot, Terminate};
+use mqttd::{
+    admin,
+    auth::{ConfiguredAuthenticator, ConfiguredAuthorizer},
+    bridge::Bridge,
+    metrics, passwd, reload, shutdown, snapshot, Terminate,
+};
+
+const DEFAULT_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(5 * 60);
 
 #[tokio::main]
 async fn main() -> Result<(), Terminate> {
@@ -19,14 +37,34 @@ async fn main() -> Result<(), Terminate> {
         .finish();
     let _ = tracing::subscriber::set_global_default(subscriber);
 
-    run().await?;
+    let matches = create_app().get_matches();
+    match matches.subcommand() {
+        ("passwd", Some(matches)) => run_passwd(matches)?,
+        _ => run(&matches).await?,
+    }
     Ok(())
 }
 
-async fn run() -> Result<(), Error> {
-    let config = create_app()
-        .get_matches()
-        .value_of("config")
+fn run_passwd(matches: &ArgMatches<'_>) -> Result<(), Error> {
+    let path = Path::new(matches.value_of("file").expect("file is required"));
+    match matches.subcommand() {
+        ("add", Some(matches)) => passwd::add(
+            path,
+            matches.value_of("username").expect("username is required"),
+        )?,
+        ("remove", Some(matches)) => passwd::remove(
+            path,
+            matches.value_of("username").expect("username is required"),
+        )?,
+        _ => unreachable!("subcommand is required"),
+    }
+    Ok(())
+}
+
+async fn run(matches: &ArgMatches<'_>) -> Result<(), Error> {
+    let config_path = matches.value_of("config").map(PathBuf::from);
+    let config = config_path
+        .as_ref()
         .map_or(BrokerConfig::new(), BrokerConfig::from_file)
         .map_err(InitializeBrokerError::LoadConfiguration)?;
 
@@ -35,46 +73,118 @@ async fn run() -> Result<(), Error> {
     pin_mut!(shutdown);
 
     // Setup the snapshotter
-    let mut persistor = FilePersistor::new(
-        env::current_dir().expect("can't get cwd").join("state"),
-        VersionedFileFormat::default(),
-    );
+    let (state_dir, snapshot_interval) = match config.persistence() {
+        Some(persistence) => (
+            PathBuf::from(persistence.file_path()),
+            persistence.time_interval(),
+        ),
+        None => (
+            env::current_dir().expect("can't get cwd").join("state"),
+            DEFAULT_SNAPSHOT_INTERVAL,
+        ),
+    };
+
+    // Journal state changes to a write-ahead log if configured
+    if config
+        .persistence()
+        .map_or(false, SessionPersistence::journal)
+    {
+        let persistor = WalPersistor::new(state_dir);
+        serve(config, config_path, persistor, snapshot_interval, shutdown).await
+    } else {
+        let persistor = FilePersistor::new(state_dir, VersionedFileFormat::default());
+        serve(config, config_path, persistor, snapshot_interval, shutdown).await
+    }
+}
+
+async fn serve<P, F>(
+    config: BrokerConfig,
+    config_path: Option<PathBuf>,
+    mut persistor: P,
+    snapshot_interval: Duration,
+    shutdown: F,
+) -> Result<(), Error>
+where
+    P: Persist<Error = PersistError> + Send + 'static,
+    F: Future<Output = ()> + Unpin,
+{
     info!("Loading state...");
     let state = persistor.load().await?.unwrap_or_else(BrokerState::default);
-    let broker = BrokerBuilder::default()
-        .authenticator(|_| Ok(Some(AuthId::Anonymous)))
-        .authorizer(|_| Ok(true))
-        .state(state)
-        .build();
-    info!("state loaded.");
 
     let snapshotter = Snapshotter::new(persistor);
     let snapshot_handle = snapshotter.snapshot_handle();
     let mut shutdown_handle = snapshotter.shutdown_handle();
     let join_handle = tokio::spawn(snapshotter.run());
 
+    let authenticator = ConfiguredAuthenticator::from_config(config.authentication())
+        .map_err(InitializeBrokerError::LoadPasswordFile)?;
+    let authorizer = ConfiguredAuthorizer::from_config(&config)
+        .map_err(InitializeBrokerError::LoadAuthorization)?;
+
+    let broker = BrokerBuilder::default()
+        .authenticator(authenticator)
+        .authorizer(authorizer)
+        .state(state)
+        .with_config(config.clone())
+        .with_snapshot_handle(snapshot_handle.clone())
+        .build();
+    info!("state loaded.");
+
     // Tick the snapshotter
-    let tick = tick_snapshot(
-        Duration::from_secs(5 * 60),
-        broker.handle(),
-        snapshot_handle.clone(),
-    );
+    let tick = tick_snapshot(snapshot_interval, broker.handle(), snapshot_handle.clone());
     tokio::spawn(tick);
 
+    // Periodically remove expired state
+    let cleanup = tick_cleanup(Duration::from_secs(60), broker.handle());
+    tokio::spawn(cleanup);
+
+    // Serve the admin API if configured
+    if let Some(admin_config) = config.admin() {
+        let max_message_size = config.session().messages().max_message_size();
+        let admin = admin::serve(
+            admin_config,
+            max_message_size,
+            broker.handle(),
+            snapshot_handle.clone(),
+        )?;
+        tokio::spawn(admin);
+    }
+
+    // Serve metrics if configured
+    if let Some(config) = config.metrics() {
+        let metrics = metrics::serve(config.address())?;
+        tokio::spawn(metrics);
+    }
+
+    // Periodically publish broker statistics
+    if config.sys_interval() != Duration::default() {
+        let stats = tick_stats(config.sys_interval(), broker.handle());
+        tokio::spawn(stats);
+    }
+
     // Signal the snapshotter
     let snapshot = snapshot::snapshot(broker.handle(), snapshot_handle.clone());
     tokio::spawn(snapshot);
 
-    // Create configured transports
-    let transports = config
-        .transports()
-        .iter()
-        .map(|transport| transport.clone().try_into())
-        .collect::<Result<Vec<_>, _>>()?;
+    // Reload the configuration on signal
+    let (transports_tx, transports_rx) = mpsc::unbounded_channel();
+    let reload = reload::reload(config_path, broker.handle(), transports_tx);
+    tokio::spawn(reload);
+
+    // Start configured bridges
+    for bridge in config.bridges() {
+        let bridge = Bridge::new(bridge.clone(), broker.handle())?;
+        tokio::spawn(bridge.run());
+    }
+
+    let mut server = Server::from_broker(broker);
+    if config.edgehub_translation().unwrap_or(true) {
+        server = server.with_interceptor(TranslationInterceptor);
+    }
 
     info!("Starting server...");
-    let state = Server::from_broker(broker)
-        .serve(transports, shutdown)
+    let state = server
+        .serve_with_updates(config.transports().clone(), transports_rx, shutdown)
         .await?;
 
     // Stop snapshotting
@@ -111,6 +221,35 @@ async fn tick_snapshot(
     }
 }
 
+async fn tick_cleanup(period: Duration, mut broker_handle: BrokerHandle) {
+    info!("Removing expired state every {:?}", period);
+    let start = Instant::now() + period;
+    let mut interval = tokio::time::interval_at(start, period);
+    loop {
+        interval.tick().await;
+        if let Err(e) = broker_handle
+            .send(Message::System(SystemEvent::Cleanup))
+            .await
+        {
+            warn!(message = "failed to tick the broker cleanup", error=%e);
+        }
+    }
+}
+
+async fn tick_stats(period: Duration, mut broker_handle: BrokerHandle) {
+    info!("Publishing broker statistics every {:?}", period);
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        if let Err(e) = broker_handle
+            .send(Message::System(SystemEvent::PublishStats))
+            .await
+        {
+            warn!(message = "failed to tick the broker statistics", error=%e);
+        }
+    }
+}
+
 fn create_app() -> App<'static, 'static> {
     App::new(crate_name!())
         .version(crate_version!())
@@ -123,4 +262,31 @@ fn create_app() -> App<'static, 'static> {
                 .help("Sets a custom config file")
                 .takes_value(true),
         )
+        .subcommand(
+            SubCommand::with_name("passwd")
+                .about("Manages users of a password file")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .arg(
+                    Arg::with_name("file")
+                        .short("f")
+                        .long("file")
+                        .value_name("FILE")
+                        .help("Password file to update")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about(
+                            "Adds a user or changes the password of an existing one. \
+                             The password is read from stdin",
+                        )
+                        .arg(Arg::with_name("username").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Removes a user")
+                        .arg(Arg::with_name("username").required(true)),
+                ),
+        )
 }
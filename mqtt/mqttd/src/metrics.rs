@@ -0,0 +1,50 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use tracing::{info, warn};
+
+use mqtt_broker::{encode_metrics, metrics_content_type, InitializeBrokerError};
+
+/// Serves broker metrics in the Prometheus text format on `/metrics`.
+///
+/// Binds the listener right away, so that the broker fails to start if the
+/// address is not available, and returns the future serving the requests.
+pub fn serve(address: SocketAddr) -> Result<impl Future<Output = ()>, InitializeBrokerError> {
+    let server = Server::try_bind(&address)
+        .map_err(|e| InitializeBrokerError::BindServer(io::Error::new(io::ErrorKind::Other, e)))?;
+
+    let make_service =
+        make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle_request)) });
+
+    info!("Serving metrics on address {}", address);
+    Ok(async move {
+        if let Err(e) = server.serve(make_service).await {
+            warn!(message = "metrics listener exited with an error", error=%e);
+        }
+    })
+}
+
+async fn handle_request(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => match encode_metrics() {
+            Ok(metrics) => Response::builder()
+                .header(header::CONTENT_TYPE, metrics_content_type())
+                .body(Body::from(metrics)),
+            Err(e) => {
+                warn!(message = "failed to encode metrics", error=%e);
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::empty())
+            }
+        },
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+
+    Ok(response.expect("response must be valid"))
+}
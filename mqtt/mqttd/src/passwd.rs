@@ -0,0 +1,47 @@
+use std::io::{self, BufRead};
+use std::path::Path;
+
+use mqtt_broker::{PasswordFile, PasswordFileError};
+use tracing::info;
+
+/// Adds a user to a password file, creating the file if needed.
+///
+/// The password is read from the first line of standard input, so that it
+/// never shows up in the command line of the process.
+pub fn add(path: &Path, username: &str) -> Result<(), PasswordFileError> {
+    let password = read_password()?;
+
+    let mut file = PasswordFile::load_or_empty(path)?;
+    file.set(username, &password)?;
+    file.save()?;
+
+    info!("user {} added to {}", username, path.display());
+    Ok(())
+}
+
+/// Removes a user from a password file.
+pub fn remove(path: &Path, username: &str) -> Result<(), PasswordFileError> {
+    let mut file = PasswordFile::load(path)?;
+    if file.remove(username) {
+        file.save()?;
+        info!("user {} removed from {}", username, path.display());
+    } else {
+        info!("no user {} found in {}", username, path.display());
+    }
+
+    Ok(())
+}
+
+fn read_password() -> Result<String, PasswordFileError> {
+    if atty::is(atty::Stream::Stdin) {
+        eprint!("Password: ");
+    }
+
+    let mut password = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut password)
+        .map_err(|e| PasswordFileError::ReadFile("<stdin>".into(), e))?;
+
+    Ok(password.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
@@ -0,0 +1,83 @@
+use std::path::PathBuf;
+
+use mqtt_broker::{BrokerHandle, Transport};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Re-reads the configuration file on signal and applies it to the running
+/// broker and its transports.
+pub async fn reload(
+    config_path: Option<PathBuf>,
+    broker_handle: BrokerHandle,
+    transports: UnboundedSender<Vec<Transport>>,
+) {
+    imp::reload(config_path, broker_handle, transports).await;
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::path::PathBuf;
+
+    use tokio::signal::unix::{signal, SignalKind};
+    use tokio::sync::mpsc::UnboundedSender;
+    use tracing::{info, warn};
+
+    use mqtt_broker::{BrokerConfig, BrokerHandle, Message, SystemEvent, Transport};
+
+    pub(super) async fn reload(
+        config_path: Option<PathBuf>,
+        mut broker_handle: BrokerHandle,
+        transports: UnboundedSender<Vec<Transport>>,
+    ) {
+        let mut stream = match signal(SignalKind::hangup()) {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!(message = "an error occurred setting up the signal handler", error=%e);
+                return;
+            }
+        };
+
+        info!("Setup to reload configuration on HUP signal");
+        loop {
+            stream.recv().await;
+            info!("Received signal HUP");
+
+            let config = match &config_path {
+                Some(path) => BrokerConfig::from_file(path),
+                None => BrokerConfig::new(),
+            };
+            let config = match config {
+                Ok(config) => config,
+                Err(e) => {
+                    warn!(message = "failed to reload configuration. keeping current configuration", error=%e);
+                    continue;
+                }
+            };
+
+            if let Err(e) = transports.send(config.transports().clone()) {
+                warn!(message = "failed to update transports", error=%e);
+            }
+
+            if let Err(e) = broker_handle
+                .send(Message::System(SystemEvent::ConfigUpdate(config)))
+                .await
+            {
+                warn!(message = "failed to signal the broker", error=%e);
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use std::path::PathBuf;
+
+    use mqtt_broker::{BrokerHandle, Transport};
+    use tokio::sync::mpsc::UnboundedSender;
+
+    pub(super) async fn reload(
+        _config_path: Option<PathBuf>,
+        _broker_handle: BrokerHandle,
+        _transports: UnboundedSender<Vec<Transport>>,
+    ) {
+    }
+}